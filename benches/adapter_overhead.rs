@@ -0,0 +1,119 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Measures the per-invoke overhead of the adapter compared to sending the
+//! same request directly to the web application.
+//!
+//! Run with `cargo bench --bench adapter_overhead`. The benchmark exits with a
+//! non-zero status when the median overhead exceeds the budget, which defaults
+//! to 100µs and can be changed with `LWA_BENCH_BUDGET_US`.
+
+use std::convert::Infallible;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::service::service_fn;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use lambda_http::request::LambdaRequest;
+use lambda_http::{Body, Context, Request, RequestExt};
+use lambda_web_adapter::{Adapter, AdapterOptions};
+use serde_json::json;
+use tokio::net::TcpListener;
+use tower::Service;
+
+const WARMUP: usize = 200;
+const ITERATIONS: usize = 2000;
+
+#[tokio::main]
+async fn main() {
+    let budget = Duration::from_micros(
+        std::env::var("LWA_BENCH_BUDGET_US")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(100),
+    );
+
+    let port = start_app_server().await;
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: "127.0.0.1".to_string(),
+        port: port.to_string(),
+        readiness_check_port: port.to_string(),
+        async_init: false,
+        ..Default::default()
+    });
+    let client = Client::builder(TokioExecutor::new()).build_http::<Body>();
+    let url = format!("http://127.0.0.1:{port}/hello");
+
+    for _ in 0..WARMUP {
+        adapter.call(lambda_request()).await.unwrap().collect().await.unwrap();
+        direct_request(&client, &url).await;
+    }
+
+    let mut direct = Vec::with_capacity(ITERATIONS);
+    let mut adapted = Vec::with_capacity(ITERATIONS);
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        direct_request(&client, &url).await;
+        direct.push(start.elapsed());
+
+        let request = lambda_request();
+        let start = Instant::now();
+        adapter.call(request).await.unwrap().collect().await.unwrap();
+        adapted.push(start.elapsed());
+    }
+
+    let direct = median(&mut direct);
+    let adapted = median(&mut adapted);
+    let overhead = adapted.saturating_sub(direct);
+    println!("direct:   {direct:?} (median of {ITERATIONS})");
+    println!("adapter:  {adapted:?} (median of {ITERATIONS})");
+    println!("overhead: {overhead:?} (budget {budget:?})");
+
+    if overhead > budget {
+        eprintln!("adapter overhead exceeds budget");
+        std::process::exit(1);
+    }
+}
+
+async fn start_app_server() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let service = service_fn(|_req| async {
+                    Ok::<_, Infallible>(hyper::Response::new(Full::new(Bytes::from("OK"))))
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    port
+}
+
+fn lambda_request() -> Request {
+    let event: LambdaRequest = serde_json::from_value(json!({
+        "httpMethod": "GET",
+        "path": "/hello",
+        "headers": {},
+        "isBase64Encoded": false,
+        "requestContext": { "elb": { "targetGroupArn": "arn:aws:elasticloadbalancing:us-east-1:123456789012:targetgroup/bench/0" } }
+    }))
+    .unwrap();
+    Request::from(event).with_lambda_context(Context::default())
+}
+
+async fn direct_request(client: &Client<hyper_util::client::legacy::connect::HttpConnector, Body>, url: &str) {
+    let request = hyper::Request::get(url).body(Body::Empty).unwrap();
+    client.request(request).await.unwrap().collect().await.unwrap();
+}
+
+fn median(samples: &mut [Duration]) -> Duration {
+    samples.sort_unstable();
+    samples[samples.len() / 2]
+}
@@ -0,0 +1,142 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use http::header::ACCEPT;
+use http::{HeaderMap, HeaderValue};
+use lambda_http::Error;
+use serde_json::Value;
+
+use crate::glob_match;
+
+#[derive(Debug)]
+enum AcceptAction {
+    /// Replace the `Accept` header.
+    Force(HeaderValue),
+    /// Set the `Accept` header when the client did not state a preference.
+    Default(HeaderValue),
+}
+
+/// Rewrites the `Accept` header of requests by path, for frameworks that
+/// route on it and get confused by what browsers send.
+#[derive(Debug)]
+pub(crate) struct AcceptRules {
+    /// Longest patterns first, so the most specific rule wins.
+    rules: Vec<(String, AcceptAction)>,
+}
+
+impl AcceptRules {
+    /// Parse a JSON object of path patterns to rules, such as
+    /// `{"/api/*":"application/json","/docs/*":{"default":"text/html"}}`.
+    pub(crate) fn new(rules: &str) -> Result<Self, Error> {
+        let rules: serde_json::Map<String, Value> = serde_json::from_str(rules)?;
+        let mut rules = rules
+            .into_iter()
+            .map(|(pattern, rule)| {
+                let action = match &rule {
+                    Value::String(accept) => AcceptAction::Force(HeaderValue::from_str(accept)?),
+                    Value::Object(rule) => match rule.get("default").and_then(Value::as_str) {
+                        Some(accept) => AcceptAction::Default(HeaderValue::from_str(accept)?),
+                        None => return Err(Error::from(format!("accept rule {pattern} needs a default"))),
+                    },
+                    _ => return Err(Error::from(format!("invalid accept rule for {pattern}"))),
+                };
+                Ok((pattern, action))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        rules.sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.len()));
+        Ok(AcceptRules { rules })
+    }
+
+    /// Rewrite the `Accept` header for a request to `path`.
+    pub(crate) fn apply(&self, path: &str, headers: &mut HeaderMap) {
+        let Some((_, action)) = self.rules.iter().find(|(pattern, _)| glob_match(pattern, path)) else {
+            return;
+        };
+        match action {
+            AcceptAction::Force(accept) => {
+                headers.insert(ACCEPT, accept.clone());
+            }
+            AcceptAction::Default(accept) if !has_preference(headers) => {
+                headers.insert(ACCEPT, accept.clone());
+            }
+            AcceptAction::Default(_) => {}
+        }
+    }
+}
+
+/// Whether the client asked for specific media types. A missing header, or
+/// one with a `*/*` wildcard like browsers and HTTP clients send, does not.
+fn has_preference(headers: &HeaderMap) -> bool {
+    let mut values = headers.get_all(ACCEPT).iter().peekable();
+    if values.peek().is_none() {
+        return false;
+    }
+    values
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .all(|media_range| {
+            let media_type = media_range.split(';').next().unwrap_or_default().trim();
+            !media_type.is_empty() && media_type != "*/*"
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(rules: &AcceptRules, path: &str, accept: Option<&'static str>) -> Option<String> {
+        let mut headers = HeaderMap::new();
+        if let Some(accept) = accept {
+            headers.insert(ACCEPT, HeaderValue::from_static(accept));
+        }
+        rules.apply(path, &mut headers);
+        headers.get(ACCEPT).map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_accept_rules() {
+        let rules = AcceptRules::new(
+            r#"{"/api/*":"application/json","/api/export/*":{"default":"text/csv"},"/docs/*":{"default":"text/html"}}"#,
+        )
+        .unwrap();
+        let browser = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+
+        assert_eq!(
+            Some("application/json".to_string()),
+            accept(&rules, "/api/orders", Some(browser))
+        );
+        assert_eq!(
+            Some("application/json".to_string()),
+            accept(&rules, "/api/orders", None)
+        );
+
+        // the longest pattern wins
+        assert_eq!(
+            Some("text/csv".to_string()),
+            accept(&rules, "/api/export/orders", Some("*/*"))
+        );
+        assert_eq!(
+            Some("application/json".to_string()),
+            accept(&rules, "/api/export/orders", Some("application/json"))
+        );
+
+        assert_eq!(
+            Some("text/html".to_string()),
+            accept(&rules, "/docs/intro", Some(browser))
+        );
+        assert_eq!(Some("text/html".to_string()), accept(&rules, "/docs/intro", None));
+        assert_eq!(
+            Some("text/markdown".to_string()),
+            accept(&rules, "/docs/intro", Some("text/markdown"))
+        );
+
+        assert_eq!(None, accept(&rules, "/other", None));
+    }
+
+    #[test]
+    fn test_invalid_accept_rules() {
+        assert!(AcceptRules::new(r#"{"/api/*":42}"#).is_err());
+        assert!(AcceptRules::new(r#"{"/api/*":{"force":"application/json"}}"#).is_err());
+        assert!(AcceptRules::new("[]").is_err());
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Access logs as JSON lines on stdout, one line per invoke.
+//!
+//! Lambda sends stdout to CloudWatch Logs, so the values of headers and
+//! query parameters that carry credentials are redacted before a line is
+//! written. The built-in lists can be extended with more names.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use http::{HeaderMap, HeaderName};
+use lambda_http::{Error, Request, RequestExt, Response};
+use serde_json::{json, Map, Value};
+
+use crate::capture::SENSITIVE_HEADERS;
+use crate::replay::{is_sensitive_param, rfc3339, REDACTED};
+use crate::ResponseBody;
+
+/// How long the app took to send the response headers, kept in the
+/// extensions of responses from the app.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct UpstreamLatency(pub(crate) Duration);
+
+pub(crate) struct AccessLog {
+    redact_headers: Vec<HeaderName>,
+    redact_params: Vec<String>,
+}
+
+impl AccessLog {
+    /// Log with the values of these comma separated headers and query
+    /// parameters redacted, on top of the built-in ones.
+    pub(crate) fn new(redact_headers: Option<&str>, redact_params: Option<&str>) -> Result<Self, Error> {
+        let names = |list: Option<&str>| {
+            list.unwrap_or_default()
+                .split(',')
+                .map(|name| name.trim().to_ascii_lowercase())
+                .filter(|name| !name.is_empty())
+                .collect::<Vec<_>>()
+        };
+        let mut headers = SENSITIVE_HEADERS
+            .iter()
+            .map(|name| HeaderName::from_static(name))
+            .collect::<Vec<_>>();
+        for name in names(redact_headers) {
+            headers.push(HeaderName::try_from(name.as_str()).map_err(|_| format!("invalid header name {name}"))?);
+        }
+        Ok(AccessLog {
+            redact_headers: headers,
+            redact_params: names(redact_params),
+        })
+    }
+
+    /// Start the line of a request, before the adapter consumes the event.
+    pub(crate) fn start(&self, event: &Request) -> AccessEntry {
+        let mut line = json!({
+            "time": rfc3339(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|now| now.as_millis() as u64)
+                    .unwrap_or_default()
+            ),
+            "request_id": event.lambda_context_ref().map(|context| context.request_id.as_str()),
+            "method": event.method().as_str(),
+            "path": event.raw_http_path(),
+            "headers": self.headers(event.headers()),
+        });
+        if let Some(query) = event.uri().query().filter(|query| !query.is_empty()) {
+            line["query"] = Value::from(self.query(query));
+        }
+        AccessEntry {
+            started: Instant::now(),
+            line,
+        }
+    }
+
+    fn headers(&self, headers: &HeaderMap) -> Value {
+        let mut logged = Map::new();
+        for name in headers.keys() {
+            let value = match self.redact_headers.contains(name) {
+                true => REDACTED.to_string(),
+                false => headers
+                    .get_all(name)
+                    .iter()
+                    .map(|value| String::from_utf8_lossy(value.as_bytes()))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            };
+            logged.insert(name.to_string(), Value::from(value));
+        }
+        Value::Object(logged)
+    }
+
+    /// The query string with the values of sensitive parameters replaced,
+    /// and the rest as it was sent.
+    fn query(&self, query: &str) -> String {
+        query
+            .split('&')
+            .map(|pair| {
+                let Some((name, _)) = pair.split_once('=') else {
+                    return pair.to_string();
+                };
+                let decoded = url::form_urlencoded::parse(name.as_bytes())
+                    .next()
+                    .map(|(name, _)| name.to_lowercase())
+                    .unwrap_or_default();
+                match is_sensitive_param(&decoded) || self.redact_params.contains(&decoded) {
+                    true => format!("{name}={REDACTED}"),
+                    false => pair.to_string(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+/// The line of a request that is still being processed.
+pub(crate) struct AccessEntry {
+    started: Instant,
+    line: Value,
+}
+
+impl AccessEntry {
+    /// Write the line with the outcome of the request.
+    pub(crate) fn finish(self, response: &Result<Response<ResponseBody>, Error>) {
+        println!("{}", self.complete(response));
+    }
+
+    fn complete(mut self, response: &Result<Response<ResponseBody>, Error>) -> Value {
+        match response {
+            Ok(response) => {
+                self.line["status"] = Value::from(response.status().as_u16());
+                self.line["upstream_ms"] = Value::from(
+                    response
+                        .extensions()
+                        .get::<UpstreamLatency>()
+                        .map(|latency| latency.0.as_secs_f64() * 1000.0),
+                );
+            }
+            Err(err) => {
+                self.line["status"] = Value::Null;
+                self.line["upstream_ms"] = Value::Null;
+                self.line["error"] = Value::from(err.to_string());
+            }
+        }
+        self.line["duration_ms"] = Value::from(self.started.elapsed().as_secs_f64() * 1000.0);
+        self.line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::full_body;
+    use http::{HeaderValue, StatusCode};
+
+    #[test]
+    fn test_access_log_redacts_headers() {
+        let log = AccessLog::new(Some("X-Session-Id, x-csrf-token"), None).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer secret"));
+        headers.insert("x-session-id", HeaderValue::from_static("secret"));
+        headers.append("accept", HeaderValue::from_static("text/html"));
+        headers.append("accept", HeaderValue::from_static("application/json"));
+        assert_eq!(
+            json!({
+                "authorization": REDACTED,
+                "x-session-id": REDACTED,
+                "accept": "text/html, application/json",
+            }),
+            log.headers(&headers)
+        );
+        assert!(AccessLog::new(Some("bad header"), None).is_err());
+    }
+
+    #[test]
+    fn test_access_log_redacts_query() {
+        let log = AccessLog::new(None, Some("email,SSN")).unwrap();
+        assert_eq!(
+            "page=2&access_token=[REDACTED]&email=[REDACTED]&ssn=[REDACTED]&q=a%20b&flag",
+            log.query("page=2&access_token=abc&email=a%40b.com&ssn=123&q=a%20b&flag")
+        );
+        assert_eq!("Api%5FKey=[REDACTED]", log.query("Api%5FKey=abc"));
+    }
+
+    #[test]
+    fn test_access_log_line() {
+        let log = AccessLog::new(None, None).unwrap();
+        let event = http::Request::builder()
+            .method("POST")
+            .uri("https://example.com/orders?token=abc&page=1")
+            .header("x-api-key", "secret")
+            .body(lambda_http::Body::Empty)
+            .unwrap()
+            .with_raw_http_path("/orders");
+        let entry = log.start(&event);
+        let mut response = Response::builder()
+            .status(StatusCode::CREATED)
+            .body(full_body(""))
+            .unwrap();
+        response
+            .extensions_mut()
+            .insert(UpstreamLatency(Duration::from_millis(12)));
+
+        let line = entry.complete(&Ok(response));
+        assert_eq!(json!("POST"), line["method"]);
+        assert_eq!(json!("/orders"), line["path"]);
+        assert_eq!(json!("token=[REDACTED]&page=1"), line["query"]);
+        assert_eq!(json!({ "x-api-key": REDACTED }), line["headers"]);
+        assert_eq!(json!(201), line["status"]);
+        assert_eq!(json!(12.0), line["upstream_ms"]);
+        assert!(line["duration_ms"].is_number());
+
+        let entry = log.start(&Request::default());
+        let line = entry.complete(&Err(Error::from("connection refused")));
+        assert_eq!(Value::Null, line["status"]);
+        assert_eq!(json!("connection refused"), line["error"]);
+        assert!(line.get("query").is_none());
+    }
+}
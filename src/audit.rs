@@ -0,0 +1,272 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Access-log records shipped to a Kinesis Data Firehose delivery stream.
+//!
+//! Records are batched by a background task, which sends a batch when it is
+//! full or when the flush interval passes. The adapter registers as a Lambda
+//! extension, so Lambda sends `SIGTERM` before it shuts an execution
+//! environment down, and the task sends the records it still holds then.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use http::header::{CONTENT_LENGTH, USER_AGENT};
+use lambda_http::request::RequestContext;
+use lambda_http::{Error, Request, RequestExt, Response};
+use serde_json::{json, Value};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+
+use crate::aws::AwsClient;
+use crate::ResponseBody;
+
+/// Firehose accepts at most 500 records in a batch.
+const MAX_BATCH_SIZE: usize = 500;
+
+/// Records kept while Firehose is unavailable, before the oldest are dropped.
+const MAX_BUFFERED_RECORDS: usize = 10_000;
+
+/// An access-log record of a request that is still being processed.
+pub(crate) struct AccessRecord {
+    started: Instant,
+    record: Value,
+}
+
+impl AccessRecord {
+    pub(crate) fn new(event: &Request) -> Self {
+        let lambda_context = event.lambda_context_ref();
+        let time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_millis() as u64)
+            .unwrap_or_default();
+        let header = |name| event.headers().get(name).and_then(|value| value.to_str().ok());
+
+        AccessRecord {
+            started: Instant::now(),
+            record: json!({
+                "time_ms": time_ms,
+                "request_id": lambda_context.map(|context| context.request_id.as_str()),
+                "method": event.method().as_str(),
+                "path": event.raw_http_path(),
+                "source_ip": source_ip(event),
+                "user_agent": header(USER_AGENT),
+                "request_bytes": event.body().len(),
+            }),
+        }
+    }
+
+    /// Complete the record with the outcome of the request.
+    pub(crate) fn finish(mut self, response: &Result<Response<ResponseBody>, Error>) -> Value {
+        let record = self.record.as_object_mut().expect("access records are objects");
+        record.insert(
+            "duration_ms".to_string(),
+            Value::from(self.started.elapsed().as_millis() as u64),
+        );
+        match response {
+            Ok(response) => {
+                record.insert("status".to_string(), Value::from(response.status().as_u16()));
+                let response_bytes = response
+                    .headers()
+                    .get(CONTENT_LENGTH)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse::<u64>().ok());
+                record.insert("response_bytes".to_string(), Value::from(response_bytes));
+            }
+            Err(err) => {
+                record.insert("status".to_string(), Value::Null);
+                record.insert("error".to_string(), Value::from(err.to_string()));
+            }
+        }
+        self.record
+    }
+}
+
+/// The client address from the request context, or else from `x-forwarded-for`.
+fn source_ip(event: &Request) -> Option<String> {
+    let from_context = match event.request_context_ref() {
+        Some(RequestContext::ApiGatewayV2(context)) => context.http.source_ip.clone(),
+        Some(RequestContext::ApiGatewayV1(context)) => context.identity.source_ip.clone(),
+        _ => None,
+    };
+    from_context.or_else(|| {
+        event
+            .headers()
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(|value| value.trim().to_string())
+    })
+}
+
+/// Sends access-log records to the batching task.
+pub(crate) struct AuditLog {
+    sender: mpsc::UnboundedSender<Value>,
+}
+
+impl AuditLog {
+    pub(crate) fn new(stream: &str, batch_size: usize, flush_interval: Duration) -> Result<Self, Error> {
+        let sink = FirehoseSink {
+            aws: AwsClient::new()?,
+            stream: stream.to_string(),
+            batch_size: batch_size.clamp(1, MAX_BATCH_SIZE),
+            buffer: VecDeque::new(),
+        };
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(sink.run(receiver, flush_interval));
+        Ok(AuditLog { sender })
+    }
+
+    pub(crate) fn record(&self, record: Value) {
+        if self.sender.send(record).is_err() {
+            tracing::warn!("audit log is closed, dropping access record");
+        }
+    }
+}
+
+struct FirehoseSink {
+    aws: AwsClient,
+    stream: String,
+    batch_size: usize,
+    buffer: VecDeque<Value>,
+}
+
+impl FirehoseSink {
+    async fn run(mut self, mut receiver: mpsc::UnboundedReceiver<Value>, flush_interval: Duration) {
+        // the first tick of an interval is immediate, which would flush the first record on its own
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + flush_interval, flush_interval);
+        let mut sigterm = match signal(SignalKind::terminate()) {
+            Ok(sigterm) => Some(sigterm),
+            Err(err) => {
+                tracing::warn!("audit log will not be flushed on shutdown: {}", err);
+                None
+            }
+        };
+
+        loop {
+            tokio::select! {
+                record = receiver.recv() => match record {
+                    Some(record) => {
+                        self.push(record);
+                        if self.buffer.len() >= self.batch_size {
+                            self.flush().await;
+                        }
+                    }
+                    None => {
+                        self.flush().await;
+                        return;
+                    }
+                },
+                _ = interval.tick() => self.flush().await,
+                Some(_) = async { sigterm.as_mut()?.recv().await } => {
+                    while let Ok(record) = receiver.try_recv() {
+                        self.push(record);
+                    }
+                    self.flush().await;
+                    std::process::exit(0);
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, record: Value) {
+        if self.buffer.len() >= MAX_BUFFERED_RECORDS {
+            tracing::warn!("audit log buffer is full, dropping the oldest access record");
+            self.buffer.pop_front();
+        }
+        self.buffer.push_back(record);
+    }
+
+    /// Send everything in the buffer. Records Firehose failed to accept are
+    /// kept for the next flush.
+    async fn flush(&mut self) {
+        while !self.buffer.is_empty() {
+            let batch: Vec<Value> = self.buffer.drain(..self.batch_size.min(self.buffer.len())).collect();
+            match self.put_record_batch(&batch).await {
+                Ok(failed) if failed.is_empty() => {}
+                Ok(failed) => {
+                    tracing::warn!(count = failed.len(), "firehose rejected access records, retrying later");
+                    self.requeue(failed);
+                    return;
+                }
+                Err(err) => {
+                    tracing::warn!("failed to send access records to firehose: {}", err);
+                    self.requeue(batch);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn requeue(&mut self, records: Vec<Value>) {
+        for record in records.into_iter().rev() {
+            self.buffer.push_front(record);
+        }
+        self.buffer.truncate(MAX_BUFFERED_RECORDS);
+    }
+
+    /// Returns the records that failed.
+    async fn put_record_batch(&self, batch: &[Value]) -> Result<Vec<Value>, Error> {
+        let records: Vec<Value> = batch
+            .iter()
+            .map(|record| json!({ "Data": BASE64.encode(format!("{record}\n")) }))
+            .collect();
+        let input = json!({ "DeliveryStreamName": self.stream, "Records": records });
+        let output = self
+            .aws
+            .call_json("firehose", "Firehose_20150804.PutRecordBatch", "1.1", &input)
+            .await?;
+
+        let responses = output.get("RequestResponses").and_then(Value::as_array);
+        let failed = match responses {
+            Some(responses) => batch
+                .iter()
+                .zip(responses)
+                .filter(|(_, response)| response.get("ErrorCode").is_some_and(|code| !code.is_null()))
+                .map(|(record, _)| record.clone())
+                .collect(),
+            None => Vec::new(),
+        };
+        Ok(failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::full_body;
+    use bytes::Bytes;
+
+    #[test]
+    fn test_access_record() {
+        let request = http::Request::builder()
+            .method("POST")
+            .uri("/orders")
+            .header("user-agent", "curl/8.0")
+            .header("x-forwarded-for", "203.0.113.7, 10.0.0.1")
+            .body(lambda_http::Body::from("order"))
+            .unwrap()
+            .with_raw_http_path("/orders");
+
+        let response = Response::builder()
+            .status(201)
+            .header(CONTENT_LENGTH, "7")
+            .body(full_body(Bytes::from("created")))
+            .unwrap();
+        let record = AccessRecord::new(&request).finish(&Ok(response));
+        assert_eq!("POST", record["method"]);
+        assert_eq!("/orders", record["path"]);
+        assert_eq!("203.0.113.7", record["source_ip"]);
+        assert_eq!("curl/8.0", record["user_agent"]);
+        assert_eq!(5, record["request_bytes"]);
+        assert_eq!(201, record["status"]);
+        assert_eq!(7, record["response_bytes"]);
+        assert!(record["duration_ms"].is_u64());
+
+        let record = AccessRecord::new(&request).finish(&Err(Error::from("boom")));
+        assert_eq!(Value::Null, record["status"]);
+        assert_eq!("boom", record["error"]);
+    }
+}
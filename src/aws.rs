@@ -0,0 +1,217 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A minimal SigV4 client for the AWS APIs used by the adapter.
+//! Credentials and region come from the Lambda environment variables.
+
+use std::env;
+use std::time::{Duration, SystemTime};
+
+use aws_credential_types::Credentials;
+use aws_sigv4::http_request::{
+    sign, PayloadChecksumKind, SignableBody, SignableRequest, SignatureLocation, SigningParams, SigningSettings,
+};
+use aws_sigv4::sign::v4;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use lambda_http::Error;
+
+pub(crate) struct AwsClient {
+    client: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    region: String,
+}
+
+impl AwsClient {
+    pub(crate) fn new() -> Result<Self, Error> {
+        let region = env::var("AWS_REGION")
+            .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+            .map_err(|_| Error::from("AWS_REGION is not set"))?;
+        let connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()?
+            .https_or_http()
+            .enable_http1()
+            .build();
+        let client = Client::builder(TokioExecutor::new()).build(connector);
+        Ok(AwsClient { client, region })
+    }
+
+    pub(crate) fn region(&self) -> &str {
+        &self.region
+    }
+
+    /// The endpoint for a service, honoring `AWS_ENDPOINT_URL_<SERVICE>` and
+    /// `AWS_ENDPOINT_URL` overrides.
+    pub(crate) fn endpoint_override(service: &str) -> Option<String> {
+        env::var(format!("AWS_ENDPOINT_URL_{}", service.to_uppercase()))
+            .or_else(|_| env::var("AWS_ENDPOINT_URL"))
+            .ok()
+            .map(|endpoint| endpoint.trim_end_matches('/').to_string())
+    }
+
+    /// The endpoint of a regional service, such as `https://sqs.us-east-1.amazonaws.com`.
+    pub(crate) fn endpoint(&self, service: &str) -> String {
+        Self::endpoint_override(service).unwrap_or_else(|| format!("https://{}.{}.amazonaws.com", service, self.region))
+    }
+
+    fn credentials() -> Result<Credentials, Error> {
+        let access_key_id = env::var("AWS_ACCESS_KEY_ID").map_err(|_| Error::from("AWS_ACCESS_KEY_ID is not set"))?;
+        let secret_access_key =
+            env::var("AWS_SECRET_ACCESS_KEY").map_err(|_| Error::from("AWS_SECRET_ACCESS_KEY is not set"))?;
+        let session_token = env::var("AWS_SESSION_TOKEN").ok();
+        Ok(Credentials::new(
+            access_key_id,
+            secret_access_key,
+            session_token,
+            None,
+            "environment",
+        ))
+    }
+
+    fn signing_settings(service: &str) -> SigningSettings {
+        let mut settings = SigningSettings::default();
+        if service == "s3" {
+            settings.payload_checksum_kind = PayloadChecksumKind::XAmzSha256;
+        }
+        settings
+    }
+
+    fn sign<B>(
+        &self,
+        service: &str,
+        request: &mut http::Request<B>,
+        body: SignableBody<'_>,
+        settings: SigningSettings,
+    ) -> Result<(), Error> {
+        let identity = Self::credentials()?.into();
+        let params = v4::SigningParams::builder()
+            .identity(&identity)
+            .region(&self.region)
+            .name(service)
+            .time(SystemTime::now())
+            .settings(settings)
+            .build()?;
+
+        let headers = request
+            .headers()
+            .iter()
+            .map(|(name, value)| Ok((name.as_str(), value.to_str()?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let uri = request.uri().to_string();
+        let signable = SignableRequest::new(request.method().as_str(), uri, headers.into_iter(), body)?;
+        let (instructions, _signature) = sign(signable, &SigningParams::from(params))?.into_parts();
+        instructions.apply_to_request_http1x(request);
+        Ok(())
+    }
+
+    /// Sign and send a request, returning the response with its body collected.
+    pub(crate) async fn send(
+        &self,
+        service: &str,
+        mut request: http::Request<Bytes>,
+    ) -> Result<http::Response<Bytes>, Error> {
+        let body = request.body().clone();
+        self.sign(
+            service,
+            &mut request,
+            SignableBody::Bytes(&body),
+            Self::signing_settings(service),
+        )?;
+
+        self.send_unsigned(service, request).await
+    }
+
+    /// Send a request without signing it, such as a webhook call.
+    pub(crate) async fn send_unsigned(
+        &self,
+        service: &str,
+        request: http::Request<Bytes>,
+    ) -> Result<http::Response<Bytes>, Error> {
+        let response = self.client.request(request.map(Full::new)).await?;
+        let (parts, body) = response.into_parts();
+        let body = body.collect().await?.to_bytes();
+        let response = http::Response::from_parts(parts, body);
+        if !response.status().is_success() {
+            return Err(Error::from(format!(
+                "{} request failed with {}: {}",
+                service,
+                response.status(),
+                String::from_utf8_lossy(response.body())
+            )));
+        }
+        Ok(response)
+    }
+
+    /// Call an action of an AWS JSON protocol API, such as `AmazonSQS.SendMessage`.
+    pub(crate) async fn call_json(
+        &self,
+        service: &str,
+        target: &str,
+        json_version: &str,
+        input: &serde_json::Value,
+    ) -> Result<serde_json::Value, Error> {
+        let request = http::Request::builder()
+            .method(http::Method::POST)
+            .uri(format!("{}/", self.endpoint(service)))
+            .header(
+                http::header::CONTENT_TYPE,
+                format!("application/x-amz-json-{json_version}"),
+            )
+            .header("x-amz-target", target)
+            .body(Bytes::from(serde_json::to_vec(input)?))?;
+        let response = self.send(service, request).await?;
+        if response.body().is_empty() {
+            return Ok(serde_json::Value::Null);
+        }
+        Ok(serde_json::from_slice(response.body())?)
+    }
+
+    /// A presigned URL that allows `method` on `url` until `expires_in` passes.
+    pub(crate) fn presign(
+        &self,
+        service: &str,
+        method: http::Method,
+        url: &str,
+        expires_in: Duration,
+    ) -> Result<String, Error> {
+        let mut request = http::Request::builder().method(method).uri(url).body(())?;
+        let mut settings = SigningSettings::default();
+        settings.signature_location = SignatureLocation::QueryParams;
+        settings.expires_in = Some(expires_in);
+        self.sign(service, &mut request, SignableBody::UnsignedPayload, settings)?;
+        Ok(request.uri().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presign() {
+        env::set_var("AWS_REGION", "us-east-1");
+        env::set_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE");
+        env::set_var("AWS_SECRET_ACCESS_KEY", "secret");
+        env::set_var("AWS_SESSION_TOKEN", "token");
+
+        let client = AwsClient::new().unwrap();
+        let url = client
+            .presign(
+                "s3",
+                http::Method::GET,
+                "https://bucket.s3.us-east-1.amazonaws.com/key",
+                Duration::from_secs(300),
+            )
+            .unwrap();
+
+        assert!(url.starts_with("https://bucket.s3.us-east-1.amazonaws.com/key?"));
+        assert!(url.contains("X-Amz-Algorithm=AWS4-HMAC-SHA256"));
+        assert!(url.contains("X-Amz-Credential=AKIDEXAMPLE%2F"));
+        assert!(url.contains("X-Amz-Expires=300"));
+        assert!(url.contains("X-Amz-Security-Token=token"));
+        assert!(url.contains("X-Amz-Signature="));
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A single structured log line at init, describing the execution environment
+//! and the adapter configuration.
+//!
+//! The configuration is summed up in a hash of the adapter's variables, from
+//! the environment and configuration documents, so functions that should
+//! share a configuration can be compared from their logs alone, without
+//! printing values that may be secret.
+
+use std::env;
+
+use sha2::{Digest, Sha256};
+
+use crate::AdapterOptions;
+
+/// Unprefixed variables still read for backwards compatibility.
+const LEGACY_VARS: [&str; 7] = [
+    "HOST",
+    "PORT",
+    "READINESS_CHECK_PORT",
+    "READINESS_CHECK_PATH",
+    "READINESS_CHECK_PROTOCOL",
+    "REMOVE_BASE_PATH",
+    "ASYNC_INIT",
+];
+
+/// Log the environment fingerprint of the adapter.
+pub(crate) fn log(options: &AdapterOptions) {
+    let var = |name: &str| env::var(name).unwrap_or_default();
+    tracing::info!(
+        adapter_version = env!("CARGO_PKG_VERSION"),
+        function_name = var("AWS_LAMBDA_FUNCTION_NAME"),
+        function_version = var("AWS_LAMBDA_FUNCTION_VERSION"),
+        memory_mb = var("AWS_LAMBDA_FUNCTION_MEMORY_SIZE"),
+        region = var("AWS_REGION"),
+        arch = env::consts::ARCH,
+        runtime = var("AWS_EXECUTION_ENV"),
+        initialization_type = var("AWS_LAMBDA_INITIALIZATION_TYPE"),
+        port = options.port.as_str(),
+        invoke_mode = ?options.invoke_mode,
+        config_hash = options.config_hash.as_str(),
+        "lambda web adapter starting"
+    );
+}
+
+/// A short hash of the adapter's variables, independent of their order.
+pub(crate) fn config_hash(vars: impl Iterator<Item = (String, String)>) -> String {
+    let mut vars: Vec<(String, String)> = vars
+        .filter(|(name, _)| name.starts_with("AWS_LWA_") || LEGACY_VARS.contains(&name.as_str()))
+        .collect();
+    vars.sort();
+    let mut hasher = Sha256::new();
+    for (name, value) in vars {
+        hasher.update(name.as_bytes());
+        hasher.update(b"=");
+        hasher.update(value.as_bytes());
+        hasher.update(b"\n");
+    }
+    hasher.finalize()[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(vars: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn test_config_hash() {
+        let hash = config_hash(vars(&[("AWS_LWA_PORT", "8000"), ("PORT", "8000")]));
+        assert_eq!(16, hash.len());
+
+        // the order and unrelated variables do not matter
+        assert_eq!(
+            hash,
+            config_hash(vars(&[
+                ("PATH", "/usr/bin"),
+                ("PORT", "8000"),
+                ("AWS_LWA_PORT", "8000")
+            ]))
+        );
+        assert_ne!(hash, config_hash(vars(&[("AWS_LWA_PORT", "8080"), ("PORT", "8000")])));
+    }
+}
@@ -0,0 +1,294 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Asynchronous requests, for work that takes longer than API Gateway waits.
+//!
+//! A request with `Prefer: respond-async` is answered with `202 Accepted` and
+//! a tracking ID right away. The adapter invokes its own function again with
+//! `InvocationType=Event`, handing over the request in a pass-through event.
+//! That invocation sends the request to the app and delivers the response to
+//! the callback target.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bytes::Bytes;
+use http::header::CONTENT_TYPE;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use lambda_http::{Error, Response};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde_json::{json, Map, Value};
+use url::Url;
+
+use crate::aws::AwsClient;
+use crate::events;
+use crate::{full_body, ResponseBody};
+
+/// The key of the pass-through event that carries an accepted request.
+const ENVELOPE_KEY: &str = "lwaAsyncRequest";
+
+const PREFER: HeaderName = HeaderName::from_static("prefer");
+
+/// Characters escaped in the function name and qualifier of an invoke URL.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.');
+
+/// Where the responses of asynchronous requests are delivered.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum CallbackTarget {
+    /// `POST` the response to a URL.
+    Webhook(Url),
+    /// Send the response to an SQS queue, by queue URL.
+    Sqs(String),
+    /// Put the response on an EventBridge event bus, by name or ARN.
+    EventBridge(String),
+}
+
+impl CallbackTarget {
+    /// Parse an SQS queue ARN or URL, an EventBridge event bus ARN, or a webhook URL.
+    pub(crate) fn parse(target: &str, aws: &AwsClient) -> Result<Self, Error> {
+        if let Some(queue) = target.strip_prefix("arn:aws:sqs:") {
+            let [_region, account, name] = queue.splitn(3, ':').collect::<Vec<_>>()[..] else {
+                return Err(Error::from(format!("invalid SQS queue ARN: {target}")));
+            };
+            return Ok(CallbackTarget::Sqs(format!(
+                "{}/{}/{}",
+                aws.endpoint("sqs"),
+                account,
+                name
+            )));
+        }
+        if target.starts_with("arn:aws:events:") {
+            return Ok(CallbackTarget::EventBridge(target.to_string()));
+        }
+
+        let url = Url::parse(target)?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(Error::from(format!("invalid callback target: {target}")));
+        }
+        match url.host_str() {
+            Some(host) if host.starts_with("sqs.") && host.ends_with(".amazonaws.com") => {
+                Ok(CallbackTarget::Sqs(target.to_string()))
+            }
+            _ => Ok(CallbackTarget::Webhook(url)),
+        }
+    }
+}
+
+/// A request handed over to the asynchronous invocation.
+#[derive(Debug, PartialEq)]
+pub(crate) struct AsyncRequest {
+    pub(crate) tracking_id: String,
+    pub(crate) method: Method,
+    pub(crate) path: String,
+    pub(crate) uri: http::Uri,
+    pub(crate) headers: HeaderMap,
+    pub(crate) body: Vec<u8>,
+}
+
+pub(crate) struct AsyncRequests {
+    aws: AwsClient,
+    target: CallbackTarget,
+    function_name: String,
+    qualifier: Option<String>,
+}
+
+impl AsyncRequests {
+    pub(crate) fn new(target: &str) -> Result<Self, Error> {
+        let aws = AwsClient::new()?;
+        let target = CallbackTarget::parse(target, &aws)?;
+        let function_name = std::env::var("AWS_LAMBDA_FUNCTION_NAME")
+            .map_err(|_| Error::from("AWS_LAMBDA_FUNCTION_NAME is not set"))?;
+        let qualifier = std::env::var("AWS_LAMBDA_FUNCTION_VERSION").ok();
+        Ok(AsyncRequests {
+            aws,
+            target,
+            function_name,
+            qualifier,
+        })
+    }
+
+    /// Whether the client asked for an asynchronous response.
+    pub(crate) fn is_requested(headers: &HeaderMap) -> bool {
+        headers
+            .get_all(PREFER)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|preference| preference.trim().eq_ignore_ascii_case("respond-async"))
+    }
+
+    /// Hand a request over to an asynchronous invocation and answer with `202 Accepted`.
+    pub(crate) async fn accept(
+        &self,
+        tracking_id: &str,
+        method: &Method,
+        path: &str,
+        uri: &http::Uri,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<Response<ResponseBody>, Error> {
+        let headers: Map<String, Value> = headers
+            .iter()
+            .filter(|(name, _)| *name != PREFER)
+            .filter_map(|(name, value)| Some((name.to_string(), Value::from(value.to_str().ok()?))))
+            .collect();
+        let envelope = json!({
+            ENVELOPE_KEY: {
+                "tracking_id": tracking_id,
+                "method": method.as_str(),
+                "path": path,
+                "query": uri.query(),
+                "headers": headers,
+                "body": BASE64.encode(body),
+            }
+        });
+
+        let mut url = format!(
+            "{}/2015-03-31/functions/{}/invocations",
+            self.aws.endpoint("lambda"),
+            utf8_percent_encode(&self.function_name, PATH_SEGMENT)
+        );
+        if let Some(qualifier) = self.qualifier.as_deref() {
+            url = format!("{}?Qualifier={}", url, utf8_percent_encode(qualifier, PATH_SEGMENT));
+        }
+        let invoke = http::Request::builder()
+            .method(Method::POST)
+            .uri(url)
+            .header("x-amz-invocation-type", "Event")
+            .body(Bytes::from(serde_json::to_vec(&envelope)?))?;
+        self.aws.send("lambda", invoke).await?;
+        tracing::debug!(tracking_id, "accepted asynchronous request");
+
+        Ok(Response::builder()
+            .status(StatusCode::ACCEPTED)
+            .header(CONTENT_TYPE, "application/json")
+            .body(full_body(serde_json::to_vec(&json!({ "tracking_id": tracking_id }))?))?)
+    }
+
+    /// The accepted request in the pass-through event of an asynchronous invocation.
+    pub(crate) fn envelope(body: &[u8]) -> Option<AsyncRequest> {
+        let envelope: Value = serde_json::from_slice(body).ok()?;
+        let request = envelope.get(ENVELOPE_KEY)?;
+
+        let path = request.get("path")?.as_str()?.to_string();
+        let uri = match request.get("query").and_then(Value::as_str) {
+            Some(query) => format!("{path}?{query}"),
+            None => path.clone(),
+        };
+        let mut headers = HeaderMap::new();
+        for (name, value) in request.get("headers")?.as_object()? {
+            headers.append(
+                HeaderName::try_from(name.as_str()).ok()?,
+                HeaderValue::from_str(value.as_str()?).ok()?,
+            );
+        }
+
+        Some(AsyncRequest {
+            tracking_id: request.get("tracking_id")?.as_str()?.to_string(),
+            method: Method::try_from(request.get("method")?.as_str()?).ok()?,
+            path,
+            uri: uri.parse().ok()?,
+            headers,
+            body: BASE64.decode(request.get("body")?.as_str()?).ok()?,
+        })
+    }
+
+    /// Deliver the response of an asynchronous request to the callback target.
+    pub(crate) async fn deliver(
+        &self,
+        tracking_id: &str,
+        status: StatusCode,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<(), Error> {
+        let response_headers: Map<String, Value> = headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), Value::from(value.to_str().ok()?))))
+            .collect();
+        let (body, is_base64_encoded) = match std::str::from_utf8(body) {
+            Ok(text) => (text.to_string(), false),
+            Err(_) => (BASE64.encode(body), true),
+        };
+        let payload = json!({
+            "tracking_id": tracking_id,
+            "status": status.as_u16(),
+            "headers": response_headers,
+            "body": body,
+            "is_base64_encoded": is_base64_encoded,
+        });
+
+        match &self.target {
+            CallbackTarget::Webhook(url) => {
+                let request = http::Request::builder()
+                    .method(Method::POST)
+                    .uri(url.as_str())
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Bytes::from(serde_json::to_vec(&payload)?))?;
+                self.aws.send_unsigned("webhook", request).await?;
+            }
+            CallbackTarget::Sqs(queue_url) => {
+                let input = json!({ "QueueUrl": queue_url, "MessageBody": payload.to_string() });
+                self.aws
+                    .call_json("sqs", "AmazonSQS.SendMessage", "1.0", &input)
+                    .await?;
+            }
+            CallbackTarget::EventBridge(bus) => {
+                events::put_event(&self.aws, bus, "Async Response", &payload).await?;
+            }
+        }
+        tracing::debug!(tracking_id, "delivered asynchronous response");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_callback_target() {
+        std::env::set_var("AWS_REGION", "us-east-1");
+        let aws = AwsClient::new().unwrap();
+
+        assert_eq!(
+            CallbackTarget::Sqs("https://sqs.us-east-1.amazonaws.com/123456789012/responses".to_string()),
+            CallbackTarget::parse("arn:aws:sqs:us-east-1:123456789012:responses", &aws).unwrap()
+        );
+        assert_eq!(
+            CallbackTarget::Sqs("https://sqs.eu-west-1.amazonaws.com/123456789012/responses".to_string()),
+            CallbackTarget::parse("https://sqs.eu-west-1.amazonaws.com/123456789012/responses", &aws).unwrap()
+        );
+        assert_eq!(
+            CallbackTarget::EventBridge("arn:aws:events:us-east-1:123456789012:event-bus/jobs".to_string()),
+            CallbackTarget::parse("arn:aws:events:us-east-1:123456789012:event-bus/jobs", &aws).unwrap()
+        );
+        assert_eq!(
+            CallbackTarget::Webhook("https://example.com/hooks/jobs".parse().unwrap()),
+            CallbackTarget::parse("https://example.com/hooks/jobs", &aws).unwrap()
+        );
+        assert!(CallbackTarget::parse("arn:aws:sqs:us-east-1", &aws).is_err());
+        assert!(CallbackTarget::parse("ftp://example.com", &aws).is_err());
+    }
+
+    #[test]
+    fn test_is_requested() {
+        let mut headers = HeaderMap::new();
+        assert!(!AsyncRequests::is_requested(&headers));
+        headers.insert(PREFER, HeaderValue::from_static("return=minimal, respond-async"));
+        assert!(AsyncRequests::is_requested(&headers));
+    }
+
+    #[test]
+    fn test_envelope() {
+        let body = br#"{"lwaAsyncRequest":{"tracking_id":"id","method":"POST","path":"/jobs","query":"a=1","headers":{"content-type":"text/plain"},"body":"aGVsbG8="}}"#;
+        let request = AsyncRequests::envelope(body).unwrap();
+        assert_eq!("id", request.tracking_id);
+        assert_eq!(Method::POST, request.method);
+        assert_eq!("/jobs", request.path);
+        assert_eq!(Some("a=1"), request.uri.query());
+        assert_eq!("text/plain", request.headers[CONTENT_TYPE]);
+        assert_eq!(b"hello".to_vec(), request.body);
+
+        assert_eq!(None, AsyncRequests::envelope(br#"{"Records":[]}"#));
+        assert_eq!(None, AsyncRequests::envelope(b"not json"));
+    }
+}
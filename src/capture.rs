@@ -0,0 +1,255 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Failure bundles for postmortems of intermittent errors.
+//!
+//! When the adapter answers a request with a 5xx error of its own, such as
+//! an unreachable app, it writes a JSON bundle with the sanitized request
+//! metadata, the error and its causes, and the timing, to a directory in
+//! `/tmp` or to S3. When the adapter runs the app, the bundle also has the
+//! last lines of its output. Responses of the app itself are not captured.
+
+use std::error::Error as StdError;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use http::header::CONTENT_TYPE;
+use http::{HeaderMap, Method, StatusCode};
+use lambda_http::{Error, Request, RequestExt};
+use serde_json::{json, Map, Value};
+
+use crate::aws::AwsClient;
+use crate::s3::object_url;
+
+/// Bundles written by one execution environment, so an outage does not fill
+/// `/tmp` or the bucket.
+const MAX_BUNDLES: usize = 100;
+
+/// Headers whose values are replaced, so bundles carry no credentials.
+pub(crate) const SENSITIVE_HEADERS: [&str; 6] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "x-api-key",
+    "x-amz-security-token",
+    "x-amzn-oidc-data",
+];
+
+/// The error behind a 5xx response the adapter generated, kept in the
+/// response extensions for the failure capture.
+#[derive(Clone, Debug)]
+pub(crate) struct AdapterFailure(pub(crate) String);
+
+/// Where bundles are written.
+pub(crate) enum Target {
+    Dir(PathBuf),
+    S3 {
+        aws: Box<AwsClient>,
+        bucket: String,
+        prefix: String,
+    },
+}
+
+impl Target {
+    /// Write to `s3://bucket/prefix` or to a local directory.
+    pub(crate) fn new(target: &str) -> Result<Self, Error> {
+        match target.strip_prefix("s3://") {
+            Some(location) => {
+                let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+                if bucket.is_empty() {
+                    return Err(Error::from(format!("missing bucket in {target}")));
+                }
+                Ok(Target::S3 {
+                    aws: Box::new(AwsClient::new()?),
+                    bucket: bucket.to_string(),
+                    prefix: prefix.to_string(),
+                })
+            }
+            None => Ok(Target::Dir(PathBuf::from(target))),
+        }
+    }
+
+    /// Write a JSON document under `key`, returning where it was written.
+    pub(crate) async fn write(&self, key: &str, body: Bytes) -> Result<String, Error> {
+        match self {
+            Target::Dir(dir) => {
+                let path = dir.join(key);
+                write_file(dir, &path, body).await.map(|_| path.display().to_string())
+            }
+            Target::S3 { aws, bucket, prefix } => {
+                let url = object_url(aws, bucket, &format!("{prefix}{key}"));
+                let request = http::Request::builder()
+                    .method(Method::PUT)
+                    .uri(&url)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(body)?;
+                aws.send("s3", request).await.map(|_| url)
+            }
+        }
+    }
+}
+
+pub(crate) struct FailureCapture {
+    target: Target,
+    written: AtomicUsize,
+}
+
+impl FailureCapture {
+    /// Write bundles to `s3://bucket/prefix` or to a local directory.
+    pub(crate) fn new(target: &str) -> Result<Self, Error> {
+        Ok(FailureCapture {
+            target: Target::new(target)?,
+            written: AtomicUsize::new(0),
+        })
+    }
+
+    /// Write the bundle of a failed request, with the last lines of the app's
+    /// output if the adapter runs it. Failures to write it are only logged,
+    /// so they never change the response.
+    pub(crate) async fn capture(
+        &self,
+        request: FailureRequest,
+        status: Option<StatusCode>,
+        error: &str,
+        app_log: Option<Vec<String>>,
+    ) {
+        if self.written.fetch_add(1, Ordering::AcqRel) >= MAX_BUNDLES {
+            return;
+        }
+        let time_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_millis() as u64)
+            .unwrap_or_default();
+        let request_id = request.request_id.clone();
+        let bundle = request.bundle(time_ms, status, error, app_log);
+        // keys start with the time, so bundles sort by it
+        let key = format!("{}-{}.json", time_ms, sanitize_key(&request_id));
+        let body = Bytes::from(serde_json::to_vec_pretty(&bundle).unwrap_or_default());
+
+        match self.target.write(&key, body).await {
+            Ok(location) => tracing::info!(request_id, location, "captured failure bundle"),
+            Err(err) => tracing::warn!(request_id, error = %err, "failed to capture failure bundle"),
+        }
+    }
+}
+
+/// The metadata of a request, taken before the adapter consumes it.
+pub(crate) struct FailureRequest {
+    started: Instant,
+    request_id: String,
+    metadata: Value,
+}
+
+impl FailureRequest {
+    pub(crate) fn new(event: &Request) -> Self {
+        let lambda_context = event.lambda_context_ref();
+        FailureRequest {
+            started: Instant::now(),
+            request_id: lambda_context
+                .map(|context| context.request_id.clone())
+                .unwrap_or_default(),
+            metadata: json!({
+                "method": event.method().as_str(),
+                "path": event.raw_http_path(),
+                "query_parameters": event.uri().query().map(query_names),
+                "headers": sanitize_headers(event.headers()),
+                "body_bytes": event.body().len(),
+                "function_arn": lambda_context.map(|context| context.invoked_function_arn.as_str()),
+                "deadline_ms": lambda_context.map(|context| context.deadline),
+            }),
+        }
+    }
+
+    fn bundle(self, time_ms: u64, status: Option<StatusCode>, error: &str, app_log: Option<Vec<String>>) -> Value {
+        let mut bundle = json!({
+            "request_id": self.request_id,
+            "time_ms": time_ms,
+            "duration_ms": self.started.elapsed().as_millis() as u64,
+            "status": status.map(|status| status.as_u16()),
+            "error": error,
+            "request": self.metadata,
+        });
+        if let Some(app_log) = app_log {
+            bundle["app_log"] = Value::from(app_log);
+        }
+        bundle
+    }
+}
+
+/// The error and its causes, outermost first.
+pub(crate) fn error_chain(err: &(dyn StdError + 'static)) -> String {
+    let mut chain = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        chain.push_str(": ");
+        chain.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    chain
+}
+
+/// Header names with their values, except for credentials.
+fn sanitize_headers(headers: &HeaderMap) -> Value {
+    let mut sanitized = Map::new();
+    for (name, value) in headers {
+        let value = match SENSITIVE_HEADERS.contains(&name.as_str()) {
+            true => "[REDACTED]".to_string(),
+            false => String::from_utf8_lossy(value.as_bytes()).to_string(),
+        };
+        sanitized.insert(name.to_string(), Value::from(value));
+    }
+    Value::Object(sanitized)
+}
+
+/// Query parameter names only, since values often carry tokens.
+fn query_names(query: &str) -> Vec<String> {
+    url::form_urlencoded::parse(query.as_bytes())
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+async fn write_file(dir: &Path, path: &Path, body: Bytes) -> Result<(), Error> {
+    tokio::fs::create_dir_all(dir).await?;
+    tokio::fs::write(path, body).await?;
+    Ok(())
+}
+
+pub(crate) fn sanitize_key(request_id: &str) -> String {
+    request_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    #[test]
+    fn test_sanitize_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer secret"));
+        headers.insert("cookie", HeaderValue::from_static("session=secret"));
+        headers.insert("accept", HeaderValue::from_static("application/json"));
+        assert_eq!(
+            json!({"authorization": "[REDACTED]", "cookie": "[REDACTED]", "accept": "application/json"}),
+            sanitize_headers(&headers)
+        );
+        assert_eq!(vec!["token", "page"], query_names("token=secret&page=2"));
+    }
+
+    #[test]
+    fn test_failure_bundle_app_log() {
+        let request = || FailureRequest::new(&Request::default());
+        let bundle = request().bundle(1, Some(StatusCode::BAD_GATEWAY), "connection refused", None);
+        assert_eq!(json!(502), bundle["status"]);
+        assert!(bundle.get("app_log").is_none());
+
+        let app_log = vec!["listening on 8080".to_string(), "panic: out of memory".to_string()];
+        let bundle = request().bundle(1, None, "connection refused", Some(app_log));
+        assert_eq!(json!(["listening on 8080", "panic: out of memory"]), bundle["app_log"]);
+    }
+}
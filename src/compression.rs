@@ -0,0 +1,323 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::Write;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use bytes::Bytes;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use http::header::{HeaderMap, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE};
+use http_body::{Body, Frame, SizeHint};
+use lambda_http::Error;
+use tower_http::compression::Predicate;
+
+use crate::{RequestCompression, ResponseBody};
+
+/// A gzip encoded body that flushes the encoder after every upstream chunk,
+/// so each chunk reaches the client as soon as the web application sends it.
+pub(crate) struct FlushingGzipBody {
+    inner: ResponseBody,
+    encoder: Option<GzEncoder<Vec<u8>>>,
+}
+
+impl FlushingGzipBody {
+    pub(crate) fn new(inner: ResponseBody) -> Self {
+        FlushingGzipBody {
+            inner,
+            encoder: Some(GzEncoder::new(Vec::new(), Compression::default())),
+        }
+    }
+}
+
+impl Body for FlushingGzipBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        let this = &mut *self;
+        let Some(encoder) = this.encoder.as_mut() else {
+            return Poll::Ready(None);
+        };
+
+        match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+            Some(Ok(frame)) => match frame.into_data() {
+                Ok(data) => {
+                    encoder.write_all(&data)?;
+                    encoder.flush()?;
+                    let compressed = std::mem::take(encoder.get_mut());
+                    Poll::Ready(Some(Ok(Frame::data(Bytes::from(compressed)))))
+                }
+                Err(frame) => Poll::Ready(Some(Ok(frame))),
+            },
+            Some(Err(err)) => Poll::Ready(Some(Err(err))),
+            None => {
+                let remaining = this.encoder.take().map(GzEncoder::finish).transpose()?;
+                Poll::Ready(remaining.map(|bytes| Ok(Frame::data(Bytes::from(bytes)))))
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.encoder.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::default()
+    }
+}
+
+/// Whether the client accepts gzip encoded responses.
+pub(crate) fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| {
+            let mut parts = encoding.trim().split(';');
+            let name = parts.next().unwrap_or_default().trim();
+            let disabled = parts.any(|param| matches!(param.trim(), "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !disabled
+        })
+}
+
+/// Responses smaller than this are sent as they are, since the gzip framing
+/// outweighs what compression saves.
+pub(crate) const DEFAULT_MIN_SIZE: u64 = 32;
+
+/// Content types that are compressed already, so gzip only costs CPU time.
+const COMPRESSED_TYPES: [&str; 14] = [
+    "image/",
+    "video/",
+    "audio/",
+    "font/woff",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/zstd",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/vnd.rar",
+    "application/vnd.openxmlformats-officedocument.",
+];
+
+/// Why a body is left uncompressed, or `None` when it should be compressed.
+/// Already compressed types (except SVG images), gRPC, partial and already
+/// encoded bodies, and bodies smaller than `min_size` are left alone.
+pub(crate) fn skip_reason(headers: &HeaderMap, size: Option<u64>, min_size: u64) -> Option<&'static str> {
+    if headers.contains_key(CONTENT_ENCODING) {
+        return Some("already encoded");
+    }
+    if headers.contains_key(CONTENT_RANGE) {
+        return Some("partial content");
+    }
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if content_type.starts_with("application/grpc") {
+        return Some("grpc");
+    }
+    if COMPRESSED_TYPES.iter().any(|prefix| content_type.starts_with(prefix))
+        && !content_type.starts_with("image/svg+xml")
+    {
+        return Some("compressed content type");
+    }
+    let size = size.or_else(|| headers.get(CONTENT_LENGTH)?.to_str().ok()?.parse().ok());
+    if size.is_some_and(|size| size < min_size) {
+        return Some("small body");
+    }
+    None
+}
+
+/// Whether a body from the web application should be compressed.
+pub(crate) fn should_compress(headers: &HeaderMap) -> bool {
+    skip_reason(headers, None, 0).is_none()
+}
+
+/// Whether a response should be compressed, logging why when it is not.
+pub(crate) fn compress_response(headers: &HeaderMap, size: Option<u64>, min_size: u64) -> bool {
+    match skip_reason(headers, size, min_size) {
+        Some(reason) => {
+            tracing::debug!(reason, "skipping response compression");
+            false
+        }
+        None => true,
+    }
+}
+
+/// Decides which responses the compression layer encodes, by content type
+/// and size. Event streams are left to `AWS_LWA_COMPRESSION_FLUSH`, since the
+/// layer would hold back their events.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ContentAware {
+    min_size: u64,
+}
+
+impl ContentAware {
+    pub(crate) fn new(min_size: u64) -> Self {
+        ContentAware { min_size }
+    }
+}
+
+impl Predicate for ContentAware {
+    fn should_compress<B>(&self, response: &http::Response<B>) -> bool
+    where
+        B: Body,
+    {
+        let event_stream = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .is_some_and(|value| value.as_bytes().starts_with(b"text/event-stream"));
+        if event_stream {
+            tracing::debug!(reason = "event stream", "skipping response compression");
+            return false;
+        }
+        compress_response(response.headers(), response.body().size_hint().exact(), self.min_size)
+    }
+}
+
+/// Encode a request body, returning it with its `content-encoding`.
+pub(crate) fn encode_request(compression: RequestCompression, body: &[u8]) -> Result<(Vec<u8>, &'static str), Error> {
+    match compression {
+        RequestCompression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::with_capacity(body.len() / 4), Compression::fast());
+            encoder.write_all(body)?;
+            Ok((encoder.finish()?, "gzip"))
+        }
+        RequestCompression::Zstd => Ok((zstd::encode_all(body, zstd::DEFAULT_COMPRESSION_LEVEL)?, "zstd")),
+        RequestCompression::None => Ok((body.to_vec(), "identity")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunked_body;
+    use flate2::write::GzDecoder;
+    use http::HeaderValue;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn test_flushing_gzip_body_emits_every_chunk() {
+        let chunks = ["data: one\n\n", "data: two\n\n"];
+        let mut body = FlushingGzipBody::new(chunked_body(&chunks));
+
+        // every upstream chunk can be decoded as soon as it is emitted
+        let mut decoder = GzDecoder::new(Vec::new());
+        for chunk in chunks {
+            let frame = body.frame().await.unwrap().unwrap().into_data().unwrap();
+            decoder.write_all(&frame).unwrap();
+            decoder.flush().unwrap();
+            assert!(decoder.get_ref().ends_with(chunk.as_bytes()));
+        }
+
+        // the remaining frame completes the gzip stream
+        let trailer = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        decoder.write_all(&trailer).unwrap();
+        assert_eq!(b"data: one\n\ndata: two\n\n".to_vec(), decoder.finish().unwrap());
+        assert!(body.frame().await.is_none());
+        assert!(body.is_end_stream());
+    }
+
+    #[test]
+    fn test_encode_request() {
+        let body = br#"{"items":[1,2,3,4,5,6,7,8,9,10]}"#.repeat(100);
+
+        let (encoded, encoding) = encode_request(RequestCompression::Gzip, &body).unwrap();
+        assert_eq!("gzip", encoding);
+        let mut decoder = GzDecoder::new(Vec::new());
+        decoder.write_all(&encoded).unwrap();
+        assert_eq!(body, decoder.finish().unwrap());
+
+        let (encoded, encoding) = encode_request(RequestCompression::Zstd, &body).unwrap();
+        assert_eq!("zstd", encoding);
+        assert!(encoded.len() < body.len());
+        assert_eq!(body, zstd::decode_all(encoded.as_slice()).unwrap());
+    }
+
+    #[test]
+    fn test_accepts_gzip() {
+        let mut headers = HeaderMap::new();
+        assert!(!accepts_gzip(&headers));
+
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("br, gzip;q=0.8"));
+        assert!(accepts_gzip(&headers));
+
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip;q=0, br"));
+        assert!(!accepts_gzip(&headers));
+    }
+
+    #[test]
+    fn test_should_compress() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+        assert!(should_compress(&headers));
+
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("image/png"));
+        assert!(!should_compress(&headers));
+
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("image/svg+xml"));
+        assert!(should_compress(&headers));
+
+        headers.insert(CONTENT_RANGE, HeaderValue::from_static("bytes 0-99/1000"));
+        assert!(!should_compress(&headers));
+        headers.remove(CONTENT_RANGE);
+
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("br"));
+        assert!(!should_compress(&headers));
+    }
+
+    #[test]
+    fn test_skip_reason() {
+        let headers = |content_type: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+            headers
+        };
+        assert_eq!(
+            None,
+            skip_reason(&headers("application/json"), Some(1024), DEFAULT_MIN_SIZE)
+        );
+        assert_eq!(
+            Some("compressed content type"),
+            skip_reason(&headers("application/zip"), Some(1024), DEFAULT_MIN_SIZE)
+        );
+        assert_eq!(
+            Some("compressed content type"),
+            skip_reason(&headers("Video/MP4"), None, DEFAULT_MIN_SIZE)
+        );
+        assert_eq!(Some("grpc"), skip_reason(&headers("application/grpc+proto"), None, 0));
+        assert_eq!(
+            Some("small body"),
+            skip_reason(&headers("text/html"), Some(10), DEFAULT_MIN_SIZE)
+        );
+
+        // the size comes from content-length when the body does not know it
+        let mut small = headers("text/html");
+        small.insert(CONTENT_LENGTH, HeaderValue::from(100));
+        assert_eq!(Some("small body"), skip_reason(&small, None, 1024));
+        assert_eq!(None, skip_reason(&small, None, DEFAULT_MIN_SIZE));
+    }
+
+    #[test]
+    fn test_content_aware_predicate() {
+        let predicate = ContentAware::new(DEFAULT_MIN_SIZE);
+        let response = |content_type: &str, body: &'static str| {
+            http::Response::builder()
+                .header(CONTENT_TYPE, content_type)
+                .body(http_body_util::Full::new(Bytes::from_static(body.as_bytes())))
+                .unwrap()
+        };
+        let large = "hello world, hello world, hello world, hello world";
+        assert!(predicate.should_compress(&response("application/json", large)));
+        assert!(!predicate.should_compress(&response("application/json", "{}")));
+        assert!(!predicate.should_compress(&response("application/zip", large)));
+        assert!(!predicate.should_compress(&response("text/event-stream", large)));
+    }
+}
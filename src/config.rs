@@ -0,0 +1,265 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Adapter options from configuration documents, in TOML, YAML or JSON.
+//!
+//! A document is a mapping of option names to values, which stand in for the
+//! `AWS_LWA_*` environment variables that are not set. Options are read
+//! through [`Settings`], so the environment overrides the documents, and
+//! loading a document never changes the environment of the process. Options
+//! such as routes and transform rules, which take JSON, can be written as
+//! tables or mappings.
+
+use std::collections::BTreeMap;
+use std::env::{self, VarError};
+use std::path::Path;
+
+use lambda_http::Error;
+use serde_json::Value;
+
+pub(crate) const CONFIG_FILE: &str = "AWS_LWA_CONFIG_FILE";
+
+/// Loaded when `AWS_LWA_CONFIG_FILE` is not set, if it exists, such as from
+/// a layer.
+const DEFAULT_CONFIG_FILE: &str = "/opt/aws-lwa.toml";
+
+/// Options that are also read without the `AWS_LWA_` prefix. Setting either
+/// name in the environment overrides the value of a document.
+const LEGACY_NAMES: [&str; 7] = [
+    "PORT",
+    "HOST",
+    "READINESS_CHECK_PORT",
+    "READINESS_CHECK_PATH",
+    "READINESS_CHECK_PROTOCOL",
+    "ASYNC_INIT",
+    "REMOVE_BASE_PATH",
+];
+
+/// The variables of options: the environment, over the variables of the
+/// configuration documents added to it, each over the ones added after it.
+#[derive(Default)]
+pub(crate) struct Settings {
+    documents: Vec<BTreeMap<String, String>>,
+}
+
+impl Settings {
+    /// Add the variables of a document, and return how many it has and how
+    /// many of them are set already. Documents cannot set the `reserved`
+    /// options.
+    pub(crate) fn add(&mut self, document: &Value, reserved: &[&str]) -> Result<(usize, usize), Error> {
+        let variables = variables(document)?;
+        if let Some((name, _)) = variables.iter().find(|(name, _)| reserved.contains(&name.as_str())) {
+            return Err(Error::from(format!("{name} cannot be set in a configuration document")));
+        }
+        let overridden = variables.iter().filter(|(name, _)| self.var(name).is_ok()).count();
+        let total = variables.len();
+        self.documents.push(variables.into_iter().collect());
+        Ok((total, overridden))
+    }
+
+    /// The value of a variable, from the environment or else from the first
+    /// document that has it.
+    pub(crate) fn var(&self, name: &str) -> Result<String, VarError> {
+        match env::var(name) {
+            Err(VarError::NotPresent) if !is_set(name) => self
+                .documents
+                .iter()
+                .find_map(|document| document.get(name).cloned())
+                .ok_or(VarError::NotPresent),
+            value => value,
+        }
+    }
+
+    /// All the variables, of the environment and of the documents.
+    pub(crate) fn vars(&self) -> Vec<(String, String)> {
+        let mut vars = BTreeMap::new();
+        for document in self.documents.iter().rev() {
+            vars.extend(
+                document
+                    .iter()
+                    .filter(|(name, _)| !is_set(name))
+                    .map(|(name, value)| (name.clone(), value.clone())),
+            );
+        }
+        vars.extend(env::vars());
+        vars.into_iter().collect()
+    }
+}
+
+/// Add the configuration file in `AWS_LWA_CONFIG_FILE`, which must exist,
+/// or else the default file when it exists.
+pub(crate) fn load(settings: &mut Settings) -> Result<(), Error> {
+    match env::var_os(CONFIG_FILE) {
+        Some(path) => load_file(settings, Path::new(&path)),
+        None if Path::new(DEFAULT_CONFIG_FILE).exists() => load_file(settings, Path::new(DEFAULT_CONFIG_FILE)),
+        None => Ok(()),
+    }
+}
+
+/// Add the variables of a configuration file.
+pub(crate) fn load_file(settings: &mut Settings, path: &Path) -> Result<(), Error> {
+    let text = std::fs::read_to_string(path).map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    let document =
+        parse(&path.to_string_lossy(), &text).map_err(|err| format!("failed to parse {}: {}", path.display(), err))?;
+    let (total, overridden) = settings.add(&document, &[CONFIG_FILE])?;
+    tracing::info!(path = %path.display(), options = total, overridden, "loaded configuration file");
+    Ok(())
+}
+
+/// Parse a document as TOML when its name ends with `.toml`, else as JSON
+/// or YAML.
+pub(crate) fn parse(name: &str, text: &str) -> Result<Value, Error> {
+    match name.ends_with(".toml") {
+        true => Ok(toml::from_str(text)?),
+        false => match serde_json::from_str(text) {
+            Ok(document) => Ok(document),
+            Err(_) => Ok(serde_yaml::from_str(text)?),
+        },
+    }
+}
+
+/// The environment variables of a configuration document. Names are given
+/// with or without the `AWS_LWA_` prefix, in any case; lists of values are
+/// joined with commas, and mappings are passed on as JSON.
+fn variables(document: &Value) -> Result<Vec<(String, String)>, Error> {
+    let Value::Object(options) = document else {
+        return Err(Error::from("a configuration document must be a mapping of options"));
+    };
+    let mut variables = Vec::new();
+    for (name, value) in options {
+        let name = name.trim().to_uppercase().replace('-', "_");
+        let name = match name.starts_with("AWS_LWA_") {
+            true => name,
+            false => format!("AWS_LWA_{name}"),
+        };
+        let value = match value {
+            Value::Null => continue,
+            Value::String(value) => value.clone(),
+            Value::Array(values) if values.iter().all(|value| !value.is_array() && !value.is_object()) => values
+                .iter()
+                .map(|value| match value {
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(","),
+            value => value.to_string(),
+        };
+        variables.push((name, value));
+    }
+    Ok(variables)
+}
+
+fn is_set(name: &str) -> bool {
+    env::var_os(name).is_some()
+        || name
+            .strip_prefix("AWS_LWA_")
+            .is_some_and(|legacy| LEGACY_NAMES.contains(&legacy) && env::var_os(legacy).is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_variables() {
+        let document = serde_yaml::from_str::<Value>(
+            "AWS_LWA_PORT: 8000\n\
+             readiness-check-path: /healthz\n\
+             enable_compression: true\n\
+             error_status_codes: [500, 502-504]\n\
+             traffic_split:\n  \"8080\": 90\n  \"9090\": 10\n\
+             aws_lwa_pass_through_path: null\n",
+        )
+        .unwrap();
+        let mut variables = variables(&document).unwrap();
+        variables.sort();
+        assert_eq!(
+            vec![
+                ("AWS_LWA_ENABLE_COMPRESSION".to_string(), "true".to_string()),
+                ("AWS_LWA_ERROR_STATUS_CODES".to_string(), "500,502-504".to_string()),
+                ("AWS_LWA_PORT".to_string(), "8000".to_string()),
+                ("AWS_LWA_READINESS_CHECK_PATH".to_string(), "/healthz".to_string()),
+                (
+                    "AWS_LWA_TRAFFIC_SPLIT".to_string(),
+                    r#"{"8080":90,"9090":10}"#.to_string()
+                ),
+            ],
+            variables
+        );
+        assert!(super::variables(&serde_yaml::from_str::<Value>("- port").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_config_file_under_environment() {
+        let path = env::temp_dir().join(format!("aws-lwa-config-file-test-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "test_config_file_value = \"from file\"\n\
+             test_config_file_override = \"from file\"\n\
+             [test_config_file_table]\n\
+             \"/orders\" = 8081\n",
+        )
+        .unwrap();
+        env::set_var("AWS_LWA_TEST_CONFIG_FILE_OVERRIDE", "from env");
+
+        let mut settings = Settings::default();
+        load_file(&mut settings, &path).unwrap();
+        assert_eq!(
+            Ok("from file".to_string()),
+            settings.var("AWS_LWA_TEST_CONFIG_FILE_VALUE")
+        );
+        assert_eq!(
+            Ok("from env".to_string()),
+            settings.var("AWS_LWA_TEST_CONFIG_FILE_OVERRIDE")
+        );
+        assert_eq!(
+            Ok(r#"{"/orders":8081}"#.to_string()),
+            settings.var("AWS_LWA_TEST_CONFIG_FILE_TABLE")
+        );
+        // the environment of the process is left as it is
+        assert!(env::var("AWS_LWA_TEST_CONFIG_FILE_VALUE").is_err());
+
+        std::fs::write(&path, "config_file = \"/tmp/other.toml\"\n").unwrap();
+        assert!(load_file(&mut Settings::default(), &path).is_err());
+        std::fs::write(&path, "port = \n").unwrap();
+        assert!(load_file(&mut Settings::default(), &path).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(load_file(&mut Settings::default(), &path).is_err());
+
+        // a configuration file that is named must exist
+        env::set_var(CONFIG_FILE, &path);
+        assert!(load(&mut Settings::default()).is_err());
+        env::remove_var(CONFIG_FILE);
+    }
+
+    #[test]
+    fn test_config_settings_layers() {
+        env::set_var("AWS_LWA_TEST_SETTINGS_ENV", "from env");
+        let mut settings = Settings::default();
+        let file = serde_json::json!({ "test_settings_env": "from file", "test_settings_file": "from file" });
+        assert_eq!((2, 1), settings.add(&file, &[CONFIG_FILE]).unwrap());
+        let shared = serde_json::json!({ "test_settings_file": "from s3", "test_settings_shared": "from s3" });
+        assert_eq!((2, 1), settings.add(&shared, &[CONFIG_FILE]).unwrap());
+        assert!(settings
+            .add(&serde_json::json!({ "config_file": "/tmp/other.toml" }), &[CONFIG_FILE])
+            .is_err());
+
+        assert_eq!(Ok("from env".to_string()), settings.var("AWS_LWA_TEST_SETTINGS_ENV"));
+        assert_eq!(Ok("from file".to_string()), settings.var("AWS_LWA_TEST_SETTINGS_FILE"));
+        assert_eq!(Ok("from s3".to_string()), settings.var("AWS_LWA_TEST_SETTINGS_SHARED"));
+        assert_eq!(Err(VarError::NotPresent), settings.var("AWS_LWA_TEST_SETTINGS_NONE"));
+        assert!(env::var("AWS_LWA_TEST_SETTINGS_SHARED").is_err());
+        let vars = settings.vars();
+        assert!(vars.contains(&("AWS_LWA_TEST_SETTINGS_ENV".to_string(), "from env".to_string())));
+        assert!(vars.contains(&("AWS_LWA_TEST_SETTINGS_FILE".to_string(), "from file".to_string())));
+    }
+
+    #[test]
+    fn test_config_parse_by_name() {
+        let expected = serde_json::json!({ "port": 8000 });
+        assert_eq!(expected, parse("/opt/aws-lwa.toml", "port = 8000\n").unwrap());
+        assert_eq!(expected, parse("lwa.yaml", "port: 8000\n").unwrap());
+        assert_eq!(expected, parse("lwa.json", "{\n  \"port\": 8000\n}\n").unwrap());
+    }
+}
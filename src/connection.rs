@@ -0,0 +1,103 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::time::Duration;
+
+use hyper::body::Incoming;
+use hyper::client::conn::http2::{self, SendRequest};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use lambda_http::{Body, Error, Response};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+use url::Url;
+
+/// How long the app has to answer the h2c probe.
+const H2C_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A single HTTP/2 connection to the web application shared by all requests.
+///
+/// Requests are multiplexed as h2 streams over one pre-established connection.
+/// The connection is re-established on the next request after it is closed.
+pub(crate) struct SharedConnection {
+    authority: String,
+    nodelay: bool,
+    sender: Mutex<Option<SendRequest<Body>>>,
+}
+
+impl SharedConnection {
+    pub(crate) fn new(host: &str, port: &str, nodelay: bool) -> Self {
+        SharedConnection {
+            authority: format!("{host}:{port}"),
+            nodelay,
+            sender: Mutex::new(None),
+        }
+    }
+
+    /// Send a request over the shared connection, reconnecting once if the
+    /// current connection has gone away.
+    pub(crate) async fn send_request(&self, request: hyper::Request<Body>) -> Result<Response<Incoming>, Error> {
+        let mut sender = self.sender().await?;
+        if sender.ready().await.is_err() {
+            tracing::debug!(authority = %self.authority, "shared http2 connection closed, reconnecting");
+            self.reset().await;
+            sender = self.sender().await?;
+            sender.ready().await?;
+        }
+        Ok(sender.send_request(request).await?)
+    }
+
+    async fn sender(&self) -> Result<SendRequest<Body>, Error> {
+        let mut guard = self.sender.lock().await;
+        if let Some(sender) = guard.as_ref().filter(|sender| !sender.is_closed()) {
+            return Ok(sender.clone());
+        }
+
+        let stream = TcpStream::connect(&self.authority).await?;
+        stream.set_nodelay(self.nodelay)?;
+        let (sender, connection) = http2::handshake(TokioExecutor::new(), TokioIo::new(stream)).await?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                tracing::debug!(error = %err, "shared http2 connection terminated");
+            }
+        });
+        tracing::debug!(authority = %self.authority, "established shared http2 connection");
+
+        *guard = Some(sender.clone());
+        Ok(sender)
+    }
+
+    async fn reset(&self) {
+        self.sender.lock().await.take();
+    }
+}
+
+/// Whether the app speaks HTTP/2 with prior knowledge on cleartext
+/// connections (h2c), found by sending a request to `url` over a fresh
+/// HTTP/2 connection. HTTP/1.1 servers reject the connection preface.
+pub(crate) async fn supports_h2c(url: &Url) -> bool {
+    let probe = async {
+        let authority = format!(
+            "{}:{}",
+            url.host_str().unwrap_or_default(),
+            url.port_or_known_default().unwrap_or(80)
+        );
+        let stream = TcpStream::connect(&authority).await?;
+        let (mut sender, connection) = http2::handshake(TokioExecutor::new(), TokioIo::new(stream)).await?;
+        tokio::spawn(connection);
+        let request = hyper::Request::get(url.as_str()).body(Body::Empty)?;
+        sender.send_request(request).await?;
+        Ok::<_, Error>(())
+    };
+    match timeout(H2C_PROBE_TIMEOUT, probe).await {
+        Ok(Ok(())) => true,
+        Ok(Err(err)) => {
+            tracing::debug!(error = %err, "app does not accept h2c");
+            false
+        }
+        Err(_) => {
+            tracing::debug!("h2c probe timed out");
+            false
+        }
+    }
+}
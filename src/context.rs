@@ -0,0 +1,332 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The stable request context the adapter sends in `x-amzn-request-context-v2`.
+//!
+//! `x-amzn-request-context` is the request context as lambda_http serializes
+//! it, so its shape changes with the event source and with lambda_http
+//! releases. This header carries a documented subset with the same names for
+//! every event source:
+//!
+//! ```json
+//! {
+//!   "version": "2",
+//!   "source": "apigateway-rest | apigateway-http | apigateway-websocket | function-url | alb",
+//!   "requestId": "...", "accountId": "...", "apiId": "...", "stage": "...", "domainName": "...",
+//!   "http": { "method": "GET", "path": "/", "protocol": "HTTP/1.1", "sourceIp": "...", "userAgent": "..." },
+//!   "identity": { "sourceIp": "...", "userAgent": "...", "accountId": "...", "caller": "...", "user": "...",
+//!                 "userArn": "...", "accessKey": "...", "cognitoIdentityId": "...",
+//!                 "cognitoIdentityPoolId": "...", "principalId": "...", "claims": {} },
+//!   "websocket": { "connectionId": "...", "routeKey": "...", "eventType": "CONNECT | MESSAGE | DISCONNECT" }
+//! }
+//! ```
+//!
+//! Fields the event does not carry are left out rather than set to `null`.
+//! Fields are only ever added within a version.
+
+use http::Method;
+use lambda_http::aws_lambda_events::apigw::{ApiGatewayRequestAuthorizer, ApiGatewayRequestIdentity};
+use lambda_http::request::RequestContext;
+use serde_json::{Map, Value};
+
+/// The version of the stable request context schema.
+pub(crate) const VERSION: &str = "2";
+
+/// A JSON object that only gets the fields that have a value.
+#[derive(Default)]
+struct Fields(Map<String, Value>);
+
+impl Fields {
+    fn set(mut self, name: &str, value: Option<impl Into<Value>>) -> Self {
+        if let Some(value) = value {
+            self.0.insert(name.to_string(), value.into());
+        }
+        self
+    }
+
+    fn object(self, name: &str, object: Fields) -> Self {
+        let object = (!object.0.is_empty()).then_some(Value::Object(object.0));
+        self.set(name, object)
+    }
+}
+
+/// The event source of a request, or `None` for events that are not HTTP
+/// requests.
+pub(crate) fn source(context: &RequestContext) -> Option<&'static str> {
+    match context {
+        RequestContext::ApiGatewayV1(_) => Some("apigateway-rest"),
+        RequestContext::ApiGatewayV2(context) => {
+            let function_url = context
+                .domain_name
+                .as_deref()
+                .is_some_and(|domain| domain.contains(".lambda-url."));
+            Some(if function_url {
+                "function-url"
+            } else {
+                "apigateway-http"
+            })
+        }
+        RequestContext::Alb(_) => Some("alb"),
+        RequestContext::WebSocket(_) => Some("apigateway-websocket"),
+        RequestContext::PassThrough => None,
+    }
+}
+
+/// The stable context of a request, or `None` for events that are not HTTP
+/// requests.
+pub(crate) fn stable(context: &RequestContext, method: &Method, path: &str) -> Option<Value> {
+    let fields = Fields::default()
+        .set("version", Some(VERSION))
+        .set("source", source(context));
+    let fields = match context {
+        RequestContext::ApiGatewayV1(context) => fields
+            .set("requestId", context.request_id.clone())
+            .set("accountId", context.account_id.clone())
+            .set("apiId", context.apiid.clone())
+            .set("stage", context.stage.clone())
+            .set("domainName", context.domain_name.clone())
+            .object(
+                "http",
+                Fields::default()
+                    .set("method", Some(context.http_method.as_str()))
+                    .set("path", Some(context.path.as_deref().unwrap_or(path)))
+                    .set("protocol", context.protocol.clone())
+                    .set("sourceIp", context.identity.source_ip.clone())
+                    .set("userAgent", context.identity.user_agent.clone()),
+            )
+            .object("identity", identity(&context.identity, &context.authorizer)),
+        RequestContext::ApiGatewayV2(context) => {
+            let iam = context
+                .authorizer
+                .as_ref()
+                .and_then(|authorizer| authorizer.iam.as_ref());
+            let cognito = iam.and_then(|iam| iam.cognito_identity.as_ref());
+            let claims = context
+                .authorizer
+                .as_ref()
+                .and_then(|authorizer| authorizer.jwt.as_ref())
+                .map(|jwt| Value::from_iter(jwt.claims.iter().map(|(name, value)| (name.clone(), value.clone()))));
+            fields
+                .set("requestId", context.request_id.clone())
+                .set("accountId", context.account_id.clone())
+                .set("apiId", context.apiid.clone())
+                .set("stage", context.stage.clone())
+                .set("domainName", context.domain_name.clone())
+                .object(
+                    "http",
+                    Fields::default()
+                        .set("method", Some(context.http.method.as_str()))
+                        .set("path", Some(context.http.path.as_deref().unwrap_or(path)))
+                        .set("protocol", context.http.protocol.clone())
+                        .set("sourceIp", context.http.source_ip.clone())
+                        .set("userAgent", context.http.user_agent.clone()),
+                )
+                .object(
+                    "identity",
+                    Fields::default()
+                        .set("sourceIp", context.http.source_ip.clone())
+                        .set("userAgent", context.http.user_agent.clone())
+                        .set("accountId", iam.and_then(|iam| iam.account_id.clone()))
+                        .set("caller", iam.and_then(|iam| iam.caller_id.clone()))
+                        .set("user", iam.and_then(|iam| iam.user_id.clone()))
+                        .set("userArn", iam.and_then(|iam| iam.user_arn.clone()))
+                        .set("accessKey", iam.and_then(|iam| iam.access_key.clone()))
+                        .set(
+                            "cognitoIdentityId",
+                            cognito.and_then(|cognito| cognito.identity_id.clone()),
+                        )
+                        .set(
+                            "cognitoIdentityPoolId",
+                            cognito.and_then(|cognito| cognito.identity_pool_id.clone()),
+                        )
+                        .set("claims", claims),
+                )
+        }
+        RequestContext::Alb(_) => fields.object(
+            "http",
+            Fields::default()
+                .set("method", Some(method.as_str()))
+                .set("path", Some(path)),
+        ),
+        RequestContext::WebSocket(context) => fields
+            .set("requestId", context.request_id.clone())
+            .set("accountId", context.account_id.clone())
+            .set("apiId", context.apiid.clone())
+            .set("stage", context.stage.clone())
+            .set("domainName", context.domain_name.clone())
+            .object("identity", identity(&context.identity, &context.authorizer))
+            .object(
+                "websocket",
+                Fields::default()
+                    .set("connectionId", context.connection_id.clone())
+                    .set("routeKey", context.route_key.clone())
+                    .set("eventType", context.event_type.clone()),
+            ),
+        RequestContext::PassThrough => return None,
+    };
+    Some(Value::Object(fields.0))
+}
+
+fn identity(identity: &ApiGatewayRequestIdentity, authorizer: &ApiGatewayRequestAuthorizer) -> Fields {
+    let principal_id = authorizer.fields.get("principalId").and_then(Value::as_str);
+    let claims = authorizer
+        .fields
+        .get("claims")
+        .filter(|claims| claims.is_object())
+        .cloned();
+    Fields::default()
+        .set("sourceIp", identity.source_ip.clone())
+        .set("userAgent", identity.user_agent.clone())
+        .set("accountId", identity.account_id.clone())
+        .set("caller", identity.caller.clone())
+        .set("user", identity.user.clone())
+        .set("userArn", identity.user_arn.clone())
+        .set("accessKey", identity.access_key.clone())
+        .set("cognitoIdentityId", identity.cognito_identity_id.clone())
+        .set("cognitoIdentityPoolId", identity.cognito_identity_pool_id.clone())
+        .set("principalId", principal_id)
+        .set("claims", claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lambda_http::request::from_str;
+    use lambda_http::RequestExt;
+    use serde_json::json;
+
+    fn stable_context(event: Value) -> Value {
+        let request = from_str(&event.to_string()).unwrap();
+        stable(&request.request_context(), request.method(), request.uri().path()).unwrap()
+    }
+
+    #[test]
+    fn test_stable_context_http_api() {
+        let context = stable_context(json!({
+            "version": "2.0",
+            "routeKey": "GET /hello",
+            "rawPath": "/hello",
+            "rawQueryString": "",
+            "headers": {"host": "api.example.com"},
+            "requestContext": {
+                "accountId": "123456789012",
+                "apiId": "api-id",
+                "authorizer": {"jwt": {"claims": {"sub": "user-1"}, "scopes": null}},
+                "domainName": "api.example.com",
+                "http": {
+                    "method": "GET",
+                    "path": "/hello",
+                    "protocol": "HTTP/1.1",
+                    "sourceIp": "192.0.2.1",
+                    "userAgent": "curl/8.0"
+                },
+                "requestId": "request-1",
+                "routeKey": "GET /hello",
+                "stage": "$default",
+                "time": "14/Oct/2026:00:00:00 +0000",
+                "timeEpoch": 0
+            },
+            "isBase64Encoded": false
+        }));
+        assert_eq!(
+            json!({
+                "version": "2",
+                "source": "apigateway-http",
+                "requestId": "request-1",
+                "accountId": "123456789012",
+                "apiId": "api-id",
+                "stage": "$default",
+                "domainName": "api.example.com",
+                "http": {
+                    "method": "GET",
+                    "path": "/hello",
+                    "protocol": "HTTP/1.1",
+                    "sourceIp": "192.0.2.1",
+                    "userAgent": "curl/8.0"
+                },
+                "identity": {"sourceIp": "192.0.2.1", "userAgent": "curl/8.0", "claims": {"sub": "user-1"}}
+            }),
+            context
+        );
+    }
+
+    #[test]
+    fn test_stable_context_rest_api() {
+        let context = stable_context(json!({
+            "resource": "/hello",
+            "path": "/hello",
+            "httpMethod": "POST",
+            "headers": {},
+            "multiValueHeaders": {},
+            "requestContext": {
+                "accountId": "123456789012",
+                "apiId": "api-id",
+                "authorizer": {"principalId": "user-1"},
+                "httpMethod": "POST",
+                "identity": {"sourceIp": "192.0.2.1", "caller": "caller-1"},
+                "path": "/prod/hello",
+                "protocol": "HTTP/1.1",
+                "requestId": "request-1",
+                "resourcePath": "/hello",
+                "stage": "prod"
+            },
+            "isBase64Encoded": false
+        }));
+        assert_eq!("apigateway-rest", context["source"]);
+        assert_eq!("prod", context["stage"]);
+        assert_eq!(
+            json!({"method": "POST", "path": "/prod/hello", "protocol": "HTTP/1.1", "sourceIp": "192.0.2.1"}),
+            context["http"]
+        );
+        assert_eq!(
+            json!({"sourceIp": "192.0.2.1", "caller": "caller-1", "principalId": "user-1"}),
+            context["identity"]
+        );
+    }
+
+    #[test]
+    fn test_stable_context_function_url_and_pass_through() {
+        let context = stable_context(json!({
+            "version": "2.0",
+            "rawPath": "/",
+            "rawQueryString": "",
+            "headers": {},
+            "requestContext": {
+                "domainName": "abc.lambda-url.us-east-1.on.aws",
+                "http": {"method": "GET", "path": "/"},
+                "timeEpoch": 0
+            },
+            "isBase64Encoded": false
+        }));
+        assert_eq!("function-url", context["source"]);
+        assert!(context.get("identity").is_none());
+
+        assert_eq!(None, stable(&RequestContext::PassThrough, &Method::POST, "/events"));
+    }
+
+    #[test]
+    fn test_stable_context_websocket_api() {
+        let context = stable_context(json!({
+            "headers": {},
+            "multiValueHeaders": {},
+            "requestContext": {
+                "routeKey": "$connect",
+                "eventType": "CONNECT",
+                "connectionId": "conn-1",
+                "apiId": "api-id",
+                "stage": "prod",
+                "requestId": "request-1",
+                "identity": {"sourceIp": "192.0.2.1"},
+                "connectedAt": 0,
+                "requestTimeEpoch": 0
+            },
+            "isBase64Encoded": false
+        }));
+        assert_eq!("apigateway-websocket", context["source"]);
+        assert_eq!(
+            json!({"connectionId": "conn-1", "routeKey": "$connect", "eventType": "CONNECT"}),
+            context["websocket"]
+        );
+        assert_eq!(json!({"sourceIp": "192.0.2.1"}), context["identity"]);
+    }
+}
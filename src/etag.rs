@@ -0,0 +1,160 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use bytes::Bytes;
+use http::header::{CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG};
+use http::{HeaderValue, StatusCode};
+use http_body_util::BodyExt;
+use lambda_http::{Error, Response};
+use sha2::{Digest, Sha256};
+
+use crate::{full_body, glob_match, ResponseBody};
+
+/// Answers conditional requests of polling clients with `304 Not Modified`
+/// on paths of apps that do not implement `ETag`s themselves.
+///
+/// The entity tag is a hash of the response body, so it stays the same
+/// across execution environments that computed the same response.
+#[derive(Debug)]
+pub(crate) struct PollingEtags {
+    patterns: Vec<String>,
+}
+
+impl PollingEtags {
+    /// Parse a comma-separated list of path patterns, such as `/status,/jobs/*`.
+    pub(crate) fn new(patterns: &str) -> Self {
+        let patterns = patterns
+            .split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(str::to_string)
+            .collect();
+        PollingEtags { patterns }
+    }
+
+    pub(crate) fn matches(&self, path: &str) -> bool {
+        self.patterns.iter().any(|pattern| glob_match(pattern, path))
+    }
+
+    /// Tag a full `200 OK` response, and answer with `304 Not Modified` when
+    /// the client already has it. An `ETag` set by the app is kept.
+    pub(crate) async fn apply(
+        &self,
+        response: Response<ResponseBody>,
+        if_none_match: Option<&HeaderValue>,
+    ) -> Result<Response<ResponseBody>, Error> {
+        let event_stream = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .is_some_and(|value| value.as_bytes().starts_with(b"text/event-stream"));
+        if response.status() != StatusCode::OK || response.headers().contains_key(CONTENT_RANGE) || event_stream {
+            return Ok(response);
+        }
+
+        let (mut parts, body) = response.into_parts();
+        let (etag, body) = match parts.headers.get(ETAG) {
+            Some(etag) => (etag.clone(), body),
+            None => {
+                let bytes = body.collect().await?.to_bytes();
+                let etag = body_etag(&bytes);
+                parts.headers.insert(ETAG, etag.clone());
+                (etag, full_body(bytes))
+            }
+        };
+
+        if if_none_match.is_some_and(|if_none_match| matches_etag(if_none_match, &etag)) {
+            parts.status = StatusCode::NOT_MODIFIED;
+            parts.headers.remove(CONTENT_LENGTH);
+            return Ok(Response::from_parts(parts, full_body(Bytes::new())));
+        }
+        Ok(Response::from_parts(parts, body))
+    }
+}
+
+/// A weak entity tag, since the response may still be compressed on its way
+/// to the client.
+fn body_etag(body: &[u8]) -> HeaderValue {
+    let hash: String = Sha256::digest(body)[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    HeaderValue::from_str(&format!("W/\"{hash}\"")).unwrap()
+}
+
+/// Whether an `If-None-Match` header lists the entity tag, by weak comparison.
+fn matches_etag(if_none_match: &HeaderValue, etag: &HeaderValue) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let (Ok(if_none_match), Ok(etag)) = (if_none_match.to_str(), etag.to_str()) else {
+        return false;
+    };
+    let etag = opaque(etag);
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn poll(
+        etags: &PollingEtags,
+        body: &'static str,
+        if_none_match: Option<&HeaderValue>,
+    ) -> Response<ResponseBody> {
+        let response = Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .header(CONTENT_LENGTH, body.len())
+            .body(full_body(body))
+            .unwrap();
+        etags.apply(response, if_none_match).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_polling_etags() {
+        let etags = PollingEtags::new("/status, /jobs/*");
+        assert!(etags.matches("/status"));
+        assert!(etags.matches("/jobs/1"));
+        assert!(!etags.matches("/orders"));
+
+        let response = poll(&etags, r#"{"state":"running"}"#, None).await;
+        assert_eq!(StatusCode::OK, response.status());
+        let etag = response.headers()[ETAG].clone();
+        assert!(etag.to_str().unwrap().starts_with("W/\""));
+
+        // the same body is not sent again
+        let response = poll(&etags, r#"{"state":"running"}"#, Some(&etag)).await;
+        assert_eq!(StatusCode::NOT_MODIFIED, response.status());
+        assert_eq!(etag, response.headers()[ETAG]);
+        assert!(!response.headers().contains_key(CONTENT_LENGTH));
+
+        // a changed body is
+        let response = poll(&etags, r#"{"state":"done"}"#, Some(&etag)).await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_ne!(etag, response.headers()[ETAG]);
+    }
+
+    #[tokio::test]
+    async fn test_polling_etags_keep_app_etags() {
+        let etags = PollingEtags::new("*");
+        let response = Response::builder()
+            .header(ETAG, "\"v1\"")
+            .body(full_body("hello"))
+            .unwrap();
+        let response = etags
+            .apply(response, Some(&HeaderValue::from_static("W/\"v1\"")))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_MODIFIED, response.status());
+        assert_eq!("\"v1\"", response.headers()[ETAG]);
+    }
+
+    #[test]
+    fn test_matches_etag() {
+        let etag = HeaderValue::from_static("W/\"abc\"");
+        assert!(matches_etag(&HeaderValue::from_static("\"abc\""), &etag));
+        assert!(matches_etag(&HeaderValue::from_static("\"x\", W/\"abc\""), &etag));
+        assert!(matches_etag(&HeaderValue::from_static("*"), &etag));
+        assert!(!matches_etag(&HeaderValue::from_static("\"abcd\""), &etag));
+    }
+}
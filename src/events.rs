@@ -0,0 +1,137 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use http::{HeaderMap, HeaderName, Method};
+use lambda_http::request::RequestContext;
+use lambda_http::Error;
+use serde_json::{json, Value};
+
+use crate::aws::AwsClient;
+use crate::ErrorStatusRule;
+
+/// The source of the events published by the adapter.
+pub(crate) const EVENT_SOURCE: &str = "lambda-web-adapter";
+
+/// Put a single event on an EventBridge event bus.
+pub(crate) async fn put_event(aws: &AwsClient, bus: &str, detail_type: &str, detail: &Value) -> Result<(), Error> {
+    let input = json!({
+        "Entries": [{
+            "EventBusName": bus,
+            "Source": EVENT_SOURCE,
+            "DetailType": detail_type,
+            "Detail": detail.to_string(),
+        }]
+    });
+    let output = aws.call_json("events", "AWSEvents.PutEvents", "1.1", &input).await?;
+    if output
+        .get("FailedEntryCount")
+        .and_then(Value::as_u64)
+        .unwrap_or_default()
+        > 0
+    {
+        return Err(Error::from(format!("failed to put event: {output}")));
+    }
+    Ok(())
+}
+
+/// Publishes a summary of selected responses to EventBridge, so other
+/// services can react to HTTP activity without changes to the app.
+pub(crate) struct ResponseEvents {
+    aws: AwsClient,
+    bus: String,
+    rules: Vec<ErrorStatusRule>,
+    detail_header: Option<HeaderName>,
+}
+
+impl ResponseEvents {
+    pub(crate) fn new(bus: &str, rules: Vec<ErrorStatusRule>, detail_header: Option<&str>) -> Result<Self, Error> {
+        Ok(ResponseEvents {
+            aws: AwsClient::new()?,
+            bus: bus.to_string(),
+            rules,
+            detail_header: detail_header.map(HeaderName::try_from).transpose()?,
+        })
+    }
+
+    /// Whether a response is published. Every response is published when
+    /// there are no rules.
+    pub(crate) fn selects(&self, method: &Method, path: &str, status: u16) -> bool {
+        self.rules.is_empty() || self.rules.iter().any(|rule| rule.matches(method, path, status))
+    }
+
+    /// Remove the custom detail from the response headers. JSON values are
+    /// kept as JSON, anything else as a string.
+    pub(crate) fn take_detail(&self, headers: &mut HeaderMap) -> Option<Value> {
+        let value = headers.remove(self.detail_header.as_ref()?)?;
+        let value = value.to_str().ok()?;
+        Some(serde_json::from_str(value).unwrap_or_else(|_| Value::from(value)))
+    }
+
+    /// Publish an `HTTP Response` event.
+    pub(crate) async fn publish(
+        &self,
+        method: &Method,
+        path: &str,
+        context: &RequestContext,
+        status: u16,
+        request_id: &str,
+        custom: Option<Value>,
+    ) -> Result<(), Error> {
+        let detail = json!({
+            "method": method.as_str(),
+            "path": path,
+            "route": route(context),
+            "status": status,
+            "request_id": request_id,
+            "custom": custom,
+        });
+        put_event(&self.aws, &self.bus, "HTTP Response", &detail).await
+    }
+}
+
+/// The API Gateway route or resource that matched a request.
+fn route(context: &RequestContext) -> Option<&str> {
+    match context {
+        RequestContext::ApiGatewayV2(context) => context.route_key.as_deref(),
+        RequestContext::ApiGatewayV1(context) => context.resource_path.as_deref(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    #[test]
+    fn test_response_events_selection() {
+        std::env::set_var("AWS_REGION", "us-east-1");
+        let events = ResponseEvents::new("default", Vec::new(), Some("x-event-detail")).unwrap();
+        assert!(events.selects(&Method::GET, "/", 404));
+
+        let rule = ErrorStatusRule {
+            method: Some(Method::POST),
+            path: "/orders*".to_string(),
+            codes: vec![201],
+        };
+        let events = ResponseEvents::new("default", vec![rule], Some("x-event-detail")).unwrap();
+        assert!(events.selects(&Method::POST, "/orders/1", 201));
+        assert!(!events.selects(&Method::POST, "/orders/1", 500));
+        assert!(!events.selects(&Method::GET, "/orders/1", 201));
+    }
+
+    #[test]
+    fn test_take_detail() {
+        std::env::set_var("AWS_REGION", "us-east-1");
+        let events = ResponseEvents::new("default", Vec::new(), Some("x-event-detail")).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-event-detail", HeaderValue::from_static(r#"{"order_id":42}"#));
+        assert_eq!(Some(json!({"order_id": 42})), events.take_detail(&mut headers));
+        assert!(headers.is_empty());
+
+        headers.insert("x-event-detail", HeaderValue::from_static("shipped"));
+        assert_eq!(Some(json!("shipped")), events.take_detail(&mut headers));
+        assert_eq!(None, events.take_detail(&mut headers));
+    }
+}
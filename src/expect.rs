@@ -0,0 +1,332 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The `Expect: 100-continue` handshake with the app for large uploads.
+//!
+//! The request headers go first, and the body only follows once the app
+//! answers with an interim `100 Continue`, so the app can reject an oversized
+//! or unauthorized upload before it is transferred. Apps that ignore the
+//! expectation get the body after a short wait, as HTTP allows.
+//!
+//! The handshake needs a connection of its own: the client does not surface
+//! interim responses, so the first status line the app sends is read off the
+//! connection here. The connection is closed after the response, which also
+//! ends it without a body when the app answered with a final status.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::header::{CONNECTION, EXPECT};
+use http::HeaderValue;
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use hyper::body::Incoming;
+use hyper::client::conn::http1;
+use hyper_util::rt::TokioIo;
+use lambda_http::{Body, Error, Response};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+
+/// The length of `HTTP/1.1 100`, enough to tell the status of a response.
+const STATUS_LINE_PREFIX: usize = 12;
+
+/// How long the connection stays open after the app answered without
+/// asking for the body, for apps that do not close it after their response.
+const REJECTED_LINGER: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub(crate) struct ExpectContinue {
+    min_bytes: usize,
+    wait: Duration,
+    nodelay: bool,
+}
+
+impl ExpectContinue {
+    /// Hand shake for request bodies of at least `min_bytes`, sending the
+    /// body anyway when the app did not answer within `wait`.
+    pub(crate) fn new(min_bytes: usize, wait: Duration, nodelay: bool) -> Self {
+        ExpectContinue {
+            min_bytes,
+            wait,
+            nodelay,
+        }
+    }
+
+    /// Whether a request to the app goes through the handshake: a large
+    /// enough body over cleartext HTTP/1.1.
+    pub(crate) fn applies(&self, request: &hyper::Request<Body>) -> bool {
+        request.uri().scheme_str() == Some("http")
+            && request.version() <= http::Version::HTTP_11
+            && request.body().len() >= self.min_bytes.max(1)
+    }
+
+    /// Send a request over a new connection, holding back its body until the
+    /// app asks for it.
+    pub(crate) async fn send(&self, request: hyper::Request<Body>) -> Result<Response<Incoming>, Error> {
+        let authority = request
+            .uri()
+            .authority()
+            .map(|authority| authority.to_string())
+            .ok_or_else(|| Error::from("no authority in the request to the app"))?;
+        let host_port = match request.uri().port() {
+            Some(_) => authority,
+            None => format!("{authority}:80"),
+        };
+        let stream = TcpStream::connect(host_port).await?;
+        stream.set_nodelay(self.nodelay)?;
+
+        let (interim, decided) = oneshot::channel();
+        let stream = Interim {
+            stream,
+            head: Vec::with_capacity(STATUS_LINE_PREFIX),
+            decided: Some(interim),
+        };
+        let (mut sender, connection) = http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                tracing::debug!(error = %err, "expect-continue connection terminated");
+            }
+        });
+
+        let (mut parts, body) = request.into_parts();
+        parts.headers.insert(EXPECT, HeaderValue::from_static("100-continue"));
+        parts.headers.insert(CONNECTION, HeaderValue::from_static("close"));
+        let body = DeferredBody::new(body, decided, self.wait);
+        Ok(sender.send_request(hyper::Request::from_parts(parts, body)).await?)
+    }
+}
+
+/// A connection to the app that reports whether the first response on it is
+/// interim, which means the app wants the body.
+struct Interim {
+    stream: TcpStream,
+    head: Vec<u8>,
+    decided: Option<oneshot::Sender<bool>>,
+}
+
+impl AsyncRead for Interim {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut self.stream).poll_read(cx, buf))?;
+        if self.decided.is_some() {
+            let read = &buf.filled()[filled..];
+            let eof = read.is_empty();
+            let missing = STATUS_LINE_PREFIX - self.head.len();
+            self.head.extend_from_slice(&read[..read.len().min(missing)]);
+            if self.head.len() == STATUS_LINE_PREFIX || eof {
+                let interim = is_interim(&self.head);
+                if let Some(decided) = self.decided.take() {
+                    let _ = decided.send(interim);
+                }
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Interim {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// Whether a response starting with `head` is interim, such as
+/// `HTTP/1.1 100 Continue`.
+fn is_interim(head: &[u8]) -> bool {
+    head.len() == STATUS_LINE_PREFIX && head.starts_with(b"HTTP/1.") && head[9] == b'1'
+}
+
+/// A request body sent once the app answered with an interim response, or
+/// when it did not answer in time. It is never sent when the app answered
+/// with a final response first.
+struct DeferredBody {
+    body: Option<Bytes>,
+    len: u64,
+    /// Whether to send the body, once that is known.
+    release: Pin<Box<dyn Future<Output = bool> + Send>>,
+}
+
+impl DeferredBody {
+    fn new(body: Body, decided: oneshot::Receiver<bool>, wait: Duration) -> Self {
+        let body = match body {
+            Body::Empty => Bytes::new(),
+            Body::Text(text) => Bytes::from(text),
+            Body::Binary(binary) => Bytes::from(binary),
+        };
+        let release = async move {
+            match timeout(wait, decided).await {
+                Ok(Ok(false)) => {
+                    tracing::debug!("the app answered before the request body, not sending it");
+                    // ending the body short aborts the connection
+                    tokio::time::sleep(REJECTED_LINGER).await;
+                    false
+                }
+                _ => true,
+            }
+        };
+        DeferredBody {
+            len: body.len() as u64,
+            body: Some(body),
+            release: Box::pin(release),
+        }
+    }
+}
+
+impl HttpBody for DeferredBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        if self.body.is_none() {
+            return Poll::Ready(None);
+        }
+        let send = ready!(self.release.as_mut().poll(cx));
+        let body = self.body.take();
+        Poll::Ready(body.filter(|_| send).map(|body| Ok(Frame::data(body))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.body.as_ref().map_or(0, |_| self.len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const BODY: &str = "a large upload";
+
+    /// An app answering one request with `answer`, returning the request
+    /// head, whether the body was held back until then, and the body read
+    /// after the answer.
+    async fn app(
+        answer: &'static [u8],
+        read_after: bool,
+    ) -> (SocketAddr, tokio::task::JoinHandle<(String, bool, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(stream.read_u8().await.unwrap());
+            }
+            let mut byte = [0; 1];
+            let held_back = timeout(Duration::from_millis(50), stream.read(&mut byte))
+                .await
+                .is_err();
+            stream.write_all(answer).await.unwrap();
+            let mut body = Vec::new();
+            if read_after {
+                let mut chunk = vec![0; BODY.len()];
+                stream.read_exact(&mut chunk).await.unwrap();
+                body = chunk;
+                stream
+                    .write_all(b"HTTP/1.1 201 Created\r\ncontent-length: 2\r\n\r\nok")
+                    .await
+                    .unwrap();
+            } else {
+                // the app closes its side after the answer, so does the adapter, without the body
+                stream.shutdown().await.unwrap();
+                stream.read_to_end(&mut body).await.unwrap();
+            }
+            (String::from_utf8(head).unwrap(), held_back, body)
+        });
+        (address, app)
+    }
+
+    async fn send(address: SocketAddr, wait: Duration) -> Response<Incoming> {
+        let request = hyper::Request::post(format!("http://{address}/upload"))
+            .body(Body::Text(BODY.to_string()))
+            .unwrap();
+        ExpectContinue::new(1, wait, true).send(request).await.unwrap()
+    }
+
+    #[test]
+    fn test_expect_continue_applies() {
+        let expect = ExpectContinue::new(4, Duration::from_secs(1), true);
+        let request = |uri: &str, body: &str| hyper::Request::post(uri).body(Body::Text(body.to_string())).unwrap();
+        assert!(expect.applies(&request("http://127.0.0.1:8080/", "large")));
+        assert!(!expect.applies(&request("http://127.0.0.1:8080/", "abc")));
+        assert!(!expect.applies(&request("https://127.0.0.1:8443/", "large")));
+    }
+
+    #[test]
+    fn test_is_interim() {
+        assert!(is_interim(b"HTTP/1.1 100"));
+        assert!(is_interim(b"HTTP/1.0 103"));
+        assert!(!is_interim(b"HTTP/1.1 413"));
+        assert!(!is_interim(b"HTTP/1.1"));
+    }
+
+    #[tokio::test]
+    async fn test_expect_continue_sends_body_after_interim_response() {
+        let (address, app) = app(b"HTTP/1.1 100 Continue\r\n\r\n", true).await;
+        let response = send(address, Duration::from_secs(5)).await;
+        assert_eq!(201, response.status());
+        assert_eq!("ok", response.into_body().collect().await.unwrap().to_bytes());
+
+        let (head, held_back, body) = app.await.unwrap();
+        assert!(head.contains("expect: 100-continue\r\n"), "{head}");
+        assert!(head.contains("content-length: 14\r\n"), "{head}");
+        assert!(held_back);
+        assert_eq!(BODY.as_bytes(), body);
+    }
+
+    #[tokio::test]
+    async fn test_expect_continue_rejected_upload_is_not_sent() {
+        let (address, app) = app(
+            b"HTTP/1.1 413 Payload Too Large\r\ncontent-length: 8\r\n\r\ntoo long",
+            false,
+        )
+        .await;
+        let response = send(address, Duration::from_millis(100)).await;
+        assert_eq!(413, response.status());
+        assert_eq!("too long", response.into_body().collect().await.unwrap().to_bytes());
+
+        let (_, held_back, body) = timeout(Duration::from_secs(5), app).await.unwrap().unwrap();
+        assert!(held_back);
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_expect_continue_sends_body_when_app_does_not_answer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(BODY.as_bytes()) {
+                request.push(stream.read_u8().await.unwrap());
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+        });
+        let response = send(address, Duration::from_millis(10)).await;
+        assert_eq!(200, response.status());
+        app.await.unwrap();
+    }
+}
@@ -0,0 +1,71 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! One request at a time for apps that are not reentrant.
+//!
+//! With response streaming, Lambda may deliver a new request while the
+//! response to the previous one is still being streamed. Behind the fence,
+//! requests queue until the one in flight has finished, including its body.
+
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+#[derive(Debug)]
+pub(crate) struct RequestFence {
+    semaphore: Arc<Semaphore>,
+}
+
+impl RequestFence {
+    pub(crate) fn new() -> Self {
+        RequestFence {
+            semaphore: Arc::new(Semaphore::new(1)),
+        }
+    }
+
+    /// Wait until no other request is in flight.
+    pub(crate) async fn enter(&self) -> OwnedSemaphorePermit {
+        if self.semaphore.available_permits() == 0 {
+            tracing::debug!("another request is in flight, queueing the request");
+        }
+        self.semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the request fence is never closed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{full_body, guard};
+    use http_body_util::BodyExt;
+    use lambda_http::Response;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_fence_holds_until_body_is_sent() {
+        let fence = RequestFence::new();
+        let response = guard::hold(Response::new(full_body("done")), fence.enter().await);
+        assert!(tokio::time::timeout(Duration::from_millis(10), fence.enter())
+            .await
+            .is_err());
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!("done", body);
+        assert!(tokio::time::timeout(Duration::from_millis(10), fence.enter())
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_fence_opens_when_body_is_dropped() {
+        let fence = RequestFence::new();
+        let response = guard::hold(Response::new(full_body("unread")), fence.enter().await);
+        drop(response);
+        assert!(tokio::time::timeout(Duration::from_millis(10), fence.enter())
+            .await
+            .is_ok());
+    }
+}
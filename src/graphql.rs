@@ -0,0 +1,250 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use http::header::CONTENT_TYPE;
+use http::StatusCode;
+use lambda_http::{Error, Response};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::{full_body, ResponseBody};
+
+/// Why a persisted query cannot be expanded.
+#[derive(Debug, PartialEq)]
+pub(crate) enum PersistedQueryError {
+    /// The hash is not known yet. The client sends the full query next.
+    NotFound,
+    /// The query does not hash to the given hash.
+    HashMismatch,
+}
+
+impl PersistedQueryError {
+    /// The response the Automatic Persisted Queries protocol expects.
+    pub(crate) fn response(&self) -> Result<Response<ResponseBody>, Error> {
+        let (status, message, code) = match self {
+            PersistedQueryError::NotFound => (StatusCode::OK, "PersistedQueryNotFound", "PERSISTED_QUERY_NOT_FOUND"),
+            PersistedQueryError::HashMismatch => (
+                StatusCode::BAD_REQUEST,
+                "provided sha does not match query",
+                "BAD_USER_INPUT",
+            ),
+        };
+        let body = json!({ "errors": [{ "message": message, "extensions": { "code": code } }] });
+        Ok(Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(full_body(serde_json::to_vec(&body)?))?)
+    }
+}
+
+struct Queries {
+    by_hash: HashMap<String, String>,
+    order: VecDeque<String>,
+}
+
+/// Implements the Automatic Persisted Queries protocol for a GraphQL
+/// endpoint, so clients can send a query hash instead of the full query.
+/// Requests are expanded with the full query before they reach the app.
+///
+/// Queries are kept in memory, and the oldest are evicted first.
+pub(crate) struct PersistedQueries {
+    path: String,
+    max_entries: usize,
+    queries: Mutex<Queries>,
+}
+
+impl PersistedQueries {
+    pub(crate) fn new(path: &str, max_entries: usize) -> Self {
+        PersistedQueries {
+            path: path.to_string(),
+            max_entries: max_entries.max(1),
+            queries: Mutex::new(Queries {
+                by_hash: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub(crate) fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Expand a JSON request body, or a batch of them. Returns `None` when
+    /// the body needs no changes.
+    pub(crate) fn expand_body(&self, body: &[u8]) -> Result<Option<Vec<u8>>, PersistedQueryError> {
+        let Ok(mut document) = serde_json::from_slice::<Value>(body) else {
+            return Ok(None);
+        };
+        let mut expanded = false;
+        match &mut document {
+            Value::Array(batch) => {
+                for request in batch {
+                    expanded |= self.expand(request)?;
+                }
+            }
+            request => expanded = self.expand(request)?,
+        }
+        if !expanded {
+            return Ok(None);
+        }
+        Ok(serde_json::to_vec(&document).ok())
+    }
+
+    /// Expand the query string of a `GET` request. Returns `None` when the
+    /// query string needs no changes.
+    pub(crate) fn expand_query(&self, query: &str) -> Result<Option<String>, PersistedQueryError> {
+        let mut request = serde_json::Map::new();
+        for pair in query.split('&') {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            if key != "query" && key != "extensions" {
+                continue;
+            }
+            let value = percent_decode_str(&value.replace('+', " "))
+                .decode_utf8_lossy()
+                .to_string();
+            let value = match key {
+                "extensions" => serde_json::from_str(&value).unwrap_or(Value::Null),
+                _ => Value::from(value),
+            };
+            request.insert(key.to_string(), value);
+        }
+
+        let mut request = Value::Object(request);
+        if !self.expand(&mut request)? {
+            return Ok(None);
+        }
+        let full_query = request["query"].as_str().unwrap_or_default();
+        Ok(Some(format!(
+            "{}&query={}",
+            query,
+            utf8_percent_encode(full_query, NON_ALPHANUMERIC)
+        )))
+    }
+
+    /// Add the full query to a request that only has its hash, and remember
+    /// the queries of requests that have both. Returns whether the query was added.
+    fn expand(&self, request: &mut Value) -> Result<bool, PersistedQueryError> {
+        let Some(hash) = request
+            .pointer("/extensions/persistedQuery/sha256Hash")
+            .and_then(Value::as_str)
+            .map(str::to_lowercase)
+        else {
+            return Ok(false);
+        };
+
+        match request.get("query").and_then(Value::as_str) {
+            Some(query) => {
+                if sha256_hex(query) != hash {
+                    return Err(PersistedQueryError::HashMismatch);
+                }
+                self.store(hash, query.to_string());
+                Ok(false)
+            }
+            None => {
+                let query = self
+                    .queries
+                    .lock()
+                    .unwrap()
+                    .by_hash
+                    .get(&hash)
+                    .cloned()
+                    .ok_or(PersistedQueryError::NotFound)?;
+                if let Some(request) = request.as_object_mut() {
+                    request.insert("query".to_string(), Value::from(query));
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    fn store(&self, hash: String, query: String) {
+        let mut queries = self.queries.lock().unwrap();
+        if queries.by_hash.contains_key(&hash) {
+            return;
+        }
+        while queries.order.len() >= self.max_entries {
+            if let Some(oldest) = queries.order.pop_front() {
+                queries.by_hash.remove(&oldest);
+            }
+        }
+        queries.order.push_back(hash.clone());
+        queries.by_hash.insert(hash, query);
+    }
+}
+
+fn sha256_hex(text: &str) -> String {
+    Sha256::digest(text.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUERY: &str = "{ hello }";
+
+    fn request(query: Option<&str>) -> Vec<u8> {
+        let mut request = json!({
+            "extensions": { "persistedQuery": { "version": 1, "sha256Hash": sha256_hex(QUERY) } }
+        });
+        if let Some(query) = query {
+            request["query"] = Value::from(query);
+        }
+        serde_json::to_vec(&request).unwrap()
+    }
+
+    #[test]
+    fn test_expand_body() {
+        let queries = PersistedQueries::new("/graphql", 10);
+
+        // unknown hashes ask the client for the full query
+        assert_eq!(Err(PersistedQueryError::NotFound), queries.expand_body(&request(None)));
+
+        // the full query is remembered, and the request forwarded as is
+        assert_eq!(Ok(None), queries.expand_body(&request(Some(QUERY))));
+
+        let expanded = queries.expand_body(&request(None)).unwrap().unwrap();
+        let expanded: Value = serde_json::from_slice(&expanded).unwrap();
+        assert_eq!(QUERY, expanded["query"]);
+
+        assert_eq!(
+            Err(PersistedQueryError::HashMismatch),
+            queries.expand_body(&request(Some("{ other }")))
+        );
+        assert_eq!(Ok(None), queries.expand_body(br#"{"query":"{ hello }"}"#));
+        assert_eq!(Ok(None), queries.expand_body(b"not json"));
+    }
+
+    #[test]
+    fn test_expand_query() {
+        let queries = PersistedQueries::new("/graphql", 10);
+        queries.store(sha256_hex(QUERY), QUERY.to_string());
+        let extensions = format!(
+            r#"{{"persistedQuery":{{"version":1,"sha256Hash":"{}"}}}}"#,
+            sha256_hex(QUERY)
+        );
+        let query = format!("extensions={}", utf8_percent_encode(&extensions, NON_ALPHANUMERIC));
+
+        assert_eq!(
+            Ok(Some(format!("{query}&query=%7B%20hello%20%7D"))),
+            queries.expand_query(&query)
+        );
+        assert_eq!(Ok(None), queries.expand_query("query=%7B%20hello%20%7D"));
+    }
+
+    #[test]
+    fn test_oldest_queries_are_evicted() {
+        let queries = PersistedQueries::new("/graphql", 1);
+        queries.store("a".to_string(), "{ a }".to_string());
+        queries.store("b".to_string(), "{ b }".to_string());
+        let queries = queries.queries.lock().unwrap();
+        assert!(!queries.by_hash.contains_key("a"));
+        assert!(queries.by_hash.contains_key("b"));
+    }
+}
@@ -0,0 +1,249 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! gRPC server streaming over Lambda response streaming.
+//!
+//! Lambda response streaming does not carry HTTP trailers, so the adapter
+//! translates gRPC responses into the gRPC-Web framing: every message is sent
+//! as a length-prefixed frame, and the trailers (`grpc-status`, `grpc-message`)
+//! are sent as a final frame with the `0x80` flag set.
+//!
+//! Each frame starts with a one byte flag and a four byte big endian length:
+//!
+//! ```text
+//! +-------+----------------+-------------------+
+//! | flags | length (u32be) | payload (length)  |
+//! +-------+----------------+-------------------+
+//! ```
+//!
+//! [`decode_frame`] is a client-side helper to read these frames back.
+//!
+//! With `AWS_LWA_APP_PROTOCOL=grpc`, plain gRPC responses are passed through
+//! with their trailers. Buffered responses carry them as headers instead.
+
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use http::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, TRAILER};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::BodyExt;
+use lambda_http::{Error, Response};
+
+use crate::{full_body, ResponseBody};
+
+const TRAILERS_FLAG: u8 = 0x80;
+const FRAME_HEADER_LEN: usize = 5;
+
+/// A frame decoded from a gRPC response stream.
+#[derive(Debug, PartialEq)]
+pub enum GrpcFrame {
+    /// A length-prefixed gRPC message, including its compression flag.
+    Message { compressed: bool, payload: Bytes },
+    /// The trailers sent by the server at the end of the stream.
+    Trailers(HeaderMap),
+}
+
+/// Decode the next frame from `buf`, advancing it past the frame.
+/// Returns `Ok(None)` when `buf` does not contain a complete frame yet.
+pub fn decode_frame(buf: &mut BytesMut) -> Result<Option<GrpcFrame>, Error> {
+    if buf.len() < FRAME_HEADER_LEN {
+        return Ok(None);
+    }
+    let flags = buf[0];
+    let len = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+    if buf.len() < FRAME_HEADER_LEN + len {
+        return Ok(None);
+    }
+    buf.advance(FRAME_HEADER_LEN);
+    let payload = buf.split_to(len).freeze();
+
+    if flags & TRAILERS_FLAG == 0 {
+        return Ok(Some(GrpcFrame::Message {
+            compressed: flags & 0x01 != 0,
+            payload,
+        }));
+    }
+
+    let mut trailers = HeaderMap::new();
+    for line in std::str::from_utf8(&payload)?
+        .split("\r\n")
+        .filter(|line| !line.is_empty())
+    {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| Error::from(format!("invalid grpc trailer: {line}")))?;
+        trailers.append(
+            HeaderName::from_bytes(name.trim().as_bytes())?,
+            HeaderValue::from_str(value.trim())?,
+        );
+    }
+    Ok(Some(GrpcFrame::Trailers(trailers)))
+}
+
+fn encode_trailers(trailers: &HeaderMap) -> Bytes {
+    let mut payload = Vec::new();
+    for (name, value) in trailers {
+        payload.extend_from_slice(name.as_str().as_bytes());
+        payload.extend_from_slice(b": ");
+        payload.extend_from_slice(value.as_bytes());
+        payload.extend_from_slice(b"\r\n");
+    }
+    let mut frame = BytesMut::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.put_u8(TRAILERS_FLAG);
+    frame.put_u32(payload.len() as u32);
+    frame.extend_from_slice(&payload);
+    frame.freeze()
+}
+
+/// Whether a content type is a gRPC (not gRPC-Web) content type.
+pub(crate) fn is_grpc(content_type: &str) -> bool {
+    content_type.starts_with("application/grpc") && !content_type.starts_with("application/grpc-web")
+}
+
+/// Map a gRPC-Web request content type to its gRPC counterpart.
+/// Base64 encoded `grpc-web-text` requests are not translated.
+pub(crate) fn grpc_request_content_type(content_type: &str) -> Option<String> {
+    content_type
+        .strip_prefix("application/grpc-web")
+        .filter(|suffix| !suffix.starts_with("-text"))
+        .map(|suffix| format!("application/grpc{suffix}"))
+}
+
+/// Map a gRPC response content type to its gRPC-Web counterpart.
+pub(crate) fn grpc_web_content_type(content_type: &str) -> String {
+    content_type.replacen("application/grpc", "application/grpc-web", 1)
+}
+
+/// Collect a gRPC response and move its trailers into the headers, for
+/// buffered responses, which Lambda returns without trailers.
+pub(crate) async fn trailers_to_headers(response: Response<ResponseBody>) -> Result<Response<ResponseBody>, Error> {
+    let (mut parts, body) = response.into_parts();
+    let collected = body.collect().await?;
+    if let Some(trailers) = collected.trailers() {
+        parts.headers.remove(TRAILER);
+        for (name, value) in trailers {
+            parts.headers.append(name, value.clone());
+        }
+    }
+    let body = collected.to_bytes();
+    parts.headers.insert(CONTENT_LENGTH, body.len().into());
+    Ok(Response::from_parts(parts, full_body(body)))
+}
+
+/// Passes gRPC messages through and sends the trailers as a final frame.
+pub(crate) struct GrpcWebBody {
+    inner: ResponseBody,
+}
+
+impl GrpcWebBody {
+    pub(crate) fn new(inner: ResponseBody) -> Self {
+        GrpcWebBody { inner }
+    }
+}
+
+impl Body for GrpcWebBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        Poll::Ready(frame.map(|frame| {
+            frame.map(|frame| match frame.into_trailers() {
+                Ok(trailers) => Frame::data(encode_trailers(&trailers)),
+                Err(frame) => frame,
+            })
+        }))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frames_body;
+
+    #[tokio::test]
+    async fn test_grpc_web_body_encodes_trailers() {
+        let mut message = BytesMut::new();
+        message.put_u8(0);
+        message.put_u32(5);
+        message.extend_from_slice(b"hello");
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        let inner = frames_body(vec![
+            Frame::data(message.clone().freeze()),
+            Frame::trailers(trailers.clone()),
+        ]);
+        let mut buf = BytesMut::from(&GrpcWebBody::new(inner).collect().await.unwrap().to_bytes()[..]);
+
+        assert_eq!(
+            Some(GrpcFrame::Message {
+                compressed: false,
+                payload: Bytes::from("hello")
+            }),
+            decode_frame(&mut buf).unwrap()
+        );
+        assert_eq!(Some(GrpcFrame::Trailers(trailers)), decode_frame(&mut buf).unwrap());
+        assert!(buf.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_trailers_to_headers() {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("5"));
+        trailers.insert("grpc-message", HeaderValue::from_static("not found"));
+        let frames = vec![
+            Frame::data(Bytes::from_static(&[0, 0, 0, 0, 0])),
+            Frame::trailers(trailers),
+        ];
+        let response = Response::builder()
+            .header(TRAILER, "grpc-status, grpc-message")
+            .body(frames_body(frames))
+            .unwrap();
+
+        let response = trailers_to_headers(response).await.unwrap();
+        assert_eq!("5", response.headers()["grpc-status"]);
+        assert_eq!("not found", response.headers()["grpc-message"]);
+        assert_eq!("5", response.headers()[CONTENT_LENGTH]);
+        assert!(!response.headers().contains_key(TRAILER));
+    }
+
+    #[test]
+    fn test_decode_frame_waits_for_complete_frame() {
+        let mut buf = BytesMut::from(&[0u8, 0, 0, 0, 3, b'a'][..]);
+        assert_eq!(None, decode_frame(&mut buf).unwrap());
+        buf.extend_from_slice(b"bc");
+        assert_eq!(
+            Some(GrpcFrame::Message {
+                compressed: false,
+                payload: Bytes::from("abc")
+            }),
+            decode_frame(&mut buf).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_content_types() {
+        assert!(is_grpc("application/grpc+proto"));
+        assert!(!is_grpc("application/grpc-web+proto"));
+        assert_eq!(
+            Some("application/grpc+proto".to_string()),
+            grpc_request_content_type("application/grpc-web+proto")
+        );
+        assert_eq!(None, grpc_request_content_type("application/grpc-web-text"));
+        assert_eq!(None, grpc_request_content_type("application/json"));
+        assert_eq!(
+            "application/grpc-web+proto",
+            grpc_web_content_type("application/grpc+proto")
+        );
+    }
+}
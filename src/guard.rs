@@ -0,0 +1,82 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Guards that last as long as the body of a response.
+//!
+//! A handler returns once the app has sent the response headers, but a
+//! streamed body can take much longer to send. Work that has to wait for
+//! the whole response holds a guard in the body, which is dropped after its
+//! last frame, or when the body is dropped unread.
+
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::BodyExt;
+use lambda_http::{Error, Response};
+
+use crate::ResponseBody;
+
+/// Keep `guard` alive until the body of the response has been sent.
+pub(crate) fn hold<G: Send + Unpin + 'static>(response: Response<ResponseBody>, guard: G) -> Response<ResponseBody> {
+    response.map(|body| {
+        GuardedBody {
+            inner: body,
+            guard: Some(guard),
+        }
+        .boxed_unsync()
+    })
+}
+
+struct GuardedBody<G> {
+    inner: ResponseBody,
+    guard: Option<G>,
+}
+
+impl<G: Unpin> Body for GuardedBody<G> {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        if !matches!(frame, Some(Ok(_))) || self.inner.is_end_stream() {
+            self.guard = None;
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::full_body;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_guard_held_until_body_is_sent() {
+        let guard = Arc::new(());
+        let response = hold(Response::new(full_body("done")), guard.clone());
+        assert_eq!(2, Arc::strong_count(&guard));
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!("done", body);
+        assert_eq!(1, Arc::strong_count(&guard));
+    }
+
+    #[test]
+    fn test_guard_dropped_with_body() {
+        let guard = Arc::new(());
+        let response = hold(Response::new(full_body("unread")), guard.clone());
+        drop(response);
+        assert_eq!(1, Arc::strong_count(&guard));
+    }
+}
@@ -0,0 +1,178 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Keeps response headers within the limits of the event source.
+//!
+//! API Gateway, function URLs and ALB reject a response whose headers are too
+//! large with a generic error that does not say why. The adapter measures the
+//! headers before returning the response, drops the headers configured as
+//! low-priority until they fit, and fails with a diagnostic that names the
+//! largest headers when they still do not.
+
+use std::fmt;
+
+use http::{HeaderMap, HeaderName};
+
+/// The combined size of response headers API Gateway and function URLs accept.
+const API_GATEWAY_LIMIT: usize = 10 * 1024;
+
+/// The combined size of response headers ALB accepts.
+const ALB_LIMIT: usize = 32 * 1024;
+
+/// How many of the largest headers the diagnostic names.
+const LARGEST_HEADERS: usize = 3;
+
+pub(crate) struct HeaderLimit {
+    limit: Option<usize>,
+    trim: Vec<HeaderName>,
+}
+
+impl HeaderLimit {
+    /// A guard with the limit of each event source, or `limit` for all of
+    /// them, that drops the comma-separated `trim` headers in order.
+    pub(crate) fn new(limit: Option<usize>, trim: Option<&str>) -> Self {
+        let trim = trim
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| match HeaderName::try_from(name) {
+                Ok(name) => Some(name),
+                Err(_) => {
+                    tracing::warn!("ignoring invalid header name in AWS_LWA_RESPONSE_HEADER_TRIM: {}", name);
+                    None
+                }
+            })
+            .collect();
+        HeaderLimit { limit, trim }
+    }
+
+    /// Fit response headers within the limit of `source`, dropping
+    /// low-priority headers as needed.
+    pub(crate) fn fit(&self, source: &'static str, headers: &mut HeaderMap) -> Result<(), Oversize> {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None if source == "alb" => ALB_LIMIT,
+            None => API_GATEWAY_LIMIT,
+        };
+        let mut size = headers_size(headers);
+        for name in &self.trim {
+            if size <= limit {
+                break;
+            }
+            if headers.contains_key(name) {
+                tracing::warn!(header = %name, size, limit, "dropping a response header to fit the header limit");
+                headers.remove(name);
+                size = headers_size(headers);
+            }
+        }
+        if size <= limit {
+            return Ok(());
+        }
+        Err(Oversize {
+            source,
+            size,
+            limit,
+            largest: largest_headers(headers),
+        })
+    }
+}
+
+/// Response headers that do not fit the limit of the event source.
+#[derive(Debug)]
+pub(crate) struct Oversize {
+    source: &'static str,
+    size: usize,
+    limit: usize,
+    largest: Vec<(String, usize)>,
+}
+
+impl fmt::Display for Oversize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the app's response headers are {} bytes, over the {} byte limit of {}. The largest are ",
+            self.size, self.limit, self.source
+        )?;
+        for (i, (name, size)) in self.largest.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{name} ({size} bytes)")?;
+        }
+        f.write_str(". Set AWS_LWA_RESPONSE_HEADER_TRIM to headers the client can do without.")
+    }
+}
+
+/// The size of headers as the event sources count it, names and values.
+fn headers_size(headers: &HeaderMap) -> usize {
+    headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len())
+        .sum()
+}
+
+/// The header names with the most bytes, summed over their values.
+fn largest_headers(headers: &HeaderMap) -> Vec<(String, usize)> {
+    let mut sizes: Vec<(String, usize)> = headers
+        .keys()
+        .map(|name| {
+            let size = headers
+                .get_all(name)
+                .iter()
+                .map(|value| name.as_str().len() + value.len())
+                .sum();
+            (name.to_string(), size)
+        })
+        .collect();
+    sizes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    sizes.truncate(LARGEST_HEADERS);
+    sizes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn headers(sizes: &[(&'static str, usize)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, size) in sizes {
+            headers.append(*name, HeaderValue::from_str(&"x".repeat(*size)).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_header_limit_trims_low_priority_headers() {
+        let limit = HeaderLimit::new(None, Some("x-debug, server-timing"));
+        let mut small = headers(&[("content-type", 16), ("x-debug", 100)]);
+        assert!(limit.fit("function-url", &mut small).is_ok());
+        assert!(small.contains_key("x-debug"));
+
+        // only as many headers are dropped as needed
+        let mut large = headers(&[("content-type", 16), ("x-debug", 6000), ("server-timing", 6000)]);
+        assert!(limit.fit("apigateway-http", &mut large).is_ok());
+        assert!(!large.contains_key("x-debug"));
+        assert!(large.contains_key("server-timing"));
+
+        // ALB takes larger headers
+        let mut large = headers(&[("x-debug", 6000), ("server-timing", 6000)]);
+        assert!(limit.fit("alb", &mut large).is_ok());
+        assert_eq!(2, large.len());
+    }
+
+    #[test]
+    fn test_header_limit_diagnostic() {
+        let limit = HeaderLimit::new(Some(1024), None);
+        let mut oversized = headers(&[("set-cookie", 400), ("set-cookie", 400), ("x-trace", 300), ("etag", 10)]);
+        let err = limit.fit("function-url", &mut oversized).unwrap_err();
+        assert_eq!(
+            "the app's response headers are 1141 bytes, over the 1024 byte limit of function-url. The largest are \
+             set-cookie (820 bytes), x-trace (307 bytes), etag (14 bytes). Set AWS_LWA_RESPONSE_HEADER_TRIM to \
+             headers the client can do without.",
+            err.to_string()
+        );
+        assert_eq!(4, oversized.len());
+    }
+}
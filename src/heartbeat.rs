@@ -0,0 +1,72 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A periodic request to the app while the execution environment is warm,
+//! for background work such as flushing buffers or refreshing caches.
+//!
+//! The heartbeat is sent from the extension event loop, after an invocation
+//! has finished and before the environment is frozen. A frozen environment
+//! sends nothing, and after a long freeze only one heartbeat is sent.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use url::Url;
+
+/// How long a heartbeat request may take, since it delays the freeze.
+pub(crate) const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub(crate) struct Heartbeat {
+    url: Url,
+    interval: Duration,
+    /// When the last heartbeat was sent.
+    last: Mutex<Option<Instant>>,
+}
+
+impl Heartbeat {
+    pub(crate) fn new(url: Url, interval: Duration) -> Self {
+        Heartbeat {
+            url,
+            interval,
+            last: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Whether a heartbeat is due at `now`. A due heartbeat counts as sent.
+    pub(crate) fn take_due(&self, now: Instant) -> bool {
+        let mut last = self.last.lock().unwrap();
+        match *last {
+            Some(sent) if now.saturating_duration_since(sent) < self.interval => false,
+            _ => {
+                *last = Some(now);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heartbeat_due() {
+        let heartbeat = Heartbeat::new(
+            Url::parse("http://127.0.0.1:8080/tick").unwrap(),
+            Duration::from_secs(60),
+        );
+        let start = Instant::now();
+        assert!(heartbeat.take_due(start));
+        assert!(!heartbeat.take_due(start + Duration::from_secs(30)));
+        assert!(heartbeat.take_due(start + Duration::from_secs(60)));
+
+        // a long freeze is made up with a single heartbeat
+        assert!(heartbeat.take_due(start + Duration::from_secs(3600)));
+        assert!(!heartbeat.take_due(start + Duration::from_secs(3601)));
+    }
+}
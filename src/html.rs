@@ -0,0 +1,130 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use http::header::{CONTENT_ENCODING, CONTENT_RANGE, CONTENT_TYPE};
+use http::HeaderMap;
+use lambda_http::request::RequestContext;
+
+const LINK_ATTRIBUTES: [&str; 3] = ["href=", "src=", "action="];
+
+/// The external path prefix of a request, when the app is served under an
+/// API Gateway stage. `auto` takes the base path removed with
+/// `REMOVE_BASE_PATH`, or else the stage of a REST API request.
+pub(crate) fn external_prefix(setting: &str, base_path: Option<&str>, context: &RequestContext) -> Option<String> {
+    if setting != "auto" {
+        return Some(setting.to_string());
+    }
+    if let Some(base_path) = base_path {
+        return Some(base_path.to_string());
+    }
+    match context {
+        RequestContext::ApiGatewayV1(context) => context
+            .stage
+            .as_deref()
+            .filter(|stage| !stage.is_empty() && *stage != "$default")
+            .map(|stage| format!("/{stage}")),
+        _ => None,
+    }
+}
+
+/// Whether a response is a whole, uncompressed HTML document.
+pub(crate) fn is_html(headers: &HeaderMap) -> bool {
+    !headers.contains_key(CONTENT_ENCODING)
+        && !headers.contains_key(CONTENT_RANGE)
+        && headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/html"))
+}
+
+/// Add `prefix` to absolute links in `href`, `src` and `action` attributes,
+/// including `<base href>`. Protocol-relative links and links that already
+/// start with the prefix are left alone.
+pub(crate) fn rewrite_links(html: &str, prefix: &str) -> String {
+    let prefix = prefix.trim_end_matches('/');
+    if prefix.is_empty() {
+        return html.to_string();
+    }
+
+    let lowercase = html.to_ascii_lowercase();
+    let mut rewritten = String::with_capacity(html.len() + 64);
+    let mut copied = 0;
+    let mut index = 0;
+
+    while index < html.len() {
+        let link = LINK_ATTRIBUTES.iter().find_map(|attribute| {
+            let rest = lowercase[index..].strip_prefix(attribute)?;
+            let quote = rest.chars().next().filter(|quote| *quote == '"' || *quote == '\'')?;
+            let path_start = index + attribute.len() + 1;
+            let link = &html[path_start..];
+
+            let is_absolute = link.starts_with('/') && !link.starts_with("//");
+            let has_prefix = link.strip_prefix(prefix).is_some_and(|rest| {
+                rest.is_empty() || rest.starts_with('/') || rest.starts_with(quote) || rest.starts_with('?')
+            });
+            (is_absolute && !has_prefix).then_some(path_start)
+        });
+
+        match link {
+            Some(path_start) => {
+                rewritten.push_str(&html[copied..path_start]);
+                rewritten.push_str(prefix);
+                copied = path_start;
+                index = path_start;
+            }
+            None => index += html[index..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    rewritten.push_str(&html[copied..]);
+    rewritten
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_links() {
+        let html =
+            r#"<base href="/"><link href="/app.css"><script src='/app.js'></script><form action="/login"></form>"#;
+        assert_eq!(
+            r#"<base href="/prod/"><link href="/prod/app.css"><script src='/prod/app.js'></script><form action="/prod/login"></form>"#,
+            rewrite_links(html, "/prod")
+        );
+    }
+
+    #[test]
+    fn test_rewrite_links_leaves_other_links() {
+        let html = r#"<a href="//cdn.example.com/x.js"></a><a HREF="/prod/ok"></a><a href="https://example.com/"></a><a href="relative/path"></a><a href="/production">ü</a>"#;
+        assert_eq!(
+            r#"<a href="//cdn.example.com/x.js"></a><a HREF="/prod/ok"></a><a href="https://example.com/"></a><a href="relative/path"></a><a href="/prod/production">ü</a>"#,
+            rewrite_links(html, "/prod/")
+        );
+        assert_eq!(html, rewrite_links(html, "/"));
+    }
+
+    #[test]
+    fn test_is_html() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "text/html; charset=utf-8".parse().unwrap());
+        assert!(is_html(&headers));
+        headers.insert(CONTENT_RANGE, "bytes 0-99/1000".parse().unwrap());
+        assert!(!is_html(&headers));
+        headers.remove(CONTENT_RANGE);
+        headers.insert(CONTENT_ENCODING, "gzip".parse().unwrap());
+        assert!(!is_html(&headers));
+    }
+
+    #[test]
+    fn test_external_prefix() {
+        assert_eq!(
+            Some("/v1".to_string()),
+            external_prefix("/v1", None, &RequestContext::PassThrough)
+        );
+        assert_eq!(
+            Some("/base".to_string()),
+            external_prefix("auto", Some("/base"), &RequestContext::PassThrough)
+        );
+        assert_eq!(None, external_prefix("auto", None, &RequestContext::PassThrough));
+    }
+}
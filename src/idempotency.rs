@@ -0,0 +1,114 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue, StatusCode};
+use lambda_http::Response;
+
+use crate::{full_body, ResponseBody};
+
+pub(crate) const IDEMPOTENCY_KEY: &str = "idempotency-key";
+const REPLAYED: &str = "idempotent-replayed";
+
+struct CachedResponse {
+    stored_at: Instant,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+/// Remembers the first response for each `Idempotency-Key`, so duplicate
+/// deliveries from at-least-once event sources are answered without calling
+/// the web application again.
+///
+/// Responses are kept in memory, so only duplicates that reach the same
+/// execution environment within the TTL are replayed.
+pub(crate) struct IdempotencyCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, CachedResponse>>,
+}
+
+impl IdempotencyCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        IdempotencyCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The response stored for `key`, marked with an `idempotent-replayed` header.
+    pub(crate) fn replay(&self, key: &str) -> Option<Response<ResponseBody>> {
+        let entries = self.entries.lock().unwrap();
+        let cached = entries
+            .get(key)
+            .filter(|cached| cached.stored_at.elapsed() < self.ttl)?;
+
+        let mut response = Response::new(full_body(cached.body.clone()));
+        *response.status_mut() = cached.status;
+        *response.headers_mut() = cached.headers.clone();
+        response
+            .headers_mut()
+            .insert(REPLAYED, HeaderValue::from_static("true"));
+        Some(response)
+    }
+
+    /// Store a response. Server errors are not stored, so the request can be retried.
+    pub(crate) fn store(&self, key: String, status: StatusCode, headers: &HeaderMap, body: Bytes) {
+        if status.is_server_error() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, cached| cached.stored_at.elapsed() < self.ttl);
+        entries.insert(
+            key,
+            CachedResponse {
+                stored_at: Instant::now(),
+                status,
+                headers: headers.clone(),
+                body,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idempotency_cache_replay() {
+        let cache = IdempotencyCache::new(Duration::from_secs(60));
+        assert!(cache.replay("POST /orders key-1").is_none());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+        cache.store(
+            "POST /orders key-1".into(),
+            StatusCode::CREATED,
+            &headers,
+            Bytes::from("{}"),
+        );
+
+        let response = cache.replay("POST /orders key-1").unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+        assert_eq!("application/json", response.headers()["content-type"]);
+        assert_eq!("true", response.headers()[REPLAYED]);
+        assert!(cache.replay("POST /orders key-2").is_none());
+    }
+
+    #[test]
+    fn test_idempotency_cache_skips_server_errors_and_expires() {
+        let cache = IdempotencyCache::new(Duration::from_millis(10));
+        cache.store("key-1".into(), StatusCode::BAD_GATEWAY, &HeaderMap::new(), Bytes::new());
+        assert!(cache.replay("key-1").is_none());
+
+        cache.store("key-2".into(), StatusCode::OK, &HeaderMap::new(), Bytes::new());
+        assert!(cache.replay("key-2").is_some());
+        std::thread::sleep(Duration::from_millis(15));
+        assert!(cache.replay("key-2").is_none());
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Detects when the invoke mode of the adapter does not match how Lambda
+//! invokes the function.
+//!
+//! The invoke mode is a setting of the function URL, so the adapter cannot
+//! read it. A mismatch shows in the traffic instead: a streaming adapter
+//! behind an event source that only buffers answers with raw stream bytes,
+//! and a buffering adapter holds back event streams until the app finishes
+//! them. Each kind of mismatch is logged once per execution environment.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use http::header::CONTENT_TYPE;
+use http::HeaderMap;
+use lambda_http::request::RequestContext;
+
+use crate::{context, LambdaInvokeMode};
+
+#[derive(Debug, PartialEq)]
+enum Mismatch {
+    /// The adapter streams, but the event source only takes buffered responses.
+    BufferedSource(&'static str),
+    /// The adapter buffers a response the app streams.
+    BufferedStream,
+}
+
+pub(crate) struct InvokeModeCheck {
+    mode: LambdaInvokeMode,
+    warned_source: AtomicBool,
+    warned_stream: AtomicBool,
+}
+
+impl InvokeModeCheck {
+    pub(crate) fn new(mode: LambdaInvokeMode) -> Self {
+        InvokeModeCheck {
+            mode,
+            warned_source: AtomicBool::new(false),
+            warned_stream: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn check(&self, request_context: &RequestContext, response_headers: &HeaderMap) {
+        match mismatch(self.mode, request_context, response_headers) {
+            Some(Mismatch::BufferedSource(source)) if !self.warned_source.swap(true, Ordering::Relaxed) => {
+                tracing::warn!(
+                    source,
+                    "INVOKE MODE MISMATCH: AWS_LWA_INVOKE_MODE is response_stream, but {} only takes buffered \
+                     responses, so clients get the raw response stream or a 502. Set AWS_LWA_INVOKE_MODE=buffered \
+                     for this event source.",
+                    source
+                );
+            }
+            Some(Mismatch::BufferedStream) if !self.warned_stream.swap(true, Ordering::Relaxed) => {
+                tracing::warn!(
+                    "INVOKE MODE MISMATCH: the app streams a text/event-stream response, but AWS_LWA_INVOKE_MODE is \
+                     buffered, so clients get nothing until the stream ends. Set AWS_LWA_INVOKE_MODE=response_stream \
+                     and the function URL's InvokeMode to RESPONSE_STREAM."
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+fn mismatch(
+    mode: LambdaInvokeMode,
+    request_context: &RequestContext,
+    response_headers: &HeaderMap,
+) -> Option<Mismatch> {
+    let source = context::source(request_context)?;
+    match mode {
+        LambdaInvokeMode::ResponseStream if matches!(source, "alb" | "apigateway-http") => {
+            Some(Mismatch::BufferedSource(source))
+        }
+        LambdaInvokeMode::Buffered
+            if response_headers
+                .get(CONTENT_TYPE)
+                .is_some_and(|value| value.as_bytes().starts_with(b"text/event-stream")) =>
+        {
+            Some(Mismatch::BufferedStream)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+    use lambda_http::aws_lambda_events::alb::AlbTargetGroupRequestContext;
+    use lambda_http::aws_lambda_events::apigw::ApiGatewayV2httpRequestContext;
+
+    #[test]
+    fn test_invoke_mode_mismatch() {
+        let alb = RequestContext::Alb(AlbTargetGroupRequestContext::default());
+        let function_url = RequestContext::ApiGatewayV2(ApiGatewayV2httpRequestContext {
+            domain_name: Some("abc.lambda-url.us-east-1.on.aws".to_string()),
+            ..Default::default()
+        });
+        let mut event_stream = HeaderMap::new();
+        event_stream.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+        let json = HeaderMap::new();
+
+        assert_eq!(
+            Some(Mismatch::BufferedSource("alb")),
+            mismatch(LambdaInvokeMode::ResponseStream, &alb, &json)
+        );
+        assert_eq!(
+            None,
+            mismatch(LambdaInvokeMode::ResponseStream, &function_url, &event_stream)
+        );
+        assert_eq!(
+            Some(Mismatch::BufferedStream),
+            mismatch(LambdaInvokeMode::Buffered, &function_url, &event_stream)
+        );
+        assert_eq!(None, mismatch(LambdaInvokeMode::Buffered, &alb, &json));
+        assert_eq!(
+            None,
+            mismatch(LambdaInvokeMode::Buffered, &RequestContext::PassThrough, &event_stream)
+        );
+    }
+}
@@ -0,0 +1,43 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Answers the scheduled pings that keep execution environments warm, so
+//! apps need no route for them.
+//!
+//! A ping is an event with the payload `{"keepWarm": true}`, as an
+//! EventBridge Scheduler schedule or rule sends it with a constant input.
+//! Other scheduled events still reach the app, since they may start real
+//! jobs.
+
+use lambda_http::request::RequestContext;
+use lambda_http::{Request, RequestExt};
+use serde_json::Value;
+
+/// The field of a keep-warm ping payload.
+const PING_FIELD: &str = "keepWarm";
+
+pub(crate) fn is_ping(event: &Request) -> bool {
+    if !matches!(event.request_context_ref(), Some(RequestContext::PassThrough)) {
+        return false;
+    }
+    serde_json::from_slice::<Value>(event.body())
+        .ok()
+        .and_then(|payload| payload.get(PING_FIELD).and_then(Value::as_bool))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lambda_http::request::from_str;
+
+    #[test]
+    fn test_is_ping() {
+        assert!(is_ping(&from_str(r#"{"keepWarm": true}"#).unwrap()));
+        assert!(!is_ping(&from_str(r#"{"keepWarm": false}"#).unwrap()));
+        assert!(!is_ping(
+            &from_str(r#"{"source": "aws.events", "detail-type": "Scheduled Event", "detail": {}}"#).unwrap()
+        ));
+        assert!(!is_ping(&from_str(r#"[1, 2]"#).unwrap()));
+    }
+}
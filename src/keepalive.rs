@@ -0,0 +1,157 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Keeps the connection pool to the web application usable across the idle
+//! periods between invokes.
+//!
+//! Lambda freezes the execution environment between invokes, so the adapter
+//! cannot notice when the app closes an idle keep-alive connection. The next
+//! request then goes out on a dead connection and fails with a 502. When the
+//! keep-alive probe is enabled, the adapter's extension subscribes to `INVOKE`
+//! events, waits for the requests of each invoke to finish, and checks one
+//! pooled connection with a readiness request. The pool is replaced when the
+//! check fails.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use http_body::Body as HttpBody;
+use hyper_util::client::legacy::connect::Connect;
+use hyper_util::client::legacy::{Builder, Client};
+use tokio::sync::Notify;
+
+/// How long the keep-alive probe waits for the app before it gives up on
+/// the connection.
+pub(crate) const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How many finished invokes are remembered for the extension to catch up
+/// with.
+const FINISHED_INVOKES: usize = 16;
+
+/// The pooled HTTP client to the web application, which can be replaced by a
+/// fresh one with the same settings.
+pub(crate) struct ClientPool<C, B> {
+    builder: RwLock<Builder>,
+    connector: C,
+    client: RwLock<Client<C, B>>,
+}
+
+impl<C, B> ClientPool<C, B>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+{
+    pub(crate) fn new(builder: Builder, connector: C) -> Self {
+        let client = builder.build(connector.clone());
+        ClientPool {
+            builder: RwLock::new(builder),
+            connector,
+            client: RwLock::new(client),
+        }
+    }
+
+    /// The current client. Clients are cheap to clone and share their pool.
+    pub(crate) fn current(&self) -> Client<C, B> {
+        self.client.read().unwrap().clone()
+    }
+
+    /// Drop every pooled connection by replacing the client.
+    pub(crate) fn reset(&self) {
+        *self.client.write().unwrap() = self.builder.read().unwrap().build(self.connector.clone());
+    }
+
+    /// Replace the client by one that only speaks HTTP/2.
+    pub(crate) fn use_http2_only(&self) {
+        let mut builder = self.builder.write().unwrap();
+        builder.http2_only(true);
+        *self.client.write().unwrap() = builder.build(self.connector.clone());
+    }
+}
+
+/// Counts the requests in flight and remembers the invokes whose response
+/// has been sent, so the probe runs between invokes instead of competing
+/// with their requests.
+#[derive(Default)]
+pub(crate) struct InFlight {
+    count: AtomicUsize,
+    finished: Mutex<VecDeque<String>>,
+    changed: Notify,
+}
+
+impl InFlight {
+    /// Count the request of an invoke until the guard is dropped, which is
+    /// once the body of its response has been sent.
+    pub(crate) fn start(self: &Arc<Self>, request_id: &str) -> InFlightGuard {
+        self.count.fetch_add(1, Ordering::AcqRel);
+        InFlightGuard {
+            in_flight: self.clone(),
+            request_id: request_id.to_string(),
+        }
+    }
+
+    /// Wait until the request of the invoke has finished and no other request
+    /// is in flight. The `INVOKE` event can reach the extension before the
+    /// runtime has even received the request, so being idle is not enough.
+    pub(crate) async fn finished(&self, request_id: &str) {
+        loop {
+            let notified = self.changed.notified();
+            if self.count.load(Ordering::Acquire) == 0
+                && self.finished.lock().unwrap().iter().any(|id| id == request_id)
+            {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+pub(crate) struct InFlightGuard {
+    in_flight: Arc<InFlight>,
+    request_id: String,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut finished = self.in_flight.finished.lock().unwrap();
+        if finished.len() == FINISHED_INVOKES {
+            finished.pop_front();
+        }
+        finished.push_back(std::mem::take(&mut self.request_id));
+        drop(finished);
+        self.in_flight.count.fetch_sub(1, Ordering::AcqRel);
+        self.in_flight.changed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_flight_finished() {
+        let in_flight = Arc::new(InFlight::default());
+        let finished = |request_id| tokio::time::timeout(Duration::from_millis(10), in_flight.finished(request_id));
+        // the INVOKE event arrives before the request has started
+        assert!(finished("invoke-1").await.is_err());
+
+        let first = in_flight.start("invoke-1");
+        let second = in_flight.start("invoke-2");
+        assert!(finished("invoke-1").await.is_err());
+        drop(first);
+        assert!(finished("invoke-1").await.is_err());
+
+        let (_, done) = tokio::join!(
+            async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                drop(second);
+            },
+            tokio::time::timeout(Duration::from_secs(1), in_flight.finished("invoke-1"))
+        );
+        assert!(done.is_ok());
+        assert!(finished("invoke-2").await.is_ok());
+        assert!(finished("invoke-3").await.is_err());
+    }
+}
@@ -1,37 +1,179 @@
 // Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+mod accept;
+mod access_log;
+mod audit;
+mod aws;
+mod banner;
+mod callback;
+mod capture;
+mod compression;
+mod config;
+mod connection;
+mod context;
+mod etag;
+mod events;
+mod expect;
+mod fence;
+mod graphql;
+pub mod grpc;
+mod guard;
+mod header_limit;
+mod heartbeat;
+mod html;
+mod idempotency;
+mod invoke_mode;
+mod keep_warm;
+mod keepalive;
+mod maintenance;
+mod metrics;
+mod net;
+mod normalize;
+mod openapi;
+#[cfg(feature = "otel")]
+mod otel;
+mod pass_through;
+mod plugin;
+mod problem;
+mod prometheus;
+mod range;
 mod readiness;
+mod replay;
+mod request_timeout;
+mod response_limit;
+mod retry;
+mod routing;
+mod runtime_api;
+mod s3;
+mod shared_config;
+mod shedding;
+mod shutdown;
+mod sniff;
+mod soap;
+mod static_files;
+mod static_headers;
+mod streaming;
+mod supervisor;
+mod telemetry;
+mod timing;
+mod tls;
+mod transform;
+mod upstream;
+mod websocket;
+mod xray;
 
+use accept::AcceptRules;
+use access_log::{AccessLog, UpstreamLatency};
+use audit::{AccessRecord, AuditLog};
+use bytes::Bytes;
+use callback::AsyncRequests;
+use capture::{error_chain, AdapterFailure, FailureCapture, FailureRequest};
+use compression::{ContentAware, FlushingGzipBody};
+use config::Settings;
+use connection::SharedConnection;
+use etag::PollingEtags;
+use events::ResponseEvents;
+use expect::ExpectContinue;
+use fence::RequestFence;
+use graphql::PersistedQueries;
+use grpc::GrpcWebBody;
+use header_limit::HeaderLimit;
+use heartbeat::Heartbeat;
 use http::{
     header::{HeaderName, HeaderValue},
     Method, StatusCode,
 };
 use http_body::Body as HttpBody;
+use http_body_util::{combinators::UnsyncBoxBody, BodyExt, Full};
 use hyper::body::Incoming;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
 use hyper_util::client::legacy::connect::HttpConnector;
 use hyper_util::client::legacy::Client;
+use idempotency::{IdempotencyCache, IDEMPOTENCY_KEY};
+use invoke_mode::InvokeModeCheck;
+use keepalive::{ClientPool, InFlight};
 use lambda_http::request::RequestContext;
 use lambda_http::Body;
 pub use lambda_http::Error;
 use lambda_http::{Request, RequestExt, Response};
-use readiness::Checkpoint;
+use maintenance::{Maintenance, Switch};
+use metrics::EmfMetrics;
+use openapi::OpenApiSpec;
+#[cfg(feature = "otel")]
+use otel::Tracer;
+pub use plugin::LwaPlugin;
+use problem::ProblemDetails;
+use prometheus::PrometheusMetrics;
+use range::RangeRequest;
+pub use readiness::ReadinessCheck;
+use readiness::{Checkpoint, Health, HealthGate};
+use replay::ReplayCapture;
+use request_timeout::RequestTimeout;
+use response_limit::{ResponseLimit, ResponseTooLarge};
+use retry::{RetryBudget, UpstreamRetry};
+use routing::{HostRoutes, MethodRoutes, SidecarRoutes, TrafficSplit};
+use runtime_api::RuntimeApi;
+use s3::{S3Offload, S3Uploads};
+use shedding::{LoadShedder, RoutePriorities};
+use soap::SoapRoutes;
+use static_files::StaticFiles;
+use static_headers::StaticHeaders;
 use std::fmt::Debug;
 use std::{
+    collections::HashMap,
     env,
     future::Future,
+    path::{Path, PathBuf},
     pin::Pin,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use streaming::{MeteredBody, NdjsonBody, SseBody};
+use supervisor::Supervisor;
+use telemetry::Telemetry;
+use timing::Stage;
+use tokio::{
+    net::{TcpStream, UnixStream},
+    time::timeout,
 };
-use tokio::{net::TcpStream, time::timeout};
 use tokio_retry::{strategy::FixedInterval, Retry};
 use tower::{Service, ServiceBuilder};
 use tower_http::compression::CompressionLayer;
+use transform::{BodyTransforms, Direction};
+use upstream::UpstreamConnector;
 use url::Url;
+use websocket::{WebSocketBridge, WebSocketEvent};
+use xray::XRay;
+
+/// The body of a response returned by the adapter, either streamed from the
+/// web application or generated by the adapter itself.
+pub type ResponseBody = UnsyncBoxBody<Bytes, Error>;
+
+/// A response body with the given bytes.
+pub fn full_body(bytes: impl Into<Bytes>) -> ResponseBody {
+    Full::new(bytes.into()).map_err(|never| match never {}).boxed_unsync()
+}
+
+/// A response body that sends the given frames one at a time.
+#[cfg(test)]
+pub(crate) fn frames_body(frames: Vec<http_body::Frame<Bytes>>) -> ResponseBody {
+    http_body_util::StreamBody::new(futures_util::stream::iter(frames.into_iter().map(Ok))).boxed_unsync()
+}
+
+/// A response body that sends each chunk as a data frame.
+#[cfg(test)]
+pub(crate) fn chunked_body(chunks: &[&'static str]) -> ResponseBody {
+    frames_body(
+        chunks
+            .iter()
+            .map(|chunk| http_body::Frame::data(Bytes::from_static(chunk.as_bytes())))
+            .collect(),
+    )
+}
 
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 pub enum Protocol {
@@ -50,6 +192,35 @@ impl From<&str> for Protocol {
     }
 }
 
+/// The protocol the app serves requests on.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AppProtocol {
+    #[default]
+    Http,
+    Https,
+    /// Cleartext HTTP/2 with prior knowledge, keeping gRPC trailers.
+    Grpc,
+}
+
+impl AppProtocol {
+    fn scheme(self) -> &'static str {
+        match self {
+            AppProtocol::Http | AppProtocol::Grpc => "http",
+            AppProtocol::Https => "https",
+        }
+    }
+}
+
+impl From<&str> for AppProtocol {
+    fn from(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "https" => AppProtocol::Https,
+            "grpc" => AppProtocol::Grpc,
+            _ => AppProtocol::Http,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 pub enum LambdaInvokeMode {
     #[default]
@@ -67,11 +238,162 @@ impl From<&str> for LambdaInvokeMode {
     }
 }
 
+/// When to remove the `transfer-encoding` header from responses.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum StripTransferEncoding {
+    /// Strip in buffered mode, where the body is collected, and when running in `sam local`.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl From<&str> for StripTransferEncoding {
+    fn from(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "auto" => StripTransferEncoding::Auto,
+            "always" => StripTransferEncoding::Always,
+            "never" => StripTransferEncoding::Never,
+            _ => StripTransferEncoding::Auto,
+        }
+    }
+}
+
+/// How to encode large request bodies before sending them to the web application.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RequestCompression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl From<&str> for RequestCompression {
+    fn from(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "gzip" => RequestCompression::Gzip,
+            "zstd" => RequestCompression::Zstd,
+            _ => RequestCompression::None,
+        }
+    }
+}
+
+/// How to stream newline-delimited JSON responses.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum NdjsonStreaming {
+    #[default]
+    None,
+    /// Send complete lines only, never half a record.
+    Lines,
+    /// Send every line as a server-sent event.
+    Sse,
+}
+
+impl From<&str> for NdjsonStreaming {
+    fn from(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "lines" => NdjsonStreaming::Lines,
+            "sse" => NdjsonStreaming::Sse,
+            _ => NdjsonStreaming::None,
+        }
+    }
+}
+
+/// How the events of API Gateway WebSocket APIs are passed to the web application.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum WebSocketMode {
+    /// A `POST` request for each connection event and message.
+    #[default]
+    Http,
+    /// Messages written to a WebSocket connection to the app.
+    WebSocket,
+}
+
+impl From<&str> for WebSocketMode {
+    fn from(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "websocket" => WebSocketMode::WebSocket,
+            _ => WebSocketMode::Http,
+        }
+    }
+}
+
+/// How header names are written on HTTP/1.1 requests to the web application.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum HeaderCase {
+    /// Lowercase, as the headers of the Lambda event are parsed.
+    #[default]
+    Lower,
+    /// Title-Case, such as `Content-Type`, for apps that are case-sensitive
+    /// about header names.
+    Title,
+}
+
+impl From<&str> for HeaderCase {
+    fn from(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "title" => HeaderCase::Title,
+            _ => HeaderCase::Lower,
+        }
+    }
+}
+
+/// The address family to connect to the app with, when its host is a name
+/// that resolves to both IPv4 and IPv6 addresses.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum IpPreference {
+    /// Whichever address the system resolver returns first.
+    #[default]
+    Any,
+    /// An IPv4 address.
+    Ipv4,
+    /// An IPv6 address.
+    Ipv6,
+}
+
+impl From<&str> for IpPreference {
+    fn from(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "ipv4" => IpPreference::Ipv4,
+            "ipv6" => IpPreference::Ipv6,
+            _ => IpPreference::Any,
+        }
+    }
+}
+
+/// Faults injected into the proxy path to test how clients handle them.
+/// Meant for test environments only.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChaosOptions {
+    /// Extra latency added before each request is forwarded.
+    pub latency: Duration,
+    /// The share of requests, from 0.0 to 1.0, that fail instead of being forwarded.
+    pub error_rate: f64,
+}
+
+impl ChaosOptions {
+    /// Parse a JSON object such as `{"latency_ms":200,"error_rate":0.05}`.
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        let config: serde_json::Value = serde_json::from_str(value)?;
+        let latency_ms = config.get("latency_ms").and_then(|v| v.as_u64()).unwrap_or(0);
+        let error_rate = config.get("error_rate").and_then(|v| v.as_f64()).unwrap_or(0.0);
+        if !(0.0..=1.0).contains(&error_rate) {
+            return Err(Error::from(format!(
+                "chaos error_rate must be between 0 and 1: {error_rate}"
+            )));
+        }
+        Ok(ChaosOptions {
+            latency: Duration::from_millis(latency_ms),
+            error_rate,
+        })
+    }
+}
+
 // Helper function to detect if application is a reactive or async framework
 // This function efficiently checks for environment variables across multiple programming languages and frameworks
-fn detect_reactive_framework() -> bool {
+fn detect_reactive_framework(var: impl Fn(&str) -> Result<String, env::VarError>) -> bool {
     // Core detection configuration - allows explicitly setting reactive mode
-    if let Ok(value) = env::var("AWS_LWA_IS_REACTIVE_APPLICATION") {
+    if let Ok(value) = var("AWS_LWA_IS_REACTIVE_APPLICATION") {
         if value.to_lowercase() == "true" {
             tracing::info!("Reactive application explicitly configured via AWS_LWA_IS_REACTIVE_APPLICATION");
             return true;
@@ -80,9 +402,9 @@ fn detect_reactive_framework() -> bool {
             return false;
         }
     }
-    
+
     // Allow disabling auto-detection completely
-    if let Ok(value) = env::var("AWS_LWA_DISABLE_FRAMEWORK_DETECTION") {
+    if let Ok(value) = var("AWS_LWA_DISABLE_FRAMEWORK_DETECTION") {
         if value.to_lowercase() == "true" {
             tracing::info!("Framework auto-detection disabled via AWS_LWA_DISABLE_FRAMEWORK_DETECTION");
             return false;
@@ -92,114 +414,111 @@ fn detect_reactive_framework() -> bool {
     // Define framework categories and their associated environment variables
     let framework_categories = [
         // Java-based frameworks
-        ("Java reactive", vec![
-            "SPRING_WEBFLUX_VERSION", 
-            "REACTOR_VERSION", 
-            "VERTX_VERSION", 
-            "VERTX_HOME",
-            "QUARKUS_REACTIVE", 
-            "QUARKUS_MUTINY_VERSION",
-            "MICRONAUT_REACTOR", 
-            "MICRONAUT_REACTIVE",
-            "HELIDON_REACTIVE",
-            "AKKA_VERSION", 
-            "AKKA_HTTP_VERSION"
-        ]),
-        
+        (
+            "Java reactive",
+            vec![
+                "SPRING_WEBFLUX_VERSION",
+                "REACTOR_VERSION",
+                "VERTX_VERSION",
+                "VERTX_HOME",
+                "QUARKUS_REACTIVE",
+                "QUARKUS_MUTINY_VERSION",
+                "MICRONAUT_REACTOR",
+                "MICRONAUT_REACTIVE",
+                "HELIDON_REACTIVE",
+                "AKKA_VERSION",
+                "AKKA_HTTP_VERSION",
+            ],
+        ),
         // Python-based frameworks
-        ("Python async", vec![
-            "FASTAPI_VERSION", 
-            "STARLETTE_VERSION", 
-            "SANIC_VERSION", 
-            "QUART_APP", 
-            "QUART_ENV",
-            "AIOHTTP_VERSION", 
-            "CHANNELS_VERSION", 
-            "PYTHON_ASYNC_APP"
-        ]),
-        
+        (
+            "Python async",
+            vec![
+                "FASTAPI_VERSION",
+                "STARLETTE_VERSION",
+                "SANIC_VERSION",
+                "QUART_APP",
+                "QUART_ENV",
+                "AIOHTTP_VERSION",
+                "CHANNELS_VERSION",
+                "PYTHON_ASYNC_APP",
+            ],
+        ),
         // Node.js frameworks
-        ("Node.js async", vec![
-            "NESTJS_VERSION", 
-            "FASTIFY_VERSION", 
-            "KOA_VERSION", 
-            "NODE_STREAMING_APP"
-        ]),
-        
+        (
+            "Node.js async",
+            vec!["NESTJS_VERSION", "FASTIFY_VERSION", "KOA_VERSION", "NODE_STREAMING_APP"],
+        ),
         // Ruby frameworks
-        ("Ruby async", vec![
-            "ASYNC_RUBY", 
-            "HANAMI_STREAMING"
-        ]),
-        
+        ("Ruby async", vec!["ASYNC_RUBY", "HANAMI_STREAMING"]),
         // PHP frameworks
-        ("PHP async", vec![
-            "REACTPHP_VERSION", 
-            "SWOOLE_VERSION", 
-            "LARAVEL_ASYNC"
-        ]),
-        
+        ("PHP async", vec!["REACTPHP_VERSION", "SWOOLE_VERSION", "LARAVEL_ASYNC"]),
         // Go frameworks
-        ("Go async", vec![
-            "GO_ASYNC_APP", 
-            "ECHO_VERSION"
-        ]),
-        
+        ("Go async", vec!["GO_ASYNC_APP", "ECHO_VERSION"]),
         // Rust frameworks
-        ("Rust async", vec![
-            "ACTIX_WEB_VERSION", 
-            "ROCKET_VERSION"
-        ])
+        ("Rust async", vec!["ACTIX_WEB_VERSION", "ROCKET_VERSION"]),
     ];
-    
+
     // Check for profile/settings that might indicate a reactive framework
     let profile_indicators = [
         ("SPRING_PROFILES_ACTIVE", "reactive"),
         ("DJANGO_SETTINGS_MODULE", "channels"),
-        ("QUARKUS_VERSION", ""), // Just check existence for this one
+        ("QUARKUS_VERSION", ""),   // Just check existence for this one
         ("MICRONAUT_VERSION", ""), // Just check existence for this one
-        ("LARAVEL_VERSION", "") // Just check existence for this one
+        ("LARAVEL_VERSION", ""),   // Just check existence for this one
     ];
-    
+
     // Check each framework category
     for (category, env_vars) in &framework_categories {
         for &env_var in env_vars {
-            if env::var(env_var).is_ok() {
-                tracing::info!("Detected {} framework ({}): enabling response streaming by default", 
-                              category, env_var);
+            if var(env_var).is_ok() {
+                tracing::info!(
+                    "Detected {} framework ({}): enabling response streaming by default",
+                    category,
+                    env_var
+                );
                 return true;
             }
         }
     }
-    
+
     // Check profile indicators
     for (env_var, indicator) in &profile_indicators {
-        if let Ok(value) = env::var(env_var) {
+        if let Ok(value) = var(env_var) {
             if indicator.is_empty() || value.contains(indicator) {
-                tracing::info!("Detected framework indicator {} ({}): enabling response streaming by default", 
-                              env_var, if indicator.is_empty() { "exists" } else { indicator });
+                tracing::info!(
+                    "Detected framework indicator {} ({}): enabling response streaming by default",
+                    env_var,
+                    if indicator.is_empty() { "exists" } else { indicator }
+                );
                 return true;
             }
         }
     }
-    
+
     // Check for streaming content types
-    if env::var("AWS_LWA_CHECK_CONTENT_TYPES").unwrap_or_else(|_| "true".to_string()).to_lowercase() == "true" {
+    if var("AWS_LWA_CHECK_CONTENT_TYPES")
+        .unwrap_or_else(|_| "true".to_string())
+        .to_lowercase()
+        == "true"
+    {
         let streaming_content_types = ["text/event-stream", "application/octet-stream", "multipart/"];
-        
-        if let Ok(content_types) = env::var("HTTP_ACCEPT") {
+
+        if let Ok(content_types) = var("HTTP_ACCEPT") {
             let content_types = content_types.to_lowercase();
-            
+
             for &streaming_type in &streaming_content_types {
                 if content_types.contains(streaming_type) {
-                    tracing::info!("Detected streaming content type {}: enabling response streaming by default", 
-                                  streaming_type);
+                    tracing::info!(
+                        "Detected streaming content type {}: enabling response streaming by default",
+                        streaming_type
+                    );
                     return true;
                 }
             }
         }
     }
-    
+
     // No reactive/streaming framework detected
     tracing::debug!("No reactive/streaming framework detected - using default buffered mode");
     false
@@ -216,9 +535,12 @@ pub struct AdapterOptions {
     pub pass_through_path: String,
     pub async_init: bool,
     pub compression: bool,
+    pub compression_flush: bool,
     pub invoke_mode: LambdaInvokeMode,
     pub authorization_source: Option<String>,
     pub error_status_codes: Option<Vec<u16>>,
+    pub error_status_rules: Vec<ErrorStatusRule>,
+    pub strip_transfer_encoding: StripTransferEncoding,
     // New options for HTTP client configuration
     pub http_keepalive: Option<Duration>,
     pub http_nodelay: bool,
@@ -226,47 +548,206 @@ pub struct AdapterOptions {
     pub http_pool_idle_timeout: Duration,
     pub http_pool_max_idle: usize,
     pub http_http2_only: bool,
+    pub http_http2_single_connection: bool,
+    pub http_http2_auto: bool,
+    pub hedge_after: Option<Duration>,
+    pub streaming_buffer_threshold: Option<u64>,
+    pub stream_pacing: bool,
+    pub sse_flush: bool,
+    pub grpc_streaming: bool,
+    pub retry_budget: Option<u32>,
+    pub fast_fail_retry_after: Option<Duration>,
+    pub chaos: Option<ChaosOptions>,
+    pub problem_details: bool,
+    pub problem_details_template: Option<String>,
+    pub idempotency_ttl: Option<Duration>,
+    pub mirror_target: Option<String>,
+    pub mirror_percent: f64,
+    pub traffic_split: Option<String>,
+    pub traffic_split_sticky_header: Option<String>,
+    pub traffic_split_affinity_cookie: Option<String>,
+    pub host_routes: Option<String>,
+    pub method_routes: Option<String>,
+    pub soap_routes: Option<String>,
+    pub static_dir: Option<String>,
+    pub static_prefix: String,
+    pub static_cache_control: String,
+    pub spa_fallback: Option<String>,
+    pub html_rewrite_prefix: Option<String>,
+    pub max_response_bytes: Option<u64>,
+    pub max_response_overflow: String,
+    pub s3_offload_bucket: Option<String>,
+    pub s3_offload_prefix: String,
+    pub s3_offload_threshold: u64,
+    pub s3_offload_url_ttl: Duration,
+    pub s3_offload_redirect: u16,
+    pub s3_upload_bucket: Option<String>,
+    pub s3_upload_prefix: String,
+    pub s3_upload_threshold: u64,
+    pub s3_upload_url_ttl: Duration,
+    pub async_callback: Option<String>,
+    pub eventbridge_bus: Option<String>,
+    pub eventbridge_responses: Vec<ErrorStatusRule>,
+    pub eventbridge_detail_header: Option<String>,
+    pub audit_log_stream: Option<String>,
+    pub audit_log_batch_size: usize,
+    pub audit_log_flush_interval: Duration,
+    pub openapi_spec: Option<String>,
+    pub graphql_apq_path: Option<String>,
+    pub graphql_apq_cache_size: usize,
+    pub normalize_requests: bool,
+    pub adapter_health_path: Option<String>,
+    pub keepalive_probe: bool,
+    pub accept_rules: Option<String>,
+    pub request_compression: RequestCompression,
+    pub request_compression_threshold: usize,
+    pub upgrade_status: u16,
+    pub synthesize_methods: bool,
+    pub range_slicing: bool,
+    pub deadline_header: Option<String>,
+    pub duplicate_invocation_ttl: Option<Duration>,
+    pub maintenance_mode: Option<String>,
+    pub maintenance_allowlist: Option<String>,
+    pub maintenance_response: Option<String>,
+    pub accounting_headers: bool,
+    pub sniff_content_type: bool,
+    pub pre_stop_path: Option<String>,
+    pub pre_stop_timeout: Duration,
+    pub register_extension_after_ready: bool,
+    pub body_transforms: Option<String>,
+    pub static_headers: Option<String>,
+    pub ndjson_streaming: NdjsonStreaming,
+    pub add_base_path: Option<String>,
+    pub health_scoring: bool,
+    pub stream_prelude_wait: Option<Duration>,
+    pub sse_fallback_path: Option<String>,
+    pub polling_etag_paths: Option<String>,
+    pub sidecar_routes: Option<String>,
+    pub sidecar_registry: Option<String>,
+    pub sidecar_ports: HashMap<String, u16>,
+    pub failure_capture: Option<String>,
+    pub replay_capture: Option<String>,
+    pub replay_sample_percent: f64,
+    pub runtime_api_port: Option<u16>,
+    pub compression_min_size: u64,
+    pub header_case: HeaderCase,
+    pub ip_preference: IpPreference,
+    pub app_protocol: AppProtocol,
+    pub app_tls_skip_verify: bool,
+    pub app_tls_ca_file: Option<String>,
+    pub response_header_limit: Option<usize>,
+    pub response_header_trim: Option<String>,
+    pub keep_warm: bool,
+    pub keep_warm_path: Option<String>,
+    pub upstream_unix_socket: Option<String>,
+    pub shutdown_signal: Option<String>,
+    pub shutdown_drain: Duration,
+    pub emf_metrics: bool,
+    pub emf_dimensions: Option<String>,
+    pub latency_buckets: Option<String>,
+    pub metrics_port: Option<u16>,
+    pub stage_timing: bool,
+    pub xray_subsegments: bool,
+    pub app_cmd: Option<String>,
+    pub app_env: Option<String>,
+    pub request_timeout: Option<String>,
+    pub request_timeout_margin: Duration,
+    pub request_timeout_response: Option<String>,
+    pub upstream_retries: u32,
+    pub upstream_retry_backoff: Duration,
+    pub serialize_requests: bool,
+    pub max_in_flight: Option<usize>,
+    pub route_priorities: Option<String>,
+    pub websocket_mode: WebSocketMode,
+    pub websocket_path: String,
+    pub heartbeat_path: Option<String>,
+    pub heartbeat_interval: Duration,
+    pub expect_continue_bytes: Option<usize>,
+    pub expect_continue_timeout: Duration,
+    pub telemetry: bool,
+    pub telemetry_port: u16,
+    pub telemetry_forward_path: Option<String>,
+    pub access_log: bool,
+    pub access_log_redact_headers: Option<String>,
+    pub access_log_redact_query: Option<String>,
+    #[cfg(feature = "otel")]
+    pub otel_exporter_endpoint: Option<String>,
+    pub config_hash: String,
 }
 
 impl Default for AdapterOptions {
     fn default() -> Self {
+        Self::from_settings(&Settings::default())
+    }
+}
+
+impl AdapterOptions {
+    /// Options from the variables of `settings`: the environment, over the
+    /// configuration documents loaded into it.
+    fn from_settings(settings: &Settings) -> Self {
+        let var = |name: &str| settings.var(name);
+        let (error_status_codes, error_status_rules) = match var("AWS_LWA_ERROR_STATUS_CODES") {
+            Ok(rules) => {
+                let (codes, rules) = parse_error_status_rules(&rules);
+                (Some(codes), rules)
+            }
+            Err(_) => (None, Vec::new()),
+        };
+        let eventbridge_responses = match var("AWS_LWA_EVENTBRIDGE_RESPONSES") {
+            Ok(rules) => {
+                let (codes, mut rules) = parse_error_status_rules(&rules);
+                if !codes.is_empty() {
+                    rules.push(ErrorStatusRule {
+                        method: None,
+                        path: "*".to_string(),
+                        codes,
+                    });
+                }
+                rules
+            }
+            Err(_) => Vec::new(),
+        };
+
         AdapterOptions {
-            host: env::var("AWS_LWA_HOST").unwrap_or(env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string())),
-            port: env::var("AWS_LWA_PORT").unwrap_or(env::var("PORT").unwrap_or_else(|_| "8080".to_string())),
-            readiness_check_port: env::var("AWS_LWA_READINESS_CHECK_PORT").unwrap_or(
-                env::var("READINESS_CHECK_PORT").unwrap_or(
-                    env::var("AWS_LWA_PORT")
-                        .unwrap_or_else(|_| env::var("PORT").unwrap_or_else(|_| "8080".to_string())),
+            host: var("AWS_LWA_HOST").unwrap_or(var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string())),
+            port: var("AWS_LWA_PORT").unwrap_or(var("PORT").unwrap_or_else(|_| "8080".to_string())),
+            readiness_check_port: var("AWS_LWA_READINESS_CHECK_PORT").unwrap_or(
+                var("READINESS_CHECK_PORT").unwrap_or(
+                    var("AWS_LWA_PORT").unwrap_or_else(|_| var("PORT").unwrap_or_else(|_| "8080".to_string())),
                 ),
             ),
-            readiness_check_min_unhealthy_status: env::var("AWS_LWA_READINESS_CHECK_MIN_UNHEALTHY_STATUS")
+            readiness_check_min_unhealthy_status: var("AWS_LWA_READINESS_CHECK_MIN_UNHEALTHY_STATUS")
                 .unwrap_or_else(|_| "500".to_string())
                 .parse()
                 .unwrap_or(500),
-            readiness_check_path: env::var("AWS_LWA_READINESS_CHECK_PATH")
-                .unwrap_or(env::var("READINESS_CHECK_PATH").unwrap_or_else(|_| "/".to_string())),
-            readiness_check_protocol: env::var("AWS_LWA_READINESS_CHECK_PROTOCOL")
-                .unwrap_or(env::var("READINESS_CHECK_PROTOCOL").unwrap_or_else(|_| "HTTP".to_string()))
+            readiness_check_path: var("AWS_LWA_READINESS_CHECK_PATH")
+                .unwrap_or(var("READINESS_CHECK_PATH").unwrap_or_else(|_| "/".to_string())),
+            readiness_check_protocol: var("AWS_LWA_READINESS_CHECK_PROTOCOL")
+                .unwrap_or(var("READINESS_CHECK_PROTOCOL").unwrap_or_else(|_| "HTTP".to_string()))
                 .as_str()
                 .into(),
-            base_path: env::var("AWS_LWA_REMOVE_BASE_PATH").map_or_else(|_| env::var("REMOVE_BASE_PATH").ok(), Some),
-            pass_through_path: env::var("AWS_LWA_PASS_THROUGH_PATH").unwrap_or_else(|_| "/events".to_string()),
-            async_init: env::var("AWS_LWA_ASYNC_INIT")
-                .unwrap_or(env::var("ASYNC_INIT").unwrap_or_else(|_| "false".to_string()))
+            base_path: var("AWS_LWA_REMOVE_BASE_PATH").map_or_else(|_| var("REMOVE_BASE_PATH").ok(), Some),
+            pass_through_path: var("AWS_LWA_PASS_THROUGH_PATH").unwrap_or_else(|_| "/events".to_string()),
+            async_init: var("AWS_LWA_ASYNC_INIT")
+                .unwrap_or(var("ASYNC_INIT").unwrap_or_else(|_| "false".to_string()))
+                .parse()
+                .unwrap_or(false),
+            compression: var("AWS_LWA_ENABLE_COMPRESSION")
+                .unwrap_or_else(|_| "false".to_string())
                 .parse()
                 .unwrap_or(false),
-            compression: env::var("AWS_LWA_ENABLE_COMPRESSION")
+            compression_flush: var("AWS_LWA_COMPRESSION_FLUSH")
                 .unwrap_or_else(|_| "false".to_string())
                 .parse()
                 .unwrap_or(false),
-            invoke_mode: if let Ok(invoke_mode_str) = env::var("AWS_LWA_INVOKE_MODE") {
+            invoke_mode: if let Ok(invoke_mode_str) = var("AWS_LWA_INVOKE_MODE") {
                 // Explicit setting takes precedence
                 let mode = invoke_mode_str.as_str().into();
                 tracing::info!("Using explicitly configured invoke mode: {:?}", mode);
                 mode
             } else {
                 // If AWS_LWA_INVOKE_MODE isn't set explicitly, check for reactive frameworks
-                if detect_reactive_framework() {
+                if detect_reactive_framework(var) {
                     tracing::info!("Reactive framework detected - using response streaming mode");
                     LambdaInvokeMode::ResponseStream // Use streaming mode for reactive frameworks
                 } else {
@@ -274,39 +755,454 @@ impl Default for AdapterOptions {
                     LambdaInvokeMode::Buffered // Default to buffered mode for non-reactive apps
                 }
             },
-            authorization_source: env::var("AWS_LWA_AUTHORIZATION_SOURCE").ok(),
-            error_status_codes: env::var("AWS_LWA_ERROR_STATUS_CODES")
-                .ok()
-                .map(|codes| parse_status_codes(&codes)),
+            authorization_source: var("AWS_LWA_AUTHORIZATION_SOURCE").ok(),
+            error_status_codes,
+            error_status_rules,
+            strip_transfer_encoding: var("AWS_LWA_STRIP_TRANSFER_ENCODING")
+                .unwrap_or_else(|_| "auto".to_string())
+                .as_str()
+                .into(),
             // New HTTP client configuration with environment variable support
-            http_keepalive: env::var("AWS_LWA_HTTP_KEEPALIVE_SEC")
+            http_keepalive: var("AWS_LWA_HTTP_KEEPALIVE_SEC")
                 .ok()
                 .and_then(|v| v.parse::<u64>().ok())
                 .map(Duration::from_secs)
                 .or(Some(Duration::from_secs(30))), // Default to 30 seconds keepalive
-            http_nodelay: env::var("AWS_LWA_HTTP_NODELAY")
+            http_nodelay: var("AWS_LWA_HTTP_NODELAY")
                 .unwrap_or_else(|_| "true".to_string())
                 .parse()
                 .unwrap_or(true),
-            http_reuse_address: env::var("AWS_LWA_HTTP_REUSE_ADDRESS")
+            http_reuse_address: var("AWS_LWA_HTTP_REUSE_ADDRESS")
                 .unwrap_or_else(|_| "true".to_string())
                 .parse()
                 .unwrap_or(true),
-            http_pool_idle_timeout: env::var("AWS_LWA_HTTP_POOL_IDLE_TIMEOUT_SEC")
+            http_pool_idle_timeout: var("AWS_LWA_HTTP_POOL_IDLE_TIMEOUT_SEC")
                 .ok()
                 .and_then(|v| v.parse::<u64>().ok())
                 .map(Duration::from_secs)
                 .unwrap_or(Duration::from_secs(60)), // Default to 60 seconds (increased from 4)
-            http_pool_max_idle: env::var("AWS_LWA_HTTP_POOL_MAX_IDLE")
+            http_pool_max_idle: var("AWS_LWA_HTTP_POOL_MAX_IDLE")
                 .ok()
                 .and_then(|v| v.parse::<usize>().ok())
                 .unwrap_or(32), // Default to 32 connections per host
-            http_http2_only: env::var("AWS_LWA_HTTP_HTTP2_ONLY")
+            http_http2_only: var("AWS_LWA_HTTP_HTTP2_ONLY")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            http_http2_single_connection: var("AWS_LWA_HTTP_HTTP2_SINGLE_CONNECTION")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            http_http2_auto: var("AWS_LWA_HTTP_HTTP2_AUTO")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            hedge_after: var("AWS_LWA_HEDGE_AFTER_MS")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(Duration::from_millis),
+            streaming_buffer_threshold: var("AWS_LWA_STREAMING_BUFFER_THRESHOLD")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok()),
+            stream_pacing: var("AWS_LWA_STREAM_PACING")
                 .unwrap_or_else(|_| "false".to_string())
                 .parse()
                 .unwrap_or(false),
+            sse_flush: var("AWS_LWA_SSE_FLUSH")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            grpc_streaming: var("AWS_LWA_GRPC_STREAMING")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            retry_budget: var("AWS_LWA_RETRY_BUDGET").ok().and_then(|v| v.parse::<u32>().ok()),
+            fast_fail_retry_after: var("AWS_LWA_FAST_FAIL_RETRY_AFTER")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(Duration::from_secs),
+            chaos: var("AWS_LWA_CHAOS").ok().and_then(|v| match ChaosOptions::parse(&v) {
+                Ok(chaos) => Some(chaos),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_LWA_CHAOS: {}", err);
+                    None
+                }
+            }),
+            problem_details: var("AWS_LWA_PROBLEM_DETAILS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            problem_details_template: var("AWS_LWA_PROBLEM_DETAILS_TEMPLATE").ok(),
+            idempotency_ttl: var("AWS_LWA_IDEMPOTENCY_TTL")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(Duration::from_secs),
+            mirror_target: var("AWS_LWA_MIRROR_TARGET").ok(),
+            mirror_percent: var("AWS_LWA_MIRROR_PERCENT")
+                .ok()
+                .and_then(|v| v.parse::<f64>().ok())
+                .unwrap_or(100.0),
+            traffic_split: var("AWS_LWA_TRAFFIC_SPLIT").ok(),
+            traffic_split_sticky_header: var("AWS_LWA_TRAFFIC_SPLIT_STICKY_HEADER").ok(),
+            traffic_split_affinity_cookie: var("AWS_LWA_TRAFFIC_SPLIT_AFFINITY_COOKIE").ok(),
+            host_routes: var("AWS_LWA_HOST_ROUTES").ok(),
+            method_routes: var("AWS_LWA_METHOD_ROUTES").ok(),
+            soap_routes: var("AWS_LWA_SOAP_ROUTES").ok(),
+            static_dir: var("AWS_LWA_STATIC_DIR").ok(),
+            static_prefix: var("AWS_LWA_STATIC_PREFIX").unwrap_or_else(|_| "/".to_string()),
+            static_cache_control: var("AWS_LWA_STATIC_CACHE_CONTROL")
+                .unwrap_or_else(|_| "public, max-age=3600".to_string()),
+            spa_fallback: var("AWS_LWA_SPA_FALLBACK").ok(),
+            html_rewrite_prefix: var("AWS_LWA_HTML_REWRITE_PREFIX").ok(),
+            max_response_bytes: var("AWS_LWA_MAX_RESPONSE_BYTES")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok()),
+            max_response_overflow: var("AWS_LWA_MAX_RESPONSE_OVERFLOW").unwrap_or_else(|_| "reject".to_string()),
+            s3_offload_bucket: var("AWS_LWA_S3_OFFLOAD_BUCKET")
+                .map_or_else(|_| var("AWS_LWA_LARGE_RESPONSE_BUCKET").ok(), Some),
+            s3_offload_prefix: var("AWS_LWA_S3_OFFLOAD_PREFIX").unwrap_or_default(),
+            s3_offload_threshold: var("AWS_LWA_S3_OFFLOAD_THRESHOLD")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(s3::DEFAULT_OFFLOAD_THRESHOLD),
+            s3_offload_url_ttl: Duration::from_secs(
+                var("AWS_LWA_S3_OFFLOAD_URL_TTL")
+                    .or_else(|_| var("AWS_LWA_LARGE_RESPONSE_URL_TTL"))
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(300),
+            ),
+            s3_offload_redirect: var("AWS_LWA_S3_OFFLOAD_REDIRECT_STATUS")
+                .ok()
+                .and_then(|v| v.parse::<u16>().ok())
+                .unwrap_or(303),
+            s3_upload_bucket: var("AWS_LWA_S3_UPLOAD_BUCKET").ok(),
+            s3_upload_prefix: var("AWS_LWA_S3_UPLOAD_PREFIX").unwrap_or_else(|_| "uploads/".to_string()),
+            s3_upload_threshold: var("AWS_LWA_S3_UPLOAD_THRESHOLD")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(s3::DEFAULT_OFFLOAD_THRESHOLD),
+            s3_upload_url_ttl: Duration::from_secs(
+                var("AWS_LWA_S3_UPLOAD_URL_TTL")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(300),
+            ),
+            async_callback: var("AWS_LWA_ASYNC_CALLBACK").ok(),
+            eventbridge_bus: var("AWS_LWA_EVENTBRIDGE_BUS").ok(),
+            eventbridge_responses,
+            eventbridge_detail_header: var("AWS_LWA_EVENTBRIDGE_DETAIL_HEADER").ok(),
+            audit_log_stream: var("AWS_LWA_AUDIT_LOG_STREAM").ok(),
+            audit_log_batch_size: var("AWS_LWA_AUDIT_LOG_BATCH_SIZE")
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(100),
+            audit_log_flush_interval: Duration::from_secs(
+                var("AWS_LWA_AUDIT_LOG_FLUSH_INTERVAL")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(5),
+            ),
+            openapi_spec: var("AWS_LWA_OPENAPI_SPEC").ok(),
+            graphql_apq_path: var("AWS_LWA_GRAPHQL_APQ_PATH").ok(),
+            graphql_apq_cache_size: var("AWS_LWA_GRAPHQL_APQ_CACHE_SIZE")
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(1000),
+            normalize_requests: var("AWS_LWA_NORMALIZE_REQUESTS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            adapter_health_path: var("AWS_LWA_ADAPTER_HEALTH_PATH").ok(),
+            keepalive_probe: var("AWS_LWA_KEEPALIVE_PROBE")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            accept_rules: var("AWS_LWA_ACCEPT_RULES").ok(),
+            request_compression: var("AWS_LWA_REQUEST_COMPRESSION").as_deref().unwrap_or("none").into(),
+            request_compression_threshold: var("AWS_LWA_REQUEST_COMPRESSION_THRESHOLD")
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(1024 * 1024),
+            upgrade_status: var("AWS_LWA_UPGRADE_STATUS")
+                .ok()
+                .and_then(|v| v.parse::<u16>().ok())
+                .unwrap_or(501),
+            synthesize_methods: var("AWS_LWA_SYNTHESIZE_METHODS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            range_slicing: var("AWS_LWA_RANGE_SLICING")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            deadline_header: var("AWS_LWA_DEADLINE_HEADER").ok(),
+            duplicate_invocation_ttl: var("AWS_LWA_DUPLICATE_INVOCATION_TTL")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(Duration::from_secs),
+            maintenance_mode: var("AWS_LWA_MAINTENANCE_MODE").ok(),
+            maintenance_allowlist: var("AWS_LWA_MAINTENANCE_ALLOWLIST").ok(),
+            maintenance_response: var("AWS_LWA_MAINTENANCE_RESPONSE").ok(),
+            accounting_headers: var("AWS_LWA_ACCOUNTING_HEADERS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            sniff_content_type: var("AWS_LWA_SNIFF_CONTENT_TYPE")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            pre_stop_path: var("AWS_LWA_PRE_STOP_PATH").ok(),
+            pre_stop_timeout: Duration::from_millis(
+                var("AWS_LWA_PRE_STOP_TIMEOUT_MS")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(1000),
+            ),
+            register_extension_after_ready: var("AWS_LWA_REGISTER_EXTENSION_AFTER_READY")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            body_transforms: var("AWS_LWA_BODY_TRANSFORMS").ok(),
+            static_headers: var("AWS_LWA_STATIC_HEADERS").ok(),
+            ndjson_streaming: var("AWS_LWA_NDJSON_STREAMING").as_deref().unwrap_or("none").into(),
+            add_base_path: var("AWS_LWA_ADD_BASE_PATH").ok(),
+            health_scoring: var("AWS_LWA_HEALTH_SCORING")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            stream_prelude_wait: var("AWS_LWA_STREAM_PRELUDE_WAIT_MS")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(Duration::from_millis),
+            sse_fallback_path: var("AWS_LWA_SSE_FALLBACK_PATH").ok(),
+            polling_etag_paths: var("AWS_LWA_POLLING_ETAG_PATHS").ok(),
+            sidecar_routes: var("AWS_LWA_SIDECAR_ROUTES").ok(),
+            sidecar_registry: var("AWS_LWA_SIDECAR_REGISTRY").ok(),
+            sidecar_ports: settings
+                .vars()
+                .into_iter()
+                .filter_map(|(name, port)| {
+                    let name = name.strip_prefix("AWS_LWA_SIDECAR_")?.strip_suffix("_PORT")?;
+                    Some((name.to_string(), port.parse().ok()?))
+                })
+                .collect(),
+            failure_capture: var("AWS_LWA_FAILURE_CAPTURE").ok(),
+            replay_capture: var("AWS_LWA_REPLAY_CAPTURE").ok(),
+            replay_sample_percent: var("AWS_LWA_REPLAY_SAMPLE_PERCENT")
+                .ok()
+                .and_then(|v| v.parse::<f64>().ok())
+                .unwrap_or(10.0),
+            runtime_api_port: var("AWS_LWA_RUNTIME_API_PORT").ok().and_then(|v| v.parse().ok()),
+            compression_min_size: var("AWS_LWA_COMPRESSION_MIN_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(compression::DEFAULT_MIN_SIZE),
+            header_case: var("AWS_LWA_HEADER_CASE").as_deref().unwrap_or("lower").into(),
+            ip_preference: var("AWS_LWA_IP_PREFERENCE").as_deref().unwrap_or("any").into(),
+            app_protocol: var("AWS_LWA_APP_PROTOCOL").as_deref().unwrap_or("http").into(),
+            app_tls_skip_verify: var("AWS_LWA_APP_TLS_SKIP_VERIFY")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            app_tls_ca_file: var("AWS_LWA_APP_TLS_CA_FILE").ok(),
+            response_header_limit: var("AWS_LWA_RESPONSE_HEADER_LIMIT").ok().and_then(|v| v.parse().ok()),
+            response_header_trim: var("AWS_LWA_RESPONSE_HEADER_TRIM").ok(),
+            keep_warm: var("AWS_LWA_KEEP_WARM")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            keep_warm_path: var("AWS_LWA_KEEP_WARM_PATH").ok(),
+            upstream_unix_socket: var("AWS_LWA_UPSTREAM_UNIX_SOCKET").ok(),
+            shutdown_signal: var("AWS_LWA_SHUTDOWN_SIGNAL").ok(),
+            shutdown_drain: Duration::from_millis(
+                var("AWS_LWA_SHUTDOWN_DRAIN_MS")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(1500),
+            ),
+            emf_metrics: var("AWS_LWA_EMF_METRICS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            emf_dimensions: var("AWS_LWA_EMF_DIMENSIONS").ok(),
+            latency_buckets: var("AWS_LWA_LATENCY_BUCKETS").ok(),
+            metrics_port: var("AWS_LWA_METRICS_PORT").ok().and_then(|v| v.parse().ok()),
+            stage_timing: var("AWS_LWA_STAGE_TIMING")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            xray_subsegments: var("AWS_LWA_XRAY_SUBSEGMENTS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            app_cmd: var("AWS_LWA_APP_CMD").ok(),
+            app_env: var("AWS_LWA_APP_ENV").ok(),
+            request_timeout: var("AWS_LWA_REQUEST_TIMEOUT_MS").ok(),
+            request_timeout_margin: Duration::from_millis(
+                var("AWS_LWA_REQUEST_TIMEOUT_MARGIN_MS")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(500),
+            ),
+            request_timeout_response: var("AWS_LWA_REQUEST_TIMEOUT_RESPONSE").ok(),
+            upstream_retries: var("AWS_LWA_UPSTREAM_RETRIES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            upstream_retry_backoff: Duration::from_millis(
+                var("AWS_LWA_UPSTREAM_RETRY_BACKOFF_MS")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(50),
+            ),
+            serialize_requests: var("AWS_LWA_SERIALIZE_REQUESTS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            max_in_flight: var("AWS_LWA_MAX_IN_FLIGHT")
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .filter(|limit| *limit > 0),
+            route_priorities: var("AWS_LWA_ROUTE_PRIORITIES").ok(),
+            websocket_mode: var("AWS_LWA_WEBSOCKET_MODE").as_deref().unwrap_or("http").into(),
+            websocket_path: var("AWS_LWA_WEBSOCKET_PATH").unwrap_or_else(|_| "/websocket".to_string()),
+            heartbeat_path: var("AWS_LWA_HEARTBEAT_PATH").ok(),
+            heartbeat_interval: Duration::from_millis(
+                var("AWS_LWA_HEARTBEAT_INTERVAL_MS")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(60_000),
+            ),
+            expect_continue_bytes: var("AWS_LWA_EXPECT_CONTINUE_BYTES").ok().and_then(|v| v.parse().ok()),
+            expect_continue_timeout: Duration::from_millis(
+                var("AWS_LWA_EXPECT_CONTINUE_TIMEOUT_MS")
+                    .ok()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(1000),
+            ),
+            telemetry: var("AWS_LWA_TELEMETRY")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            telemetry_port: var("AWS_LWA_TELEMETRY_PORT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(9009),
+            telemetry_forward_path: var("AWS_LWA_TELEMETRY_FORWARD_PATH").ok(),
+            access_log: var("AWS_LWA_ACCESS_LOG")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()
+                .unwrap_or(false),
+            access_log_redact_headers: var("AWS_LWA_ACCESS_LOG_REDACT_HEADERS").ok(),
+            access_log_redact_query: var("AWS_LWA_ACCESS_LOG_REDACT_QUERY").ok(),
+            #[cfg(feature = "otel")]
+            otel_exporter_endpoint: otel::traces_endpoint(),
+            config_hash: banner::config_hash(settings.vars().into_iter()),
+        }
+    }
+
+    /// Options from environment variables, after filling in the ones that
+    /// are not set from a configuration file in TOML, YAML or JSON. The
+    /// environment of the process is left as it is.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut settings = Settings::default();
+        config::load_file(&mut settings, path.as_ref())?;
+        Ok(Self::from_settings(&settings))
+    }
+
+    /// Options from environment variables, after filling in the ones that
+    /// are not set from the configuration file in `AWS_LWA_CONFIG_FILE` or
+    /// `/opt/aws-lwa.toml`, and then from the shared configuration in
+    /// `AWS_LWA_CONFIG_S3_URI`. Neither changes the environment.
+    ///
+    /// Fails when the file in `AWS_LWA_CONFIG_FILE` cannot be read or parsed.
+    pub async fn load() -> Result<Self, Error> {
+        let mut settings = Settings::default();
+        config::load(&mut settings)?;
+        if let Ok(uri) = settings.var(shared_config::CONFIG_S3_URI) {
+            if let Err(err) = shared_config::load(&mut settings, &uri).await {
+                tracing::warn!("ignoring invalid {}: {}", shared_config::CONFIG_S3_URI, err);
+            }
+        }
+        Ok(Self::from_settings(&settings))
+    }
+}
+
+/// Error status codes that only apply to some methods and paths, written as
+/// `codes@[METHOD] [path]` in `AWS_LWA_ERROR_STATUS_CODES`,
+/// e.g. `500-599@/payments/*` or `502@GET /orders/*`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorStatusRule {
+    /// The method the rule applies to, or every method when `None`.
+    pub method: Option<Method>,
+    /// The path pattern the rule applies to. `*` matches any characters.
+    pub path: String,
+    pub codes: Vec<u16>,
+}
+
+impl ErrorStatusRule {
+    fn parse(codes: &str, scope: &str) -> Option<Self> {
+        let scope = scope.trim();
+        let (method, path) = match scope.split_once(char::is_whitespace) {
+            Some((method, path)) => (Some(method), path.trim()),
+            None if scope.starts_with('/') || scope.starts_with('*') => (None, scope),
+            None => (Some(scope), "*"),
+        };
+        let method = match method {
+            Some(method) => match Method::from_bytes(method.to_uppercase().as_bytes()) {
+                Ok(method) => Some(method),
+                Err(_) => {
+                    tracing::warn!("Failed to parse error status rule method: {}", method);
+                    return None;
+                }
+            },
+            None => None,
+        };
+        Some(ErrorStatusRule {
+            method,
+            path: path.to_string(),
+            codes: parse_status_codes(codes),
+        })
+    }
+
+    pub fn matches(&self, method: &Method, path: &str, status: u16) -> bool {
+        self.method.as_ref().is_none_or(|m| m == method) && glob_match(&self.path, path) && self.codes.contains(&status)
+    }
+}
+
+/// Split `AWS_LWA_ERROR_STATUS_CODES` into the codes that apply everywhere
+/// and the rules scoped to methods and paths. Rules are separated by `;`.
+fn parse_error_status_rules(input: &str) -> (Vec<u16>, Vec<ErrorStatusRule>) {
+    let mut codes = Vec::new();
+    let mut rules = Vec::new();
+    for part in input.split(';') {
+        match part.split_once('@') {
+            Some((rule_codes, scope)) => rules.extend(ErrorStatusRule::parse(rule_codes, scope)),
+            None => codes.extend(parse_status_codes(part)),
+        }
+    }
+    (codes, rules)
+}
+
+/// Match `text` against a pattern where `*` matches any run of characters.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = parts.peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
         }
     }
+    rest.is_empty()
 }
 
 fn parse_status_codes(input: &str) -> Vec<u16> {
@@ -340,7 +1236,10 @@ fn parse_status_codes(input: &str) -> Vec<u16> {
 
 #[derive(Clone)]
 pub struct Adapter<C, B> {
-    client: Arc<Client<C, B>>,
+    client: Arc<ClientPool<C, B>>,
+    shared_connection: Option<Arc<SharedConnection>>,
+    expect_continue: Option<Arc<ExpectContinue>>,
+    http2_auto: bool,
     healthcheck_url: Url,
     healthcheck_protocol: Protocol,
     healthcheck_min_unhealthy_status: u16,
@@ -350,61 +1249,643 @@ pub struct Adapter<C, B> {
     base_path: Option<String>,
     path_through_path: String,
     compression: bool,
+    compression_flush: bool,
     invoke_mode: LambdaInvokeMode,
     authorization_source: Option<String>,
     error_status_codes: Option<Vec<u16>>,
+    error_status_rules: Vec<ErrorStatusRule>,
+    strip_transfer_encoding: bool,
+    hedge_after: Option<Duration>,
+    streaming_buffer_threshold: Option<u64>,
+    stream_pacing: bool,
+    sse_flush: bool,
+    grpc_streaming: bool,
+    grpc_passthrough: bool,
+    retry_budget: Option<u32>,
+    health_gate: Option<Arc<HealthGate>>,
+    chaos: Option<ChaosOptions>,
+    problem_details: Option<ProblemDetails>,
+    idempotency_cache: Option<Arc<IdempotencyCache>>,
+    mirror_target: Option<Url>,
+    mirror_percent: f64,
+    traffic_split: Option<Arc<TrafficSplit>>,
+    host_routes: Option<Arc<HostRoutes>>,
+    method_routes: Option<Arc<MethodRoutes>>,
+    soap_routes: Option<Arc<SoapRoutes>>,
+    static_files: Option<Arc<StaticFiles>>,
+    spa_fallback: Option<String>,
+    html_rewrite_prefix: Option<String>,
+    s3_offload: Option<Arc<S3Offload>>,
+    response_limit: Option<Arc<ResponseLimit>>,
+    s3_uploads: Option<Arc<S3Uploads>>,
+    async_requests: Option<Arc<AsyncRequests>>,
+    response_events: Option<Arc<ResponseEvents>>,
+    audit_log: Option<Arc<AuditLog>>,
+    openapi: Option<Arc<OpenApiSpec>>,
+    persisted_queries: Option<Arc<PersistedQueries>>,
+    normalize_requests: bool,
+    adapter_health_path: Option<String>,
+    plugins: Arc<Vec<Arc<dyn LwaPlugin>>>,
+    readiness_checks: Arc<Vec<Arc<dyn ReadinessCheck>>>,
+    in_flight: Option<Arc<InFlight>>,
+    accept_rules: Option<Arc<AcceptRules>>,
+    request_compression: RequestCompression,
+    request_compression_threshold: usize,
+    upgrade_status: StatusCode,
+    synthesize_methods: bool,
+    range_slicing: bool,
+    deadline_header: Option<HeaderName>,
+    invocation_cache: Option<Arc<IdempotencyCache>>,
+    maintenance: Option<Arc<Maintenance>>,
+    accounting_headers: bool,
+    sniff_content_type: bool,
+    pre_stop_url: Option<Url>,
+    pre_stop_timeout: Duration,
+    register_extension_after_ready: bool,
+    extension_deferred: Arc<AtomicBool>,
+    body_transforms: Option<Arc<BodyTransforms>>,
+    static_headers: Option<Arc<StaticHeaders>>,
+    ndjson_streaming: NdjsonStreaming,
+    add_base_path: Option<String>,
+    stream_prelude_wait: Option<Duration>,
+    sse_fallback_path: Option<String>,
+    polling_etags: Option<Arc<PollingEtags>>,
+    sidecar_routes: Option<Arc<SidecarRoutes>>,
+    failure_capture: Option<Arc<FailureCapture>>,
+    replay_capture: Option<Arc<ReplayCapture>>,
+    runtime_api: Option<Arc<RuntimeApi>>,
+    prometheus: Option<Arc<PrometheusMetrics>>,
+    compression_min_size: u64,
+    invoke_mode_check: Arc<InvokeModeCheck>,
+    header_limit: Arc<HeaderLimit>,
+    keep_warm: bool,
+    keep_warm_url: Option<Url>,
+    upstream_unix_socket: Option<PathBuf>,
+    shutdown_signal: Option<i32>,
+    shutdown_drain: Duration,
+    emf_metrics: Option<Arc<EmfMetrics>>,
+    stage_timing: bool,
+    xray: Option<Arc<XRay>>,
+    supervisor: Option<Arc<Supervisor>>,
+    request_timeout: Option<Arc<RequestTimeout>>,
+    upstream_retry: Option<UpstreamRetry>,
+    request_fence: Option<Arc<RequestFence>>,
+    load_shedder: Option<Arc<LoadShedder>>,
+    websocket: Arc<WebSocketBridge>,
+    keepalive_probe: bool,
+    heartbeat: Option<Arc<Heartbeat>>,
+    telemetry: Option<Arc<Telemetry>>,
+    access_log: Option<Arc<AccessLog>>,
+    #[cfg(feature = "otel")]
+    tracer: Option<Arc<Tracer>>,
 }
 
-impl Adapter<HttpConnector, Body> {
+impl Adapter<HttpsConnector<UpstreamConnector>, Body> {
     /// Create a new HTTP Adapter instance.
     /// This function initializes a new HTTP client
     /// to talk with the web server.
-    pub fn new(options: &AdapterOptions) -> Adapter<HttpConnector, Body> {
-        // PERFORMANCE IMPROVEMENT: Configure the HTTP connector with optimized settings
-        let mut connector = HttpConnector::new();
-        
-        // Set TCP keepalive to maintain persistent connections
-        if let Some(keepalive) = options.http_keepalive {
-            connector.set_keepalive(Some(keepalive));
-        }
-        
+    pub fn new(options: &AdapterOptions) -> Adapter<HttpsConnector<UpstreamConnector>, Body> {
+        banner::log(options);
+
+        let host = net::url_host(&options.host, options.ip_preference);
+        let schema = options.app_protocol.scheme();
+        let origin = format!("{schema}://{host}");
+
+        let healthcheck_url = format!(
+            "{}://{}:{}{}",
+            schema, host, options.readiness_check_port, options.readiness_check_path
+        )
+        .parse()
+        .unwrap();
+
+        let domain: Url = format!("{}://{}:{}", schema, host, options.port).parse().unwrap();
+
+        // PERFORMANCE IMPROVEMENT: Configure the HTTP connector with optimized settings
+        let mut connector = HttpConnector::new();
+
+        // Set TCP keepalive to maintain persistent connections
+        if let Some(keepalive) = options.http_keepalive {
+            connector.set_keepalive(Some(keepalive));
+        }
+
         // Enable TCP_NODELAY to disable Nagle's algorithm and reduce latency
         connector.set_nodelay(options.http_nodelay);
-        
+
         // Enable SO_REUSEADDR for better socket handling
         connector.set_reuse_address(options.http_reuse_address);
-        
+
+        // https URLs go through TLS, http URLs straight to the app
+        connector.enforce_http(false);
+        let tls = match options.app_protocol {
+            AppProtocol::Https => tls::client_config(options.app_tls_ca_file.as_deref(), options.app_tls_skip_verify),
+            AppProtocol::Http | AppProtocol::Grpc => tls::unused_client_config(),
+        };
+        // an app on a Unix socket gets its own requests and readiness checks there
+        let connector = match options.upstream_unix_socket.as_deref() {
+            Some(path) => UpstreamConnector::unix(connector, path, &[&domain, &healthcheck_url]),
+            None => UpstreamConnector::tcp(connector),
+        };
+        // gRPC servers only speak HTTP/2, without an upgrade from HTTP/1.1
+        let http2_only = options.http_http2_only || options.app_protocol == AppProtocol::Grpc;
+        let https = HttpsConnectorBuilder::new().with_tls_config(tls).https_or_http();
+        let connector = if http2_only {
+            https.enable_http2().wrap_connector(connector)
+        } else {
+            https.enable_http1().wrap_connector(connector)
+        };
+
         // Configure an optimized HTTP client
-        let mut client_builder = Client::builder(hyper_util::rt::TokioExecutor::new())
+        let mut client_builder = Client::builder(hyper_util::rt::TokioExecutor::new());
+        client_builder
             .pool_idle_timeout(options.http_pool_idle_timeout)
             .pool_max_idle_per_host(options.http_pool_max_idle);
-            
+
         // Optionally use HTTP/2 only for better multiplexing
-        if options.http_http2_only {
-            client_builder = client_builder.http2_only(true);
+        if http2_only {
+            client_builder.http2_only(true);
         }
-        
-        let client = client_builder.build(connector);
 
-        tracing::info!("HTTP client configured with keepalive: {:?}, nodelay: {}, pool_idle_timeout: {:?}, pool_max_idle: {}, http2_only: {}", 
-            options.http_keepalive, options.http_nodelay, options.http_pool_idle_timeout, 
-            options.http_pool_max_idle, options.http_http2_only);
+        // HTTP/2 header names are always lowercase
+        if options.header_case == HeaderCase::Title {
+            client_builder.http1_title_case_headers(true);
+        }
 
-        let schema = "http";
+        let client = ClientPool::new(client_builder, connector);
 
-        let healthcheck_url = format!(
-            "{}://{}:{}{}",
-            schema, options.host, options.readiness_check_port, options.readiness_check_path
-        )
-        .parse()
-        .unwrap();
+        tracing::info!("HTTP client configured with keepalive: {:?}, nodelay: {}, pool_idle_timeout: {:?}, pool_max_idle: {}, http2_only: {}",
+            options.http_keepalive, options.http_nodelay, options.http_pool_idle_timeout,
+            options.http_pool_max_idle, http2_only);
 
-        let domain = format!("{}://{}:{}", schema, options.host, options.port)
-            .parse()
-            .unwrap();
+        let traffic_split = options.traffic_split.as_deref().and_then(|split| {
+            match TrafficSplit::new(split, &origin, options.traffic_split_sticky_header.as_deref()) {
+                Ok(split) => Some(Arc::new(match options.traffic_split_affinity_cookie.as_deref() {
+                    Some(name) => split.with_affinity_cookie(name),
+                    None => split,
+                })),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_LWA_TRAFFIC_SPLIT: {}", err);
+                    None
+                }
+            }
+        });
+
+        let response_limit = options.max_response_bytes.and_then(|max_bytes| {
+            match ResponseLimit::new(max_bytes, &options.max_response_overflow) {
+                Ok(limit) => Some(Arc::new(limit)),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_LWA_MAX_RESPONSE_OVERFLOW: {}", err);
+                    None
+                }
+            }
+        });
+
+        // only buffered responses are limited to 6MB
+        let s3_offload = match options.s3_offload_bucket.as_deref() {
+            Some(bucket) if options.invoke_mode == LambdaInvokeMode::Buffered => {
+                match S3Offload::new(
+                    bucket,
+                    &options.s3_offload_prefix,
+                    options.s3_offload_threshold,
+                    options.s3_offload_url_ttl,
+                ) {
+                    Ok(offload) => Some(Arc::new(match options.s3_offload_redirect {
+                        307 => offload.with_temporary_redirect(),
+                        303 => offload,
+                        status => {
+                            tracing::warn!("ignoring invalid AWS_LWA_S3_OFFLOAD_REDIRECT_STATUS: {}", status);
+                            offload
+                        }
+                    })),
+                    Err(err) => {
+                        tracing::warn!("large responses will not be offloaded to S3: {}", err);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        let s3_uploads = options.s3_upload_bucket.as_deref().and_then(|bucket| {
+            match S3Uploads::new(
+                bucket,
+                &options.s3_upload_prefix,
+                options.s3_upload_threshold,
+                options.s3_upload_url_ttl,
+            ) {
+                Ok(uploads) => Some(Arc::new(uploads)),
+                Err(err) => {
+                    tracing::warn!("large requests will not be uploaded to S3: {}", err);
+                    None
+                }
+            }
+        });
+
+        let async_requests = options
+            .async_callback
+            .as_deref()
+            .and_then(|target| match AsyncRequests::new(target) {
+                Ok(async_requests) => Some(Arc::new(async_requests)),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_LWA_ASYNC_CALLBACK: {}", err);
+                    None
+                }
+            });
+
+        let websocket = Arc::new(WebSocketBridge::new(
+            options.websocket_mode,
+            &options.websocket_path,
+            &domain,
+        ));
+        let response_events = options.eventbridge_bus.as_deref().and_then(|bus| {
+            match ResponseEvents::new(
+                bus,
+                options.eventbridge_responses.clone(),
+                options.eventbridge_detail_header.as_deref(),
+            ) {
+                Ok(events) => Some(Arc::new(events)),
+                Err(err) => {
+                    tracing::warn!("responses will not be published to EventBridge: {}", err);
+                    None
+                }
+            }
+        });
+
+        let audit_log = options.audit_log_stream.as_deref().and_then(|stream| {
+            match AuditLog::new(stream, options.audit_log_batch_size, options.audit_log_flush_interval) {
+                Ok(audit_log) => Some(Arc::new(audit_log)),
+                Err(err) => {
+                    tracing::warn!("requests will not be logged to Firehose: {}", err);
+                    None
+                }
+            }
+        });
+
+        let openapi = options
+            .openapi_spec
+            .as_deref()
+            .and_then(|file| match OpenApiSpec::load(file) {
+                Ok(spec) => Some(Arc::new(spec)),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_LWA_OPENAPI_SPEC: {}", err);
+                    None
+                }
+            });
+
+        let static_files = options.static_dir.as_deref().and_then(|dir| {
+            match StaticFiles::new(dir, &options.static_prefix, &options.static_cache_control) {
+                Ok(static_files) => Some(Arc::new(static_files)),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_LWA_STATIC_CACHE_CONTROL: {}", err);
+                    None
+                }
+            }
+        });
+
+        let host_routes = options
+            .host_routes
+            .as_deref()
+            .and_then(|routes| match HostRoutes::new(routes, &origin) {
+                Ok(routes) => Some(Arc::new(routes)),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_LWA_HOST_ROUTES: {}", err);
+                    None
+                }
+            });
+
+        let method_routes =
+            options
+                .method_routes
+                .as_deref()
+                .and_then(|routes| match MethodRoutes::new(routes, &origin) {
+                    Ok(routes) => Some(Arc::new(routes)),
+                    Err(err) => {
+                        tracing::warn!("ignoring invalid AWS_LWA_METHOD_ROUTES: {}", err);
+                        None
+                    }
+                });
+
+        let soap_routes = options
+            .soap_routes
+            .as_deref()
+            .and_then(|routes| match SoapRoutes::new(routes) {
+                Ok(routes) => Some(Arc::new(routes)),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_LWA_SOAP_ROUTES: {}", err);
+                    None
+                }
+            });
+
+        let route_priorities =
+            options
+                .route_priorities
+                .as_deref()
+                .and_then(|priorities| match RoutePriorities::new(priorities) {
+                    Ok(priorities) => Some(priorities),
+                    Err(err) => {
+                        tracing::warn!("ignoring invalid AWS_LWA_ROUTE_PRIORITIES: {}", err);
+                        None
+                    }
+                });
+        let load_shedder = options
+            .max_in_flight
+            .map(|limit| Arc::new(LoadShedder::new(limit, route_priorities.unwrap_or_default())));
+
+        let failure_capture = options
+            .failure_capture
+            .as_deref()
+            .and_then(|target| match FailureCapture::new(target) {
+                Ok(capture) => Some(Arc::new(capture)),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_LWA_FAILURE_CAPTURE: {}", err);
+                    None
+                }
+            });
+
+        let replay_capture = options.replay_capture.as_deref().and_then(|target| {
+            match ReplayCapture::new(target, options.replay_sample_percent) {
+                Ok(capture) => Some(Arc::new(capture)),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_LWA_REPLAY_CAPTURE: {}", err);
+                    None
+                }
+            }
+        });
+
+        let runtime_api = options.runtime_api_port.and_then(|port| match RuntimeApi::new(port) {
+            Ok(runtime_api) => Some(runtime_api),
+            Err(err) => {
+                tracing::warn!("ignoring AWS_LWA_RUNTIME_API_PORT: {}", err);
+                None
+            }
+        });
+
+        let prometheus = options.metrics_port.and_then(|port| {
+            let buckets =
+                options
+                    .latency_buckets
+                    .as_deref()
+                    .and_then(|buckets| match metrics::parse_buckets(buckets) {
+                        Ok(buckets) => Some(buckets),
+                        Err(err) => {
+                            tracing::warn!("ignoring invalid AWS_LWA_LATENCY_BUCKETS: {}", err);
+                            None
+                        }
+                    });
+            let prometheus = Arc::new(PrometheusMetrics::new(buckets));
+            match prometheus.serve(port) {
+                Ok(()) => Some(prometheus),
+                Err(err) => {
+                    tracing::warn!("ignoring AWS_LWA_METRICS_PORT: {}", err);
+                    None
+                }
+            }
+        });
+
+        let supervisor = options
+            .app_cmd
+            .as_deref()
+            .and_then(|command| match Supervisor::new(command) {
+                Ok(supervisor) => Some(supervisor),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_LWA_APP_CMD: {}", err);
+                    None
+                }
+            })
+            .map(|mut supervisor| {
+                if let Some(Err(err)) = options
+                    .app_env
+                    .as_deref()
+                    .map(|templates| supervisor.set_env(templates, |variable| env::var(variable).ok()))
+                {
+                    tracing::warn!("ignoring invalid AWS_LWA_APP_ENV: {}", err);
+                }
+                Arc::new(supervisor)
+            });
+
+        let request_timeout = options.request_timeout.as_deref().and_then(|limit| {
+            match RequestTimeout::new(
+                limit,
+                options.request_timeout_margin,
+                options.request_timeout_response.as_deref(),
+            ) {
+                Ok(request_timeout) => Some(Arc::new(request_timeout)),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_LWA_REQUEST_TIMEOUT_MS: {}", err);
+                    None
+                }
+            }
+        });
+
+        let emf_metrics = options.emf_metrics.then(|| {
+            let mut metrics = EmfMetrics::new(options.latency_buckets.as_deref()).unwrap_or_else(|err| {
+                tracing::warn!("ignoring invalid AWS_LWA_LATENCY_BUCKETS: {}", err);
+                EmfMetrics::new(None).expect("metrics without buckets")
+            });
+            if let Some(dimensions) = options.emf_dimensions.as_deref() {
+                if let Err(err) = metrics.set_dimensions(dimensions) {
+                    tracing::warn!("ignoring invalid AWS_LWA_EMF_DIMENSIONS: {}", err);
+                }
+            }
+            metrics
+        });
+
+        let xray = options
+            .xray_subsegments
+            .then(|| env::var("AWS_XRAY_DAEMON_ADDRESS").unwrap_or_else(|_| "127.0.0.1:2000".to_string()))
+            .and_then(|address| match XRay::new(&address) {
+                Ok(xray) => Some(Arc::new(xray)),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_XRAY_DAEMON_ADDRESS: {}", err);
+                    None
+                }
+            });
+
+        let sidecar_routes = options.sidecar_routes.as_deref().and_then(|routes| {
+            match SidecarRoutes::new(routes, options.sidecar_registry.as_deref(), &options.sidecar_ports) {
+                Ok(routes) => Some(Arc::new(routes)),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_LWA_SIDECAR_ROUTES: {}", err);
+                    None
+                }
+            }
+        });
+
+        // Multiplex all requests over one HTTP/2 connection instead of a pool.
+        // A single connection can only reach one port, and only over cleartext HTTP/2.
+        let shared_connection = if http2_only
+            && options.http_http2_single_connection
+            && options.app_protocol != AppProtocol::Https
+            && options.upstream_unix_socket.is_none()
+            && traffic_split.is_none()
+            && host_routes.is_none()
+            && method_routes.is_none()
+            && sidecar_routes.is_none()
+        {
+            tracing::info!("HTTP client configured with a single shared HTTP/2 connection");
+            Some(Arc::new(SharedConnection::new(
+                &host,
+                &options.port,
+                options.http_nodelay,
+            )))
+        } else {
+            None
+        };
+
+        // the probe only reaches the app port over h2c, other upstreams might not speak HTTP/2
+        let http2_auto = options.http_http2_auto
+            && !http2_only
+            && options.app_protocol == AppProtocol::Http
+            && options.upstream_unix_socket.is_none()
+            && traffic_split.is_none()
+            && host_routes.is_none()
+            && method_routes.is_none()
+            && sidecar_routes.is_none();
+
+        // a shared connection reconnects by itself, only pooled connections go stale
+        let keepalive_probe = options.keepalive_probe && shared_connection.is_none();
+
+        // the handshake opens HTTP/1.1 connections over TCP of its own
+        let expect_continue = options.expect_continue_bytes.and_then(|min_bytes| {
+            if http2_only || options.upstream_unix_socket.is_some() {
+                tracing::warn!("ignoring AWS_LWA_EXPECT_CONTINUE_BYTES, it needs an HTTP/1.1 app on a TCP port");
+                return None;
+            }
+            Some(Arc::new(ExpectContinue::new(
+                min_bytes,
+                options.expect_continue_timeout,
+                options.http_nodelay,
+            )))
+        });
+
+        let heartbeat = options
+            .heartbeat_path
+            .as_deref()
+            .and_then(|path| match domain.join(path) {
+                Ok(url) => Some(Arc::new(Heartbeat::new(url, options.heartbeat_interval))),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_LWA_HEARTBEAT_PATH: {}", err);
+                    None
+                }
+            });
+
+        let telemetry = options.telemetry.then(|| {
+            let forward = options
+                .telemetry_forward_path
+                .as_deref()
+                .and_then(|path| match domain.join(path) {
+                    Ok(url) => Some(url),
+                    Err(err) => {
+                        tracing::warn!("ignoring invalid AWS_LWA_TELEMETRY_FORWARD_PATH: {}", err);
+                        None
+                    }
+                });
+            Arc::new(Telemetry::new(options.telemetry_port, forward))
+        });
+
+        let access_log = options.access_log.then(|| {
+            AccessLog::new(
+                options.access_log_redact_headers.as_deref(),
+                options.access_log_redact_query.as_deref(),
+            )
+        });
+        let access_log = match access_log {
+            Some(Ok(access_log)) => Some(Arc::new(access_log)),
+            Some(Err(err)) => {
+                // logging without the redactions asked for could leak secrets, so do not log at all
+                tracing::warn!("ignoring invalid AWS_LWA_ACCESS_LOG_REDACT_HEADERS: {}", err);
+                None
+            }
+            None => None,
+        };
+
+        #[cfg(feature = "otel")]
+        let tracer = options
+            .otel_exporter_endpoint
+            .as_deref()
+            .and_then(|endpoint| match Tracer::new(endpoint) {
+                Ok(tracer) => Some(Arc::new(tracer)),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid OTEL_EXPORTER_OTLP_ENDPOINT: {}", err);
+                    None
+                }
+            });
+        #[cfg(feature = "otel")]
+        let exports_spans = tracer.is_some();
+        #[cfg(not(feature = "otel"))]
+        let exports_spans = false;
+
+        // the keep-alive probe, the heartbeat and the span export wait for the invocation to finish
+        let in_flight =
+            (keepalive_probe || heartbeat.is_some() || exports_spans).then(|| Arc::new(InFlight::default()));
+
+        let problem_details = options.problem_details.then(|| {
+            ProblemDetails::new(options.problem_details_template.as_deref()).unwrap_or_else(|err| {
+                tracing::warn!("ignoring invalid AWS_LWA_PROBLEM_DETAILS_TEMPLATE: {}", err);
+                ProblemDetails::default()
+            })
+        });
+
+        if let Some(chaos) = options.chaos {
+            tracing::warn!(latency = ?chaos.latency, error_rate = chaos.error_rate,
+                "chaos mode is enabled, requests will be delayed and failed on purpose");
+        }
+
+        let pre_stop_url = options
+            .pre_stop_path
+            .as_deref()
+            .and_then(|path| match domain.join(path) {
+                Ok(url) => Some(url),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_LWA_PRE_STOP_PATH: {}", err);
+                    None
+                }
+            });
+
+        let shutdown_signal = options
+            .shutdown_signal
+            .as_deref()
+            .and_then(|name| match shutdown::parse_signal(name) {
+                Some(signal) => Some(signal),
+                None => {
+                    tracing::warn!("ignoring invalid AWS_LWA_SHUTDOWN_SIGNAL: {}", name);
+                    None
+                }
+            });
+
+        let keep_warm_url = options
+            .keep_warm_path
+            .as_deref()
+            .and_then(|path| match domain.join(path) {
+                Ok(url) => Some(url),
+                Err(err) => {
+                    tracing::warn!("ignoring invalid AWS_LWA_KEEP_WARM_PATH: {}", err);
+                    None
+                }
+            });
+
+        // a bare port mirrors to another server next to the app
+        let mirror_target = options.mirror_target.as_deref().and_then(|target| {
+            let target = match target.parse::<u16>() {
+                Ok(port) => format!("http://{}:{}", host, port),
+                Err(_) => target.to_string(),
+            };
+            match target.parse::<Url>() {
+                Ok(url) if url.scheme() == "http" => Some(url),
+                _ => {
+                    tracing::warn!(
+                        "ignoring AWS_LWA_MIRROR_TARGET, it must be a port or an http URL: {}",
+                        target
+                    );
+                    None
+                }
+            }
+        });
 
         Adapter {
             client: Arc::new(client),
+            shared_connection,
+            expect_continue,
+            http2_auto,
             healthcheck_url,
             healthcheck_protocol: options.readiness_check_protocol,
             healthcheck_min_unhealthy_status: options.readiness_check_min_unhealthy_status,
@@ -412,82 +1893,536 @@ impl Adapter<HttpConnector, Body> {
             base_path: options.base_path.clone(),
             path_through_path: options.pass_through_path.clone(),
             async_init: options.async_init,
-            ready_at_init: Arc::new(AtomicBool::new(false)),
+            ready_at_init: Arc::new(AtomicBool::new(!options.async_init)),
             compression: options.compression,
+            compression_flush: options.compression_flush,
             invoke_mode: options.invoke_mode,
             authorization_source: options.authorization_source.clone(),
             error_status_codes: options.error_status_codes.clone(),
+            error_status_rules: options.error_status_rules.clone(),
+            strip_transfer_encoding: match options.strip_transfer_encoding {
+                StripTransferEncoding::Always => true,
+                StripTransferEncoding::Never => false,
+                StripTransferEncoding::Auto => {
+                    options.invoke_mode == LambdaInvokeMode::Buffered || env::var("AWS_SAM_LOCAL").is_ok()
+                }
+            },
+            hedge_after: options.hedge_after,
+            streaming_buffer_threshold: options.streaming_buffer_threshold,
+            stream_pacing: options.stream_pacing,
+            sse_flush: options.sse_flush,
+            grpc_streaming: options.grpc_streaming,
+            grpc_passthrough: options.app_protocol == AppProtocol::Grpc,
+            retry_budget: options.retry_budget,
+            health_gate: options
+                .fast_fail_retry_after
+                .map(|retry_after| Arc::new(HealthGate::new(retry_after, options.health_scoring))),
+            chaos: options.chaos,
+            problem_details,
+            idempotency_cache: options.idempotency_ttl.map(|ttl| Arc::new(IdempotencyCache::new(ttl))),
+            mirror_target,
+            mirror_percent: options.mirror_percent,
+            traffic_split,
+            host_routes,
+            method_routes,
+            soap_routes,
+            static_files,
+            spa_fallback: options.spa_fallback.clone(),
+            html_rewrite_prefix: options.html_rewrite_prefix.clone(),
+            s3_offload,
+            response_limit,
+            s3_uploads,
+            async_requests,
+            response_events,
+            audit_log,
+            openapi,
+            persisted_queries: options
+                .graphql_apq_path
+                .as_deref()
+                .map(|path| Arc::new(PersistedQueries::new(path, options.graphql_apq_cache_size))),
+            normalize_requests: options.normalize_requests,
+            adapter_health_path: options.adapter_health_path.clone(),
+            plugins: Arc::new(Vec::new()),
+            readiness_checks: Arc::new(Vec::new()),
+            in_flight,
+            accept_rules: options
+                .accept_rules
+                .as_deref()
+                .and_then(|rules| match AcceptRules::new(rules) {
+                    Ok(rules) => Some(Arc::new(rules)),
+                    Err(err) => {
+                        tracing::warn!("ignoring invalid AWS_LWA_ACCEPT_RULES: {}", err);
+                        None
+                    }
+                }),
+            request_compression: options.request_compression,
+            request_compression_threshold: options.request_compression_threshold,
+            upgrade_status: match options.upgrade_status {
+                426 => StatusCode::UPGRADE_REQUIRED,
+                501 => StatusCode::NOT_IMPLEMENTED,
+                status => {
+                    tracing::warn!("ignoring invalid AWS_LWA_UPGRADE_STATUS: {}, use 426 or 501", status);
+                    StatusCode::NOT_IMPLEMENTED
+                }
+            },
+            synthesize_methods: options.synthesize_methods,
+            range_slicing: options.range_slicing,
+            deadline_header: options
+                .deadline_header
+                .as_deref()
+                .and_then(|name| match HeaderName::try_from(name) {
+                    Ok(name) => Some(name),
+                    Err(err) => {
+                        tracing::warn!("ignoring invalid AWS_LWA_DEADLINE_HEADER: {}", err);
+                        None
+                    }
+                }),
+            invocation_cache: options
+                .duplicate_invocation_ttl
+                .map(|ttl| Arc::new(IdempotencyCache::new(ttl))),
+            maintenance: options.maintenance_mode.as_deref().and_then(|mode| {
+                match Maintenance::new(
+                    mode,
+                    options.maintenance_allowlist.as_deref(),
+                    options.maintenance_response.as_deref(),
+                ) {
+                    Ok(maintenance) => maintenance.map(Arc::new),
+                    Err(err) => {
+                        tracing::warn!("ignoring invalid AWS_LWA_MAINTENANCE_MODE: {}", err);
+                        None
+                    }
+                }
+            }),
+            accounting_headers: options.accounting_headers,
+            sniff_content_type: options.sniff_content_type,
+            pre_stop_url,
+            pre_stop_timeout: options.pre_stop_timeout,
+            register_extension_after_ready: options.register_extension_after_ready,
+            extension_deferred: Arc::new(AtomicBool::new(false)),
+            body_transforms: options
+                .body_transforms
+                .as_deref()
+                .and_then(|rules| match BodyTransforms::load(rules) {
+                    Ok(rules) => Some(Arc::new(rules)),
+                    Err(err) => {
+                        tracing::warn!("ignoring invalid AWS_LWA_BODY_TRANSFORMS: {}", err);
+                        None
+                    }
+                }),
+            static_headers: options
+                .static_headers
+                .as_deref()
+                .and_then(|headers| match StaticHeaders::load(headers) {
+                    Ok(headers) => Some(Arc::new(headers)),
+                    Err(err) => {
+                        tracing::warn!("ignoring invalid AWS_LWA_STATIC_HEADERS: {}", err);
+                        None
+                    }
+                }),
+            ndjson_streaming: options.ndjson_streaming,
+            add_base_path: options
+                .add_base_path
+                .as_deref()
+                .map(|path| path.trim_end_matches('/'))
+                .filter(|path| !path.is_empty())
+                .map(str::to_string),
+            stream_prelude_wait: options.stream_prelude_wait,
+            sse_fallback_path: options.sse_fallback_path.clone(),
+            polling_etags: options
+                .polling_etag_paths
+                .as_deref()
+                .map(|patterns| Arc::new(PollingEtags::new(patterns))),
+            sidecar_routes,
+            failure_capture,
+            replay_capture,
+            runtime_api,
+            prometheus,
+            compression_min_size: options.compression_min_size,
+            invoke_mode_check: Arc::new(InvokeModeCheck::new(options.invoke_mode)),
+            header_limit: Arc::new(HeaderLimit::new(
+                options.response_header_limit,
+                options.response_header_trim.as_deref(),
+            )),
+            keep_warm: options.keep_warm,
+            keep_warm_url,
+            upstream_unix_socket: options.upstream_unix_socket.as_deref().map(PathBuf::from),
+            shutdown_signal,
+            shutdown_drain: options.shutdown_drain,
+            emf_metrics: emf_metrics.map(Arc::new),
+            stage_timing: options.stage_timing,
+            xray,
+            supervisor,
+            request_timeout,
+            upstream_retry: (options.upstream_retries > 0).then_some(UpstreamRetry {
+                retries: options.upstream_retries,
+                backoff: options.upstream_retry_backoff,
+            }),
+            request_fence: options.serialize_requests.then(|| Arc::new(RequestFence::new())),
+            load_shedder,
+            websocket,
+            keepalive_probe,
+            heartbeat,
+            telemetry,
+            access_log,
+            #[cfg(feature = "otel")]
+            tracer,
         }
     }
 }
 
-impl Adapter<HttpConnector, Body> {
+impl Adapter<HttpsConnector<UpstreamConnector>, Body> {
     /// Register a Lambda Extension to ensure
     /// that the adapter is loaded before any Lambda function
     /// associated with it.
+    ///
+    /// With `register_extension_after_ready`, the registration waits for
+    /// [`Adapter::check_init_health`] instead.
     pub fn register_default_extension(&self) {
+        if self.register_extension_after_ready {
+            self.extension_deferred.store(true, Ordering::Release);
+            return;
+        }
+        self.spawn_extension();
+    }
+
+    /// Register as an external extension in the background. The receiver
+    /// completes once the registration is done.
+    fn spawn_extension(&self) -> tokio::sync::oneshot::Receiver<()> {
+        let (registered, registration) = tokio::sync::oneshot::channel();
+        let plugins = self.plugins.clone();
+        let telemetry = self.telemetry.clone();
+
+        // the keep-alive probe, the heartbeat and the span export run after every invoke, and the graceful shutdown at shutdown
+        // telemetry is only delivered while the extension is active, so it takes part in every invoke
+        let mut events = Vec::new();
+        if self.in_flight.is_some() || telemetry.is_some() {
+            events.push("INVOKE");
+        }
+        if self.pre_stop_url.is_some() || self.shutdown_signal.is_some() || self.supervisor.is_some() {
+            events.push("SHUTDOWN");
+        }
+        let adapter = (!events.is_empty()).then(|| self.clone());
+
         // register as an external extension
         tokio::task::spawn(async move {
             let aws_lambda_runtime_api: String =
                 env::var("AWS_LAMBDA_RUNTIME_API").unwrap_or_else(|_| "127.0.0.1:9001".to_string());
-            
+
             // Use optimized HTTP connector for extension registration as well
             let mut connector = HttpConnector::new();
             connector.set_keepalive(Some(Duration::from_secs(30)));
             connector.set_nodelay(true);
-            
+
             let client = Client::builder(hyper_util::rt::TokioExecutor::new())
                 .pool_idle_timeout(Duration::from_secs(60))
                 .build(connector);
-                
+
             let register_req = hyper::Request::builder()
                 .method(Method::POST)
                 .uri(format!("http://{aws_lambda_runtime_api}/2020-01-01/extension/register"))
                 .header("Lambda-Extension-Name", "lambda-adapter")
-                .body(Body::from("{ \"events\": [] }"))
+                .body(Body::from(serde_json::json!({ "events": events }).to_string()))
                 .unwrap();
             let register_res = client.request(register_req).await.unwrap();
             if register_res.status() != StatusCode::OK {
                 panic!("extension registration failure");
             }
-            let next_req = hyper::Request::builder()
-                .method(Method::GET)
-                .uri(format!(
-                    "http://{aws_lambda_runtime_api}/2020-01-01/extension/event/next"
-                ))
-                .header(
-                    "Lambda-Extension-Identifier",
-                    register_res.headers().get("Lambda-Extension-Identifier").unwrap(),
-                )
-                .body(Body::Empty)
-                .unwrap();
-            client.request(next_req).await.unwrap();
+            let extension_id = register_res
+                .headers()
+                .get("Lambda-Extension-Identifier")
+                .unwrap()
+                .clone();
+            for plugin in plugins.iter() {
+                plugin.on_extension_registered(extension_id.to_str().unwrap_or_default());
+            }
+            if let Some(telemetry) = telemetry {
+                if let Err(err) = telemetry
+                    .subscribe(&client, &aws_lambda_runtime_api, &extension_id)
+                    .await
+                {
+                    tracing::warn!("failed to subscribe to the Lambda Telemetry API: {}", err);
+                }
+            }
+            let _ = registered.send(());
+            let next_req = || {
+                hyper::Request::builder()
+                    .method(Method::GET)
+                    .uri(format!(
+                        "http://{aws_lambda_runtime_api}/2020-01-01/extension/event/next"
+                    ))
+                    .header("Lambda-Extension-Identifier", extension_id.clone())
+                    .body(Body::Empty)
+                    .unwrap()
+            };
+            let Some(adapter) = adapter else {
+                client.request(next_req()).await.unwrap();
+                return;
+            };
+            loop {
+                let event = client.request(next_req()).await.unwrap();
+                let event = event.into_body().collect().await.map(|body| body.to_bytes());
+                let event = event
+                    .ok()
+                    .and_then(|event| serde_json::from_slice::<serde_json::Value>(&event).ok())
+                    .unwrap_or_default();
+                if event["eventType"] == "SHUTDOWN" {
+                    #[cfg(feature = "otel")]
+                    if let Some(tracer) = adapter.tracer.as_deref() {
+                        tracer.flush().await;
+                    }
+                    adapter.shutdown().await;
+                    return;
+                }
+                // wait for the response of this invoke to be sent, but give up at its deadline
+                let finished = match adapter.in_flight.as_deref() {
+                    Some(in_flight) => {
+                        let request_id = event["requestId"].as_str().unwrap_or_default();
+                        let deadline = event["deadlineMs"].as_u64().unwrap_or_default();
+                        let now = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map(|now| now.as_millis() as u64)
+                            .unwrap_or_default();
+                        let remaining = Duration::from_millis(deadline.saturating_sub(now));
+                        timeout(remaining, in_flight.finished(request_id)).await.is_ok()
+                    }
+                    None => true,
+                };
+                // the span of the invoke is queued by now, unless the invoke ran past its deadline
+                #[cfg(feature = "otel")]
+                if let Some(tracer) = adapter.tracer.as_deref() {
+                    tracer.flush().await;
+                }
+                if adapter.keepalive_probe && finished {
+                    adapter.probe_connection_pool().await;
+                }
+                if let Some(heartbeat) = adapter.heartbeat.as_deref().filter(|_| finished) {
+                    if heartbeat.take_due(Instant::now()) {
+                        adapter.send_heartbeat(heartbeat).await;
+                    }
+                }
+            }
         });
+        registration
+    }
+
+    /// Stop the app gracefully before Lambda ends the execution environment:
+    /// send the pre-stop request, then the shutdown signal, and wait for the
+    /// app to exit within the drain timeout before killing it. An app the
+    /// adapter started gets `SIGTERM` unless another signal is configured.
+    async fn shutdown(&self) {
+        self.pre_stop().await;
+        if let Some(supervisor) = self.supervisor.as_deref() {
+            let signal = self.shutdown_signal.unwrap_or(libc::SIGTERM);
+            supervisor.stop(signal, self.shutdown_drain).await;
+            return;
+        }
+        let Some(signal) = self.shutdown_signal else {
+            return;
+        };
+        let port = self.domain.port_or_known_default().unwrap_or_default();
+        let Some(pid) = shutdown::app_pid(port, self.upstream_unix_socket.as_deref()) else {
+            tracing::warn!("cannot find the app process to send the shutdown signal to");
+            return;
+        };
+        if let Err(err) = shutdown::send_signal(pid, signal) {
+            tracing::warn!(pid, "failed to send the shutdown signal to the app: {}", err);
+            return;
+        }
+        if shutdown::wait_for_exit(pid, self.shutdown_drain).await {
+            tracing::info!(pid, "app exited after the shutdown signal");
+        } else {
+            tracing::warn!(
+                pid,
+                "app did not exit within {:?} of the shutdown signal, killing it",
+                self.shutdown_drain
+            );
+            if let Err(err) = shutdown::send_signal(pid, libc::SIGKILL) {
+                tracing::warn!(pid, "failed to kill the app: {}", err);
+            }
+        }
+    }
+
+    /// Tell the app the execution environment is shutting down, like a
+    /// Kubernetes `preStop` hook, and give it `pre_stop_timeout` to answer.
+    async fn pre_stop(&self) {
+        let Some(uri) = self.pre_stop_url.as_ref().and_then(|url| url.as_str().parse().ok()) else {
+            return;
+        };
+        let pre_stop = async {
+            let response = self.client.current().get(uri).await?;
+            response.into_body().collect().await?;
+            Ok::<_, Error>(())
+        };
+        match timeout(self.pre_stop_timeout, pre_stop).await {
+            Ok(Ok(())) => tracing::debug!("pre-stop request completed"),
+            Ok(Err(err)) => tracing::warn!("pre-stop request failed: {}", err),
+            Err(_) => tracing::warn!("pre-stop request timed out"),
+        }
+    }
+
+    /// Send the heartbeat request to the app, before the environment is frozen.
+    async fn send_heartbeat(&self, heartbeat: &Heartbeat) {
+        let Ok(uri) = heartbeat.url().as_str().parse() else {
+            return;
+        };
+        let request = async {
+            let response = self.client.current().get(uri).await?;
+            let status = response.status();
+            response.into_body().collect().await?;
+            Ok::<_, Error>(status)
+        };
+        match timeout(heartbeat::HEARTBEAT_TIMEOUT, request).await {
+            Ok(Ok(status)) => tracing::debug!(%status, "heartbeat request completed"),
+            Ok(Err(err)) => tracing::warn!("heartbeat request failed: {}", err),
+            Err(_) => tracing::warn!("heartbeat request timed out"),
+        }
+    }
+
+    /// Send a readiness request over a pooled connection, and replace the
+    /// pool when it fails. Any response shows the connection is usable.
+    async fn probe_connection_pool(&self) -> bool {
+        let Ok(uri) = self.healthcheck_url.as_str().parse() else {
+            return false;
+        };
+        let probe = async {
+            let response = self.client.current().get(uri).await?;
+            response.into_body().collect().await?;
+            Ok::<_, Error>(())
+        };
+        match timeout(keepalive::PROBE_TIMEOUT, probe).await {
+            Ok(Ok(())) => true,
+            Ok(Err(err)) => {
+                tracing::warn!("keep-alive probe failed, resetting the connection pool: {}", err);
+                self.client.reset();
+                false
+            }
+            Err(_) => {
+                tracing::warn!("keep-alive probe timed out, resetting the connection pool");
+                self.client.reset();
+                false
+            }
+        }
+    }
+
+    /// The base path to remove from the path of a request. With `auto`, it
+    /// is detected from the API Gateway request context.
+    fn base_path(&self, event: &Request) -> Option<String> {
+        match self.base_path.as_deref() {
+            Some("auto") => event
+                .request_context_ref()
+                .and_then(|context| normalize::base_path(event.raw_http_path(), context, &event.path_parameters())),
+            base_path => base_path.map(str::to_string),
+        }
+    }
+
+    /// Whether maintenance mode is switched on. A flag that cannot be read
+    /// counts as off, so a broken flag source does not take the app down.
+    async fn in_maintenance(&self, maintenance: &Maintenance) -> bool {
+        let url = match maintenance.switch() {
+            Switch::On => return true,
+            Switch::File(file) => return tokio::fs::try_exists(file).await.unwrap_or(false),
+            Switch::Url(url) => url,
+        };
+        if let Some(enabled) = maintenance.cached() {
+            return enabled;
+        }
+        let Ok(uri) = url.as_str().parse() else {
+            return false;
+        };
+        let read = async {
+            let response = self.client.current().get(uri).await?;
+            let document = response.into_body().collect().await?.to_bytes();
+            Ok::<_, Error>(maintenance::parse_flag(&document))
+        };
+        let enabled = match timeout(maintenance::READ_TIMEOUT, read).await {
+            Ok(Ok(enabled)) => enabled,
+            Ok(Err(err)) => {
+                tracing::warn!("failed to read the maintenance flag: {}", err);
+                false
+            }
+            Err(_) => {
+                tracing::warn!("timed out reading the maintenance flag");
+                false
+            }
+        };
+        maintenance.cache(enabled);
+        enabled
     }
 
     /// Check if the web server has been initialized.
     /// If `Adapter.async_init` is true, cancel this check before
     /// Lambda's init 10s timeout, and let the server boot in the background.
     pub async fn check_init_health(&mut self) {
-        let ready_at_init = if self.async_init {
+        // an app whose variables take values from a request context starts with the first invoke
+        let deferred = self
+            .supervisor
+            .as_deref()
+            .is_some_and(|supervisor| supervisor.needs_request());
+        if let Some(supervisor) = self.supervisor.as_ref().filter(|_| !deferred) {
+            supervisor.start(self.ready_at_init.clone());
+        }
+        let ready_at_init = if deferred {
+            false
+        } else if self.async_init {
             timeout(Duration::from_secs_f32(9.8), self.check_readiness())
                 .await
                 .unwrap_or_default()
         } else {
             self.check_readiness().await
         };
-        self.ready_at_init.store(ready_at_init, Ordering::SeqCst);
+        if ready_at_init {
+            self.negotiate_http2().await;
+        }
+        self.ready_at_init.store(ready_at_init, Ordering::Release);
+
+        // the runtime must not ask for events before the extension registered
+        if self.extension_deferred.swap(false, Ordering::AcqRel) {
+            let _ = self.spawn_extension().await;
+        }
+    }
+
+    /// Switch the client to HTTP/2 when the app accepts h2c, once it is ready.
+    async fn negotiate_http2(&self) {
+        if !self.http2_auto {
+            return;
+        }
+        let mut url = self.domain.clone();
+        url.set_path(self.healthcheck_url.path());
+        if connection::supports_h2c(&url).await {
+            tracing::info!("app accepts h2c, using HTTP/2 for all requests");
+            self.client.use_http2_only();
+        } else {
+            tracing::info!("app does not accept h2c, using HTTP/1.1");
+        }
     }
 
     async fn check_readiness(&self) -> bool {
-        let url = self.healthcheck_url.clone();
-        let protocol = self.healthcheck_protocol;
-        self.is_web_ready(&url, &protocol).await
+        let ready = self
+            .is_web_ready(&self.healthcheck_url, &self.healthcheck_protocol)
+            .await;
+        if let Some(metrics) = self.emf_metrics.as_deref().filter(|_| ready) {
+            metrics.ready();
+        }
+        ready
+    }
+
+    /// Wait for the web server to finish booting after an async init timed out.
+    /// Kept out of line so the warm path is a single load and branch.
+    #[cold]
+    async fn wait_for_async_init(&self) {
+        self.check_readiness().await;
+        self.negotiate_http2().await;
+        self.ready_at_init.store(true, Ordering::Release);
     }
 
     async fn is_web_ready(&self, url: &Url, protocol: &Protocol) -> bool {
         let mut checkpoint = Checkpoint::new();
+        let mut attempts = 0;
         Retry::spawn(FixedInterval::from_millis(10), || {
+            if let Some(prometheus) = self.prometheus.as_deref().filter(|_| attempts > 0) {
+                prometheus.readiness_retry();
+            }
+            attempts += 1;
             if checkpoint.lapsed() {
                 tracing::info!(url = %url.to_string(), "app is not ready after {}ms", checkpoint.next_ms());
                 checkpoint.increment();
@@ -499,8 +2434,27 @@ impl Adapter<HttpConnector, Body> {
     }
 
     async fn check_web_readiness(&self, url: &Url, protocol: &Protocol) -> Result<(), i8> {
+        // an app the adapter started is not ready while it is not running
+        if self
+            .supervisor
+            .as_deref()
+            .is_some_and(|supervisor| !supervisor.is_running())
+        {
+            return Err(-1);
+        }
+        self.check_protocol_readiness(url, protocol).await?;
+        for check in self.readiness_checks.iter() {
+            if !check.is_ready().await {
+                tracing::trace!("app is not ready by a custom readiness check");
+                return Err(-1);
+            }
+        }
+        Ok(())
+    }
+
+    async fn check_protocol_readiness(&self, url: &Url, protocol: &Protocol) -> Result<(), i8> {
         match protocol {
-            Protocol::Http => match self.client.get(url.to_string().parse().unwrap()).await {
+            Protocol::Http => match self.client.current().get(url.to_string().parse().unwrap()).await {
                 Ok(response)
                     if {
                         self.healthcheck_min_unhealthy_status > response.status().as_u16()
@@ -515,127 +2469,1350 @@ impl Adapter<HttpConnector, Body> {
                     Err(-1)
                 }
             },
-            Protocol::Tcp => match TcpStream::connect(format!("{}:{}", url.host().unwrap(), url.port().unwrap())).await
-            {
-                Ok(_) => Ok(()),
-                Err(_) => Err(-1),
-            },
+            Protocol::Tcp => {
+                let connected = match self.upstream_unix_socket.as_deref() {
+                    Some(path) => UnixStream::connect(path).await.map(drop),
+                    None => TcpStream::connect(format!(
+                        "{}:{}",
+                        url.host().unwrap(),
+                        url.port_or_known_default().unwrap()
+                    ))
+                    .await
+                    .map(drop),
+                };
+                connected.map_err(|_| -1)
+            }
+        }
+    }
+
+    /// Add a plugin that sees every request and response. Plugins must be
+    /// registered before the adapter runs.
+    pub fn register_plugin(&mut self, plugin: impl LwaPlugin + 'static) {
+        Arc::make_mut(&mut self.plugins).push(Arc::new(plugin));
+    }
+
+    /// Add a check the app must also pass to be ready, besides the HTTP or
+    /// TCP readiness check. Checks must be registered before
+    /// [`Adapter::check_init_health`] is called.
+    pub fn register_readiness_check(&mut self, check: impl ReadinessCheck + 'static) {
+        Arc::make_mut(&mut self.readiness_checks).push(Arc::new(check));
+    }
+
+    /// Run the adapter to take events from Lambda.
+    pub async fn run(self) -> Result<(), Error> {
+        for plugin in self.plugins.iter() {
+            plugin.on_init()?;
+        }
+
+        let compression = self.compression;
+        let invoke_mode = self.invoke_mode;
+
+        if compression {
+            let predicate = ContentAware::new(self.compression_min_size);
+            let svc = ServiceBuilder::new()
+                .layer(CompressionLayer::new().compress_when(predicate))
+                .service(self);
+            match invoke_mode {
+                LambdaInvokeMode::Buffered => lambda_http::run(svc).await,
+                LambdaInvokeMode::ResponseStream => lambda_http::run_with_streaming_response(svc).await,
+            }
+        } else {
+            match invoke_mode {
+                LambdaInvokeMode::Buffered => lambda_http::run(self).await,
+                LambdaInvokeMode::ResponseStream => lambda_http::run_with_streaming_response(self).await,
+            }
+        }
+    }
+
+    /// Run a request through the plugins and the adapter.
+    async fn handle(&self, event: Request) -> Result<Response<ResponseBody>, Error> {
+        // API Gateway WebSocket APIs deliver connection events and messages as events
+        let Some(websocket) = event.request_context_ref().and_then(WebSocketEvent::of) else {
+            return self.handle_request(event).await;
+        };
+        let request_id = event.lambda_context().request_id;
+        let response = match self.websocket.mode() {
+            WebSocketMode::Http => {
+                let response = self
+                    .handle_request(self.websocket.http_request(event, &websocket))
+                    .await?;
+                self.websocket.reply(&websocket, response).await
+            }
+            WebSocketMode::WebSocket => self.websocket.relay(&websocket, event).await,
+        };
+        response.or_else(|err| self.adapter_error(StatusCode::BAD_GATEWAY, err, &request_id))
+    }
+
+    async fn handle_request(&self, mut event: Request) -> Result<Response<ResponseBody>, Error> {
+        if self.plugins.is_empty() {
+            return self.fetch_response(event).await;
+        }
+
+        let mut answered = None;
+        for plugin in self.plugins.iter() {
+            if let Some(response) = plugin.on_request(&mut event) {
+                answered = Some(Ok(response));
+                break;
+            }
+        }
+        let mut response = match answered {
+            Some(response) => response,
+            None => self.fetch_response(event).await,
+        };
+        if let Err(err) = &response {
+            if let Some(recovered) = self.plugins.iter().rev().find_map(|plugin| plugin.on_error(err)) {
+                response = Ok(recovered);
+            }
+        }
+        if let Ok(response) = &mut response {
+            for plugin in self.plugins.iter().rev() {
+                plugin.on_response(response);
+            }
+        }
+        response
+    }
+
+    async fn fetch_response(&self, mut event: Request) -> Result<Response<ResponseBody>, Error> {
+        let lambda_context = event.lambda_context();
+        let budget = RetryBudget::new(self.retry_budget, lambda_context.deadline);
+
+        // answer health checks from the adapter's own view of the app
+        if self.adapter_health_path.as_deref() == Some(event.raw_http_path()) {
+            return self.health_response();
+        }
+
+        // answer keep-warm pings without an app route for them
+        if self.keep_warm && keep_warm::is_ping(&event) {
+            return self.keep_warm_response().await;
+        }
+
+        // Lambda cannot hold the connection open for a WebSocket, but it can stream server-sent events
+        let sse_fallback = is_websocket_upgrade(event.headers());
+        if sse_fallback {
+            let Some(fallback_path) = self.sse_fallback_path.as_deref() else {
+                tracing::debug!("rejecting websocket upgrade request");
+                return self.upgrade_response(&lambda_context.request_id);
+            };
+            tracing::debug!("sending websocket upgrade request to the SSE endpoint");
+            event = sse_fallback_request(event, fallback_path);
+        }
+
+        // keep requests away from the app while it is down for maintenance
+        if let Some(maintenance) = self.maintenance.as_deref() {
+            if !maintenance.allows(event.raw_http_path()) && self.in_maintenance(maintenance).await {
+                tracing::debug!("answering request in maintenance mode");
+                return match maintenance.page() {
+                    Some(page) => page,
+                    None => self.client_error(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "the service is down for maintenance",
+                        &lambda_context.request_id,
+                    ),
+                };
+            }
+        }
+
+        // Lambda retries an invocation that failed after the app handled it with the same request ID
+        if let Some(response) = self
+            .invocation_cache
+            .as_deref()
+            .and_then(|cache| cache.replay(&lambda_context.request_id))
+        {
+            tracing::debug!("replaying response for a retried invocation");
+            return Ok(response);
+        }
+
+        // answer duplicate deliveries with the response stored for their idempotency key
+        let idempotency_key = match self.idempotency_cache.as_deref() {
+            Some(cache) => {
+                let key = event
+                    .headers()
+                    .get(IDEMPOTENCY_KEY)
+                    .and_then(|value| value.to_str().ok())
+                    .map(|key| format!("{} {} {}", event.method(), event.raw_http_path(), key));
+                if let Some(response) = key.as_deref().and_then(|key| cache.replay(key)) {
+                    tracing::debug!("replaying response for a duplicate idempotency key");
+                    return Ok(response);
+                }
+                key
+            }
+            None => None,
+        };
+
+        let base_path = self.base_path(&event);
+
+        // serve static assets without waiting for the app
+        if let Some(static_files) = self.static_files.as_deref() {
+            let path = event.raw_http_path();
+            let path = match base_path.as_deref() {
+                Some(base_path) => path.trim_start_matches(base_path),
+                None => path,
+            };
+            if let Some(response) = static_files.serve(event.method(), path, event.headers()).await? {
+                return Ok(response);
+            }
+        }
+
+        // long-running requests are answered right away and processed by an async invocation
+        if let Some(async_requests) = self.async_requests.as_deref() {
+            let pass_through = matches!(event.request_context_ref(), Some(RequestContext::PassThrough));
+            if !pass_through && AsyncRequests::is_requested(event.headers()) {
+                let accepted = async_requests
+                    .accept(
+                        &lambda_context.request_id,
+                        event.method(),
+                        event.raw_http_path(),
+                        event.uri(),
+                        event.headers(),
+                        event.body(),
+                    )
+                    .await;
+                return match accepted {
+                    Ok(response) => Ok(response),
+                    Err(err) => self.adapter_error(StatusCode::BAD_GATEWAY, err, &lambda_context.request_id),
+                };
+            }
+        }
+
+        if let Some(supervisor) = self.supervisor.as_ref() {
+            let context = || {
+                event
+                    .request_context_ref()
+                    .and_then(|context| context::stable(context, event.method(), event.raw_http_path()))
+            };
+            supervisor.start_with_context(context, self.ready_at_init.clone());
+        }
+
+        // only async init, or an app started with the first invoke, can leave the app unready once invokes start
+        if !self.ready_at_init.load(Ordering::Acquire) {
+            match budget.remaining_time() {
+                Some(remaining) => {
+                    if timeout(remaining, self.wait_for_async_init()).await.is_err() {
+                        return self.adapter_error(
+                            StatusCode::GATEWAY_TIMEOUT,
+                            Error::from("app is not ready before the invocation deadline"),
+                            &lambda_context.request_id,
+                        );
+                    }
+                }
+                None => self.wait_for_async_init().await,
+            }
+        }
+
+        // answer right away while the app is known to be unhealthy
+        if let Some(gate) = self.health_gate.as_deref() {
+            let healthy = match gate.check() {
+                Health::Healthy => true,
+                Health::Unhealthy => false,
+                Health::Recheck => {
+                    let ready = self
+                        .check_web_readiness(&self.healthcheck_url, &self.healthcheck_protocol)
+                        .await
+                        .is_ok();
+                    match ready {
+                        true => gate.mark_healthy(),
+                        false => gate.recheck_failed(),
+                    }
+                    ready
+                }
+            };
+            if !healthy {
+                return self.fast_fail_response(gate.recheck_after(), &lambda_context.request_id);
+            }
+        }
+
+        let request_context = event.request_context();
+        let html_prefix = self
+            .html_rewrite_prefix
+            .as_deref()
+            .and_then(|setting| html::external_prefix(setting, base_path.as_deref(), &request_context));
+        let path = event.raw_http_path().to_string();
+        let mut path = path.as_str();
+        let (mut parts, mut body) = event.into_parts();
+        timing::mark(Stage::Decoded);
+
+        // the async invocation of an accepted request carries it in a pass-through event
+        let async_request = match self.async_requests {
+            Some(_) if matches!(request_context, RequestContext::PassThrough) => AsyncRequests::envelope(&body),
+            _ => None,
+        };
+
+        // other non-HTTP events go to the app's own runtime when it polls the local runtime API
+        if let Some(runtime_api) = self.runtime_api.as_deref() {
+            if async_request.is_none() && matches!(request_context, RequestContext::PassThrough) {
+                tracing::debug!("handing pass-through event to the app's runtime");
+                let result = runtime_api
+                    .invoke(&lambda_context, Bytes::copy_from_slice(&body))
+                    .await?;
+                return Ok(Response::builder()
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(full_body(result))?);
+            }
+        }
+        if let Some(async_request) = &async_request {
+            path = async_request.path.as_str();
+            parts.method = async_request.method.clone();
+            parts.uri = async_request.uri.clone();
+            parts.headers = async_request.headers.clone();
+            body = Body::from(async_request.body.clone());
+        }
+        let request_bytes = body.len();
+
+        // present the same request to the app whichever front door delivered the event
+        if self.normalize_requests && !matches!(request_context, RequestContext::PassThrough) {
+            path = normalize::path(path, &request_context);
+            normalize::request(&mut parts, &mut body, &request_context)?;
+        }
+
+        // compress streamed responses chunk by chunk instead of using the compression layer
+        let flush_compression = self.compression
+            && self.compression_flush
+            && self.invoke_mode == LambdaInvokeMode::ResponseStream
+            && compression::accepts_gzip(&parts.headers);
+
+        // strip away Base Path if environment variable REMOVE_BASE_PATH is set.
+        if let Some(base_path) = base_path.as_deref() {
+            path = path.trim_start_matches(base_path);
+        }
+
+        if matches!(request_context, RequestContext::PassThrough)
+            && parts.method == Method::POST
+            && async_request.is_none()
+        {
+            path = self.path_through_path.as_str();
+        }
+
+        let mut req_headers = parts.headers;
+
+        // requests over the Lambda payload limit upload their body to S3 first
+        if let Some(uploads) = self.s3_uploads.as_deref() {
+            if let Some(key) = req_headers.remove(s3::UPLOAD_KEY) {
+                let key = key.to_str().unwrap_or_default();
+                if !uploads.is_valid_key(key) {
+                    let detail = format!("invalid {}", s3::UPLOAD_KEY);
+                    return self.client_error(StatusCode::BAD_REQUEST, &detail, &lambda_context.request_id);
+                }
+                let bytes = match uploads.fetch(key).await {
+                    Ok(bytes) => bytes,
+                    Err(err) => return self.adapter_error(StatusCode::BAD_GATEWAY, err, &lambda_context.request_id),
+                };
+                req_headers.insert(http::header::CONTENT_LENGTH, HeaderValue::from(bytes.len()));
+                body = Body::Binary(bytes.to_vec());
+            } else if uploads.exceeds(&req_headers, body.len()) {
+                return uploads.reject(&lambda_context.request_id);
+            }
+        }
+
+        // reject requests the API specification does not declare before they reach the app
+        if let Some(spec) = self.openapi.as_deref() {
+            if !matches!(request_context, RequestContext::PassThrough) || async_request.is_some() {
+                // answer OPTIONS for apps that do not implement it, leaving CORS preflights to the app
+                let preflight = req_headers.contains_key(http::header::ACCESS_CONTROL_REQUEST_METHOD);
+                if self.synthesize_methods && parts.method == Method::OPTIONS && !preflight {
+                    if let Some(allow) = spec.synthesized_allow(path) {
+                        return Ok(Response::builder()
+                            .status(StatusCode::NO_CONTENT)
+                            .header(http::header::ALLOW, allow)
+                            .body(full_body(Bytes::new()))?);
+                    }
+                }
+                if let Err(rejection) = spec.validate(&parts.method, path, parts.uri.query(), &req_headers, body.len())
+                {
+                    tracing::debug!(status = %rejection.status, detail = %rejection.detail, "rejecting request");
+                    let mut response =
+                        self.client_error(rejection.status, &rejection.detail, &lambda_context.request_id)?;
+                    if let Some(allow) = rejection.allow {
+                        response
+                            .headers_mut()
+                            .insert(http::header::ALLOW, HeaderValue::from_str(&allow)?);
+                    }
+                    return Ok(response);
+                }
+            }
+        }
+
+        // expand GraphQL requests that only carry the hash of a persisted query
+        if let Some(queries) = self
+            .persisted_queries
+            .as_deref()
+            .filter(|queries| queries.path() == path)
+        {
+            if parts.method == Method::GET {
+                match queries.expand_query(parts.uri.query().unwrap_or_default()) {
+                    Ok(Some(query)) => parts.uri = format!("{}?{}", parts.uri.path(), query).parse()?,
+                    Ok(None) => {}
+                    Err(err) => return err.response(),
+                }
+            } else {
+                match queries.expand_body(&body) {
+                    Ok(Some(expanded)) => {
+                        req_headers.insert(http::header::CONTENT_LENGTH, HeaderValue::from(expanded.len()));
+                        body = Body::from(expanded);
+                    }
+                    Ok(None) => {}
+                    Err(err) => return err.response(),
+                }
+            }
+        }
+
+        if let Some(rules) = self.accept_rules.as_deref() {
+            rules.apply(path, &mut req_headers);
+        }
+
+        // redact, rename or unwrap JSON request bodies before the app sees them
+        let transform_path = path;
+        if let Some(transforms) = self.body_transforms.as_deref() {
+            if let Some(transformed) = transforms.apply(Direction::Request, path, &req_headers, &body) {
+                req_headers.insert(http::header::CONTENT_LENGTH, HeaderValue::from(transformed.len()));
+                body = Body::from(transformed);
+            }
+        }
+
+        // include request context in http header "x-amzn-request-context"
+        req_headers.insert(
+            HeaderName::from_static("x-amzn-request-context"),
+            HeaderValue::from_bytes(serde_json::to_string(&request_context)?.as_bytes())?,
+        );
+
+        // and a stable subset of it, which keeps its names across event sources and lambda_http releases
+        if let Some(stable_context) = context::stable(&request_context, &parts.method, parts.uri.path()) {
+            req_headers.insert(
+                HeaderName::from_static("x-amzn-request-context-v2"),
+                HeaderValue::from_bytes(stable_context.to_string().as_bytes())?,
+            );
+        }
+
+        // include lambda context in http header "x-amzn-lambda-context"
+        req_headers.insert(
+            HeaderName::from_static("x-amzn-lambda-context"),
+            HeaderValue::from_bytes(serde_json::to_string(&lambda_context)?.as_bytes())?,
+        );
+
+        // tell the app how much of the invocation is left, to bound its own downstream calls
+        if let Some(name) = &self.deadline_header {
+            match budget.remaining_time() {
+                Some(remaining) => {
+                    req_headers.insert(name.clone(), HeaderValue::from(remaining.as_millis() as u64));
+                }
+                None => {
+                    req_headers.remove(name);
+                }
+            }
+        }
+
+        // gRPC-Web clients talk to the app as a regular gRPC client
+        if self.grpc_streaming {
+            let content_type = req_headers
+                .get(http::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(grpc::grpc_request_content_type);
+            if let Some(content_type) = content_type {
+                req_headers.insert(http::header::CONTENT_TYPE, HeaderValue::from_str(&content_type)?);
+                req_headers.insert(http::header::TE, HeaderValue::from_static("trailers"));
+            }
+        }
+
+        // gRPC servers reject requests that do not announce trailer support
+        if self.grpc_passthrough
+            && req_headers
+                .get(http::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(grpc::is_grpc)
+        {
+            req_headers.insert(http::header::TE, HeaderValue::from_static("trailers"));
+        }
+
+        if let Some(authorization_source) = self.authorization_source.as_deref() {
+            if req_headers.contains_key(authorization_source) {
+                let original = req_headers.remove(authorization_source).unwrap();
+                req_headers.insert("authorization", original);
+            } else {
+                tracing::warn!("\"{}\" header not found in request headers", authorization_source);
+            }
+        }
+
+        // trade CPU for smaller copies of large bodies to an app that accepts encoded requests
+        if self.request_compression != RequestCompression::None
+            && body.len() >= self.request_compression_threshold
+            && compression::should_compress(&req_headers)
+        {
+            let (encoded, content_encoding) = compression::encode_request(self.request_compression, &body)?;
+            req_headers.insert(
+                http::header::CONTENT_ENCODING,
+                HeaderValue::from_static(content_encoding),
+            );
+            req_headers.insert(http::header::CONTENT_LENGTH, HeaderValue::from(encoded.len()));
+            body = Body::Binary(encoded);
+        }
+
+        // dispatch SOAP calls to the path of their operation
+        let soap_path;
+        if let Some(route) = self
+            .soap_routes
+            .as_deref()
+            .and_then(|routes| routes.route(&req_headers))
+        {
+            soap_path = route.to_string();
+            path = soap_path.as_str();
+        }
+
+        // route custom domains to their tenant's port and base path
+        let host_route = self
+            .host_routes
+            .as_deref()
+            .and_then(|routes| routes.route(&req_headers));
+        let routed_path;
+        if let Some(base_path) = host_route.and_then(|route| route.base_path.as_deref()) {
+            routed_path = format!("{}{}", base_path, path);
+            path = routed_path.as_str();
+        }
+
+        // forward requests under the context path the app is mounted at
+        let prefixed_path;
+        if let Some(add_base_path) = self.add_base_path.as_deref() {
+            prefixed_path = format!("{}{}", add_base_path, path);
+            path = prefixed_path.as_str();
+        }
+
+        // send reads and writes to the process that serves them
+        let method_route = self
+            .method_routes
+            .as_deref()
+            .and_then(|routes| routes.route(&parts.method));
+
+        let mut affinity_cookie = None;
+        let mut app_url = match (
+            host_route.and_then(|route| route.upstream.as_ref()).or(method_route),
+            self.traffic_split.as_deref(),
+        ) {
+            (Some(upstream), _) => upstream.clone(),
+            (None, Some(split)) => {
+                let (url, cookie) = split.route(&req_headers);
+                affinity_cookie = cookie;
+                url.clone()
+            }
+            (None, None) => self.domain.clone(),
+        };
+        app_url.set_path(path);
+
+        // requests for companion extensions go to their port instead of the app
+        let sidecars = self.sidecar_routes.as_deref();
+        let mut to_sidecar = false;
+        if let Some((sidecar, sidecar_path)) = sidecars.and_then(|sidecars| sidecars.route(transform_path)) {
+            let Some(sidecar_url) = sidecars.and_then(|sidecars| sidecars.resolve(sidecar)) else {
+                return self.client_error(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    &format!("sidecar {sidecar} is not registered"),
+                    &lambda_context.request_id,
+                );
+            };
+            app_url = sidecar_url;
+            app_url.set_path(&sidecar_path);
+            affinity_cookie = None;
+            to_sidecar = true;
+        }
+        app_url.set_query(parts.uri.query());
+
+        // the span of the adapter becomes the parent of the spans of the app
+        #[cfg(feature = "otel")]
+        let span = self
+            .tracer
+            .as_deref()
+            .map(|tracer| tracer.start(&mut req_headers, lambda_context.xray_trace_id.as_deref()));
+        // so does the X-Ray subsegment of the call
+        let subsegment = self
+            .xray
+            .as_deref()
+            .and_then(|xray| xray.start(&mut req_headers, lambda_context.xray_trace_id.as_deref()));
+
+        tracing::debug!(app_url = %app_url, req_headers = ?req_headers, "sending request to app server");
+
+        let method = parts.method;
+        // apps that only implement GET get HEAD requests as GET, and the body is dropped
+        let head_as_get = self.synthesize_methods && method == Method::HEAD;
+        let range_request = match self.range_slicing {
+            true if method == Method::GET => RangeRequest::from_headers(&req_headers),
+            _ => None,
+        };
+        let polling_etags = self
+            .polling_etags
+            .as_deref()
+            .filter(|etags| method == Method::GET && etags.matches(transform_path));
+        let if_none_match = req_headers.get(http::header::IF_NONE_MATCH).cloned();
+        let mut builder = hyper::Request::builder()
+            .method(if head_as_get { Method::GET } else { method.clone() })
+            .uri(app_url.to_string());
+        if let Some(headers) = builder.headers_mut() {
+            headers.extend(req_headers);
+        }
+
+        // The Lambda Runtime API always delivers the complete request payload,
+        // even for streaming Function URL invokes, so the body is forwarded as is.
+        // Avoid unnecessary body.to_vec() calls which buffer the entire body
+        // This is particularly important for streaming/reactive applications
+        let request = match body {
+            // Use the body directly when it's already in a format that doesn't require copying
+            Body::Empty => builder.body(Body::Empty)?,
+            Body::Text(text) => builder.body(Body::Text(text))?,
+            Body::Binary(bin) => builder.body(Body::Binary(bin))?,
+        };
+
+        if let Some(mirror_target) = &self.mirror_target {
+            if fastrand::f64() * 100.0 < self.mirror_percent {
+                self.mirror_request(&request, mirror_target, &app_url);
+            }
+        }
+
+        if let Some(chaos) = self.chaos {
+            if !chaos.latency.is_zero() {
+                tokio::time::sleep(chaos.latency).await;
+            }
+            if fastrand::f64() < chaos.error_rate {
+                tracing::debug!("chaos mode: injecting an error");
+                return self.adapter_error(
+                    StatusCode::BAD_GATEWAY,
+                    Error::from("chaos mode: injected error"),
+                    &lambda_context.request_id,
+                );
+            }
+        }
+
+        // keep a copy of page navigations to fetch the SPA fallback document
+        let spa_fallback = match self.spa_fallback.as_deref() {
+            Some(fallback) if method == Method::GET && path != fallback && accepts_html(request.headers()) => {
+                Some((fallback, clone_request(&request)))
+            }
+            _ => None,
+        };
+
+        timing::mark(Stage::Built);
+        let sent_at = Instant::now();
+        let sending = async {
+            match self.hedge_after {
+                Some(hedge_after) if matches!(*request.method(), Method::GET | Method::HEAD) => {
+                    self.send_hedged_request(request, hedge_after, &budget).await
+                }
+                _ => self.send_retried_request(request, &budget).await,
+            }
+        };
+        // give up on a hung app before the function times out
+        let request_timeout = self
+            .request_timeout
+            .as_deref()
+            .and_then(|request_timeout| request_timeout.limit(budget.remaining_time()));
+        let mut timed_out = false;
+        let app_response = match request_timeout {
+            Some(limit) => match timeout(limit, sending).await {
+                Ok(response) => response,
+                Err(_) => {
+                    timed_out = true;
+                    Err(Error::from(format!("the app did not respond within {limit:?}")))
+                }
+            },
+            None => sending.await,
+        };
+        let upstream_time = sent_at.elapsed();
+        timing::mark(Stage::Responded);
+        // sidecar responses say nothing about the health of the app
+        if let Some(gate) = self.health_gate.as_deref().filter(|_| !to_sidecar) {
+            let status = app_response.as_ref().ok().map(|response| response.status());
+            gate.record(status, upstream_time);
+        }
+        // printed once the response body has been sent, or right away without a response
+        let invoke_metrics = self.emf_metrics.as_deref().filter(|_| !to_sidecar).map(|metrics| {
+            let status = app_response.as_ref().ok().map(|response| response.status());
+            metrics.measure(upstream_time, &method, status, &lambda_context.request_id)
+        });
+        if let Some(prometheus) = self.prometheus.as_deref().filter(|_| !to_sidecar) {
+            let status = app_response.as_ref().ok().map(|response| response.status());
+            prometheus.observe_upstream(upstream_time, status);
+        }
+        if let Some(telemetry) = self.telemetry.as_deref().filter(|_| !to_sidecar) {
+            let status = app_response.as_ref().ok().map(|response| response.status());
+            telemetry.record(&lambda_context.request_id, upstream_time, status);
+        }
+        if let (Some(xray), Some(subsegment)) = (self.xray.as_deref(), subsegment) {
+            let status = app_response.as_ref().ok().map(|response| response.status());
+            xray.send(subsegment, &method, &app_url, status);
+        }
+        #[cfg(feature = "otel")]
+        if let (Some(tracer), Some(span)) = (self.tracer.as_deref(), span) {
+            let status = app_response.as_ref().ok().map(|response| response.status());
+            tracer.finish(span, &method, transform_path, status, &lambda_context.request_id);
+        }
+        let mut app_response = match app_response {
+            Ok(response) => response,
+            Err(err) if timed_out => return self.timeout_response(err, &lambda_context.request_id),
+            Err(err) => return self.adapter_error(StatusCode::BAD_GATEWAY, err, &lambda_context.request_id),
+        };
+        if self.access_log.is_some() {
+            app_response.extensions_mut().insert(UpstreamLatency(upstream_time));
+        }
+        if app_response.status() == StatusCode::SWITCHING_PROTOCOLS {
+            tracing::warn!("the app tried to upgrade the connection, which Lambda does not support");
+            return self.upgrade_response(&lambda_context.request_id);
+        }
+        self.invoke_mode_check.check(&request_context, app_response.headers());
+
+        // client-side routes are unknown to the app, answer them with the SPA document
+        if let Some((fallback, mut fallback_request)) = spa_fallback {
+            if app_response.status() == StatusCode::NOT_FOUND {
+                tracing::debug!(fallback, "app has no page for the path, serving the SPA fallback");
+                if let Some(static_files) = self.static_files.as_deref() {
+                    if let Some(response) = static_files
+                        .serve(&Method::GET, fallback, fallback_request.headers())
+                        .await?
+                    {
+                        return Ok(response);
+                    }
+                }
+
+                let base_path = host_route
+                    .and_then(|route| route.base_path.as_deref())
+                    .unwrap_or_default();
+                let mut fallback_url = app_url.clone();
+                fallback_url.set_path(&format!(
+                    "{}{}{}",
+                    self.add_base_path.as_deref().unwrap_or_default(),
+                    base_path,
+                    fallback
+                ));
+                fallback_url.set_query(None);
+                *fallback_request.uri_mut() = fallback_url.as_str().parse()?;
+                app_response = match self.send_request(fallback_request).await {
+                    Ok(response) => response,
+                    Err(err) => return self.adapter_error(StatusCode::BAD_GATEWAY, err, &lambda_context.request_id),
+                };
+            }
+        }
+
+        // publish a summary of selected responses to EventBridge
+        let status = app_response.status().as_u16();
+        if let Some(events) = self.response_events.as_deref() {
+            if events.selects(&method, path, status) {
+                let custom = events.take_detail(app_response.headers_mut());
+                let published = events
+                    .publish(
+                        &method,
+                        path,
+                        &request_context,
+                        status,
+                        &lambda_context.request_id,
+                        custom,
+                    )
+                    .await;
+                if let Err(err) = published {
+                    tracing::warn!("failed to publish response event: {}", err);
+                }
+            }
+        }
+
+        // Check if status code should trigger an error
+        let is_error_status = self
+            .error_status_codes
+            .as_ref()
+            .is_some_and(|error_codes| error_codes.contains(&status))
+            || self
+                .error_status_rules
+                .iter()
+                .any(|rule| rule.matches(&method, path, status));
+        if is_error_status {
+            return Err(Error::from(format!(
+                "Request failed with configured error status code: {}",
+                status
+            )));
+        }
+
+        // remove "transfer-encoding" from the response to support "sam local start-api"
+        if self.strip_transfer_encoding {
+            app_response.headers_mut().remove("transfer-encoding");
+        }
+
+        if let Some(cookie) = affinity_cookie {
+            app_response.headers_mut().append(http::header::SET_COOKIE, cookie);
+        }
+
+        // show callers the payload sizes and the time the app took
+        if self.accounting_headers {
+            let response_bytes = app_response.body().size_hint().exact().or_else(|| {
+                app_response
+                    .headers()
+                    .get(http::header::CONTENT_LENGTH)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse().ok())
+            });
+            let headers = app_response.headers_mut();
+            headers.insert(HeaderName::from_static("x-lwa-request-bytes"), request_bytes.into());
+            if let Some(response_bytes) = response_bytes {
+                headers.insert(HeaderName::from_static("x-lwa-response-bytes"), response_bytes.into());
+            }
+            headers.insert(
+                HeaderName::from_static("x-lwa-upstream-ms"),
+                (upstream_time.as_millis() as u64).into(),
+            );
+        }
+
+        tracing::debug!(status = %app_response.status(), body_size = ?app_response.body().size_hint().lower(),
+            app_headers = ?app_response.headers().clone(), "responding to lambda event");
+
+        let mut app_response = app_response.map(|body| body.map_err(Error::from).boxed_unsync());
+        if sse_fallback {
+            app_response.headers_mut().insert(
+                HeaderName::from_static("x-lwa-transport"),
+                HeaderValue::from_static("sse"),
+            );
+        }
+        if head_as_get {
+            app_response = app_response.map(|_| full_body(Bytes::new()));
+        }
+        if let Some(invoke_metrics) = invoke_metrics {
+            app_response = invoke_metrics.track(app_response);
+        }
+
+        // server-sent events skip every layer that holds back the stream
+        let sse_flush = self.sse_flush
+            && self.invoke_mode == LambdaInvokeMode::ResponseStream
+            && streaming::is_event_stream(app_response.headers());
+
+        // Lambda takes buffered bodies without a content type for text, which mangles binary ones
+        if self.sniff_content_type
+            && self.invoke_mode == LambdaInvokeMode::Buffered
+            && sniff::is_missing(app_response.headers())
+        {
+            app_response = sniff::apply(app_response).await?;
+        }
+
+        // fix absolute links in pages of apps unaware of the stage prefix
+        if let Some(prefix) = html_prefix.as_deref() {
+            if html::is_html(app_response.headers()) {
+                let (mut parts, body) = app_response.into_parts();
+                let bytes = body.collect().await?.to_bytes();
+                let html = html::rewrite_links(&String::from_utf8_lossy(&bytes), prefix);
+                parts.headers.insert(http::header::CONTENT_LENGTH, html.len().into());
+                app_response = Response::from_parts(parts, full_body(html));
+            }
+        }
+
+        // redact, rename or unwrap JSON response bodies before the client sees them
+        if let Some(transforms) = self.body_transforms.as_deref() {
+            if transforms.matches(Direction::Response, transform_path, app_response.headers()) {
+                let (mut parts, body) = app_response.into_parts();
+                let bytes = body.collect().await?.to_bytes();
+                let bytes = match transforms.apply(Direction::Response, transform_path, &parts.headers, &bytes) {
+                    Some(transformed) => Bytes::from(transformed),
+                    None => bytes,
+                };
+                parts.headers.insert(http::header::CONTENT_LENGTH, bytes.len().into());
+                app_response = Response::from_parts(parts, full_body(bytes));
+            }
+        }
+
+        // spare polling clients a response they already have
+        if let Some(etags) = polling_etags.filter(|_| async_request.is_none()) {
+            app_response = etags.apply(app_response, if_none_match.as_ref()).await?;
+        }
+
+        // answer range requests with a slice of the full response, for apps that ignore them
+        if let Some(range_request) = range_request.filter(|_| async_request.is_none() && !sse_flush) {
+            app_response = range_request.slice(app_response).await?;
+        }
+
+        // nobody waits for the async invocation, the response goes to the callback target
+        if let (Some(async_requests), Some(async_request)) = (self.async_requests.as_deref(), &async_request) {
+            let (parts, body) = app_response.into_parts();
+            let bytes = body.collect().await?.to_bytes();
+            async_requests
+                .deliver(&async_request.tracking_id, parts.status, &parts.headers, &bytes)
+                .await?;
+            return Ok(Response::from_parts(parts, full_body(bytes)));
+        }
+
+        // move responses too large for Lambda to S3 and redirect to them
+        if let Some(offload) = self.s3_offload.as_deref() {
+            let (parts, body) = app_response.into_parts();
+            let bytes = body.collect().await?.to_bytes();
+            app_response = if bytes.len() as u64 > offload.threshold() {
+                match offload
+                    .offload(&method, &lambda_context.request_id, &parts.headers, bytes)
+                    .await
+                {
+                    Ok(response) => response,
+                    Err(err) => return self.adapter_error(StatusCode::BAD_GATEWAY, err, &lambda_context.request_id),
+                }
+            } else {
+                Response::from_parts(parts, full_body(bytes))
+            };
+        }
+
+        // answer responses Lambda would fail for their size with what the policy says
+        if let Some(limit) = self.response_limit.as_deref() {
+            let buffered = self.invoke_mode == LambdaInvokeMode::Buffered;
+            app_response = match limit.apply(app_response, buffered).await {
+                Ok(response) => response,
+                Err(err) if err.is::<ResponseTooLarge>() => {
+                    tracing::error!(error = %err, "responding with {}", StatusCode::BAD_GATEWAY);
+                    return self.client_error(StatusCode::BAD_GATEWAY, &err.to_string(), &lambda_context.request_id);
+                }
+                Err(err) => return Err(err),
+            };
+        }
+
+        // store the complete response to replay it for duplicates
+        let idempotency = self.idempotency_cache.as_deref().zip(idempotency_key);
+        if !sse_flush && (idempotency.is_some() || self.invocation_cache.is_some()) {
+            let (parts, body) = app_response.into_parts();
+            let bytes = body.collect().await?.to_bytes();
+            if let Some((cache, key)) = idempotency {
+                cache.store(key, parts.status, &parts.headers, bytes.clone());
+            }
+            if let Some(cache) = self.invocation_cache.as_deref() {
+                cache.store(
+                    lambda_context.request_id.clone(),
+                    parts.status,
+                    &parts.headers,
+                    bytes.clone(),
+                );
+            }
+            return Ok(Response::from_parts(parts, full_body(bytes)));
+        }
+
+        if let Some(threshold) = self.streaming_buffer_threshold {
+            if self.invoke_mode == LambdaInvokeMode::ResponseStream && should_buffer(app_response.headers(), threshold)
+            {
+                let (parts, body) = app_response.into_parts();
+                let bytes = body.collect().await?.to_bytes();
+                return Ok(Response::from_parts(parts, full_body(bytes)));
+            }
+        }
+
+        // stream newline-delimited JSON a record at a time, or as server-sent events
+        if self.invoke_mode == LambdaInvokeMode::ResponseStream && self.ndjson_streaming != NdjsonStreaming::None {
+            let is_ndjson = app_response
+                .headers()
+                .get(http::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(streaming::is_ndjson);
+            if is_ndjson {
+                let sse = self.ndjson_streaming == NdjsonStreaming::Sse;
+                if sse {
+                    let headers = app_response.headers_mut();
+                    headers.insert(
+                        http::header::CONTENT_TYPE,
+                        HeaderValue::from_static("text/event-stream"),
+                    );
+                    headers.remove(http::header::CONTENT_LENGTH);
+                }
+                app_response = app_response.map(|body| NdjsonBody::new(body, sse).boxed_unsync());
+            }
+        }
+
+        // send server-sent events whole, as soon as each one is complete
+        if sse_flush {
+            app_response = app_response.map(|body| SseBody::new(body).boxed_unsync());
+        }
+
+        // streams are compressed as they come, whatever their total size
+        if flush_compression && !sse_flush && compression::compress_response(app_response.headers(), None, 0) {
+            let headers = app_response.headers_mut();
+            headers.remove(http::header::CONTENT_LENGTH);
+            headers.insert(http::header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+            headers.append(http::header::VARY, HeaderValue::from_static("accept-encoding"));
+            app_response = app_response.map(|body| FlushingGzipBody::new(body).boxed_unsync());
+        }
+
+        // carry gRPC trailers, which Lambda drops, as a final gRPC-Web frame
+        if self.grpc_streaming {
+            let content_type = app_response
+                .headers()
+                .get(http::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .filter(|value| grpc::is_grpc(value))
+                .map(grpc::grpc_web_content_type);
+            if let Some(content_type) = content_type {
+                let headers = app_response.headers_mut();
+                headers.insert(http::header::CONTENT_TYPE, HeaderValue::from_str(&content_type)?);
+                headers.remove(http::header::CONTENT_LENGTH);
+                app_response = app_response.map(|body| GrpcWebBody::new(body).boxed_unsync());
+            }
+        }
+
+        // buffered responses lose their trailers, keep the gRPC status as headers
+        if self.grpc_passthrough
+            && self.invoke_mode == LambdaInvokeMode::Buffered
+            && app_response
+                .headers()
+                .get(http::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(grpc::is_grpc)
+        {
+            app_response = grpc::trailers_to_headers(app_response).await?;
+        }
+
+        // give the app a moment to finish the response before the prelude is sent
+        if let Some(wait) = self.stream_prelude_wait {
+            if self.invoke_mode == LambdaInvokeMode::ResponseStream && !sse_flush {
+                app_response = streaming::finalize_prelude(app_response, wait).await?;
+            }
+        }
+
+        // warn about response streaming size and bandwidth limits
+        if self.invoke_mode == LambdaInvokeMode::ResponseStream {
+            let pacing = self.stream_pacing;
+            app_response = app_response.map(|body| MeteredBody::new(body, pacing).boxed_unsync());
         }
+
+        Ok(app_response)
     }
 
-    /// Run the adapter to take events from Lambda.
-    pub async fn run(self) -> Result<(), Error> {
-        let compression = self.compression;
-        let invoke_mode = self.invoke_mode;
+    /// Send a copy of the request to the mirror target without waiting for it.
+    /// The response is discarded.
+    fn mirror_request(&self, request: &hyper::Request<Body>, mirror_target: &Url, app_url: &Url) {
+        let mut mirror_url = mirror_target.clone();
+        mirror_url.set_path(&format!(
+            "{}{}",
+            mirror_target.path().trim_end_matches('/'),
+            app_url.path()
+        ));
+        mirror_url.set_query(app_url.query());
 
-        if compression {
-            let svc = ServiceBuilder::new().layer(CompressionLayer::new()).service(self);
-            match invoke_mode {
-                LambdaInvokeMode::Buffered => lambda_http::run(svc).await,
-                LambdaInvokeMode::ResponseStream => lambda_http::run_with_streaming_response(svc).await,
+        let mut mirror = clone_request(request);
+        let Ok(uri) = mirror_url.as_str().parse() else {
+            return;
+        };
+        *mirror.uri_mut() = uri;
+        mirror.headers_mut().remove(http::header::HOST);
+
+        let client = self.client.current();
+        tokio::spawn(async move {
+            match client.request(mirror).await {
+                Ok(response) => tracing::debug!(status = %response.status(), "mirrored request"),
+                Err(err) => tracing::debug!(error = %err, "failed to mirror request"),
             }
-        } else {
-            match invoke_mode {
-                LambdaInvokeMode::Buffered => lambda_http::run(self).await,
-                LambdaInvokeMode::ResponseStream => lambda_http::run_with_streaming_response(self).await,
+        });
+    }
+
+    /// Surface an error raised by the adapter itself. With problem details
+    /// enabled it is answered as a `problem+json` response, otherwise the
+    /// invocation fails.
+    fn adapter_error(&self, status: StatusCode, err: Error, request_id: &str) -> Result<Response<ResponseBody>, Error> {
+        match &self.problem_details {
+            Some(problem_details) => {
+                tracing::error!(error = %err, "responding with {}", status);
+                let mut response = problem_details.response(status, &err.to_string(), request_id)?;
+                response
+                    .extensions_mut()
+                    .insert(AdapterFailure(error_chain(err.as_ref())));
+                Ok(response)
             }
+            None => Err(err),
         }
     }
 
-    async fn fetch_response(&self, event: Request) -> Result<Response<Incoming>, Error> {
-        if self.async_init && !self.ready_at_init.load(Ordering::SeqCst) {
-            self.is_web_ready(&self.healthcheck_url, &self.healthcheck_protocol)
-                .await;
-            self.ready_at_init.store(true, Ordering::SeqCst);
+    /// Answer a request the app did not respond to within the request timeout
+    /// with `504 Gateway Timeout`, so the function does not time out.
+    fn timeout_response(&self, err: Error, request_id: &str) -> Result<Response<ResponseBody>, Error> {
+        tracing::warn!(error = %err, "responding with {}", StatusCode::GATEWAY_TIMEOUT);
+        let mut response = match self.request_timeout.as_deref().and_then(RequestTimeout::page) {
+            Some(page) => page?,
+            None => self.client_error(StatusCode::GATEWAY_TIMEOUT, &err.to_string(), request_id)?,
+        };
+        response
+            .extensions_mut()
+            .insert(AdapterFailure(error_chain(err.as_ref())));
+        Ok(response)
+    }
+
+    /// Answer a request the client got wrong, as `problem+json` when problem
+    /// details are enabled.
+    fn client_error(
+        &self,
+        status: StatusCode,
+        detail: &str,
+        request_id: &str,
+    ) -> Result<Response<ResponseBody>, Error> {
+        match &self.problem_details {
+            Some(problem_details) => problem_details.response(status, detail, request_id),
+            None => Ok(Response::builder()
+                .status(status)
+                .header(http::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+                .body(full_body(Bytes::from(detail.to_string())))?),
         }
+    }
 
-        let request_context = event.request_context();
-        let lambda_context = event.lambda_context();
-        let path = event.raw_http_path().to_string();
-        let mut path = path.as_str();
-        let (parts, body) = event.into_parts();
+    /// Answer a request shed at the in-flight limit, asking the client to
+    /// come back later.
+    fn shed_response(&self, request_id: &str) -> Result<Response<ResponseBody>, Error> {
+        let mut response = self.client_error(
+            StatusCode::TOO_MANY_REQUESTS,
+            "too many requests in flight, try again later",
+            request_id,
+        )?;
+        response
+            .headers_mut()
+            .insert(http::header::RETRY_AFTER, HeaderValue::from_static("1"));
+        Ok(response)
+    }
 
-        // strip away Base Path if environment variable REMOVE_BASE_PATH is set.
-        if let Some(base_path) = self.base_path.as_deref() {
-            path = path.trim_start_matches(base_path);
+    /// Keep the response headers within the limit of the event source, or
+    /// fail with a diagnostic instead of the generic error of the event source.
+    fn fit_headers(
+        &self,
+        mut response: Response<ResponseBody>,
+        source: &'static str,
+        request_id: String,
+    ) -> Result<Response<ResponseBody>, Error> {
+        match self.header_limit.fit(source, response.headers_mut()) {
+            Ok(()) => Ok(response),
+            Err(oversize) => {
+                self.adapter_error(StatusCode::BAD_GATEWAY, Error::from(oversize.to_string()), &request_id)
+            }
         }
+    }
 
-        if matches!(request_context, RequestContext::PassThrough) && parts.method == Method::POST {
-            path = self.path_through_path.as_str();
+    /// Answer a request to upgrade the connection, which cannot work through Lambda.
+    fn upgrade_response(&self, request_id: &str) -> Result<Response<ResponseBody>, Error> {
+        let mut response = self.client_error(
+            self.upgrade_status,
+            "WebSocket and other connection upgrades are not supported by AWS Lambda. \
+             Use an API Gateway WebSocket API, which delivers each message as an event.",
+            request_id,
+        )?;
+        if self.upgrade_status == StatusCode::UPGRADE_REQUIRED {
+            // this is the only protocol the adapter speaks with clients
+            response
+                .headers_mut()
+                .insert(http::header::UPGRADE, HeaderValue::from_static("HTTP/1.1"));
+            response
+                .headers_mut()
+                .insert(http::header::CONNECTION, HeaderValue::from_static("upgrade"));
         }
+        Ok(response)
+    }
 
-        let mut req_headers = parts.headers;
+    /// Report whether the app finished initializing and is not known to be unhealthy.
+    fn health_response(&self) -> Result<Response<ResponseBody>, Error> {
+        let (status, state) = if !self.ready_at_init.load(Ordering::Acquire) {
+            (StatusCode::SERVICE_UNAVAILABLE, "initializing")
+        } else if self.health_gate.as_deref().is_some_and(HealthGate::is_unhealthy) {
+            (StatusCode::SERVICE_UNAVAILABLE, "unhealthy")
+        } else {
+            (StatusCode::OK, "ready")
+        };
+        let body = serde_json::to_vec(&serde_json::json!({ "status": state }))?;
+        Ok(Response::builder()
+            .status(status)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .header(http::header::CACHE_CONTROL, "no-store")
+            .body(full_body(Bytes::from(body)))?)
+    }
 
-        // include request context in http header "x-amzn-request-context"
-        req_headers.insert(
-            HeaderName::from_static("x-amzn-request-context"),
-            HeaderValue::from_bytes(serde_json::to_string(&request_context)?.as_bytes())?,
-        );
+    /// Answer a keep-warm ping, after touching the app on the keep-warm path
+    /// when one is set. The app's answer only shows in the response.
+    async fn keep_warm_response(&self) -> Result<Response<ResponseBody>, Error> {
+        let mut body = serde_json::json!({ "keepWarm": true });
+        if let Some(url) = self.keep_warm_url.as_ref() {
+            let touch = async {
+                let response = self.client.current().get(url.as_str().parse()?).await?;
+                let status = response.status();
+                response.into_body().collect().await?;
+                Ok::<_, Error>(status)
+            };
+            body["appStatus"] = match timeout(keepalive::PROBE_TIMEOUT, touch).await {
+                Ok(Ok(status)) => status.as_u16().into(),
+                Ok(Err(err)) => {
+                    tracing::warn!("keep-warm request to the app failed: {}", err);
+                    serde_json::Value::Null
+                }
+                Err(_) => {
+                    tracing::warn!("keep-warm request to the app timed out");
+                    serde_json::Value::Null
+                }
+            };
+        }
+        tracing::debug!("answered keep-warm ping");
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(full_body(Bytes::from(serde_json::to_vec(&body)?)))?)
+    }
 
-        // include lambda context in http header "x-amzn-lambda-context"
-        req_headers.insert(
-            HeaderName::from_static("x-amzn-lambda-context"),
-            HeaderValue::from_bytes(serde_json::to_string(&lambda_context)?.as_bytes())?,
-        );
+    /// A `503 Service Unavailable` answered without calling the web application.
+    fn fast_fail_response(&self, retry_after: Duration, request_id: &str) -> Result<Response<ResponseBody>, Error> {
+        let mut response = match &self.problem_details {
+            Some(problem_details) => {
+                problem_details.response(StatusCode::SERVICE_UNAVAILABLE, "app is unhealthy", request_id)?
+            }
+            None => Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .body(full_body(Bytes::new()))?,
+        };
+        response
+            .headers_mut()
+            .insert(http::header::RETRY_AFTER, retry_after.as_secs().max(1).into());
+        Ok(response)
+    }
 
-        if let Some(authorization_source) = self.authorization_source.as_deref() {
-            if req_headers.contains_key(authorization_source) {
-                let original = req_headers.remove(authorization_source).unwrap();
-                req_headers.insert("authorization", original);
-            } else {
-                tracing::warn!("\"{}\" header not found in request headers", authorization_source);
+    async fn send_request(&self, request: hyper::Request<Body>) -> Result<Response<Incoming>, Error> {
+        if let Some(expect) = self
+            .expect_continue
+            .as_deref()
+            .filter(|expect| expect.applies(&request))
+        {
+            return expect.send(request).await;
+        }
+        match self.shared_connection.as_deref() {
+            Some(connection) => connection.send_request(request).await,
+            None => Ok(self.client.current().request(request).await?),
+        }
+    }
+
+    /// Send a request, and send it again when it fails without a response
+    /// and the upstream retry policy and the retry budget allow it.
+    async fn send_retried_request(
+        &self,
+        request: hyper::Request<Body>,
+        budget: &RetryBudget,
+    ) -> Result<Response<Incoming>, Error> {
+        let Some(retry) = self.upstream_retry else {
+            return self.send_request(request).await;
+        };
+        let mut attempt = 0;
+        loop {
+            let err = match self.send_request(clone_request(&request)).await {
+                Ok(response) => return Ok(response),
+                Err(err) => err,
+            };
+            attempt += 1;
+            if attempt > retry.retries || !UpstreamRetry::should_retry(request.method(), &err) {
+                return Err(err);
             }
+            let delay = retry.delay(attempt);
+            if !budget.acquire(delay) {
+                tracing::debug!(attempt, "retry budget exhausted, not retrying the request");
+                return Err(err);
+            }
+            tracing::debug!(attempt, ?delay, "request to the app failed, retrying: {}", err);
+            tokio::time::sleep(delay).await;
         }
+    }
 
-        let mut app_url = self.domain.clone();
-        app_url.set_path(path);
-        app_url.set_query(parts.uri.query());
+    /// Send an idempotent request, and fire a second attempt if the first one
+    /// hasn't responded within `hedge_after`. Whichever succeeds first wins.
+    /// The second attempt is only sent if the retry budget allows it.
+    async fn send_hedged_request(
+        &self,
+        request: hyper::Request<Body>,
+        hedge_after: Duration,
+        budget: &RetryBudget,
+    ) -> Result<Response<Incoming>, Error> {
+        let hedge = clone_request(&request);
+        let primary = self.send_request(request);
+        tokio::pin!(primary);
 
-        tracing::debug!(app_url = %app_url, req_headers = ?req_headers, "sending request to app server");
+        if let Ok(result) = timeout(hedge_after, &mut primary).await {
+            return result;
+        }
+        if !budget.acquire(Duration::ZERO) {
+            tracing::debug!("retry budget exhausted, not sending hedged request");
+            return primary.await;
+        }
 
-        let mut builder = hyper::Request::builder().method(parts.method).uri(app_url.to_string());
-        if let Some(headers) = builder.headers_mut() {
-            headers.extend(req_headers);
+        tracing::debug!(hedge_after = ?hedge_after, "app has not responded, sending hedged request");
+        let secondary = self.send_request(hedge);
+        tokio::pin!(secondary);
+
+        tokio::select! {
+            result = &mut primary => match result {
+                Ok(response) => Ok(response),
+                Err(_) => secondary.await,
+            },
+            result = &mut secondary => match result {
+                Ok(response) => Ok(response),
+                Err(_) => primary.await,
+            },
         }
+    }
+}
 
-        // Avoid unnecessary body.to_vec() calls which buffer the entire body
-        // This is particularly important for streaming/reactive applications
-        let request = match body {
-            // Use the body directly when it's already in a format that doesn't require copying
-            Body::Empty => builder.body(Body::Empty)?,
-            Body::Text(text) => builder.body(Body::Text(text))?,
-            Body::Binary(bin) => builder.body(Body::Binary(bin))?,
-            // Only fallback to to_vec() when absolutely necessary
-            _ => builder.body(Body::Binary(body.to_vec()))?,
-        };
+/// In response streaming mode, small responses with a known length are
+/// buffered and sent in one piece. Event streams and responses without a
+/// `content-length` keep streaming.
+fn should_buffer(headers: &http::HeaderMap, threshold: u64) -> bool {
+    let is_event_stream = headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+    let content_length = headers
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
 
-        let mut app_response = self.client.request(request).await?;
+    !is_event_stream && content_length.is_some_and(|length| length <= threshold)
+}
 
-        // Check if status code should trigger an error
-        if let Some(error_codes) = &self.error_status_codes {
-            let status = app_response.status().as_u16();
-            if error_codes.contains(&status) {
-                return Err(Error::from(format!(
-                    "Request failed with configured error status code: {}",
-                    status
-                )));
-            }
-        }
+/// Whether the client asks for an HTML page, as browsers do when navigating.
+fn accepts_html(headers: &http::HeaderMap) -> bool {
+    headers
+        .get_all(http::header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains("text/html"))
+}
 
-        // remove "transfer-encoding" from the response to support "sam local start-api"
-        app_response.headers_mut().remove("transfer-encoding");
+fn clone_request(request: &hyper::Request<Body>) -> hyper::Request<Body> {
+    let body = match request.body() {
+        Body::Empty => Body::Empty,
+        Body::Text(text) => Body::Text(text.clone()),
+        Body::Binary(bin) => Body::Binary(bin.clone()),
+    };
+    let mut clone = hyper::Request::new(body);
+    *clone.method_mut() = request.method().clone();
+    *clone.uri_mut() = request.uri().clone();
+    *clone.version_mut() = request.version();
+    *clone.headers_mut() = request.headers().clone();
+    clone
+}
 
-        tracing::debug!(status = %app_response.status(), body_size = ?app_response.body().size_hint().lower(),
-            app_headers = ?app_response.headers().clone(), "responding to lambda event");
+/// Whether a request asks to upgrade the connection to a WebSocket.
+fn is_websocket_upgrade(headers: &http::HeaderMap) -> bool {
+    headers
+        .get_all(http::header::UPGRADE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|protocol| {
+            let name = protocol.trim().split('/').next().unwrap_or_default();
+            name.eq_ignore_ascii_case("websocket")
+        })
+}
 
-        Ok(app_response)
+/// Turn a WebSocket upgrade request into a request for the app's server-sent
+/// events endpoint. `{path}` in the fallback path stands for the requested path.
+fn sse_fallback_request(event: Request, fallback_path: &str) -> Request {
+    let path = fallback_path.replace("{path}", event.raw_http_path());
+    let mut event = event.with_raw_http_path(path);
+    *event.method_mut() = Method::GET;
+    *event.body_mut() = Body::Empty;
+    let headers = event.headers_mut();
+    for name in [
+        "upgrade",
+        "connection",
+        "sec-websocket-key",
+        "sec-websocket-version",
+        "sec-websocket-extensions",
+        "sec-websocket-protocol",
+        "content-length",
+    ] {
+        headers.remove(name);
     }
+    headers.insert(http::header::ACCEPT, HeaderValue::from_static("text/event-stream"));
+    event
 }
 
 /// Implement a `Tower.Service` that sends the requests
 /// to the web server.
-impl Service<Request> for Adapter<HttpConnector, Body> {
-    type Response = Response<Incoming>;
+impl Service<Request> for Adapter<HttpsConnector<UpstreamConnector>, Body> {
+    type Response = Response<ResponseBody>;
     type Error = Error;
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
 
@@ -645,7 +3822,115 @@ impl Service<Request> for Adapter<HttpConnector, Body> {
 
     fn call(&mut self, event: Request) -> Self::Future {
         let adapter = self.clone();
-        Box::pin(async move { adapter.fetch_response(event).await })
+        Box::pin(async move {
+            let in_flight = adapter.in_flight.as_ref().map(|in_flight| {
+                in_flight.start(
+                    event
+                        .lambda_context_ref()
+                        .map(|context| context.request_id.as_str())
+                        .unwrap_or_default(),
+                )
+            });
+            let counted = adapter.prometheus.as_ref().map(|prometheus| prometheus.start());
+            // at the in-flight limit, low-priority requests are shed and the others wait for a slot
+            let (slot, shed) = match adapter.load_shedder.as_deref() {
+                Some(shedder) => {
+                    let slot = shedder.admit(event.raw_http_path()).await;
+                    let shed = slot.is_none();
+                    (slot, shed)
+                }
+                None => (None, false),
+            };
+            // for apps that are not reentrant, wait for the request in flight to finish
+            let permit = match adapter.request_fence.as_deref() {
+                Some(fence) if !shed => Some(fence.enter().await),
+                _ => None,
+            };
+            let record = adapter.audit_log.is_some().then(|| AccessRecord::new(&event));
+            let access = adapter.access_log.as_deref().map(|access_log| access_log.start(&event));
+            let failure = adapter.failure_capture.is_some().then(|| FailureRequest::new(&event));
+            let source = context::source(&event.request_context());
+            let pass_through = matches!(event.request_context_ref(), Some(RequestContext::PassThrough));
+            let replay = match adapter.replay_capture.as_deref() {
+                Some(capture) if !pass_through && !shed => capture.sample(&event),
+                _ => None,
+            };
+            let request_id = event.lambda_context_ref().map(|context| context.request_id.clone());
+            let response = match adapter.stage_timing {
+                _ if shed => adapter.shed_response(request_id.as_deref().unwrap_or_default()),
+                true => {
+                    let (response, timings) = timing::timed(adapter.handle(event)).await;
+                    tracing::info!(
+                        request_id = request_id.as_deref().unwrap_or_default(),
+                        decode_us = timings.decode.as_micros() as u64,
+                        headers_us = timings.headers.as_micros() as u64,
+                        connect_us = timings.connect.as_micros() as u64,
+                        upstream_us = timings.upstream.as_micros() as u64,
+                        encode_us = timings.encode.as_micros() as u64,
+                        "adapter stage timings"
+                    );
+                    response
+                }
+                false => adapter.handle(event).await,
+            };
+            // lambda_http only returns JSON bodies of responses to non-HTTP events
+            let response = match response {
+                Ok(response) if pass_through && adapter.invoke_mode == LambdaInvokeMode::Buffered => {
+                    pass_through::result(response).await
+                }
+                Ok(mut response) if !pass_through => {
+                    if let Some(static_headers) = adapter.static_headers.as_deref() {
+                        static_headers.apply(response.headers_mut());
+                    }
+                    Ok(response)
+                }
+                response => response,
+            };
+            let response = match (response, source) {
+                (Ok(response), Some(source)) => adapter.fit_headers(response, source, request_id.unwrap_or_default()),
+                (response, _) => response,
+            };
+            let response = match (response, adapter.replay_capture.as_deref(), replay) {
+                (Ok(response), Some(capture), Some(replay)) => {
+                    let buffered = adapter.invoke_mode == LambdaInvokeMode::Buffered;
+                    capture.capture(replay, response, buffered).await
+                }
+                (response, _, _) => response,
+            };
+            if let (Some(audit_log), Some(record)) = (adapter.audit_log.as_deref(), record) {
+                audit_log.record(record.finish(&response));
+            }
+            if let Some(access) = access {
+                access.finish(&response);
+            }
+            // keep the details of errors the adapter answered with for postmortems
+            if let (Some(capture), Some(failure)) = (adapter.failure_capture.as_deref(), failure) {
+                let app_log = adapter.supervisor.as_deref().map(Supervisor::log_tail);
+                match &response {
+                    Ok(response) => {
+                        if let Some(AdapterFailure(error)) = response.extensions().get() {
+                            capture.capture(failure, Some(response.status()), error, app_log).await;
+                        }
+                    }
+                    Err(err) => {
+                        capture
+                            .capture(failure, None, &error_chain(err.as_ref()), app_log)
+                            .await
+                    }
+                }
+            }
+            let response = match (response, adapter.prometheus.as_ref()) {
+                (Ok(response), Some(prometheus)) => Ok(prometheus.count_bytes(response)),
+                (response, _) => response,
+            };
+            // the invoke is in flight until its body has been sent, which matters for streamed responses
+            match response {
+                Ok(response) if permit.is_some() || slot.is_some() || in_flight.is_some() || counted.is_some() => {
+                    Ok(guard::hold(response, (permit, slot, in_flight, counted)))
+                }
+                response => response,
+            }
+        })
     }
 }
 
@@ -668,6 +3953,102 @@ mod tests {
         assert_eq!(parse_status_codes(""), Vec::<u16>::new());
     }
 
+    #[test]
+    fn test_parse_error_status_rules() {
+        let (codes, rules) = parse_error_status_rules("502; 500-599@/payments/*; 404@get /orders/*; 409@POST");
+        assert_eq!(vec![502], codes);
+        assert_eq!(3, rules.len());
+
+        assert!(rules[0].matches(&Method::POST, "/payments/123", 503));
+        assert!(!rules[0].matches(&Method::POST, "/orders/123", 503));
+        assert!(!rules[0].matches(&Method::POST, "/payments/123", 404));
+
+        assert!(rules[1].matches(&Method::GET, "/orders/123", 404));
+        assert!(!rules[1].matches(&Method::DELETE, "/orders/123", 404));
+
+        assert!(rules[2].matches(&Method::POST, "/anything", 409));
+
+        // plain lists keep working
+        assert_eq!((vec![500, 502, 503], vec![]), parse_error_status_rules("500,502-503"));
+    }
+
+    #[test]
+    fn test_is_websocket_upgrade() {
+        let mut headers = http::HeaderMap::new();
+        assert!(!is_websocket_upgrade(&headers));
+        headers.insert(http::header::UPGRADE, HeaderValue::from_static("h2c"));
+        assert!(!is_websocket_upgrade(&headers));
+        headers.insert(http::header::UPGRADE, HeaderValue::from_static("WebSocket"));
+        assert!(is_websocket_upgrade(&headers));
+        headers.insert(http::header::UPGRADE, HeaderValue::from_static("h2c, websocket/13"));
+        assert!(is_websocket_upgrade(&headers));
+    }
+
+    #[test]
+    fn test_sse_fallback_request() {
+        let event = http::Request::builder()
+            .uri("/chat?room=1")
+            .header(http::header::UPGRADE, "websocket")
+            .header(http::header::CONNECTION, "Upgrade")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .header(http::header::AUTHORIZATION, "Bearer token")
+            .body(Body::Empty)
+            .unwrap()
+            .with_raw_http_path("/chat");
+
+        let request = sse_fallback_request(event, "{path}/events");
+        assert_eq!("/chat/events", request.raw_http_path());
+        assert_eq!(Method::GET, request.method());
+        assert_eq!("text/event-stream", request.headers()[http::header::ACCEPT]);
+        assert_eq!("Bearer token", request.headers()[http::header::AUTHORIZATION]);
+        assert!(!is_websocket_upgrade(request.headers()));
+        assert!(!request.headers().contains_key("sec-websocket-key"));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", "/any/path"));
+        assert!(glob_match("/payments/*", "/payments/123"));
+        assert!(glob_match("/payments/*/refund", "/payments/123/refund"));
+        assert!(glob_match("/health", "/health"));
+        assert!(!glob_match("/health", "/healthz"));
+        assert!(!glob_match("/payments/*/refund", "/payments/123"));
+    }
+
+    #[test]
+    fn test_chaos_options_parse() {
+        assert_eq!(
+            ChaosOptions {
+                latency: Duration::from_millis(200),
+                error_rate: 0.05
+            },
+            ChaosOptions::parse(r#"{"latency_ms":200,"error_rate":0.05}"#).unwrap()
+        );
+        assert_eq!(
+            Duration::from_millis(10),
+            ChaosOptions::parse(r#"{"latency_ms":10}"#).unwrap().latency
+        );
+        assert!(ChaosOptions::parse(r#"{"error_rate":2}"#).is_err());
+        assert!(ChaosOptions::parse("latency").is_err());
+    }
+
+    #[test]
+    fn test_should_buffer() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(http::header::CONTENT_LENGTH, HeaderValue::from_static("11"));
+        assert!(should_buffer(&headers, 1024));
+        assert!(!should_buffer(&headers, 10));
+
+        headers.insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static("text/event-stream"),
+        );
+        assert!(!should_buffer(&headers, 1024));
+
+        // chunked responses without a length keep streaming
+        assert!(!should_buffer(&http::HeaderMap::new(), 1024));
+    }
+
     #[tokio::test]
     async fn test_status_200_is_ok() {
         // Start app server
@@ -700,6 +4081,205 @@ mod tests {
         healthcheck.assert();
     }
 
+    #[tokio::test]
+    async fn test_custom_readiness_check() {
+        struct Flag(Arc<AtomicBool>);
+
+        impl ReadinessCheck for Flag {
+            fn is_ready(&self) -> std::pin::Pin<Box<dyn std::future::Future<Output = bool> + Send + '_>> {
+                Box::pin(async { self.0.load(Ordering::Acquire) })
+            }
+        }
+
+        let app_server = MockServer::start();
+        app_server.mock(|when, then| {
+            when.method(GET).path("/healthcheck");
+            then.status(200).body("OK");
+        });
+        let options = AdapterOptions {
+            host: app_server.host(),
+            port: app_server.port().to_string(),
+            readiness_check_port: app_server.port().to_string(),
+            readiness_check_path: "/healthcheck".to_string(),
+            ..Default::default()
+        };
+        let mut adapter = Adapter::new(&options);
+        let migrated = Arc::new(AtomicBool::new(false));
+        adapter.register_readiness_check(Flag(migrated.clone()));
+
+        let url = adapter.healthcheck_url.clone();
+        let protocol = adapter.healthcheck_protocol;
+        // the app answers, but its custom check has not passed yet
+        assert!(adapter.check_web_readiness(&url, &protocol).await.is_err());
+
+        migrated.store(true, Ordering::Release);
+        assert!(adapter.check_web_readiness(&url, &protocol).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_probe() {
+        // Start app server
+        let app_server = MockServer::start();
+        let healthcheck = app_server.mock(|when, then| {
+            when.method(GET).path("/healthcheck");
+            then.status(200).body("OK");
+        });
+
+        let options = |readiness_check_port: String| AdapterOptions {
+            host: app_server.host(),
+            port: app_server.port().to_string(),
+            readiness_check_port,
+            readiness_check_path: "/healthcheck".to_string(),
+            keepalive_probe: true,
+            ..Default::default()
+        };
+
+        let adapter = Adapter::new(&options(app_server.port().to_string()));
+        assert!(adapter.in_flight.is_some());
+        assert!(adapter.probe_connection_pool().await);
+        healthcheck.assert();
+
+        // a failed probe replaces the pool
+        let adapter = Adapter::new(&options("1".to_string()));
+        assert!(!adapter.probe_connection_pool().await);
+    }
+
+    #[tokio::test]
+    async fn test_pre_stop() {
+        // Start app server
+        let app_server = MockServer::start();
+        let pre_stop = app_server.mock(|when, then| {
+            when.method(GET).path("/shutdown");
+            then.status(200);
+        });
+
+        let adapter = Adapter::new(&AdapterOptions {
+            host: app_server.host(),
+            port: app_server.port().to_string(),
+            readiness_check_port: app_server.port().to_string(),
+            readiness_check_path: "/healthcheck".to_string(),
+            pre_stop_path: Some("/shutdown".to_string()),
+            ..Default::default()
+        });
+        adapter.pre_stop().await;
+        pre_stop.assert();
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat() {
+        // Start app server
+        let app_server = MockServer::start();
+        let tick = app_server.mock(|when, then| {
+            when.method(GET).path("/tick");
+            then.status(204);
+        });
+
+        let adapter = Adapter::new(&AdapterOptions {
+            host: app_server.host(),
+            port: app_server.port().to_string(),
+            readiness_check_port: app_server.port().to_string(),
+            readiness_check_path: "/healthcheck".to_string(),
+            heartbeat_path: Some("/tick".to_string()),
+            ..Default::default()
+        });
+        assert!(adapter.in_flight.is_some());
+        assert!(!adapter.keepalive_probe);
+        let heartbeat = adapter.heartbeat.clone().unwrap();
+        adapter.send_heartbeat(&heartbeat).await;
+        tick.assert();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_signal() {
+        use std::os::fd::AsRawFd;
+
+        // an app process that listens on the app port
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // SAFETY: clears FD_CLOEXEC, so the app process inherits the socket
+        unsafe { libc::fcntl(listener.as_raw_fd(), libc::F_SETFD, 0) };
+        let mut app = std::process::Command::new("sleep").arg("30").spawn().unwrap();
+        drop(listener);
+
+        let adapter = Adapter::new(&AdapterOptions {
+            host: "127.0.0.1".to_string(),
+            port: port.to_string(),
+            readiness_check_port: port.to_string(),
+            shutdown_signal: Some("SIGTERM".to_string()),
+            shutdown_drain: Duration::from_secs(5),
+            ..Default::default()
+        });
+        let started = Instant::now();
+        adapter.shutdown().await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(app.try_wait().unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_supervised_app_shutdown() {
+        let mut adapter = Adapter::new(&AdapterOptions {
+            host: "127.0.0.1".to_string(),
+            port: "1".to_string(),
+            readiness_check_port: "1".to_string(),
+            readiness_check_protocol: Protocol::Tcp,
+            async_init: true,
+            app_cmd: Some("sleep 30".to_string()),
+            shutdown_drain: Duration::from_secs(5),
+            ..Default::default()
+        });
+        let supervisor = adapter.supervisor.clone().unwrap();
+        supervisor.start(adapter.ready_at_init.clone());
+        while !supervisor.is_running() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        // nothing listens on the port, so the app is not ready yet
+        assert!(timeout(Duration::from_millis(50), adapter.check_init_health())
+            .await
+            .is_err());
+
+        let started = Instant::now();
+        adapter.shutdown().await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!supervisor.is_running());
+    }
+
+    #[tokio::test]
+    async fn test_supervised_app_started_by_first_request() {
+        let output = env::temp_dir().join(format!("aws-lwa-app-env-test-{}", std::process::id()));
+        let mut adapter = Adapter::new(&AdapterOptions {
+            host: "127.0.0.1".to_string(),
+            port: "1".to_string(),
+            readiness_check_port: "1".to_string(),
+            readiness_check_protocol: Protocol::Tcp,
+            app_cmd: Some(format!(
+                "sh -c 'echo \"$BASE_URL\" > {}; exec sleep 30'",
+                output.display()
+            )),
+            app_env: Some(r#"{"BASE_URL":"https://{domainName}/{stage}"}"#.to_string()),
+            ..Default::default()
+        });
+        // the app needs a request context, so init does not wait for it
+        timeout(Duration::from_secs(1), adapter.check_init_health())
+            .await
+            .unwrap();
+        let supervisor = adapter.supervisor.clone().unwrap();
+        assert!(!supervisor.is_running());
+
+        let context = || Some(serde_json::json!({ "domainName": "api.example.com", "stage": "prod" }));
+        supervisor.start_with_context(context, adapter.ready_at_init.clone());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !std::fs::read_to_string(&output).is_ok_and(|url| url.ends_with('\n')) && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(
+            "https://api.example.com/prod\n",
+            std::fs::read_to_string(&output).unwrap()
+        );
+
+        adapter.shutdown().await;
+        std::fs::remove_file(&output).unwrap();
+    }
+
     #[tokio::test]
     async fn test_status_500_is_bad() {
         // Start app server
@@ -764,7 +4344,7 @@ mod tests {
         // Assert app server's healthcheck endpoint got called
         healthcheck.assert();
     }
-    
+
     #[test]
     fn test_http_client_options() {
         // Test that environment variables are correctly parsed
@@ -774,16 +4354,16 @@ mod tests {
         std::env::set_var("AWS_LWA_HTTP_POOL_IDLE_TIMEOUT_SEC", "120");
         std::env::set_var("AWS_LWA_HTTP_POOL_MAX_IDLE", "64");
         std::env::set_var("AWS_LWA_HTTP_HTTP2_ONLY", "true");
-        
+
         let options = AdapterOptions::default();
-        
+
         assert_eq!(options.http_keepalive, Some(Duration::from_secs(60)));
-        assert_eq!(options.http_nodelay, true);
-        assert_eq!(options.http_reuse_address, true);
+        assert!(options.http_nodelay);
+        assert!(options.http_reuse_address);
         assert_eq!(options.http_pool_idle_timeout, Duration::from_secs(120));
         assert_eq!(options.http_pool_max_idle, 64);
-        assert_eq!(options.http_http2_only, true);
-        
+        assert!(options.http_http2_only);
+
         // Clean up
         std::env::remove_var("AWS_LWA_HTTP_KEEPALIVE_SEC");
         std::env::remove_var("AWS_LWA_HTTP_NODELAY");
@@ -792,4 +4372,4 @@ mod tests {
         std::env::remove_var("AWS_LWA_HTTP_POOL_MAX_IDLE");
         std::env::remove_var("AWS_LWA_HTTP_HTTP2_ONLY");
     }
-}
\ No newline at end of file
+}
@@ -12,8 +12,8 @@ async fn main() -> Result<(), Error> {
         .from_env_lossy();
     tracing_subscriber::fmt().with_env_filter(filter).without_time().init();
 
-    // get configuration options from environment variables
-    let options = AdapterOptions::default();
+    // get configuration options from environment variables, the configuration file and the shared configuration
+    let options = AdapterOptions::load().await?;
 
     // create an adapter
     let mut adapter = Adapter::new(&options);
@@ -0,0 +1,179 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use http::StatusCode;
+use lambda_http::{Error, Response};
+use serde_json::Value;
+use url::Url;
+
+use crate::{full_body, glob_match, ResponseBody};
+
+/// How long a maintenance flag read from a URL is trusted before it is read again.
+pub(crate) const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long reading the maintenance flag from a URL may take.
+pub(crate) const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Where the maintenance flag comes from.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Switch {
+    /// Always in maintenance, until the function is redeployed.
+    On,
+    /// In maintenance while the file exists.
+    File(PathBuf),
+    /// In maintenance while the document at the URL, such as a configuration
+    /// of the AWS AppConfig Lambda extension, says so.
+    Url(Url),
+}
+
+/// Answers requests with a maintenance response instead of calling the web
+/// application, except for an allowlist of paths.
+#[derive(Debug)]
+pub(crate) struct Maintenance {
+    switch: Switch,
+    allowlist: Vec<String>,
+    page: Option<(Bytes, &'static str)>,
+    cached: Mutex<Option<(Instant, bool)>>,
+}
+
+impl Maintenance {
+    /// Returns `None` when maintenance mode is off.
+    pub(crate) fn new(mode: &str, allowlist: Option<&str>, page: Option<&str>) -> Result<Option<Self>, Error> {
+        let switch = match mode.trim() {
+            "" | "false" => return Ok(None),
+            "true" => Switch::On,
+            url if url.starts_with("http://") => Switch::Url(Url::parse(url)?),
+            file => Switch::File(PathBuf::from(file)),
+        };
+        let allowlist = allowlist
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(str::to_string)
+            .collect();
+        let page = page.map(|page| (Bytes::from(page.to_string()), page_content_type(page)));
+        Ok(Some(Maintenance {
+            switch,
+            allowlist,
+            page,
+            cached: Mutex::new(None),
+        }))
+    }
+
+    pub(crate) fn switch(&self) -> &Switch {
+        &self.switch
+    }
+
+    /// Whether requests to `path` still go to the app during maintenance.
+    pub(crate) fn allows(&self, path: &str) -> bool {
+        self.allowlist.iter().any(|pattern| glob_match(pattern, path))
+    }
+
+    /// The flag last read from the URL, unless it is due to be read again.
+    pub(crate) fn cached(&self) -> Option<bool> {
+        self.cached
+            .lock()
+            .unwrap()
+            .filter(|(read_at, _)| read_at.elapsed() < REFRESH_INTERVAL)
+            .map(|(_, enabled)| enabled)
+    }
+
+    pub(crate) fn cache(&self, enabled: bool) {
+        *self.cached.lock().unwrap() = Some((Instant::now(), enabled));
+    }
+
+    /// The configured maintenance page, or `None` to use the adapter's own
+    /// error response.
+    pub(crate) fn page(&self) -> Option<Result<Response<ResponseBody>, Error>> {
+        let (body, content_type) = self.page.as_ref()?;
+        Some(
+            Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .header(CONTENT_TYPE, *content_type)
+                .header(CACHE_CONTROL, "no-store")
+                .body(full_body(body.clone()))
+                .map_err(Error::from),
+        )
+    }
+}
+
+/// The content type of a configured response body, by its first character:
+/// JSON, HTML or text.
+pub(crate) fn page_content_type(page: &str) -> &'static str {
+    match page.trim_start().as_bytes().first() {
+        Some(b'{') | Some(b'[') => "application/json",
+        Some(b'<') => "text/html; charset=utf-8",
+        _ => "text/plain; charset=utf-8",
+    }
+}
+
+/// Read a maintenance flag document: `true`, `{"enabled": true}` or
+/// `{"maintenance": true}`.
+pub(crate) fn parse_flag(document: &[u8]) -> bool {
+    match serde_json::from_slice::<Value>(document) {
+        Ok(Value::Bool(enabled)) => enabled,
+        Ok(Value::Object(flag)) => ["enabled", "maintenance"]
+            .iter()
+            .any(|key| flag.get(*key).and_then(Value::as_bool).unwrap_or(false)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maintenance_switch() {
+        assert!(Maintenance::new("false", None, None).unwrap().is_none());
+        assert!(Maintenance::new("", None, None).unwrap().is_none());
+
+        let switch = |mode| Maintenance::new(mode, None, None).unwrap().unwrap().switch;
+        assert_eq!(Switch::On, switch("true"));
+        assert_eq!(
+            Switch::File(PathBuf::from("/tmp/maintenance")),
+            switch("/tmp/maintenance")
+        );
+        assert_eq!(
+            Switch::Url(
+                Url::parse("http://localhost:2772/applications/app/environments/prod/configurations/flags").unwrap()
+            ),
+            switch("http://localhost:2772/applications/app/environments/prod/configurations/flags")
+        );
+    }
+
+    #[test]
+    fn test_maintenance_allowlist_and_page() {
+        let maintenance = Maintenance::new("true", Some("/health, /admin/*"), Some(r#"{"message":"back soon"}"#))
+            .unwrap()
+            .unwrap();
+        assert!(maintenance.allows("/health"));
+        assert!(maintenance.allows("/admin/users"));
+        assert!(!maintenance.allows("/orders"));
+
+        let page = maintenance.page().unwrap().unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, page.status());
+        assert_eq!("application/json", page.headers()[CONTENT_TYPE]);
+
+        let maintenance = Maintenance::new("true", None, None).unwrap().unwrap();
+        assert!(!maintenance.allows("/health"));
+        assert!(maintenance.page().is_none());
+    }
+
+    #[test]
+    fn test_parse_flag() {
+        assert!(parse_flag(b"true"));
+        assert!(parse_flag(br#"{"enabled":true}"#));
+        assert!(parse_flag(br#"{"maintenance":true,"message":"back soon"}"#));
+        assert!(!parse_flag(br#"{"enabled":false}"#));
+        assert!(!parse_flag(b"false"));
+        assert!(!parse_flag(b"not json"));
+    }
+}
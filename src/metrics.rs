@@ -0,0 +1,386 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Invoke metrics in the CloudWatch Embedded Metric Format (EMF).
+//!
+//! Each call to the app prints one EMF document to stdout, which CloudWatch
+//! Logs turns into metrics without an agent or API calls: the upstream
+//! latency, the size of the app's response body, whether the invoke was the
+//! cold start of the execution environment, and on a cold start, the time the
+//! app took to become ready. The document is printed once the response body
+//! has been sent, so streamed responses are measured too.
+//!
+//! With latency buckets, the latency is reported as a histogram instead of a
+//! plain value: the call is counted at the upper bound of its bucket,
+//! Prometheus style, so CloudWatch keeps the distribution and its
+//! percentiles at the resolution of the buckets.
+
+use std::env;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use http::{Method, StatusCode};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::BodyExt;
+use lambda_http::{Error, Response};
+use serde_json::{json, Value};
+
+use crate::{guard, ResponseBody};
+
+/// The CloudWatch namespace of the metrics.
+const NAMESPACE: &str = "LambdaWebAdapter";
+
+/// The name of the latency metric.
+const LATENCY_METRIC: &str = "UpstreamLatency";
+
+/// CloudWatch takes up to 30 dimensions per metric.
+const MAX_DIMENSIONS: usize = 30;
+
+/// A dimension of the metrics.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Dimension {
+    /// A dimension with the same value for every invoke, such as the
+    /// function name.
+    Fixed(String, String),
+    /// The method of the request.
+    Method,
+}
+
+impl Dimension {
+    fn name(&self) -> &str {
+        match self {
+            Dimension::Fixed(name, _) => name,
+            Dimension::Method => "Method",
+        }
+    }
+}
+
+pub(crate) struct EmfMetrics {
+    dimensions: Vec<Dimension>,
+    /// Upper bounds of the latency buckets in milliseconds, ascending.
+    buckets: Vec<f64>,
+    started: Instant,
+    /// The time from the start of the adapter until the app was ready.
+    readiness: OnceLock<Duration>,
+    cold: AtomicBool,
+}
+
+impl EmfMetrics {
+    /// Report latencies as plain values, or as a histogram with the comma
+    /// separated bucket bounds in milliseconds, such as `10,50,100,500`.
+    /// The metrics have the `FunctionName` dimension.
+    pub(crate) fn new(buckets: Option<&str>) -> Result<Self, Error> {
+        Ok(EmfMetrics {
+            dimensions: parse_dimensions("FunctionName")?,
+            buckets: buckets.map(parse_buckets).transpose()?.unwrap_or_default(),
+            started: Instant::now(),
+            readiness: OnceLock::new(),
+            cold: AtomicBool::new(true),
+        })
+    }
+
+    /// Replace the dimensions with a comma separated list, such as
+    /// `FunctionName,Method,Stage=prod`.
+    pub(crate) fn set_dimensions(&mut self, dimensions: &str) -> Result<(), Error> {
+        self.dimensions = parse_dimensions(dimensions)?;
+        Ok(())
+    }
+
+    /// Note that the app is ready, the first time it is.
+    pub(crate) fn ready(&self) {
+        let _ = self.readiness.set(self.started.elapsed());
+    }
+
+    /// Measure one call to the app. The document is printed when the
+    /// returned invoke is dropped, or when it has tracked the response body.
+    pub(crate) fn measure(
+        &self,
+        latency: Duration,
+        method: &Method,
+        status: Option<StatusCode>,
+        request_id: &str,
+    ) -> Invoke {
+        Invoke {
+            document: self.document(latency, method, status, request_id),
+            response_size: None,
+        }
+    }
+
+    fn document(&self, latency: Duration, method: &Method, status: Option<StatusCode>, request_id: &str) -> Value {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_millis() as u64)
+            .unwrap_or_default();
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        let cold = self.cold.swap(false, Ordering::AcqRel);
+        let mut metrics = vec![
+            json!({"Name": LATENCY_METRIC, "Unit": "Milliseconds"}),
+            json!({"Name": "ColdStart", "Unit": "Count"}),
+        ];
+        let readiness = self.readiness.get().filter(|_| cold);
+        if readiness.is_some() {
+            metrics.push(json!({"Name": "ReadinessDuration", "Unit": "Milliseconds"}));
+        }
+        let names: Vec<&str> = self.dimensions.iter().map(Dimension::name).collect();
+        let mut document = json!({
+            "_aws": {
+                "Timestamp": timestamp,
+                "CloudWatchMetrics": [{
+                    "Namespace": NAMESPACE,
+                    "Dimensions": [names],
+                    "Metrics": metrics,
+                }],
+            },
+            "RequestId": request_id,
+            "StatusCode": status.map(|status| status.as_u16()),
+            "ColdStart": cold as u8,
+        });
+        for dimension in &self.dimensions {
+            document[dimension.name()] = match dimension {
+                Dimension::Fixed(_, value) => json!(value),
+                Dimension::Method => json!(method.as_str()),
+            };
+        }
+        if let Some(readiness) = readiness {
+            document["ReadinessDuration"] = json!(readiness.as_secs_f64() * 1000.0);
+        }
+        if self.buckets.is_empty() {
+            document[LATENCY_METRIC] = json!(latency_ms);
+        } else {
+            // calls slower than the last bound land in the +Inf bucket with their own value
+            let bound = self.buckets.iter().copied().find(|bound| latency_ms <= *bound);
+            document[LATENCY_METRIC] = json!({"Values": [bound.unwrap_or(latency_ms)], "Counts": [1]});
+            document[format!("{LATENCY_METRIC}Bucket")] = match bound {
+                Some(bound) => json!(format!("le_{bound}")),
+                None => json!("le_+Inf"),
+            };
+        }
+        document
+    }
+}
+
+/// The metrics of one call to the app, printed when dropped.
+pub(crate) struct Invoke {
+    document: Value,
+    /// The bytes of the app's response body, when it responded.
+    response_size: Option<Arc<AtomicU64>>,
+}
+
+impl Invoke {
+    /// Count the bytes of the app's response body as it is sent, and print
+    /// the document once it has been.
+    pub(crate) fn track(mut self, response: Response<ResponseBody>) -> Response<ResponseBody> {
+        let size = Arc::new(AtomicU64::new(0));
+        self.response_size = Some(size.clone());
+        let response = response.map(|body| SizedBody { inner: body, size }.boxed_unsync());
+        guard::hold(response, self)
+    }
+
+    fn finish(&self) -> Value {
+        let mut document = self.document.clone();
+        if let Some(size) = &self.response_size {
+            if let Some(metrics) = document["_aws"]["CloudWatchMetrics"][0]["Metrics"].as_array_mut() {
+                metrics.push(json!({"Name": "ResponseSize", "Unit": "Bytes"}));
+            }
+            document["ResponseSize"] = json!(size.load(Ordering::Relaxed));
+        }
+        document
+    }
+}
+
+impl Drop for Invoke {
+    fn drop(&mut self) {
+        println!("{}", self.finish());
+    }
+}
+
+/// A response body that counts its bytes as they are sent.
+struct SizedBody {
+    inner: ResponseBody,
+    size: Arc<AtomicU64>,
+}
+
+impl Body for SizedBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        if let Some(Ok(frame)) = &frame {
+            if let Some(data) = frame.data_ref() {
+                self.size.fetch_add(data.len() as u64, Ordering::Relaxed);
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Parse comma separated dimensions: `FunctionName`, `FunctionVersion`,
+/// `Method`, or `Name=value` for a fixed value.
+fn parse_dimensions(dimensions: &str) -> Result<Vec<Dimension>, Error> {
+    let var = |name: &str| env::var(name).unwrap_or_default();
+    let mut parsed = Vec::new();
+    for dimension in dimensions.split(',') {
+        let dimension = dimension.trim();
+        let dimension = match dimension.split_once('=') {
+            Some((name, value)) if !name.trim().is_empty() => {
+                Dimension::Fixed(name.trim().to_string(), value.trim().to_string())
+            }
+            None if dimension == "FunctionName" => {
+                Dimension::Fixed(dimension.to_string(), var("AWS_LAMBDA_FUNCTION_NAME"))
+            }
+            None if dimension == "FunctionVersion" => {
+                Dimension::Fixed(dimension.to_string(), var("AWS_LAMBDA_FUNCTION_VERSION"))
+            }
+            None if dimension == "Method" => Dimension::Method,
+            _ => return Err(Error::from(format!("invalid dimension {dimension:?}"))),
+        };
+        if parsed.iter().any(|other: &Dimension| other.name() == dimension.name()) {
+            return Err(Error::from(format!("duplicate dimension {:?}", dimension.name())));
+        }
+        parsed.push(dimension);
+    }
+    if parsed.len() > MAX_DIMENSIONS {
+        return Err(Error::from(format!("more than {MAX_DIMENSIONS} dimensions")));
+    }
+    Ok(parsed)
+}
+
+/// Parse comma separated bucket bounds in milliseconds, such as
+/// `10,50,100,500`, into ascending bounds.
+pub(crate) fn parse_buckets(buckets: &str) -> Result<Vec<f64>, Error> {
+    let mut bounds = Vec::new();
+    for bound in buckets.split(',') {
+        let bound = bound.trim();
+        match bound.parse::<f64>() {
+            Ok(value) if value.is_finite() && value > 0.0 => bounds.push(value),
+            _ => return Err(Error::from(format!("invalid latency bucket {bound:?}"))),
+        }
+    }
+    bounds.sort_by(f64::total_cmp);
+    bounds.dedup();
+    Ok(bounds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunked_body;
+
+    #[test]
+    fn test_latency_buckets_parse() {
+        assert_eq!(
+            vec![10.0, 50.0, 250.5],
+            EmfMetrics::new(Some("50, 10,250.5,10")).unwrap().buckets
+        );
+        assert!(EmfMetrics::new(None).unwrap().buckets.is_empty());
+        assert!(EmfMetrics::new(Some("10,fast")).is_err());
+        assert!(EmfMetrics::new(Some("0,10")).is_err());
+    }
+
+    #[test]
+    fn test_latency_document() {
+        let plain = EmfMetrics::new(None).unwrap();
+        let document = plain.document(Duration::from_millis(42), &Method::GET, Some(StatusCode::OK), "req-1");
+        assert_eq!(json!(42.0), document["UpstreamLatency"]);
+        assert_eq!(json!(200), document["StatusCode"]);
+        assert_eq!(json!("req-1"), document["RequestId"]);
+        assert_eq!(
+            json!("UpstreamLatency"),
+            document["_aws"]["CloudWatchMetrics"][0]["Metrics"][0]["Name"]
+        );
+
+        let histogram = EmfMetrics::new(Some("10,50,100")).unwrap();
+        let document = histogram.document(Duration::from_millis(42), &Method::GET, None, "req-2");
+        assert_eq!(json!({"Values": [50.0], "Counts": [1]}), document["UpstreamLatency"]);
+        assert_eq!(json!("le_50"), document["UpstreamLatencyBucket"]);
+        assert_eq!(Value::Null, document["StatusCode"]);
+
+        let document = histogram.document(Duration::from_millis(100), &Method::GET, None, "req-3");
+        assert_eq!(json!("le_100"), document["UpstreamLatencyBucket"]);
+
+        let document = histogram.document(Duration::from_millis(250), &Method::GET, None, "req-4");
+        assert_eq!(json!({"Values": [250.0], "Counts": [1]}), document["UpstreamLatency"]);
+        assert_eq!(json!("le_+Inf"), document["UpstreamLatencyBucket"]);
+    }
+
+    #[test]
+    fn test_emf_dimensions() {
+        let mut metrics = EmfMetrics::new(None).unwrap();
+        metrics.set_dimensions("Method, Stage=prod").unwrap();
+        let document = metrics.document(Duration::from_millis(1), &Method::POST, None, "req-1");
+        assert_eq!(
+            json!([["Method", "Stage"]]),
+            document["_aws"]["CloudWatchMetrics"][0]["Dimensions"]
+        );
+        assert_eq!(json!("POST"), document["Method"]);
+        assert_eq!(json!("prod"), document["Stage"]);
+        assert_eq!(Value::Null, document["FunctionName"]);
+
+        assert!(parse_dimensions("FunctionName,FunctionVersion").is_ok());
+        assert!(parse_dimensions("Region").is_err());
+        assert!(parse_dimensions("Method,Method=GET").is_err());
+        assert!(parse_dimensions("=prod").is_err());
+        assert!(metrics.set_dimensions("").is_err());
+        // the dimensions are kept when the new ones are invalid
+        assert_eq!(
+            vec![Dimension::Method, Dimension::Fixed("Stage".into(), "prod".into())],
+            metrics.dimensions
+        );
+    }
+
+    #[test]
+    fn test_emf_cold_start() {
+        let metrics = EmfMetrics::new(None).unwrap();
+        metrics.ready();
+        let document = metrics.document(Duration::from_millis(1), &Method::GET, None, "req-1");
+        assert_eq!(json!(1), document["ColdStart"]);
+        assert!(document["ReadinessDuration"].is_f64());
+        assert_eq!(
+            json!({"Name": "ReadinessDuration", "Unit": "Milliseconds"}),
+            document["_aws"]["CloudWatchMetrics"][0]["Metrics"][2]
+        );
+
+        let document = metrics.document(Duration::from_millis(1), &Method::GET, None, "req-2");
+        assert_eq!(json!(0), document["ColdStart"]);
+        assert_eq!(Value::Null, document["ReadinessDuration"]);
+    }
+
+    #[tokio::test]
+    async fn test_emf_response_size() {
+        let metrics = EmfMetrics::new(None).unwrap();
+        let invoke = metrics.measure(Duration::from_millis(1), &Method::GET, None, "req-1");
+        assert_eq!(Value::Null, invoke.finish()["ResponseSize"]);
+
+        let mut invoke = metrics.measure(Duration::from_millis(1), &Method::GET, Some(StatusCode::OK), "req-2");
+        invoke.response_size = Some(Arc::new(AtomicU64::new(5)));
+        let document = invoke.finish();
+        assert_eq!(json!(5), document["ResponseSize"]);
+        assert_eq!(
+            json!({"Name": "ResponseSize", "Unit": "Bytes"}),
+            document["_aws"]["CloudWatchMetrics"][0]["Metrics"][2]
+        );
+
+        let size = Arc::new(AtomicU64::new(0));
+        let mut body = SizedBody {
+            inner: chunked_body(&["hello", " world"]),
+            size: size.clone(),
+        };
+        body.frame().await.unwrap().unwrap();
+        assert_eq!(5, size.load(Ordering::Relaxed));
+        body.frame().await.unwrap().unwrap();
+        assert_eq!(11, size.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,64 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The host of the app in URLs and socket addresses, for IPv4, IPv6 and
+//! dual-stack apps.
+
+use std::net::{IpAddr, ToSocketAddrs};
+
+use crate::IpPreference;
+
+/// The app host as it goes in a URL or an authority, with IPv6 addresses in
+/// brackets. A name is resolved to an address of the preferred family, so
+/// the adapter does not try the other family first.
+pub(crate) fn url_host(host: &str, preference: IpPreference) -> String {
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return ip_host(ip);
+    }
+    if preference == IpPreference::Any {
+        return host.to_string();
+    }
+
+    let resolved = (host, 0).to_socket_addrs().ok().and_then(|mut addrs| {
+        addrs.find(|addr| match preference {
+            IpPreference::Ipv4 => addr.is_ipv4(),
+            _ => addr.is_ipv6(),
+        })
+    });
+    match resolved {
+        Some(addr) => {
+            tracing::debug!(host, ip = %addr.ip(), "resolved app host to the preferred address family");
+            ip_host(addr.ip())
+        }
+        None => {
+            tracing::warn!(host, ?preference, "app host has no address of the preferred family");
+            host.to_string()
+        }
+    }
+}
+
+fn ip_host(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{ip}]"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_host() {
+        assert_eq!("127.0.0.1", url_host("127.0.0.1", IpPreference::Any));
+        assert_eq!("[::1]", url_host("::1", IpPreference::Any));
+        assert_eq!("[::1]", url_host("[::1]", IpPreference::Ipv4));
+        assert_eq!("[fd00::1]", url_host("fd00:0::1", IpPreference::Any));
+        assert_eq!("localhost", url_host("localhost", IpPreference::Any));
+        assert_eq!("127.0.0.1", url_host("localhost", IpPreference::Ipv4));
+    }
+}
@@ -0,0 +1,270 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Smooths over the differences between the events of API Gateway REST and
+//! HTTP APIs, Application Load Balancers and Function URLs, so the app sees
+//! the same request whichever of them is in front of it.
+
+use http::header::{CONTENT_LENGTH, COOKIE, HOST};
+use http::request::Parts;
+use http::uri::PathAndQuery;
+use http::{HeaderMap, HeaderName, HeaderValue, Uri};
+use lambda_http::aws_lambda_events::query_map::QueryMap;
+use lambda_http::request::RequestContext;
+use lambda_http::{Body, Error};
+use url::form_urlencoded;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+/// Remove the stage that HTTP APIs include in the path of requests to a
+/// named stage.
+pub(crate) fn path<'a>(path: &'a str, context: &RequestContext) -> &'a str {
+    let stage = match context {
+        RequestContext::ApiGatewayV2(context) => context.stage.as_deref(),
+        _ => None,
+    };
+    let Some(stage) = stage.filter(|stage| *stage != "$default") else {
+        return path;
+    };
+    match path.strip_prefix('/').and_then(|path| path.strip_prefix(stage)) {
+        Some("") => "/",
+        Some(rest) if rest.starts_with('/') => rest,
+        _ => path,
+    }
+}
+
+/// The prefix API Gateway keeps in front of the route a request matched,
+/// such as the stage of an HTTP API or the path of a custom domain mapping.
+/// It is found by filling the route template with the path parameters and
+/// taking what precedes the result in the path.
+pub(crate) fn base_path(path: &str, context: &RequestContext, parameters: &QueryMap) -> Option<String> {
+    let template = match context {
+        RequestContext::ApiGatewayV1(context) => context.resource_path.as_deref(),
+        RequestContext::ApiGatewayV2(context) => context
+            .route_key
+            .as_deref()
+            .and_then(|route_key| route_key.split_once(' '))
+            .map(|(_, template)| template),
+        _ => return None,
+    };
+    let route = template.and_then(|template| fill_route(template, parameters));
+    if let Some(prefix) = route.as_deref().and_then(|route| path.strip_suffix(route)) {
+        let prefix = prefix.trim_end_matches('/');
+        return prefix.starts_with('/').then(|| prefix.to_string());
+    }
+    // the $default route of an HTTP API has no template, but the path keeps the stage
+    match context {
+        RequestContext::ApiGatewayV2(context) => context
+            .stage
+            .as_deref()
+            .filter(|stage| *stage != "$default")
+            .map(|stage| format!("/{stage}"))
+            .filter(|stage| path == stage || path.starts_with(&format!("{stage}/"))),
+        _ => None,
+    }
+}
+
+/// A route template such as `/orders/{id}` or `/{proxy+}` with its parameters filled in.
+fn fill_route(template: &str, parameters: &QueryMap) -> Option<String> {
+    template
+        .split('/')
+        .map(
+            |segment| match segment.strip_prefix('{').and_then(|name| name.strip_suffix('}')) {
+                Some(name) => parameters.first(name.trim_end_matches('+')),
+                None => Some(segment),
+            },
+        )
+        .collect::<Option<Vec<_>>>()
+        .map(|segments| segments.join("/"))
+}
+
+/// Normalize the query string, cookies, forwarding headers and body of a request.
+pub(crate) fn request(parts: &mut Parts, body: &mut Body, context: &RequestContext) -> Result<(), Error> {
+    if let Some(query) = parts.uri.query() {
+        let path_and_query = match query_string(query) {
+            query if query.is_empty() => parts.uri.path().to_string(),
+            query => format!("{}?{}", parts.uri.path(), query),
+        };
+        let mut uri = parts.uri.clone().into_parts();
+        uri.path_and_query = Some(PathAndQuery::try_from(path_and_query)?);
+        parts.uri = Uri::from_parts(uri)?;
+    }
+
+    cookies(&mut parts.headers)?;
+    forwarding_headers(&mut parts.headers, context)?;
+
+    // some front doors send an empty, or empty and base64 encoded, body with requests that have none
+    if body.is_empty() {
+        *body = Body::Empty;
+        parts.headers.remove(CONTENT_LENGTH);
+    } else {
+        parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+    }
+    Ok(())
+}
+
+/// Decode the query string and encode it again as `application/x-www-form-urlencoded`,
+/// with the parameters in order of their names. REST APIs and load balancers
+/// do not keep the order, and HTTP APIs and Function URLs do not re-encode.
+fn query_string(query: &str) -> String {
+    let mut pairs: Vec<(String, String)> = form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+    // stable, so repeated parameters keep their order
+    pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(pairs)
+        .finish()
+}
+
+/// Send the cookies in a single `cookie` header, as HTTP/1.1 requires.
+/// HTTP APIs join them with `;`, and REST APIs can send several headers.
+fn cookies(headers: &mut HeaderMap) -> Result<(), Error> {
+    if !headers.contains_key(COOKIE) {
+        return Ok(());
+    }
+    let cookies: Vec<String> = headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .map(str::trim)
+        .filter(|cookie| !cookie.is_empty())
+        .map(str::to_string)
+        .collect();
+    headers.insert(COOKIE, HeaderValue::from_str(&cookies.join("; "))?);
+    Ok(())
+}
+
+/// Fill in the `host`, `x-forwarded-for` and `x-forwarded-proto` headers
+/// when the event does not carry them.
+fn forwarding_headers(headers: &mut HeaderMap, context: &RequestContext) -> Result<(), Error> {
+    let (domain_name, source_ip) = match context {
+        RequestContext::ApiGatewayV2(context) => (context.domain_name.as_deref(), context.http.source_ip.as_deref()),
+        RequestContext::ApiGatewayV1(context) => {
+            (context.domain_name.as_deref(), context.identity.source_ip.as_deref())
+        }
+        _ => (None, None),
+    };
+    if let (false, Some(domain_name)) = (headers.contains_key(HOST), domain_name) {
+        headers.insert(HOST, HeaderValue::from_str(domain_name)?);
+    }
+    if let (false, Some(source_ip)) = (headers.contains_key(X_FORWARDED_FOR), source_ip) {
+        headers.insert(X_FORWARDED_FOR, HeaderValue::from_str(source_ip)?);
+    }
+    if !headers.contains_key(X_FORWARDED_PROTO) {
+        headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static("https"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lambda_http::aws_lambda_events::apigw::{
+        ApiGatewayProxyRequestContext, ApiGatewayV2httpRequestContext, ApiGatewayV2httpRequestContextHttpDescription,
+    };
+    use std::collections::HashMap;
+
+    fn http_api(stage: &str) -> RequestContext {
+        RequestContext::ApiGatewayV2(ApiGatewayV2httpRequestContext {
+            stage: Some(stage.to_string()),
+            domain_name: Some("api.example.com".to_string()),
+            http: ApiGatewayV2httpRequestContextHttpDescription {
+                source_ip: Some("203.0.113.7".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_path_without_stage() {
+        assert_eq!("/items", path("/prod/items", &http_api("prod")));
+        assert_eq!("/", path("/prod", &http_api("prod")));
+        assert_eq!("/production/items", path("/production/items", &http_api("prod")));
+        assert_eq!("/prod/items", path("/prod/items", &http_api("$default")));
+    }
+
+    #[test]
+    fn test_base_path() {
+        let proxy: QueryMap = HashMap::from([("proxy".to_string(), "orders/1".to_string())]).into();
+        let rest_api = |resource: &str| {
+            RequestContext::ApiGatewayV1(ApiGatewayProxyRequestContext {
+                resource_path: Some(resource.to_string()),
+                stage: Some("prod".to_string()),
+                ..Default::default()
+            })
+        };
+        let http_api_route = |route_key: &str| {
+            RequestContext::ApiGatewayV2(ApiGatewayV2httpRequestContext {
+                route_key: Some(route_key.to_string()),
+                stage: Some("prod".to_string()),
+                ..Default::default()
+            })
+        };
+
+        // REST APIs keep the path of a custom domain mapping, but not the stage
+        assert_eq!(
+            Some("/shop".to_string()),
+            base_path("/shop/orders/1", &rest_api("/{proxy+}"), &proxy)
+        );
+        assert_eq!(None, base_path("/orders/1", &rest_api("/{proxy+}"), &proxy));
+        let id: QueryMap = HashMap::from([("id".to_string(), "1".to_string())]).into();
+        assert_eq!(
+            Some("/shop".to_string()),
+            base_path("/shop/orders/1", &rest_api("/orders/{id}"), &id)
+        );
+
+        // HTTP APIs keep the stage
+        assert_eq!(
+            Some("/prod".to_string()),
+            base_path("/prod/orders/1", &http_api_route("ANY /{proxy+}"), &proxy)
+        );
+        assert_eq!(
+            Some("/prod".to_string()),
+            base_path("/prod/", &http_api_route("GET /"), &QueryMap::default())
+        );
+        assert_eq!(
+            Some("/prod".to_string()),
+            base_path("/prod/orders", &http_api_route("$default"), &QueryMap::default())
+        );
+        assert_eq!(
+            None,
+            base_path("/production/orders", &http_api_route("$default"), &QueryMap::default())
+        );
+        assert_eq!(None, base_path("/orders", &http_api("$default"), &QueryMap::default()));
+        assert_eq!(
+            None,
+            base_path("/orders", &RequestContext::PassThrough, &QueryMap::default())
+        );
+    }
+
+    #[test]
+    fn test_query_string() {
+        assert_eq!("a=hello+world&b=2&b=1", query_string("b=2&a=hello%20world&b=1"));
+        assert_eq!("a=hello+world&b=2&b=1", query_string("b=2&b=1&a=hello+world"));
+        assert_eq!("q=%26%3D", query_string("q=%26%3D"));
+    }
+
+    #[test]
+    fn test_request() {
+        let (mut parts, _) = http::Request::builder()
+            .uri("https://api.example.com/items?b=2&a=x%20y")
+            .header(COOKIE, "a=1;b=2")
+            .header(COOKIE, "c=3")
+            .header(CONTENT_LENGTH, "0")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let mut body = Body::Binary(Vec::new());
+        request(&mut parts, &mut body, &http_api("$default")).unwrap();
+
+        assert_eq!("https://api.example.com/items?a=x+y&b=2", parts.uri.to_string());
+        assert_eq!("a=1; b=2; c=3", parts.headers[COOKIE]);
+        assert_eq!("api.example.com", parts.headers[HOST]);
+        assert_eq!("203.0.113.7", parts.headers[X_FORWARDED_FOR]);
+        assert_eq!("https", parts.headers[X_FORWARDED_PROTO]);
+        assert!(!parts.headers.contains_key(CONTENT_LENGTH));
+        assert!(matches!(body, Body::Empty));
+    }
+}
@@ -0,0 +1,400 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::Path;
+
+use http::{HeaderMap, Method, StatusCode};
+use lambda_http::Error;
+use percent_encoding::percent_decode_str;
+use serde_json::Value;
+
+const METHODS: [&str; 8] = ["get", "put", "post", "delete", "options", "head", "patch", "trace"];
+
+/// Why a request does not match the API specification.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Rejection {
+    pub(crate) status: StatusCode,
+    pub(crate) detail: String,
+    /// The declared methods of the path, for `405 Method Not Allowed`.
+    pub(crate) allow: Option<String>,
+}
+
+impl Rejection {
+    fn new(status: StatusCode, detail: String) -> Self {
+        Rejection {
+            status,
+            detail,
+            allow: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Location {
+    Query,
+    Header,
+    Cookie,
+}
+
+#[derive(Debug)]
+struct Parameter {
+    name: String,
+    location: Location,
+}
+
+#[derive(Debug)]
+struct Operation {
+    method: Method,
+    required: Vec<Parameter>,
+    body_required: bool,
+}
+
+#[derive(Debug)]
+struct PathItem {
+    template: String,
+    /// The segments of the path template, with `None` for `{parameters}`.
+    segments: Vec<Option<String>>,
+    operations: Vec<Operation>,
+}
+
+/// Checks requests against the paths, methods, required parameters and
+/// required request bodies declared in an OpenAPI document.
+#[derive(Debug)]
+pub(crate) struct OpenApiSpec {
+    paths: Vec<PathItem>,
+}
+
+impl OpenApiSpec {
+    /// Load an OpenAPI document in JSON or YAML.
+    pub(crate) fn load(file: impl AsRef<Path>) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(file)?;
+        let document = match serde_json::from_str(&text) {
+            Ok(document) => document,
+            Err(_) => serde_yaml::from_str(&text)?,
+        };
+        Self::new(&document)
+    }
+
+    pub(crate) fn new(document: &Value) -> Result<Self, Error> {
+        let paths = document
+            .get("paths")
+            .and_then(Value::as_object)
+            .ok_or_else(|| Error::from("OpenAPI document has no paths"))?;
+
+        let mut items = Vec::with_capacity(paths.len());
+        for (template, item) in paths {
+            let item = resolve(document, item);
+            let shared = item.get("parameters").and_then(Value::as_array);
+
+            let mut operations = Vec::new();
+            for method in METHODS {
+                let Some(operation) = item.get(method).map(|operation| resolve(document, operation)) else {
+                    continue;
+                };
+                let parameters = shared
+                    .into_iter()
+                    .flatten()
+                    .chain(
+                        operation
+                            .get("parameters")
+                            .and_then(Value::as_array)
+                            .into_iter()
+                            .flatten(),
+                    )
+                    .map(|parameter| resolve(document, parameter));
+
+                let mut required = Vec::new();
+                let mut body_required = false;
+                for parameter in parameters {
+                    if !parameter.get("required").and_then(Value::as_bool).unwrap_or(false) {
+                        continue;
+                    }
+                    let name = parameter.get("name").and_then(Value::as_str).unwrap_or_default();
+                    let location = match parameter.get("in").and_then(Value::as_str) {
+                        Some("query") => Location::Query,
+                        Some("header") => Location::Header,
+                        Some("cookie") => Location::Cookie,
+                        // Swagger 2.0 body parameters
+                        Some("body") => {
+                            body_required = true;
+                            continue;
+                        }
+                        _ => continue,
+                    };
+                    // a later declaration of the same parameter overrides the path-level one
+                    required.retain(|other: &Parameter| other.name != name || other.location != location);
+                    required.push(Parameter {
+                        name: name.to_string(),
+                        location,
+                    });
+                }
+                body_required |= operation
+                    .get("requestBody")
+                    .map(|body| resolve(document, body))
+                    .and_then(|body| body.get("required"))
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false);
+
+                operations.push(Operation {
+                    method: Method::from_bytes(method.to_uppercase().as_bytes())?,
+                    required,
+                    body_required,
+                });
+            }
+
+            let segments = template
+                .trim_matches('/')
+                .split('/')
+                .map(|segment| {
+                    let is_parameter = segment.starts_with('{') && segment.ends_with('}');
+                    (!is_parameter).then(|| segment.to_string())
+                })
+                .collect();
+            items.push(PathItem {
+                template: template.clone(),
+                segments,
+                operations,
+            });
+        }
+        Ok(OpenApiSpec { paths: items })
+    }
+
+    fn path_item(&self, path: &str) -> Option<&PathItem> {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        self.paths
+            .iter()
+            .filter(|item| {
+                item.segments.len() == segments.len()
+                    && item
+                        .segments
+                        .iter()
+                        .zip(&segments)
+                        .all(|(expected, segment)| match expected {
+                            Some(literal) => literal == segment,
+                            None => !segment.is_empty(),
+                        })
+            })
+            // concrete paths take precedence over templated ones
+            .max_by_key(|item| item.segments.iter().filter(|segment| segment.is_some()).count())
+    }
+
+    /// The `Allow` header for an `OPTIONS` request to a path that does not
+    /// declare an `OPTIONS` operation, or `None` when the app answers it.
+    pub(crate) fn synthesized_allow(&self, path: &str) -> Option<String> {
+        let item = self.path_item(path)?;
+        let declares = |method: &Method| item.operations.iter().any(|operation| operation.method == method);
+        if declares(&Method::OPTIONS) {
+            return None;
+        }
+        let mut allow: Vec<&str> = item
+            .operations
+            .iter()
+            .map(|operation| operation.method.as_str())
+            .collect();
+        if declares(&Method::GET) && !declares(&Method::HEAD) {
+            allow.push(Method::HEAD.as_str());
+        }
+        allow.push(Method::OPTIONS.as_str());
+        Some(allow.join(", "))
+    }
+
+    /// Check a request. `CORS` preflight requests are always allowed, and
+    /// `HEAD` is allowed wherever `GET` is.
+    pub(crate) fn validate(
+        &self,
+        method: &Method,
+        path: &str,
+        query: Option<&str>,
+        headers: &HeaderMap,
+        body_len: usize,
+    ) -> Result<(), Rejection> {
+        let Some(item) = self.path_item(path) else {
+            return Err(Rejection::new(
+                StatusCode::NOT_FOUND,
+                format!("{path} is not a path of the API"),
+            ));
+        };
+        if method == Method::OPTIONS && headers.contains_key(http::header::ACCESS_CONTROL_REQUEST_METHOD) {
+            return Ok(());
+        }
+        let operation = item
+            .operations
+            .iter()
+            .find(|operation| operation.method == method)
+            .or_else(|| {
+                let head = method == Method::HEAD;
+                item.operations
+                    .iter()
+                    .find(|operation| head && operation.method == Method::GET)
+            });
+        let Some(operation) = operation else {
+            let allow: Vec<&str> = item
+                .operations
+                .iter()
+                .map(|operation| operation.method.as_str())
+                .collect();
+            return Err(Rejection {
+                status: StatusCode::METHOD_NOT_ALLOWED,
+                detail: format!("{} is not allowed for {}", method, item.template),
+                allow: Some(allow.join(", ")),
+            });
+        };
+
+        for parameter in &operation.required {
+            let present = match parameter.location {
+                Location::Query => query.is_some_and(|query| has_query_parameter(query, &parameter.name)),
+                Location::Header => headers.contains_key(parameter.name.as_str()),
+                Location::Cookie => headers
+                    .get_all(http::header::COOKIE)
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .flat_map(|value| value.split(';'))
+                    .any(|cookie| cookie.trim().split('=').next() == Some(parameter.name.as_str())),
+            };
+            if !present {
+                let location = match parameter.location {
+                    Location::Query => "query parameter",
+                    Location::Header => "header",
+                    Location::Cookie => "cookie",
+                };
+                return Err(Rejection::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("missing required {} {}", location, parameter.name),
+                ));
+            }
+        }
+        if operation.body_required && body_len == 0 {
+            return Err(Rejection::new(
+                StatusCode::BAD_REQUEST,
+                "missing required request body".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Follow a local `$ref`, such as `#/components/parameters/limit`.
+fn resolve<'a>(document: &'a Value, value: &'a Value) -> &'a Value {
+    let mut value = value;
+    // bounded, in case of reference cycles
+    for _ in 0..16 {
+        match value
+            .get("$ref")
+            .and_then(Value::as_str)
+            .and_then(|reference| reference.strip_prefix('#'))
+            .and_then(|pointer| document.pointer(pointer))
+        {
+            Some(target) => value = target,
+            None => break,
+        }
+    }
+    value
+}
+
+fn has_query_parameter(query: &str, name: &str) -> bool {
+    query.split('&').any(|pair| {
+        let key = pair.split('=').next().unwrap_or_default();
+        percent_decode_str(&key.replace('+', " "))
+            .decode_utf8()
+            .is_ok_and(|key| key == name)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    const SPEC: &str = r#"
+openapi: 3.0.3
+paths:
+  /pets:
+    get:
+      parameters:
+      - $ref: '#/components/parameters/Limit'
+    post:
+      requestBody:
+        required: true
+  /pets/{petId}:
+    parameters:
+    - name: petId
+      in: path
+      required: true
+    - name: x-tenant
+      in: header
+      required: true
+    get: {}
+  /pets/mine:
+    get: {}
+components:
+  parameters:
+    Limit:
+      name: limit
+      in: query
+      required: true
+"#;
+
+    fn spec() -> OpenApiSpec {
+        OpenApiSpec::new(&serde_yaml::from_str::<Value>(SPEC).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_validate_paths_and_methods() {
+        let spec = spec();
+        let headers = HeaderMap::new();
+
+        let rejection = spec.validate(&Method::GET, "/dogs", None, &headers, 0).unwrap_err();
+        assert_eq!(StatusCode::NOT_FOUND, rejection.status);
+
+        let rejection = spec.validate(&Method::DELETE, "/pets", None, &headers, 0).unwrap_err();
+        assert_eq!(StatusCode::METHOD_NOT_ALLOWED, rejection.status);
+        assert_eq!(Some("GET, POST".to_string()), rejection.allow);
+
+        // concrete paths win over templates
+        assert_eq!(Ok(()), spec.validate(&Method::GET, "/pets/mine", None, &headers, 0));
+        assert_eq!(Ok(()), spec.validate(&Method::HEAD, "/pets/mine/", None, &headers, 0));
+    }
+
+    #[test]
+    fn test_synthesized_allow() {
+        let spec = spec();
+        assert_eq!(
+            Some("GET, POST, HEAD, OPTIONS".to_string()),
+            spec.synthesized_allow("/pets")
+        );
+        assert_eq!(
+            Some("GET, HEAD, OPTIONS".to_string()),
+            spec.synthesized_allow("/pets/42")
+        );
+        assert_eq!(None, spec.synthesized_allow("/dogs"));
+
+        let spec =
+            OpenApiSpec::new(&serde_yaml::from_str::<Value>("paths:\n  /pets:\n    options: {}\n").unwrap()).unwrap();
+        assert_eq!(None, spec.synthesized_allow("/pets"));
+    }
+
+    #[test]
+    fn test_validate_required_parameters() {
+        let spec = spec();
+        let mut headers = HeaderMap::new();
+
+        let rejection = spec
+            .validate(&Method::GET, "/pets", Some("page=2"), &headers, 0)
+            .unwrap_err();
+        assert_eq!(StatusCode::BAD_REQUEST, rejection.status);
+        assert_eq!("missing required query parameter limit", rejection.detail);
+        assert_eq!(
+            Ok(()),
+            spec.validate(&Method::GET, "/pets", Some("page=2&limit=10"), &headers, 0)
+        );
+
+        let rejection = spec.validate(&Method::GET, "/pets/42", None, &headers, 0).unwrap_err();
+        assert_eq!("missing required header x-tenant", rejection.detail);
+        headers.insert("x-tenant", HeaderValue::from_static("acme"));
+        assert_eq!(Ok(()), spec.validate(&Method::GET, "/pets/42", None, &headers, 0));
+
+        let rejection = spec.validate(&Method::POST, "/pets", None, &headers, 0).unwrap_err();
+        assert_eq!("missing required request body", rejection.detail);
+        assert_eq!(Ok(()), spec.validate(&Method::POST, "/pets", None, &headers, 12));
+    }
+}
@@ -0,0 +1,381 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! OpenTelemetry trace propagation and spans, with the `otel` feature.
+//!
+//! Every request to the app gets a W3C `traceparent` header naming a span of
+//! the adapter as its parent, so the spans of the app join the trace of the
+//! caller. The trace is continued from the `traceparent` of the request, or
+//! from an X-Ray `X-Amzn-Trace-Id`, converted to the W3C format.
+//!
+//! Sampled spans are queued and exported to an OTLP/HTTP collector as JSON
+//! after the invoke, before Lambda freezes the execution environment. The
+//! endpoint, headers and service name come from the standard `OTEL_*`
+//! variables.
+
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use http::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use http::{HeaderMap, Method, StatusCode};
+use lambda_http::Error;
+use serde_json::{json, Value};
+
+use crate::aws::AwsClient;
+
+const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+const XRAY_TRACE_ID: &str = "x-amzn-trace-id";
+
+/// How long an export may take, since it delays the freeze.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The OTLP span kind of the invoke.
+const SPAN_KIND_SERVER: u8 = 2;
+const STATUS_CODE_ERROR: u8 = 2;
+
+/// The position of a span in a trace.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TraceContext {
+    trace_id: String,
+    parent_span_id: Option<String>,
+    sampled: bool,
+}
+
+impl TraceContext {
+    /// The context of the caller, from a W3C `traceparent` or an X-Ray trace
+    /// header, or a new trace.
+    pub(crate) fn extract(headers: &HeaderMap, xray_trace_id: Option<&str>) -> Self {
+        let traceparent = headers
+            .get(TRACEPARENT)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_traceparent);
+        let xray = || {
+            headers
+                .get(XRAY_TRACE_ID)
+                .and_then(|value| value.to_str().ok())
+                .or(xray_trace_id)
+                .and_then(parse_xray)
+        };
+        traceparent.or_else(xray).unwrap_or_else(|| TraceContext {
+            trace_id: format!("{:032x}", fastrand::u128(1..)),
+            parent_span_id: None,
+            sampled: true,
+        })
+    }
+}
+
+/// Parse `00-<trace id>-<parent id>-<flags>`.
+fn parse_traceparent(value: &str) -> Option<TraceContext> {
+    let mut fields = value.trim().split('-');
+    let (version, trace_id, parent_id, flags) = (fields.next()?, fields.next()?, fields.next()?, fields.next()?);
+    let valid =
+        version.len() == 2 && version != "ff" && is_id(trace_id, 32) && is_id(parent_id, 16) && flags.len() == 2;
+    let flags = u8::from_str_radix(flags, 16).ok().filter(|_| valid)?;
+    Some(TraceContext {
+        trace_id: trace_id.to_ascii_lowercase(),
+        parent_span_id: Some(parent_id.to_ascii_lowercase()),
+        sampled: flags & 0x01 != 0,
+    })
+}
+
+/// Parse `Root=1-<epoch>-<unique id>;Parent=<parent id>;Sampled=<0|1>`,
+/// where the epoch and unique id together are the W3C trace id.
+fn parse_xray(value: &str) -> Option<TraceContext> {
+    let mut context = TraceContext {
+        trace_id: String::new(),
+        parent_span_id: None,
+        sampled: true,
+    };
+    for field in value.split(';') {
+        match field.trim().split_once('=') {
+            Some(("Root", root)) => {
+                let trace_id = root.strip_prefix("1-")?.replace('-', "");
+                context.trace_id = trace_id.to_lowercase();
+            }
+            Some(("Parent", parent)) if is_id(parent, 16) => {
+                context.parent_span_id = Some(parent.to_lowercase());
+            }
+            Some(("Sampled", sampled)) => context.sampled = sampled != "0",
+            _ => {}
+        }
+    }
+    is_id(&context.trace_id, 32).then_some(context)
+}
+
+/// Whether `id` is a non-zero lowercase or uppercase hex id of `len` digits.
+fn is_id(id: &str, len: usize) -> bool {
+    id.len() == len && id.bytes().all(|byte| byte.is_ascii_hexdigit()) && id.bytes().any(|byte| byte != b'0')
+}
+
+/// A span of the adapter, from the request to the app until its response.
+#[derive(Debug)]
+pub(crate) struct Span {
+    context: TraceContext,
+    span_id: String,
+    start: SystemTime,
+}
+
+pub(crate) struct Tracer {
+    aws: AwsClient,
+    endpoint: String,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    resource: Value,
+    spans: Mutex<Vec<Value>>,
+}
+
+impl Tracer {
+    /// Export to the OTLP/HTTP traces `endpoint`, such as
+    /// `http://localhost:4318/v1/traces`.
+    pub(crate) fn new(endpoint: &str) -> Result<Self, Error> {
+        let headers = env::var("OTEL_EXPORTER_OTLP_TRACES_HEADERS")
+            .or_else(|_| env::var("OTEL_EXPORTER_OTLP_HEADERS"))
+            .unwrap_or_default();
+        let headers = headers
+            .split(',')
+            .filter(|header| !header.trim().is_empty())
+            .map(|header| {
+                let (name, value) = header
+                    .split_once('=')
+                    .ok_or_else(|| Error::from(format!("invalid OTLP header {header:?}")))?;
+                let value = percent_encoding::percent_decode_str(value.trim()).decode_utf8()?;
+                Ok((
+                    HeaderName::from_bytes(name.trim().as_bytes())?,
+                    HeaderValue::from_str(&value)?,
+                ))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        let function_name = env::var("AWS_LAMBDA_FUNCTION_NAME").unwrap_or_default();
+        let service_name = env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| function_name.clone());
+        let aws = AwsClient::new()?;
+        let resource = json!({
+            "attributes": [
+                attribute("service.name", &service_name),
+                attribute("cloud.provider", "aws"),
+                attribute("cloud.region", aws.region()),
+                attribute("faas.name", &function_name),
+            ]
+        });
+
+        Ok(Tracer {
+            aws,
+            endpoint: endpoint.to_string(),
+            headers,
+            resource,
+            spans: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Start the span of a request to the app and name it in the
+    /// `traceparent` of the request.
+    pub(crate) fn start(&self, headers: &mut HeaderMap, xray_trace_id: Option<&str>) -> Span {
+        let context = TraceContext::extract(headers, xray_trace_id);
+        let span_id = format!("{:016x}", fastrand::u64(1..));
+        let traceparent = format!("00-{}-{}-{:02x}", context.trace_id, span_id, context.sampled as u8);
+        if let Ok(traceparent) = HeaderValue::from_str(&traceparent) {
+            headers.insert(TRACEPARENT, traceparent);
+        }
+        Span {
+            context,
+            span_id,
+            start: SystemTime::now(),
+        }
+    }
+
+    /// End a span with the status of the response, or `None` when the app
+    /// did not respond, and queue it for export when it is sampled.
+    pub(crate) fn finish(&self, span: Span, method: &Method, path: &str, status: Option<StatusCode>, request_id: &str) {
+        if !span.context.sampled {
+            return;
+        }
+        let mut attributes = vec![
+            attribute("http.request.method", method.as_str()),
+            attribute("url.path", path),
+            attribute("faas.invocation_id", request_id),
+            attribute("faas.trigger", "http"),
+        ];
+        if let Some(status) = status {
+            attributes.push(json!({
+                "key": "http.response.status_code",
+                "value": { "intValue": status.as_u16().to_string() }
+            }));
+        }
+        let failed = status.is_none_or(|status| status.is_server_error());
+
+        let mut record = json!({
+            "traceId": span.context.trace_id,
+            "spanId": span.span_id,
+            "name": method.as_str(),
+            "kind": SPAN_KIND_SERVER,
+            "startTimeUnixNano": unix_nanos(span.start).to_string(),
+            "endTimeUnixNano": unix_nanos(SystemTime::now()).to_string(),
+            "attributes": attributes,
+        });
+        if let Some(parent_span_id) = span.context.parent_span_id {
+            record["parentSpanId"] = json!(parent_span_id);
+        }
+        if failed {
+            record["status"] = json!({ "code": STATUS_CODE_ERROR });
+        }
+        self.spans.lock().unwrap().push(record);
+    }
+
+    /// Export the queued spans. Failures are logged and the spans dropped.
+    pub(crate) async fn flush(&self) {
+        let spans = std::mem::take(&mut *self.spans.lock().unwrap());
+        if spans.is_empty() {
+            return;
+        }
+        let count = spans.len();
+        let export = self.export(spans);
+        match tokio::time::timeout(EXPORT_TIMEOUT, export).await {
+            Ok(Ok(())) => tracing::debug!(count, "exported spans"),
+            Ok(Err(err)) => tracing::warn!(count, "failed to export spans: {}", err),
+            Err(_) => tracing::warn!(count, "exporting spans timed out"),
+        }
+    }
+
+    async fn export(&self, spans: Vec<Value>) -> Result<(), Error> {
+        let body = json!({
+            "resourceSpans": [{
+                "resource": self.resource,
+                "scopeSpans": [{
+                    "scope": { "name": "lambda-web-adapter", "version": env!("CARGO_PKG_VERSION") },
+                    "spans": spans,
+                }]
+            }]
+        });
+        let mut request = http::Request::builder()
+            .method(Method::POST)
+            .uri(&self.endpoint)
+            .header(CONTENT_TYPE, "application/json");
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let request = request.body(Bytes::from(serde_json::to_vec(&body)?))?;
+        self.aws.send_unsigned("otlp", request).await?;
+        Ok(())
+    }
+}
+
+/// The traces endpoint from `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`, or from
+/// `OTEL_EXPORTER_OTLP_ENDPOINT` with the `/v1/traces` path. `None` when
+/// neither is set, or tracing is turned off with `OTEL_SDK_DISABLED` or
+/// `OTEL_TRACES_EXPORTER=none`.
+pub(crate) fn traces_endpoint() -> Option<String> {
+    let disabled = env::var("OTEL_SDK_DISABLED").is_ok_and(|disabled| disabled.eq_ignore_ascii_case("true"))
+        || env::var("OTEL_TRACES_EXPORTER").is_ok_and(|exporter| exporter.eq_ignore_ascii_case("none"));
+    if disabled {
+        return None;
+    }
+    env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").ok().or_else(|| {
+        env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+            .ok()
+            .map(|endpoint| format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+    })
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httpmock::{Method::POST, MockServer};
+
+    #[test]
+    fn test_trace_context_from_traceparent() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            TRACEPARENT,
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00"),
+        );
+        headers.insert(
+            XRAY_TRACE_ID,
+            HeaderValue::from_static("Root=1-5759e988-bd862e3fe1be46a994272793;Sampled=1"),
+        );
+        assert_eq!(
+            TraceContext {
+                trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".to_string(),
+                parent_span_id: Some("00f067aa0ba902b7".to_string()),
+                sampled: false,
+            },
+            TraceContext::extract(&headers, None)
+        );
+
+        assert_eq!(
+            Some(TraceContext {
+                trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".to_string(),
+                parent_span_id: Some("00f067aa0ba902b7".to_string()),
+                sampled: true,
+            }),
+            parse_traceparent("00-4BF92F3577B34DA6A3CE929D0E0E4736-00F067AA0BA902B7-01")
+        );
+        assert!(parse_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none());
+        assert!(parse_traceparent("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").is_none());
+        assert!(parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736").is_none());
+    }
+
+    #[test]
+    fn test_trace_context_from_xray() {
+        let context = TraceContext::extract(
+            &HeaderMap::new(),
+            Some("Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1"),
+        );
+        assert_eq!(
+            TraceContext {
+                trace_id: "5759e988bd862e3fe1be46a994272793".to_string(),
+                parent_span_id: Some("53995c3f42cd8ad8".to_string()),
+                sampled: true,
+            },
+            context
+        );
+
+        // a new trace without either header
+        let context = TraceContext::extract(&HeaderMap::new(), None);
+        assert!(is_id(&context.trace_id, 32));
+        assert_eq!(None, context.parent_span_id);
+    }
+
+    #[tokio::test]
+    async fn test_tracer_exports_spans() {
+        env::set_var("AWS_REGION", "us-east-1");
+        let collector = MockServer::start();
+        let export = collector.mock(|when, then| {
+            when.method(POST)
+                .path("/v1/traces")
+                .header("content-type", "application/json")
+                .body_contains("\"traceId\":\"5759e988bd862e3fe1be46a994272793\"")
+                .body_contains("\"parentSpanId\":\"53995c3f42cd8ad8\"")
+                .body_contains("\"http.response.status_code\"");
+            then.status(200).body("{}");
+        });
+        let tracer = Tracer::new(&collector.url("/v1/traces")).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            XRAY_TRACE_ID,
+            HeaderValue::from_static("Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1"),
+        );
+        let span = tracer.start(&mut headers, None);
+        let traceparent = headers[TRACEPARENT].to_str().unwrap().to_string();
+        assert!(traceparent.starts_with("00-5759e988bd862e3fe1be46a994272793-"));
+        assert!(traceparent.ends_with("-01"));
+        assert!(!traceparent.contains("53995c3f42cd8ad8"));
+
+        tracer.finish(span, &Method::GET, "/hello", Some(StatusCode::OK), "request-1");
+        tracer.flush().await;
+        export.assert();
+
+        // nothing left to export
+        tracer.flush().await;
+        export.assert_hits(1);
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Lambda results of the app's responses to non-HTTP events.
+//!
+//! `lambda_http` returns the body of the response to a pass-through event as
+//! the result of the invocation, but only a JSON body: text in any other
+//! format and binary bodies turn into `null`. JSON bodies are passed on as
+//! they are. Other bodies are wrapped in a result object with the status,
+//! the content type with its charset, and the body as text decoded by that
+//! charset, or as base64 when it is binary.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bytes::Bytes;
+use http::header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE};
+use http::{HeaderMap, HeaderValue, StatusCode};
+use http_body_util::BodyExt;
+use lambda_http::{Error, Response};
+use serde_json::{json, Value};
+
+use crate::{full_body, ResponseBody};
+
+/// Content types `lambda_http` treats as text, by prefix and by suffix.
+const TEXT_PREFIXES: [&str; 5] = [
+    "text",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/yaml",
+];
+const TEXT_SUFFIXES: [&str; 3] = ["+xml", "+yaml", "+json"];
+
+/// Turn the app's response into one whose body `lambda_http` returns intact.
+pub(crate) async fn result(response: Response<ResponseBody>) -> Result<Response<ResponseBody>, Error> {
+    let (mut parts, body) = response.into_parts();
+    let bytes = body.collect().await?.to_bytes();
+    let document = match document(parts.status, &parts.headers, &bytes) {
+        Some(document) => document,
+        None => return Ok(Response::from_parts(parts, full_body(bytes))),
+    };
+    parts.headers.remove(CONTENT_ENCODING);
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    Ok(Response::from_parts(parts, full_body(document.to_string())))
+}
+
+/// The result object of a body that is not JSON, or `None` to keep the body.
+fn document(status: StatusCode, headers: &HeaderMap, bytes: &Bytes) -> Option<Value> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let encoded = headers.contains_key(CONTENT_ENCODING);
+    if bytes.is_empty() || (!encoded && serde_json::from_slice::<Value>(bytes).is_ok()) {
+        return None;
+    }
+
+    // a body without a content type is text when it is valid UTF-8
+    let text = match encoded {
+        false if is_text(content_type) => Some(decode(content_type, bytes)),
+        false if content_type.is_empty() => std::str::from_utf8(bytes).ok().map(str::to_string),
+        _ => None,
+    };
+    let mut result_headers = serde_json::Map::new();
+    if !content_type.is_empty() {
+        result_headers.insert("content-type".to_string(), Value::from(content_type));
+    }
+    if let Some(encoding) = headers.get(CONTENT_ENCODING).and_then(|value| value.to_str().ok()) {
+        result_headers.insert("content-encoding".to_string(), Value::from(encoding));
+    }
+    Some(json!({
+        "statusCode": status.as_u16(),
+        "headers": result_headers,
+        "isBase64Encoded": text.is_none(),
+        "body": text.unwrap_or_else(|| BASE64.encode(bytes)),
+    }))
+}
+
+fn is_text(content_type: &str) -> bool {
+    let mime_type = content_type.split(';').next().unwrap_or_default().trim();
+    TEXT_PREFIXES.iter().any(|prefix| mime_type.starts_with(prefix))
+        || TEXT_SUFFIXES.iter().any(|suffix| mime_type.ends_with(suffix))
+}
+
+/// Decode text by the charset of its content type, UTF-8 by default.
+fn decode(content_type: &str, bytes: &[u8]) -> String {
+    let charset = content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .map(|(_, value)| value.trim().trim_matches('"'));
+    let encoding = charset
+        .and_then(|charset| encoding_rs::Encoding::for_label(charset.as_bytes()))
+        .unwrap_or(encoding_rs::UTF_8);
+    encoding.decode(bytes).0.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(content_type: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_str(content_type).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_json_bodies_pass_through() {
+        let ok = StatusCode::OK;
+        assert_eq!(
+            None,
+            document(ok, &headers("application/json"), &Bytes::from(r#"{"a":1}"#))
+        );
+        assert_eq!(None, document(ok, &HeaderMap::new(), &Bytes::from("[1, 2]")));
+        assert_eq!(None, document(ok, &headers("text/plain"), &Bytes::new()));
+    }
+
+    #[test]
+    fn test_text_bodies_keep_their_charset() {
+        let latin1 = Bytes::from_static(b"caf\xe9");
+        assert_eq!(
+            Some(json!({
+                "statusCode": 200,
+                "headers": {"content-type": "text/plain; charset=ISO-8859-1"},
+                "isBase64Encoded": false,
+                "body": "café",
+            })),
+            document(StatusCode::OK, &headers("text/plain; charset=ISO-8859-1"), &latin1)
+        );
+
+        let xml = document(
+            StatusCode::ACCEPTED,
+            &headers("application/soap+xml"),
+            &Bytes::from("<ok/>"),
+        )
+        .unwrap();
+        assert_eq!(json!(202), xml["statusCode"]);
+        assert_eq!(json!("<ok/>"), xml["body"]);
+
+        let untyped = document(StatusCode::OK, &HeaderMap::new(), &Bytes::from("handled")).unwrap();
+        assert_eq!(json!({}), untyped["headers"]);
+        assert_eq!(json!("handled"), untyped["body"]);
+    }
+
+    #[test]
+    fn test_binary_bodies_are_base64_encoded() {
+        let png = Bytes::from_static(b"\x89PNG\r\n");
+        assert_eq!(
+            Some(json!({
+                "statusCode": 200,
+                "headers": {"content-type": "image/png"},
+                "isBase64Encoded": true,
+                "body": "iVBORw0K",
+            })),
+            document(StatusCode::OK, &headers("image/png"), &png)
+        );
+
+        let mut gzipped = headers("text/plain");
+        gzipped.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        let result = document(StatusCode::OK, &gzipped, &Bytes::from_static(b"\x1f\x8b")).unwrap();
+        assert_eq!(json!(true), result["isBase64Encoded"]);
+        assert_eq!(json!("gzip"), result["headers"]["content-encoding"]);
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Hooks for custom adapter binaries.
+//!
+//! A binary that embeds the adapter can register plugins before it calls
+//! [`Adapter::run`](crate::Adapter::run), to add its own policies without a
+//! fork of the adapter:
+//!
+//! ```no_run
+//! use lambda_http::{Request, Response};
+//! use lambda_web_adapter::{full_body, Adapter, AdapterOptions, Error, LwaPlugin, ResponseBody};
+//!
+//! struct RequireTenant;
+//!
+//! impl LwaPlugin for RequireTenant {
+//!     fn on_request(&self, request: &mut Request) -> Option<Response<ResponseBody>> {
+//!         if request.headers().contains_key("x-tenant") {
+//!             return None;
+//!         }
+//!         Response::builder().status(400).body(full_body("missing x-tenant")).ok()
+//!     }
+//! }
+//!
+//! # async fn run() -> Result<(), Error> {
+//! let mut adapter = Adapter::new(&AdapterOptions::default());
+//! adapter.register_plugin(RequireTenant);
+//! adapter.register_default_extension();
+//! adapter.check_init_health().await;
+//! adapter.run().await
+//! # }
+//! ```
+
+use http::Response;
+use lambda_http::{Error, Request};
+
+use crate::ResponseBody;
+
+/// A plugin that runs inside the adapter. Every hook has a default that does
+/// nothing, so a plugin only implements the hooks it needs.
+///
+/// Plugins see requests in the order they were registered, and responses
+/// and errors in the reverse order.
+pub trait LwaPlugin: Send + Sync {
+    /// Called once when [`Adapter::run`](crate::Adapter::run) starts. An error
+    /// stops the adapter before it takes any events.
+    fn on_init(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called once the adapter has registered as a Lambda extension, with
+    /// the extension identifier Lambda assigned. Registration happens during
+    /// [`Adapter::register_default_extension`](crate::Adapter::register_default_extension),
+    /// or after the readiness check when it is deferred.
+    fn on_extension_registered(&self, _extension_id: &str) {}
+
+    /// Called with each request before the adapter handles it. The request
+    /// can be changed, or answered by returning a response, in which case
+    /// neither the app nor later plugins see it.
+    fn on_request(&self, _request: &mut Request) -> Option<Response<ResponseBody>> {
+        None
+    }
+
+    /// Called with each response before it is returned to Lambda, including
+    /// responses returned by `on_request` and `on_error`.
+    fn on_response(&self, _response: &mut Response<ResponseBody>) {}
+
+    /// Called when the adapter fails to handle a request. Returning a
+    /// response answers the request with it instead of failing the invocation.
+    fn on_error(&self, _error: &Error) -> Option<Response<ResponseBody>> {
+        None
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use bytes::Bytes;
+use http::{header::CONTENT_TYPE, StatusCode};
+use lambda_http::{Error, Response};
+use serde_json::{Map, Value};
+
+use crate::{full_body, ResponseBody};
+
+/// Builds RFC 7807 `application/problem+json` responses for errors raised by
+/// the adapter itself, such as an unreachable app or an invocation timeout.
+///
+/// The template is a JSON object merged over the standard members. Its string
+/// values may use the `{status}`, `{title}`, `{detail}` and `{request_id}`
+/// placeholders.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ProblemDetails {
+    template: Map<String, Value>,
+}
+
+impl ProblemDetails {
+    pub(crate) fn new(template: Option<&str>) -> Result<Self, Error> {
+        let template = match template {
+            Some(template) => match serde_json::from_str(template)? {
+                Value::Object(template) => template,
+                _ => return Err(Error::from("problem details template must be a JSON object")),
+            },
+            None => Map::new(),
+        };
+        Ok(ProblemDetails { template })
+    }
+
+    pub(crate) fn response(
+        &self,
+        status: StatusCode,
+        detail: &str,
+        request_id: &str,
+    ) -> Result<Response<ResponseBody>, Error> {
+        let title = status.canonical_reason().unwrap_or_default();
+        let mut problem = Map::new();
+        problem.insert("type".into(), "about:blank".into());
+        problem.insert("title".into(), title.into());
+        problem.insert("status".into(), status.as_u16().into());
+        problem.insert("detail".into(), detail.into());
+
+        for (key, value) in &self.template {
+            let value = match value {
+                Value::String(value) => Value::String(
+                    value
+                        .replace("{status}", status.as_str())
+                        .replace("{title}", title)
+                        .replace("{detail}", detail)
+                        .replace("{request_id}", request_id),
+                ),
+                value => value.clone(),
+            };
+            problem.insert(key.clone(), value);
+        }
+
+        Ok(Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/problem+json")
+            .body(full_body(Bytes::from(serde_json::to_vec(&problem)?)))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    async fn problem(response: Response<ResponseBody>) -> Value {
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_problem_details_default() {
+        let response = ProblemDetails::default()
+            .response(StatusCode::BAD_GATEWAY, "connection refused", "req-1")
+            .unwrap();
+        assert_eq!(502, response.status());
+        assert_eq!("application/problem+json", response.headers()[CONTENT_TYPE]);
+        assert_eq!(
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Bad Gateway",
+                "status": 502,
+                "detail": "connection refused"
+            }),
+            problem(response).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_problem_details_template() {
+        let template = r#"{"type":"https://example.com/errors/{status}","instance":"{request_id}","retryable":true}"#;
+        let response = ProblemDetails::new(Some(template))
+            .unwrap()
+            .response(StatusCode::SERVICE_UNAVAILABLE, "app is unhealthy", "req-1")
+            .unwrap();
+        let problem = problem(response).await;
+        assert_eq!("https://example.com/errors/503", problem["type"]);
+        assert_eq!("req-1", problem["instance"]);
+        assert_eq!(true, problem["retryable"]);
+        assert_eq!("Service Unavailable", problem["title"]);
+    }
+
+    #[test]
+    fn test_problem_details_invalid_template() {
+        assert!(ProblemDetails::new(Some("[]")).is_err());
+        assert!(ProblemDetails::new(Some("{")).is_err());
+    }
+}
@@ -0,0 +1,304 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Adapter metrics in the Prometheus text format, on a local side port.
+//!
+//! Extensions and telemetry agents in the same execution environment can
+//! scrape `http://127.0.0.1:<port>/metrics` while it is warm. The metrics
+//! live as long as the environment, so counters start over at each cold start.
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::header::CONTENT_TYPE;
+use http::{Method, StatusCode};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use lambda_http::{Error, Response};
+use tokio::net::TcpListener;
+
+use crate::ResponseBody;
+
+/// Upper bounds of the latency buckets in seconds, unless configured.
+const DEFAULT_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+const METRICS_PATH: &str = "/metrics";
+
+#[derive(Debug)]
+struct Histogram {
+    /// Upper bounds in seconds, ascending.
+    bounds: Vec<f64>,
+    /// Observations per bucket, and past the last bound.
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+#[derive(Debug)]
+pub(crate) struct PrometheusMetrics {
+    requests: AtomicU64,
+    upstream_requests: Mutex<BTreeMap<String, u64>>,
+    upstream_latency: Mutex<Histogram>,
+    readiness_retries: AtomicU64,
+    in_flight: AtomicU64,
+    response_bytes: AtomicU64,
+}
+
+impl PrometheusMetrics {
+    /// Observe latencies in the buckets with the given bounds in
+    /// milliseconds, or in the default buckets.
+    pub(crate) fn new(buckets_ms: Option<Vec<f64>>) -> Self {
+        let bounds = match buckets_ms {
+            Some(bounds) => bounds.iter().map(|bound| bound / 1000.0).collect(),
+            None => DEFAULT_BUCKETS.to_vec(),
+        };
+        PrometheusMetrics {
+            requests: AtomicU64::new(0),
+            upstream_requests: Mutex::new(BTreeMap::new()),
+            upstream_latency: Mutex::new(Histogram {
+                counts: vec![0; bounds.len() + 1],
+                bounds,
+                sum: 0.0,
+            }),
+            readiness_retries: AtomicU64::new(0),
+            in_flight: AtomicU64::new(0),
+            response_bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// Serve the metrics on `127.0.0.1:port`.
+    pub(crate) fn serve(self: &Arc<Self>, port: u16) -> Result<(), Error> {
+        let listener = std::net::TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], port)))?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        tokio::spawn(self.clone().accept(listener));
+        tracing::info!(port, "metrics endpoint listening");
+        Ok(())
+    }
+
+    /// Count an invocation, in flight until the body of its response has
+    /// been sent.
+    pub(crate) fn start(self: &Arc<Self>) -> InFlightRequest {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightRequest { metrics: self.clone() }
+    }
+
+    /// Record a call to the app, with the status of its response or `None`
+    /// when the app did not respond.
+    pub(crate) fn observe_upstream(&self, latency: Duration, status: Option<StatusCode>) {
+        let status = status.map_or_else(|| "error".to_string(), |status| status.as_u16().to_string());
+        *self.upstream_requests.lock().unwrap().entry(status).or_default() += 1;
+
+        let seconds = latency.as_secs_f64();
+        let mut histogram = self.upstream_latency.lock().unwrap();
+        let bucket = histogram.bounds.partition_point(|bound| *bound < seconds);
+        histogram.counts[bucket] += 1;
+        histogram.sum += seconds;
+    }
+
+    /// Count a readiness check of the app that failed and will be retried.
+    pub(crate) fn readiness_retry(&self) {
+        self.readiness_retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count the bytes of the response body as it is sent.
+    pub(crate) fn count_bytes(self: &Arc<Self>, response: Response<ResponseBody>) -> Response<ResponseBody> {
+        response.map(|body| {
+            CountedBody {
+                inner: body,
+                metrics: self.clone(),
+            }
+            .boxed_unsync()
+        })
+    }
+
+    fn render(&self) -> String {
+        let mut text = String::new();
+        let metric = |text: &mut String, name: &str, kind: &str, help: &str, value: u64| {
+            let _ = write!(text, "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n");
+        };
+        metric(
+            &mut text,
+            "lwa_requests_total",
+            "counter",
+            "Invocations handled by the adapter.",
+            self.requests.load(Ordering::Relaxed),
+        );
+
+        text.push_str("# HELP lwa_upstream_requests_total Requests sent to the app, by the status of its response.\n");
+        text.push_str("# TYPE lwa_upstream_requests_total counter\n");
+        for (status, count) in self.upstream_requests.lock().unwrap().iter() {
+            let _ = writeln!(text, "lwa_upstream_requests_total{{status=\"{status}\"}} {count}");
+        }
+
+        let histogram = self.upstream_latency.lock().unwrap();
+        text.push_str(
+            "# HELP lwa_upstream_latency_seconds Time from sending a request to the app to its response headers.\n",
+        );
+        text.push_str("# TYPE lwa_upstream_latency_seconds histogram\n");
+        let mut cumulative = 0;
+        for (bound, count) in histogram.bounds.iter().zip(&histogram.counts) {
+            cumulative += count;
+            let _ = writeln!(
+                text,
+                "lwa_upstream_latency_seconds_bucket{{le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        cumulative += histogram.counts.last().copied().unwrap_or_default();
+        let _ = writeln!(text, "lwa_upstream_latency_seconds_bucket{{le=\"+Inf\"}} {cumulative}");
+        let _ = writeln!(text, "lwa_upstream_latency_seconds_sum {}", histogram.sum);
+        let _ = writeln!(text, "lwa_upstream_latency_seconds_count {cumulative}");
+        drop(histogram);
+
+        metric(
+            &mut text,
+            "lwa_readiness_retries_total",
+            "counter",
+            "Readiness checks of the app that failed and were retried.",
+            self.readiness_retries.load(Ordering::Relaxed),
+        );
+        metric(
+            &mut text,
+            "lwa_in_flight_requests",
+            "gauge",
+            "Invocations in flight, including sending their response body.",
+            self.in_flight.load(Ordering::Relaxed),
+        );
+        metric(
+            &mut text,
+            "lwa_response_bytes_total",
+            "counter",
+            "Bytes of response bodies sent to Lambda.",
+            self.response_bytes.load(Ordering::Relaxed),
+        );
+        text
+    }
+
+    async fn accept(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    tracing::warn!(error = %err, "failed to accept metrics connection");
+                    continue;
+                }
+            };
+            let metrics = self.clone();
+            tokio::spawn(async move {
+                let service = service_fn(|request| metrics.clone().respond(request));
+                if let Err(err) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    tracing::debug!(error = %err, "metrics connection closed");
+                }
+            });
+        }
+    }
+
+    async fn respond(
+        self: Arc<Self>,
+        request: hyper::Request<Incoming>,
+    ) -> Result<hyper::Response<Full<Bytes>>, Infallible> {
+        let mut response = hyper::Response::new(Full::new(Bytes::new()));
+        if request.method() != Method::GET || request.uri().path() != METRICS_PATH {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return Ok(response);
+        }
+        *response.body_mut() = Full::new(Bytes::from(self.render()));
+        response.headers_mut().insert(
+            CONTENT_TYPE,
+            http::HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"),
+        );
+        Ok(response)
+    }
+}
+
+/// An invocation in flight. Dropping it ends the invocation.
+pub(crate) struct InFlightRequest {
+    metrics: Arc<PrometheusMetrics>,
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        self.metrics.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+struct CountedBody {
+    inner: ResponseBody,
+    metrics: Arc<PrometheusMetrics>,
+}
+
+impl Body for CountedBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        if let Some(Ok(frame)) = &frame {
+            if let Some(data) = frame.data_ref() {
+                self.metrics
+                    .response_bytes
+                    .fetch_add(data.len() as u64, Ordering::Relaxed);
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{full_body, guard};
+
+    #[tokio::test]
+    async fn test_prometheus_metrics() {
+        let metrics = Arc::new(PrometheusMetrics::new(Some(vec![10.0, 100.0])));
+        let request = metrics.start();
+        metrics.observe_upstream(Duration::from_millis(5), Some(StatusCode::OK));
+        metrics.observe_upstream(Duration::from_millis(50), Some(StatusCode::OK));
+        metrics.observe_upstream(Duration::from_millis(500), None);
+        metrics.readiness_retry();
+        let response = guard::hold(metrics.count_bytes(Response::new(full_body("hello"))), request);
+        assert!(metrics.render().contains("lwa_in_flight_requests 1\n"));
+
+        response.into_body().collect().await.unwrap();
+        let text = metrics.render();
+        for line in [
+            "lwa_requests_total 1\n",
+            "lwa_upstream_requests_total{status=\"200\"} 2\n",
+            "lwa_upstream_requests_total{status=\"error\"} 1\n",
+            "lwa_upstream_latency_seconds_bucket{le=\"0.01\"} 1\n",
+            "lwa_upstream_latency_seconds_bucket{le=\"0.1\"} 2\n",
+            "lwa_upstream_latency_seconds_bucket{le=\"+Inf\"} 3\n",
+            "lwa_upstream_latency_seconds_count 3\n",
+            "lwa_readiness_retries_total 1\n",
+            "lwa_in_flight_requests 0\n",
+            "lwa_response_bytes_total 5\n",
+            "# TYPE lwa_upstream_latency_seconds histogram\n",
+        ] {
+            assert!(text.contains(line), "{line:?} missing from {text}");
+        }
+    }
+}
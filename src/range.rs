@@ -0,0 +1,188 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use bytes::Bytes;
+use http::header::{CONTENT_LENGTH, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use http::{HeaderMap, HeaderValue, StatusCode};
+use http_body_util::BodyExt;
+use lambda_http::{Error, Response};
+
+use crate::{full_body, ResponseBody};
+
+/// A single byte range of a `Range` header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ByteRange {
+    /// `bytes=first-last`
+    FromTo(u64, u64),
+    /// `bytes=first-`
+    From(u64),
+    /// `bytes=-length`, the last bytes of the response.
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// Parse a `Range` header with a single byte range. Other units, several
+    /// ranges and invalid headers are ignored, so the full response is sent.
+    fn parse(range: &str) -> Option<Self> {
+        let spec = range.trim().strip_prefix("bytes=")?.trim();
+        if spec.contains(',') {
+            return None;
+        }
+        let (first, last) = spec.split_once('-')?;
+        let (first, last) = (first.trim(), last.trim());
+        match (first.is_empty(), last.is_empty()) {
+            (true, false) => Some(ByteRange::Suffix(last.parse().ok()?)),
+            (false, true) => Some(ByteRange::From(first.parse().ok()?)),
+            (false, false) => {
+                let (first, last) = (first.parse().ok()?, last.parse().ok()?);
+                (first <= last).then_some(ByteRange::FromTo(first, last))
+            }
+            (true, true) => None,
+        }
+    }
+
+    /// The first and last byte of the range in a response of `len` bytes,
+    /// or `None` when the range is not satisfiable.
+    fn resolve(self, len: u64) -> Option<(u64, u64)> {
+        match self {
+            ByteRange::FromTo(first, last) if first < len => Some((first, last.min(len - 1))),
+            ByteRange::From(first) if first < len => Some((first, len - 1)),
+            ByteRange::Suffix(length) if length > 0 && len > 0 => Some((len.saturating_sub(length), len - 1)),
+            _ => None,
+        }
+    }
+}
+
+/// The range a client asked for, to slice full responses of apps that
+/// ignore `Range` headers.
+#[derive(Debug)]
+pub(crate) struct RangeRequest {
+    range: ByteRange,
+    if_range: Option<HeaderValue>,
+}
+
+impl RangeRequest {
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let range = headers.get(RANGE)?.to_str().ok()?;
+        Some(RangeRequest {
+            range: ByteRange::parse(range)?,
+            if_range: headers.get(IF_RANGE).cloned(),
+        })
+    }
+
+    /// Whether the response is still the representation the client has part
+    /// of. Only strong entity tags and exact dates match.
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        let Some(if_range) = &self.if_range else {
+            return true;
+        };
+        let validator = match if_range.as_bytes().first() {
+            Some(b'"') => ETAG,
+            Some(b'W') => return false,
+            _ => LAST_MODIFIED,
+        };
+        headers.get(validator) == Some(if_range)
+    }
+
+    /// Answer with the requested range of a full `200 OK` response, or with
+    /// `416 Range Not Satisfiable` when the range is outside the response.
+    pub(crate) async fn slice(&self, response: Response<ResponseBody>) -> Result<Response<ResponseBody>, Error> {
+        if response.status() != StatusCode::OK
+            || response.headers().contains_key(CONTENT_RANGE)
+            || !self.matches(response.headers())
+        {
+            return Ok(response);
+        }
+
+        let (mut parts, body) = response.into_parts();
+        let bytes = body.collect().await?.to_bytes();
+        let len = bytes.len() as u64;
+        let Some((first, last)) = self.range.resolve(len) else {
+            parts.status = StatusCode::RANGE_NOT_SATISFIABLE;
+            parts
+                .headers
+                .insert(CONTENT_RANGE, HeaderValue::from_str(&format!("bytes */{len}"))?);
+            parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(0));
+            return Ok(Response::from_parts(parts, full_body(Bytes::new())));
+        };
+
+        parts.status = StatusCode::PARTIAL_CONTENT;
+        parts.headers.insert(
+            CONTENT_RANGE,
+            HeaderValue::from_str(&format!("bytes {first}-{last}/{len}"))?,
+        );
+        parts
+            .headers
+            .insert(CONTENT_LENGTH, HeaderValue::from(last - first + 1));
+        let slice = bytes.slice(first as usize..=last as usize);
+        Ok(Response::from_parts(parts, full_body(slice)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_byte_range() {
+        assert_eq!(Some(ByteRange::FromTo(0, 99)), ByteRange::parse("bytes=0-99"));
+        assert_eq!(Some(ByteRange::From(100)), ByteRange::parse("bytes=100-"));
+        assert_eq!(Some(ByteRange::Suffix(50)), ByteRange::parse("bytes=-50"));
+        assert_eq!(None, ByteRange::parse("bytes=0-9, 20-29"));
+        assert_eq!(None, ByteRange::parse("bytes=9-0"));
+        assert_eq!(None, ByteRange::parse("items=0-9"));
+        assert_eq!(None, ByteRange::parse("bytes=-"));
+    }
+
+    #[test]
+    fn test_resolve_byte_range() {
+        assert_eq!(Some((0, 9)), ByteRange::FromTo(0, 99).resolve(10));
+        assert_eq!(Some((5, 9)), ByteRange::From(5).resolve(10));
+        assert_eq!(Some((0, 9)), ByteRange::Suffix(50).resolve(10));
+        assert_eq!(None, ByteRange::From(10).resolve(10));
+        assert_eq!(None, ByteRange::Suffix(0).resolve(10));
+    }
+
+    async fn slice(range: &str, if_range: Option<&'static str>) -> Response<ResponseBody> {
+        let mut headers = HeaderMap::new();
+        headers.insert(RANGE, HeaderValue::from_str(range).unwrap());
+        if let Some(if_range) = if_range {
+            headers.insert(IF_RANGE, HeaderValue::from_static(if_range));
+        }
+        let response = Response::builder()
+            .header(ETAG, "\"v1\"")
+            .body(full_body("0123456789"))
+            .unwrap();
+        RangeRequest::from_headers(&headers)
+            .unwrap()
+            .slice(response)
+            .await
+            .unwrap()
+    }
+
+    async fn body(response: Response<ResponseBody>) -> String {
+        String::from_utf8(response.into_body().collect().await.unwrap().to_bytes().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_slice() {
+        let response = slice("bytes=2-4", None).await;
+        assert_eq!(StatusCode::PARTIAL_CONTENT, response.status());
+        assert_eq!("bytes 2-4/10", response.headers()[CONTENT_RANGE]);
+        assert_eq!("3", response.headers()[CONTENT_LENGTH]);
+        assert_eq!("234", body(response).await);
+
+        let response = slice("bytes=-3", Some("\"v1\"")).await;
+        assert_eq!(StatusCode::PARTIAL_CONTENT, response.status());
+        assert_eq!("789", body(response).await);
+
+        // a changed representation is sent in full
+        let response = slice("bytes=2-4", Some("\"v2\"")).await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("0123456789", body(response).await);
+
+        let response = slice("bytes=20-", None).await;
+        assert_eq!(StatusCode::RANGE_NOT_SATISFIABLE, response.status());
+        assert_eq!("bytes */10", response.headers()[CONTENT_RANGE]);
+    }
+}
@@ -0,0 +1,321 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Sampled request and response pairs for offline replay.
+//!
+//! Each sampled invocation is written as a HAR 1.2 log with a single entry,
+//! to a directory in `/tmp` or to S3, so the traffic can be replayed against
+//! a new version of the app with HAR tooling. Credentials in headers and
+//! query parameters are redacted before anything is written.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use bytes::Bytes;
+use http::header::CONTENT_TYPE;
+use http::HeaderMap;
+use http_body_util::BodyExt;
+use lambda_http::{Error, Request, RequestExt, Response};
+use serde_json::{json, Value};
+
+use crate::capture::{sanitize_key, Target, SENSITIVE_HEADERS};
+use crate::{full_body, ResponseBody};
+
+/// Entries written by one execution environment, so a traffic spike does not
+/// fill `/tmp` or the bucket.
+const MAX_ENTRIES: usize = 1000;
+
+/// Bodies over this size are recorded by their size only.
+const MAX_BODY_BYTES: usize = 256 * 1024;
+
+/// Query parameters whose values are replaced when their name contains one
+/// of these words.
+const SENSITIVE_PARAMS: [&str; 8] = [
+    "token",
+    "secret",
+    "password",
+    "key",
+    "signature",
+    "auth",
+    "session",
+    "credential",
+];
+
+pub(crate) const REDACTED: &str = "[REDACTED]";
+
+pub(crate) struct ReplayCapture {
+    target: Target,
+    percent: f64,
+    written: AtomicUsize,
+}
+
+impl ReplayCapture {
+    /// Write a `percent` sample of the traffic to `s3://bucket/prefix` or to
+    /// a local directory.
+    pub(crate) fn new(target: &str, percent: f64) -> Result<Self, Error> {
+        if !(0.0..=100.0).contains(&percent) {
+            return Err(Error::from(format!("invalid sample percent {percent}")));
+        }
+        Ok(ReplayCapture {
+            target: Target::new(target)?,
+            percent,
+            written: AtomicUsize::new(0),
+        })
+    }
+
+    /// The request of the entry when the invocation is sampled.
+    pub(crate) fn sample(&self, event: &Request) -> Option<ReplayRequest> {
+        if fastrand::f64() * 100.0 >= self.percent {
+            return None;
+        }
+        if self.written.fetch_add(1, Ordering::AcqRel) >= MAX_ENTRIES {
+            return None;
+        }
+        Some(ReplayRequest::new(event))
+    }
+
+    /// Write the entry of a sampled invocation. Buffered responses are read
+    /// to record their body; streamed ones are recorded without it, so they
+    /// keep streaming. Failures to write are only logged.
+    pub(crate) async fn capture(
+        &self,
+        request: ReplayRequest,
+        response: Response<ResponseBody>,
+        buffered: bool,
+    ) -> Result<Response<ResponseBody>, Error> {
+        let (parts, body) = response.into_parts();
+        let (body, recorded) = match buffered {
+            true => {
+                let bytes = body.collect().await?.to_bytes();
+                (full_body(bytes.clone()), Some(bytes))
+            }
+            false => (body, None),
+        };
+
+        let mime_type = mime_type(&parts.headers);
+        let content = match &recorded {
+            Some(body) => content(body, mime_type),
+            None => json!({ "size": -1, "mimeType": mime_type, "comment": "streamed, body not recorded" }),
+        };
+        let started_ms = request
+            .started
+            .duration_since(UNIX_EPOCH)
+            .map(|started| started.as_millis() as u64)
+            .unwrap_or_default();
+        let time_ms = request.timer.elapsed().as_secs_f64() * 1000.0;
+        let entry = json!({
+            "startedDateTime": rfc3339(started_ms),
+            "time": time_ms,
+            "request": request.request,
+            "response": {
+                "status": parts.status.as_u16(),
+                "statusText": parts.status.canonical_reason().unwrap_or_default(),
+                "httpVersion": "HTTP/1.1",
+                "cookies": [],
+                "headers": headers(&parts.headers),
+                "content": content,
+                "redirectURL": "",
+                "headersSize": -1,
+                "bodySize": recorded.as_ref().map_or(-1, |body| body.len() as i64),
+            },
+            "cache": {},
+            "timings": { "send": 0, "wait": time_ms, "receive": 0 },
+            "_requestId": request.request_id,
+        });
+        let har = json!({
+            "log": {
+                "version": "1.2",
+                "creator": { "name": "lambda-web-adapter", "version": env!("CARGO_PKG_VERSION") },
+                "entries": [entry],
+            }
+        });
+
+        // keys start with the time, so entries sort by it
+        let key = format!("{}-{}.har", started_ms, sanitize_key(&request.request_id));
+        let document = Bytes::from(serde_json::to_vec(&har).unwrap_or_default());
+        match self.target.write(&key, document).await {
+            Ok(location) => tracing::debug!(request_id = request.request_id, location, "captured replay entry"),
+            Err(err) => tracing::warn!(request_id = request.request_id, error = %err, "failed to capture replay entry"),
+        }
+
+        Ok(Response::from_parts(parts, body))
+    }
+}
+
+/// The request half of an entry, taken before the adapter consumes the event.
+pub(crate) struct ReplayRequest {
+    started: SystemTime,
+    timer: Instant,
+    request_id: String,
+    request: Value,
+}
+
+impl ReplayRequest {
+    fn new(event: &Request) -> Self {
+        let uri = event.uri();
+        let query: Vec<(String, String)> = uri
+            .query()
+            .map(|query| {
+                url::form_urlencoded::parse(query.as_bytes())
+                    .map(|(name, value)| match is_sensitive_param(&name.to_lowercase()) {
+                        true => (name.to_string(), REDACTED.to_string()),
+                        false => (name.to_string(), value.to_string()),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut url = format!(
+            "{}://{}{}",
+            uri.scheme_str().unwrap_or("https"),
+            uri.authority()
+                .map(|authority| authority.as_str())
+                .unwrap_or("localhost"),
+            event.raw_http_path()
+        );
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(
+                &url::form_urlencoded::Serializer::new(String::new())
+                    .extend_pairs(&query)
+                    .finish(),
+            );
+        }
+
+        let body = event.body().as_ref();
+        let mut request = json!({
+            "method": event.method().as_str(),
+            "url": url,
+            "httpVersion": "HTTP/1.1",
+            "cookies": [],
+            "headers": headers(event.headers()),
+            "queryString": query
+                .iter()
+                .map(|(name, value)| json!({ "name": name, "value": value }))
+                .collect::<Vec<_>>(),
+            "headersSize": -1,
+            "bodySize": body.len(),
+        });
+        if !body.is_empty() {
+            request["postData"] = content(body, mime_type(event.headers()));
+        }
+
+        ReplayRequest {
+            started: SystemTime::now(),
+            timer: Instant::now(),
+            request_id: event
+                .lambda_context_ref()
+                .map(|context| context.request_id.clone())
+                .unwrap_or_default(),
+            request,
+        }
+    }
+}
+
+/// Whether the value of a query parameter, by its lowercase name, is a
+/// credential.
+pub(crate) fn is_sensitive_param(name: &str) -> bool {
+    SENSITIVE_PARAMS.iter().any(|word| name.contains(word))
+}
+
+/// HAR headers, with credentials and cookies redacted.
+fn headers(headers: &HeaderMap) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = match SENSITIVE_HEADERS.contains(&name.as_str()) || name == "set-cookie" {
+                true => REDACTED.to_string(),
+                false => String::from_utf8_lossy(value.as_bytes()).to_string(),
+            };
+            json!({ "name": name.as_str(), "value": value })
+        })
+        .collect()
+}
+
+fn mime_type(headers: &HeaderMap) -> &str {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
+
+/// A HAR content or post data object: text bodies as they are, binary ones
+/// in base64, large ones by their size only.
+fn content(body: &[u8], mime_type: &str) -> Value {
+    if body.len() > MAX_BODY_BYTES {
+        return json!({ "size": body.len(), "mimeType": mime_type, "comment": "over the capture limit, body not recorded" });
+    }
+    match std::str::from_utf8(body) {
+        Ok(text) => json!({ "size": body.len(), "mimeType": mime_type, "text": text }),
+        Err(_) => json!({
+            "size": body.len(),
+            "mimeType": mime_type,
+            "text": base64::engine::general_purpose::STANDARD.encode(body),
+            "encoding": "base64",
+        }),
+    }
+}
+
+/// `2024-05-01T12:34:56.789Z` for milliseconds since the epoch.
+pub(crate) fn rfc3339(millis: u64) -> String {
+    let (days, rest) = ((millis / 86_400_000) as i64, millis % 86_400_000);
+    // civil from days, http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        rest / 3_600_000,
+        rest / 60_000 % 60,
+        rest / 1000 % 60,
+        rest % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!("1970-01-01T00:00:00.000Z", rfc3339(0));
+        assert_eq!("2000-02-29T23:59:59.999Z", rfc3339(951_868_799_999));
+        assert_eq!("2024-05-01T12:34:56.789Z", rfc3339(1_714_566_896_789));
+    }
+
+    #[test]
+    fn test_replay_content() {
+        assert_eq!(
+            json!({ "size": 5, "mimeType": "text/plain", "text": "hello" }),
+            content(b"hello", "text/plain")
+        );
+        assert_eq!(json!("base64"), content(&[0xff, 0xfe], "image/png")["encoding"]);
+        assert_eq!(
+            Value::Null,
+            content(&vec![b'a'; MAX_BODY_BYTES + 1], "text/plain")["text"]
+        );
+    }
+
+    #[test]
+    fn test_replay_headers_are_sanitized() {
+        let mut map = HeaderMap::new();
+        map.insert("authorization", HeaderValue::from_static("Bearer secret"));
+        map.insert("set-cookie", HeaderValue::from_static("session=secret"));
+        map.insert("accept", HeaderValue::from_static("application/json"));
+        assert_eq!(
+            vec![
+                json!({ "name": "authorization", "value": REDACTED }),
+                json!({ "name": "set-cookie", "value": REDACTED }),
+                json!({ "name": "accept", "value": "application/json" }),
+            ],
+            headers(&map)
+        );
+    }
+}
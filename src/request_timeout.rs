@@ -0,0 +1,114 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A deadline for the request to the app, so a hung app is answered with a
+//! `504 Gateway Timeout` instead of running into the function timeout.
+//!
+//! The timeout is a fixed number of milliseconds, or `auto` to end the
+//! request shortly before the Lambda deadline. Either way, it ends early
+//! enough before the deadline to leave time for sending the response.
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use http::StatusCode;
+use lambda_http::{Error, Response};
+
+use crate::maintenance::page_content_type;
+use crate::{full_body, ResponseBody};
+
+#[derive(Debug, PartialEq)]
+enum Limit {
+    Fixed(Duration),
+    /// Only the Lambda deadline bounds the request.
+    Auto,
+}
+
+#[derive(Debug)]
+pub(crate) struct RequestTimeout {
+    limit: Limit,
+    /// Time kept back before the Lambda deadline to send the response.
+    margin: Duration,
+    page: Option<(Bytes, &'static str)>,
+}
+
+impl RequestTimeout {
+    pub(crate) fn new(timeout: &str, margin: Duration, page: Option<&str>) -> Result<Self, Error> {
+        let limit = match timeout.trim() {
+            "auto" => Limit::Auto,
+            ms => Limit::Fixed(Duration::from_millis(
+                ms.parse().map_err(|_| Error::from(format!("invalid timeout {ms:?}")))?,
+            )),
+        };
+        Ok(RequestTimeout {
+            limit,
+            margin,
+            page: page.map(|page| (Bytes::from(page.to_string()), page_content_type(page))),
+        })
+    }
+
+    /// How long the request to the app may take, given the time left before
+    /// the Lambda deadline. `None` means no limit.
+    pub(crate) fn limit(&self, remaining: Option<Duration>) -> Option<Duration> {
+        let before_deadline = remaining.map(|remaining| remaining.saturating_sub(self.margin));
+        match (&self.limit, before_deadline) {
+            (Limit::Fixed(limit), Some(before_deadline)) => Some((*limit).min(before_deadline)),
+            (Limit::Fixed(limit), None) => Some(*limit),
+            (Limit::Auto, before_deadline) => before_deadline,
+        }
+    }
+
+    /// The configured response to a request that timed out.
+    pub(crate) fn page(&self) -> Option<Result<Response<ResponseBody>, Error>> {
+        let (body, content_type) = self.page.as_ref()?;
+        Some(
+            Response::builder()
+                .status(StatusCode::GATEWAY_TIMEOUT)
+                .header(CONTENT_TYPE, *content_type)
+                .header(CACHE_CONTROL, "no-store")
+                .body(full_body(body.clone()))
+                .map_err(Error::from),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_timeout_limit() {
+        let margin = Duration::from_millis(500);
+        let fixed = RequestTimeout::new("3000", margin, None).unwrap();
+        assert_eq!(Some(Duration::from_secs(3)), fixed.limit(None));
+        assert_eq!(Some(Duration::from_secs(3)), fixed.limit(Some(Duration::from_secs(10))));
+        assert_eq!(
+            Some(Duration::from_millis(1500)),
+            fixed.limit(Some(Duration::from_secs(2)))
+        );
+
+        let auto = RequestTimeout::new("auto", margin, None).unwrap();
+        assert_eq!(None, auto.limit(None));
+        assert_eq!(
+            Some(Duration::from_millis(9500)),
+            auto.limit(Some(Duration::from_secs(10)))
+        );
+        assert_eq!(Some(Duration::ZERO), auto.limit(Some(Duration::from_millis(100))));
+
+        assert!(RequestTimeout::new("soon", margin, None).is_err());
+    }
+
+    #[test]
+    fn test_request_timeout_page() {
+        let timeout = RequestTimeout::new("auto", Duration::ZERO, Some("<h1>Try again</h1>")).unwrap();
+        let page = timeout.page().unwrap().unwrap();
+        assert_eq!(StatusCode::GATEWAY_TIMEOUT, page.status());
+        assert_eq!("text/html; charset=utf-8", page.headers()[CONTENT_TYPE]);
+
+        assert!(RequestTimeout::new("auto", Duration::ZERO, None)
+            .unwrap()
+            .page()
+            .is_none());
+    }
+}
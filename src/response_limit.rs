@@ -0,0 +1,264 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A limit on the size of response bodies. Lambda fails buffered responses
+//! over its 6MB payload limit with a generic error, after the app has done
+//! all the work; the limit decides what the client gets instead.
+//!
+//! Responses within the limit pass untouched. Larger responses are cut at the
+//! limit, answered with a `502 Bad Gateway` that says why, or end in an error
+//! after the bytes within the limit, depending on the overflow policy.
+
+use std::fmt;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use bytes::Bytes;
+use http::header::{HeaderName, HeaderValue, CONTENT_LENGTH};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::BodyExt;
+use lambda_http::{Error, Response};
+
+use crate::{full_body, ResponseBody};
+
+/// What happens to a response over the limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Overflow {
+    /// Send the bytes within the limit, marked with `x-lwa-response-truncated`.
+    Truncate,
+    /// Answer with a `502 Bad Gateway` instead.
+    Reject,
+    /// Send the bytes within the limit, then fail the body.
+    Error,
+}
+
+/// The error a body over the limit fails with.
+#[derive(Debug)]
+pub(crate) struct ResponseTooLarge(u64);
+
+impl fmt::Display for ResponseTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the app's response is larger than the {} byte limit", self.0)
+    }
+}
+
+impl std::error::Error for ResponseTooLarge {}
+
+#[derive(Debug)]
+pub(crate) struct ResponseLimit {
+    max_bytes: u64,
+    overflow: Overflow,
+}
+
+impl ResponseLimit {
+    pub(crate) fn new(max_bytes: u64, overflow: &str) -> Result<Self, Error> {
+        let overflow = match overflow.trim().to_lowercase().as_str() {
+            "truncate" => Overflow::Truncate,
+            "reject" | "502" => Overflow::Reject,
+            "error" => Overflow::Error,
+            other => return Err(Error::from(format!("invalid overflow policy {other:?}"))),
+        };
+        Ok(ResponseLimit { max_bytes, overflow })
+    }
+
+    /// Apply the limit to a response, or `Err` with [`ResponseTooLarge`] when
+    /// it is rejected. Buffered responses are read here, since Lambda reads
+    /// them whole anyway, so even those without a `content-length` can be
+    /// rejected or marked as truncated. Streamed responses that turn out too
+    /// large after their headers were sent can only end in an error.
+    pub(crate) async fn apply(
+        &self,
+        response: Response<ResponseBody>,
+        buffered: bool,
+    ) -> Result<Response<ResponseBody>, Error> {
+        let length = response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        if length.is_some_and(|length| length <= self.max_bytes) {
+            return Ok(response);
+        }
+        let too_large = length.is_some();
+
+        match self.overflow {
+            Overflow::Reject if too_large => Err(ResponseTooLarge(self.max_bytes).into()),
+            Overflow::Truncate if too_large => {
+                let (mut parts, body) = response.into_parts();
+                parts.headers.insert(CONTENT_LENGTH, self.max_bytes.into());
+                parts.headers.insert(TRUNCATED, HeaderValue::from_static("true"));
+                Ok(Response::from_parts(parts, limited(body, self.max_bytes, true)))
+            }
+            Overflow::Reject | Overflow::Truncate if buffered => {
+                let (mut parts, body) = response.into_parts();
+                // one byte over the limit tells a truncated body from one that just fits
+                let truncate = self.overflow == Overflow::Truncate;
+                let mut bytes = limited(body, self.max_bytes + truncate as u64, truncate)
+                    .collect()
+                    .await?
+                    .to_bytes();
+                if bytes.len() as u64 > self.max_bytes {
+                    bytes.truncate(self.max_bytes as usize);
+                    parts.headers.insert(TRUNCATED, HeaderValue::from_static("true"));
+                }
+                parts.headers.insert(CONTENT_LENGTH, bytes.len().into());
+                Ok(Response::from_parts(parts, full_body(bytes)))
+            }
+            overflow => Ok(response.map(|body| limited(body, self.max_bytes, overflow == Overflow::Truncate))),
+        }
+    }
+}
+
+fn limited(inner: ResponseBody, max_bytes: u64, truncate: bool) -> ResponseBody {
+    LimitedBody {
+        inner,
+        max_bytes,
+        remaining: max_bytes,
+        truncate,
+        done: false,
+    }
+    .boxed_unsync()
+}
+
+const TRUNCATED: HeaderName = HeaderName::from_static("x-lwa-response-truncated");
+
+/// Passes a body through up to `max_bytes`, then ends it or fails it.
+struct LimitedBody {
+    inner: ResponseBody,
+    max_bytes: u64,
+    remaining: u64,
+    truncate: bool,
+    done: bool,
+}
+
+impl Body for LimitedBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        let Some(Ok(frame)) = frame else {
+            return Poll::Ready(frame);
+        };
+        let mut data = match frame.into_data() {
+            Ok(data) => data,
+            Err(frame) => return Poll::Ready(Some(Ok(frame))),
+        };
+        if data.len() as u64 <= self.remaining {
+            self.remaining -= data.len() as u64;
+            return Poll::Ready(Some(Ok(Frame::data(data))));
+        }
+
+        self.done = true;
+        if !self.truncate {
+            tracing::error!(max_bytes = self.max_bytes, "ending a response over the size limit");
+            return Poll::Ready(Some(Err(ResponseTooLarge(self.max_bytes).into())));
+        }
+        tracing::warn!(max_bytes = self.max_bytes, "truncating a response over the size limit");
+        data.truncate(self.remaining as usize);
+        self.remaining = 0;
+        Poll::Ready((!data.is_empty()).then(|| Ok(Frame::data(data))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done || self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.inner.size_hint().exact() {
+            Some(exact) if exact <= self.remaining => SizeHint::with_exact(exact),
+            _ => SizeHint::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunked_body;
+
+    fn response(length: Option<usize>, chunks: &[&'static str]) -> Response<ResponseBody> {
+        let mut response = Response::new(chunked_body(chunks));
+        if let Some(length) = length {
+            response.headers_mut().insert(CONTENT_LENGTH, length.into());
+        }
+        response
+    }
+
+    async fn body(response: Response<ResponseBody>) -> Result<Bytes, Error> {
+        Ok(response.into_body().collect().await?.to_bytes())
+    }
+
+    fn is_too_large(err: &Error) -> bool {
+        err.downcast_ref::<ResponseTooLarge>().is_some()
+    }
+
+    #[tokio::test]
+    async fn test_response_limit_passes_small_responses() {
+        let limit = ResponseLimit::new(10, "reject").unwrap();
+        for buffered in [true, false] {
+            let response = limit
+                .apply(response(None, &["hello", "world"]), buffered)
+                .await
+                .unwrap();
+            assert!(!response.headers().contains_key(TRUNCATED));
+            assert_eq!("helloworld", body(response).await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_response_limit_reject() {
+        let limit = ResponseLimit::new(8, "reject").unwrap();
+        let err = limit
+            .apply(response(Some(10), &["hello", "world"]), false)
+            .await
+            .unwrap_err();
+        assert!(is_too_large(&err));
+
+        // buffered responses without a length are read to find out
+        let err = limit
+            .apply(response(None, &["hello", "world"]), true)
+            .await
+            .unwrap_err();
+        assert!(is_too_large(&err));
+
+        // streamed ones can only fail after their headers
+        let response = limit.apply(response(None, &["hello", "world"]), false).await.unwrap();
+        assert!(is_too_large(&body(response).await.unwrap_err()));
+    }
+
+    #[tokio::test]
+    async fn test_response_limit_truncate() {
+        let limit = ResponseLimit::new(8, "truncate").unwrap();
+        for (length, buffered) in [(Some(10), false), (None, true)] {
+            let response = limit
+                .apply(response(length, &["hello", "world"]), buffered)
+                .await
+                .unwrap();
+            assert_eq!("true", response.headers()[TRUNCATED]);
+            assert_eq!("8", response.headers()[CONTENT_LENGTH]);
+            assert_eq!("hellowor", body(response).await.unwrap());
+        }
+
+        let response = limit.apply(response(None, &["hello", "world"]), false).await.unwrap();
+        assert_eq!("hellowor", body(response).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_response_limit_error() {
+        let limit = ResponseLimit::new(8, "error").unwrap();
+        let response = limit
+            .apply(response(Some(10), &["hello", "world"]), true)
+            .await
+            .unwrap();
+        let mut body = response.into_body();
+        assert_eq!("hello", body.frame().await.unwrap().unwrap().into_data().unwrap());
+        assert!(is_too_large(&body.frame().await.unwrap().unwrap_err()));
+        assert!(body.frame().await.is_none());
+
+        assert!(ResponseLimit::new(8, "drop").is_err());
+    }
+}
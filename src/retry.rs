@@ -0,0 +1,142 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use http::Method;
+use lambda_http::Error;
+
+/// A retry budget shared by every retry made while serving one invocation:
+/// upstream retries of failed requests and hedged requests.
+///
+/// The budget is bounded by a maximum number of retries and by the Lambda
+/// deadline, so layered retry features cannot multiply into timeouts.
+pub(crate) struct RetryBudget {
+    max_retries: Option<u32>,
+    used: AtomicU32,
+    deadline: Option<Instant>,
+}
+
+impl RetryBudget {
+    /// Create a budget for an invocation. `deadline_ms` is the Lambda deadline
+    /// in milliseconds since the epoch; deadlines already in the past are
+    /// treated as unknown.
+    pub(crate) fn new(max_retries: Option<u32>, deadline_ms: u64) -> Self {
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_millis() as u64)
+            .unwrap_or_default();
+        let deadline = deadline_ms
+            .checked_sub(now_ms)
+            .filter(|remaining| *remaining > 0)
+            .map(|remaining| Instant::now() + Duration::from_millis(remaining));
+
+        RetryBudget {
+            max_retries,
+            used: AtomicU32::new(0),
+            deadline,
+        }
+    }
+
+    /// Time left before the Lambda deadline, if it is known.
+    pub(crate) fn remaining_time(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Take one retry from the budget, to be made after `delay`. Returns
+    /// `false` when the budget is exhausted or the delay would run past the
+    /// deadline.
+    pub(crate) fn acquire(&self, delay: Duration) -> bool {
+        if self.remaining_time().is_some_and(|remaining| remaining <= delay) {
+            tracing::debug!("retry budget exhausted by the invocation deadline");
+            return false;
+        }
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| match self.max_retries {
+                Some(max) if used >= max => None,
+                _ => Some(used + 1),
+            })
+            .is_ok()
+    }
+}
+
+/// Retries of requests to the app that fail without a response, such as
+/// when the app recycles a worker and drops its connections.
+///
+/// A request that failed to connect never reached the app, so it is retried
+/// whatever its method. Other failures, such as a connection closed before
+/// the response, are only retried for idempotent methods.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct UpstreamRetry {
+    pub(crate) retries: u32,
+    pub(crate) backoff: Duration,
+}
+
+impl UpstreamRetry {
+    pub(crate) fn should_retry(method: &Method, err: &Error) -> bool {
+        let connect = err
+            .downcast_ref::<hyper_util::client::legacy::Error>()
+            .is_some_and(|err| err.is_connect());
+        connect || is_idempotent(method)
+    }
+
+    /// The delay before retry `attempt`, counted from 1, doubling each time.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        self.backoff.saturating_mul(1 << (attempt - 1).min(16))
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE | Method::TRACE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upstream_retry() {
+        let retry = UpstreamRetry {
+            retries: 3,
+            backoff: Duration::from_millis(50),
+        };
+        assert_eq!(Duration::from_millis(50), retry.delay(1));
+        assert_eq!(Duration::from_millis(200), retry.delay(3));
+
+        let err = Error::from("connection closed before message completed");
+        assert!(UpstreamRetry::should_retry(&Method::GET, &err));
+        assert!(UpstreamRetry::should_retry(&Method::PUT, &err));
+        assert!(!UpstreamRetry::should_retry(&Method::POST, &err));
+        assert!(!UpstreamRetry::should_retry(&Method::PATCH, &err));
+    }
+
+    #[test]
+    fn test_retry_budget_max_retries() {
+        let budget = RetryBudget::new(Some(2), 0);
+        assert!(budget.remaining_time().is_none());
+        assert!(budget.acquire(Duration::ZERO));
+        assert!(budget.acquire(Duration::from_secs(60)));
+        assert!(!budget.acquire(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_retry_budget_deadline() {
+        let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+
+        let budget = RetryBudget::new(None, now_ms + 60_000);
+        let remaining = budget.remaining_time().unwrap();
+        assert!(remaining > Duration::from_secs(59) && remaining <= Duration::from_secs(60));
+        assert!(budget.acquire(Duration::from_secs(1)));
+        assert!(!budget.acquire(Duration::from_secs(61)));
+
+        // no time left for any retry
+        let budget = RetryBudget::new(None, now_ms + 200);
+        std::thread::sleep(Duration::from_millis(250));
+        assert!(!budget.acquire(Duration::ZERO));
+    }
+}
@@ -0,0 +1,499 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use http::{HeaderMap, HeaderName, HeaderValue, Method};
+use lambda_http::Error;
+use url::Url;
+
+use crate::glob_match;
+
+/// Splits traffic between several ports of the app by weight, e.g. between
+/// a blue and a green version running in the same container.
+#[derive(Clone, Debug)]
+pub(crate) struct TrafficSplit {
+    targets: Vec<(Url, u64)>,
+    total: u64,
+    sticky_header: Option<HeaderName>,
+    affinity_cookie: Option<String>,
+}
+
+impl TrafficSplit {
+    /// Parse a JSON object of ports to weights, such as `{"8080":90,"9090":10}`,
+    /// for ports of `origin`, such as `http://127.0.0.1`.
+    pub(crate) fn new(split: &str, origin: &str, sticky_header: Option<&str>) -> Result<Self, Error> {
+        let split: serde_json::Map<String, serde_json::Value> = serde_json::from_str(split)?;
+        let mut targets = Vec::with_capacity(split.len());
+        for (port, weight) in split {
+            let port: u16 = port
+                .parse()
+                .map_err(|_| format!("invalid traffic split port: {port}"))?;
+            let weight = weight
+                .as_u64()
+                .ok_or_else(|| format!("invalid traffic split weight for port {port}: {weight}"))?;
+            targets.push((format!("{origin}:{port}").parse()?, weight));
+        }
+
+        let total = targets.iter().map(|(_, weight)| weight).sum();
+        if total == 0 {
+            return Err(Error::from("traffic split weights must add up to more than 0"));
+        }
+        let sticky_header = sticky_header.map(HeaderName::try_from).transpose()?;
+
+        Ok(TrafficSplit {
+            targets,
+            total,
+            sticky_header,
+            affinity_cookie: None,
+        })
+    }
+
+    /// Keep each client on the port it was first sent to, with a cookie the
+    /// adapter sets on the first response.
+    pub(crate) fn with_affinity_cookie(mut self, name: &str) -> Self {
+        self.affinity_cookie = Some(name.to_string());
+        self
+    }
+
+    /// Pick the target for a request, and the `set-cookie` header to add to
+    /// its response when the request did not carry a valid affinity cookie.
+    pub(crate) fn route(&self, headers: &HeaderMap) -> (&Url, Option<HeaderValue>) {
+        let Some(name) = self.affinity_cookie.as_deref() else {
+            return (self.pick(headers), None);
+        };
+        if let Some(url) = self.affinity_target(name, headers) {
+            return (url, None);
+        }
+        let url = self.pick(headers);
+        let port = url.port().unwrap_or_default();
+        let cookie = HeaderValue::from_str(&format!("{name}={port}; Path=/; HttpOnly; SameSite=Lax")).ok();
+        (url, cookie)
+    }
+
+    /// The target named by the affinity cookie, unless it no longer gets traffic.
+    fn affinity_target(&self, name: &str, headers: &HeaderMap) -> Option<&Url> {
+        let port: u16 = headers
+            .get_all(http::header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(cookie, _)| *cookie == name)
+            .and_then(|(_, port)| port.parse().ok())?;
+        self.targets
+            .iter()
+            .find(|(url, weight)| *weight > 0 && url.port() == Some(port))
+            .map(|(url, _)| url)
+    }
+
+    /// Pick the target for a request. Requests with the same sticky header
+    /// value always go to the same target.
+    pub(crate) fn pick(&self, headers: &HeaderMap) -> &Url {
+        let sticky = self
+            .sticky_header
+            .as_ref()
+            .and_then(|name| headers.get(name))
+            .map(|value| fnv1a(value.as_bytes()));
+        let mut point = sticky.unwrap_or_else(|| fastrand::u64(..)) % self.total;
+
+        for (url, weight) in &self.targets {
+            if point < *weight {
+                return url;
+            }
+            point -= weight;
+        }
+        &self.targets[self.targets.len() - 1].0
+    }
+}
+
+/// Where requests for a host are sent.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct HostRoute {
+    /// The app port for this host, instead of the default port.
+    pub(crate) upstream: Option<Url>,
+    /// A path prefix added in front of the request path.
+    pub(crate) base_path: Option<String>,
+}
+
+/// Routes requests by their `Host` header, so one function can serve several
+/// custom domains with tenant-specific ports and base paths.
+#[derive(Clone, Debug)]
+pub(crate) struct HostRoutes {
+    routes: Vec<(String, HostRoute)>,
+}
+
+impl HostRoutes {
+    /// Parse a JSON object of hosts to routes, such as
+    /// `{"a.example.com":{"port":8081},"*.example.org":{"base_path":"/tenant-b"}}`,
+    /// for ports of `origin`, such as `http://127.0.0.1`.
+    pub(crate) fn new(routes: &str, origin: &str) -> Result<Self, Error> {
+        let routes: serde_json::Map<String, serde_json::Value> = serde_json::from_str(routes)?;
+        let routes = routes
+            .into_iter()
+            .map(|(pattern, route)| {
+                let port = route.get("port").map(|port| match port {
+                    serde_json::Value::Number(port) => port.as_u64().and_then(|port| u16::try_from(port).ok()),
+                    serde_json::Value::String(port) => port.parse().ok(),
+                    _ => None,
+                });
+                let upstream = match port {
+                    Some(Some(port)) => Some(format!("{origin}:{port}").parse()?),
+                    Some(None) => return Err(Error::from(format!("invalid port for host route {pattern}"))),
+                    None => None,
+                };
+                let base_path = route
+                    .get("base_path")
+                    .and_then(|base_path| base_path.as_str())
+                    .map(|base_path| format!("/{}", base_path.trim_matches('/')));
+                if upstream.is_none() && base_path.is_none() {
+                    return Err(Error::from(format!("host route {pattern} needs a port or a base_path")));
+                }
+                Ok((pattern.to_lowercase(), HostRoute { upstream, base_path }))
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(HostRoutes { routes })
+    }
+
+    /// The route for a request, matched on its `Host` header. Exact hosts take
+    /// precedence over `*.` wildcard patterns.
+    pub(crate) fn route(&self, headers: &HeaderMap) -> Option<&HostRoute> {
+        let host = headers.get(http::header::HOST)?.to_str().ok()?;
+        let host = host.split(':').next().unwrap_or_default().to_lowercase();
+
+        self.routes
+            .iter()
+            .find(|(pattern, _)| *pattern == host)
+            .or_else(|| {
+                self.routes.iter().find(|(pattern, _)| {
+                    pattern
+                        .strip_prefix('*')
+                        .is_some_and(|suffix| suffix.starts_with('.') && host.ends_with(suffix))
+                })
+            })
+            .map(|(_, route)| route)
+    }
+}
+
+/// Routes requests by their method, for images that run separate processes
+/// for reads and writes, e.g. GETs to a read replica on another port.
+#[derive(Clone, Debug)]
+pub(crate) struct MethodRoutes {
+    routes: Vec<(Method, Url)>,
+}
+
+impl MethodRoutes {
+    /// Parse a JSON object of methods to ports, such as `{"GET":8081,"HEAD":8081}`,
+    /// for ports of `origin`, such as `http://127.0.0.1`.
+    pub(crate) fn new(routes: &str, origin: &str) -> Result<Self, Error> {
+        let routes: serde_json::Map<String, serde_json::Value> = serde_json::from_str(routes)?;
+        let routes = routes
+            .into_iter()
+            .map(|(method, port)| {
+                let method = Method::from_bytes(method.to_uppercase().as_bytes())
+                    .map_err(|_| Error::from(format!("invalid method route {method}")))?;
+                let port = match &port {
+                    serde_json::Value::Number(port) => port.as_u64().and_then(|port| u16::try_from(port).ok()),
+                    serde_json::Value::String(port) => port.parse().ok(),
+                    _ => None,
+                }
+                .ok_or_else(|| Error::from(format!("invalid port for method route {method}: {port}")))?;
+                Ok((method, format!("{origin}:{port}").parse()?))
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(MethodRoutes { routes })
+    }
+
+    /// The app port for a method, or `None` for the default port.
+    pub(crate) fn route(&self, method: &Method) -> Option<&Url> {
+        self.routes
+            .iter()
+            .find(|(route, _)| route == method)
+            .map(|(_, url)| url)
+    }
+}
+
+/// Routes paths to companion extensions running next to the app, such as a
+/// telemetry collector, so the adapter is the single local ingress of the
+/// execution environment.
+///
+/// Sidecars are found by name in a registry file that extensions write when
+/// they start, `{"otel":{"port":4318}}`, or in `AWS_LWA_SIDECAR_<NAME>_PORT`
+/// variables. The registry is read again whenever it changes, since
+/// extensions may register after the adapter started.
+#[derive(Debug)]
+pub(crate) struct SidecarRoutes {
+    routes: Vec<(String, String)>,
+    ports: HashMap<String, u16>,
+    registry: Option<PathBuf>,
+    registered: Mutex<(Option<SystemTime>, HashMap<String, Url>)>,
+}
+
+impl SidecarRoutes {
+    /// Parse a JSON object of path patterns to sidecar names, such as `{"/otel/*":"otel"}`.
+    pub(crate) fn new(routes: &str, registry: Option<&str>, ports: &HashMap<String, u16>) -> Result<Self, Error> {
+        let routes: serde_json::Map<String, serde_json::Value> = serde_json::from_str(routes)?;
+        let routes = routes
+            .into_iter()
+            .map(|(pattern, name)| match name.as_str() {
+                Some(name) if !name.is_empty() => Ok((pattern, name.to_lowercase())),
+                _ => Err(Error::from(format!("invalid sidecar for path {pattern}: {name}"))),
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(SidecarRoutes {
+            routes,
+            ports: ports.iter().map(|(name, port)| (name.to_lowercase(), *port)).collect(),
+            registry: registry.map(PathBuf::from),
+            registered: Mutex::new((None, HashMap::new())),
+        })
+    }
+
+    /// The sidecar for a request path, and the path to send it to. Patterns
+    /// ending in `/*` mount the sidecar there, so `/otel/*` sends `/otel/v1/traces`
+    /// to `/v1/traces`.
+    pub(crate) fn route(&self, path: &str) -> Option<(&str, String)> {
+        let (pattern, name) = self.routes.iter().find(|(pattern, _)| glob_match(pattern, path))?;
+        let path = match pattern.strip_suffix("/*") {
+            Some(mount) => match path.strip_prefix(mount).unwrap_or(path) {
+                "" => "/",
+                path => path,
+            },
+            None => path,
+        };
+        Some((name, path.to_string()))
+    }
+
+    /// Where a sidecar listens, or `None` while it has not registered.
+    pub(crate) fn resolve(&self, name: &str) -> Option<Url> {
+        if let Some(registry) = &self.registry {
+            let modified = std::fs::metadata(registry)
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            let mut registered = self.registered.lock().unwrap();
+            if registered.0 != modified {
+                *registered = (modified, read_registry(registry));
+            }
+            if let Some(url) = registered.1.get(name) {
+                return Some(url.clone());
+            }
+        }
+        let port = self.ports.get(name)?;
+        format!("http://127.0.0.1:{port}").parse().ok()
+    }
+}
+
+/// Read a registry of sidecar names to ports, or to objects with a `port` and
+/// an optional `host`. Entries that cannot be read are skipped.
+fn read_registry(registry: &Path) -> HashMap<String, Url> {
+    let sidecars = match std::fs::read(registry).map(|registry| serde_json::from_slice(&registry)) {
+        Ok(Ok(serde_json::Value::Object(sidecars))) => sidecars,
+        Ok(_) => {
+            tracing::warn!(registry = %registry.display(), "ignoring invalid sidecar registry");
+            return HashMap::new();
+        }
+        Err(_) => return HashMap::new(),
+    };
+    sidecars
+        .into_iter()
+        .filter_map(|(name, sidecar)| {
+            let (host, port) = match &sidecar {
+                serde_json::Value::Object(sidecar) => (
+                    sidecar
+                        .get("host")
+                        .and_then(|host| host.as_str())
+                        .unwrap_or("127.0.0.1"),
+                    sidecar.get("port")?,
+                ),
+                port => ("127.0.0.1", port),
+            };
+            let port = match port {
+                serde_json::Value::Number(port) => port.as_u64().and_then(|port| u16::try_from(port).ok()),
+                serde_json::Value::String(port) => port.parse().ok(),
+                _ => None,
+            }?;
+            let url = format!("http://{host}:{port}").parse().ok()?;
+            Some((name.to_lowercase(), url))
+        })
+        .collect()
+}
+
+/// A stable hash, so sticky requests land on the same target in every execution environment.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    #[test]
+    fn test_traffic_split_weights() {
+        let split = TrafficSplit::new(r#"{"8080":100,"9090":0}"#, "http://127.0.0.1", None).unwrap();
+        for _ in 0..100 {
+            assert_eq!(Some(8080), split.pick(&HeaderMap::new()).port());
+        }
+
+        let split = TrafficSplit::new(r#"{"8080":50,"9090":50}"#, "http://127.0.0.1", None).unwrap();
+        let green = (0..1000)
+            .filter(|_| split.pick(&HeaderMap::new()).port() == Some(9090))
+            .count();
+        assert!((350..650).contains(&green));
+    }
+
+    #[test]
+    fn test_traffic_split_sticky_header() {
+        let split = TrafficSplit::new(r#"{"8080":50,"9090":50}"#, "http://127.0.0.1", Some("x-user-id")).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-user-id", HeaderValue::from_static("user-1"));
+
+        let port = split.pick(&headers).port();
+        for _ in 0..100 {
+            assert_eq!(port, split.pick(&headers).port());
+        }
+    }
+
+    #[test]
+    fn test_traffic_split_affinity_cookie() {
+        let split = TrafficSplit::new(r#"{"8080":50,"9090":50,"7070":0}"#, "http://127.0.0.1", None)
+            .unwrap()
+            .with_affinity_cookie("lwa-worker");
+
+        let (url, cookie) = split.route(&HeaderMap::new());
+        let port = url.port().unwrap();
+        assert_eq!(
+            format!("lwa-worker={port}; Path=/; HttpOnly; SameSite=Lax"),
+            cookie.unwrap().to_str().unwrap()
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            http::header::COOKIE,
+            HeaderValue::from_str(&format!("theme=dark; lwa-worker={port}")).unwrap(),
+        );
+        for _ in 0..100 {
+            assert_eq!((Some(port), None), {
+                let (url, cookie) = split.route(&headers);
+                (url.port(), cookie)
+            });
+        }
+
+        // ports without traffic and unknown ports get a new cookie
+        for stale in ["lwa-worker=7070", "lwa-worker=1234", "lwa-worker=blue"] {
+            headers.insert(http::header::COOKIE, HeaderValue::from_static(stale));
+            assert!(split.route(&headers).1.is_some());
+        }
+    }
+
+    #[test]
+    fn test_host_routes() {
+        let routes = HostRoutes::new(
+            r#"{"a.example.com":{"port":8081},"*.example.org":{"base_path":"tenant-b/"},"b.example.org":{"port":"8082","base_path":"/b"}}"#,
+            "http://127.0.0.1",
+        )
+        .unwrap();
+        let route = |host: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(http::header::HOST, HeaderValue::from_static(host));
+            routes.route(&headers).cloned()
+        };
+
+        let a = route("A.example.com:443").unwrap();
+        assert_eq!(Some(8081), a.upstream.unwrap().port());
+        assert_eq!(None, a.base_path);
+
+        let tenant = route("tenant.example.org").unwrap();
+        assert_eq!(None, tenant.upstream);
+        assert_eq!(Some("/tenant-b".to_string()), tenant.base_path);
+
+        // exact hosts win over wildcards
+        let b = route("b.example.org").unwrap();
+        assert_eq!(Some(8082), b.upstream.unwrap().port());
+
+        assert_eq!(None, route("example.org"));
+        assert_eq!(None, route("other.com"));
+        assert_eq!(None, routes.route(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn test_host_routes_invalid() {
+        assert!(HostRoutes::new(r#"{"a.example.com":{}}"#, "http://127.0.0.1").is_err());
+        assert!(HostRoutes::new(r#"{"a.example.com":{"port":99999}}"#, "http://127.0.0.1").is_err());
+    }
+
+    #[test]
+    fn test_method_routes() {
+        let routes = MethodRoutes::new(r#"{"get":8081,"HEAD":"8081","POST":8082}"#, "http://127.0.0.1").unwrap();
+        assert_eq!(Some(8081), routes.route(&Method::GET).unwrap().port());
+        assert_eq!(Some(8081), routes.route(&Method::HEAD).unwrap().port());
+        assert_eq!(Some(8082), routes.route(&Method::POST).unwrap().port());
+        assert_eq!(None, routes.route(&Method::DELETE));
+
+        assert!(MethodRoutes::new(r#"{"GET":"read"}"#, "http://127.0.0.1").is_err());
+        assert!(MethodRoutes::new(r#"{"GET":70000}"#, "http://127.0.0.1").is_err());
+        assert!(MethodRoutes::new(r#"{"G E T":8081}"#, "http://127.0.0.1").is_err());
+    }
+
+    #[test]
+    fn test_sidecar_routes() {
+        let registry = std::env::temp_dir().join(format!("lwa-sidecars-{}.json", std::process::id()));
+        std::fs::write(
+            &registry,
+            r#"{"otel":{"port":4318},"Logs":"2020","broken":{"port":"none"}}"#,
+        )
+        .unwrap();
+        let ports = HashMap::from([("XRAY".to_string(), 2000)]);
+        let routes = SidecarRoutes::new(
+            r#"{"/otel/*":"otel","/logs":"logs","/xray/*":"xray","/missing/*":"missing"}"#,
+            registry.to_str(),
+            &ports,
+        )
+        .unwrap();
+
+        assert_eq!(
+            Some(("otel", "/v1/traces".to_string())),
+            routes.route("/otel/v1/traces")
+        );
+        assert_eq!(Some(("otel", "/".to_string())), routes.route("/otel/"));
+        assert_eq!(Some(("logs", "/logs".to_string())), routes.route("/logs"));
+        assert_eq!(None, routes.route("/orders"));
+
+        assert_eq!(Some(4318), routes.resolve("otel").unwrap().port());
+        assert_eq!(Some(2020), routes.resolve("logs").unwrap().port());
+        assert_eq!(Some(2000), routes.resolve("xray").unwrap().port());
+        assert_eq!(None, routes.resolve("broken"));
+        assert_eq!(None, routes.resolve("missing"));
+
+        // sidecars that register later are found
+        std::fs::write(&registry, r#"{"otel":4318,"missing":{"host":"localhost","port":9000}}"#).unwrap();
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(1);
+        std::fs::File::options()
+            .write(true)
+            .open(&registry)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert_eq!("http://localhost:9000/", routes.resolve("missing").unwrap().as_str());
+        std::fs::remove_file(&registry).unwrap();
+    }
+
+    #[test]
+    fn test_sidecar_routes_invalid() {
+        assert!(SidecarRoutes::new(r#"{"/otel/*":4318}"#, None, &HashMap::new()).is_err());
+        assert!(SidecarRoutes::new(r#"{"/otel/*":""}"#, None, &HashMap::new()).is_err());
+        assert!(SidecarRoutes::new("/otel/*=otel", None, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_traffic_split_invalid() {
+        assert!(TrafficSplit::new(r#"{"8080":0}"#, "http://127.0.0.1", None).is_err());
+        assert!(TrafficSplit::new(r#"{"http":10}"#, "http://127.0.0.1", None).is_err());
+        assert!(TrafficSplit::new(r#"{"8080":"ten"}"#, "http://127.0.0.1", None).is_err());
+        assert!(TrafficSplit::new("8080", "http://127.0.0.1", None).is_err());
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A local endpoint compatible with the Lambda Runtime API, for functions
+//! that serve HTTP requests and handle other events in one container.
+//!
+//! HTTP requests keep going through the proxy path. Non-HTTP events, which
+//! would otherwise be posted to the pass-through path, are handed to a
+//! language runtime in the app instead: the runtime is started with
+//! `AWS_LAMBDA_RUNTIME_API` pointing at this endpoint, polls it for the next
+//! event, and posts the result back as it would to Lambda. Only the
+//! invocation routes are implemented.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use http::header::CONTENT_TYPE;
+use http::{Method, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use lambda_http::{Context, Error};
+use serde_json::Value;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+
+const NEXT_PATH: &str = "/2018-06-01/runtime/invocation/next";
+const INVOCATION_PREFIX: &str = "/2018-06-01/runtime/invocation/";
+const INIT_ERROR_PATH: &str = "/2018-06-01/runtime/init/error";
+
+/// An event waiting for the runtime to poll it.
+struct Invocation {
+    request_id: String,
+    deadline: u64,
+    function_arn: String,
+    trace_id: Option<String>,
+    payload: Bytes,
+}
+
+/// What the runtime posted for an invocation.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Response(Bytes),
+    Error(String),
+}
+
+/// Hands events to a runtime that polls the local endpoint.
+pub(crate) struct RuntimeApi {
+    sender: mpsc::UnboundedSender<Invocation>,
+    receiver: tokio::sync::Mutex<mpsc::UnboundedReceiver<Invocation>>,
+    pending: Mutex<HashMap<String, oneshot::Sender<Outcome>>>,
+}
+
+impl RuntimeApi {
+    /// Listen on `127.0.0.1:port` for the runtime.
+    pub(crate) fn new(port: u16) -> Result<Arc<Self>, Error> {
+        let listener = std::net::TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], port)))?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let api = Arc::new(RuntimeApi {
+            sender,
+            receiver: tokio::sync::Mutex::new(receiver),
+            pending: Mutex::new(HashMap::new()),
+        });
+        tokio::spawn(api.clone().serve(listener));
+        tracing::info!(port, "runtime API endpoint listening for the app's runtime");
+        Ok(api)
+    }
+
+    /// Hand an event to the runtime and wait for its result.
+    pub(crate) async fn invoke(&self, context: &Context, payload: Bytes) -> Result<Bytes, Error> {
+        let (outcome, result) = oneshot::channel();
+        self.pending.lock().unwrap().insert(context.request_id.clone(), outcome);
+        let invocation = Invocation {
+            request_id: context.request_id.clone(),
+            deadline: context.deadline,
+            function_arn: context.invoked_function_arn.clone(),
+            trace_id: context.xray_trace_id.clone(),
+            payload,
+        };
+        if self.sender.send(invocation).is_err() {
+            self.pending.lock().unwrap().remove(&context.request_id);
+            return Err(Error::from("runtime API endpoint is closed"));
+        }
+
+        match result.await {
+            Ok(Outcome::Response(response)) => Ok(response),
+            Ok(Outcome::Error(error)) => Err(Error::from(error)),
+            Err(_) => Err(Error::from("runtime API endpoint is closed")),
+        }
+    }
+
+    async fn serve(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    tracing::warn!(error = %err, "failed to accept runtime API connection");
+                    continue;
+                }
+            };
+            let api = self.clone();
+            tokio::spawn(async move {
+                let service = service_fn(|request| api.clone().route(request));
+                if let Err(err) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    tracing::debug!(error = %err, "runtime API connection closed");
+                }
+            });
+        }
+    }
+
+    async fn route(
+        self: Arc<Self>,
+        request: hyper::Request<Incoming>,
+    ) -> Result<hyper::Response<Full<Bytes>>, Infallible> {
+        let (parts, body) = request.into_parts();
+        let path = parts.uri.path();
+        if parts.method == Method::GET && path == NEXT_PATH {
+            return Ok(self.next().await);
+        }
+        if parts.method != Method::POST {
+            return Ok(status_response(StatusCode::NOT_FOUND));
+        }
+
+        let body = match body.collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(_) => return Ok(status_response(StatusCode::BAD_REQUEST)),
+        };
+        if path == INIT_ERROR_PATH {
+            tracing::error!(error = %error_message(&body), "app runtime failed to initialize");
+            return Ok(status_response(StatusCode::ACCEPTED));
+        }
+        let outcome = match path
+            .strip_prefix(INVOCATION_PREFIX)
+            .and_then(|path| path.rsplit_once('/'))
+        {
+            Some((request_id, "response")) => Some((request_id, Outcome::Response(body))),
+            Some((request_id, "error")) => Some((request_id, Outcome::Error(error_message(&body)))),
+            _ => None,
+        };
+        let Some((request_id, outcome)) = outcome else {
+            return Ok(status_response(StatusCode::NOT_FOUND));
+        };
+        match self.pending.lock().unwrap().remove(request_id) {
+            Some(sender) => {
+                let _ = sender.send(outcome);
+                Ok(status_response(StatusCode::ACCEPTED))
+            }
+            None => Ok(status_response(StatusCode::BAD_REQUEST)),
+        }
+    }
+
+    /// Wait for the next event, as `GET /runtime/invocation/next` does.
+    async fn next(&self) -> hyper::Response<Full<Bytes>> {
+        let Some(invocation) = self.receiver.lock().await.recv().await else {
+            return status_response(StatusCode::INTERNAL_SERVER_ERROR);
+        };
+        let mut response = hyper::Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .header("lambda-runtime-aws-request-id", invocation.request_id)
+            .header("lambda-runtime-deadline-ms", invocation.deadline)
+            .header("lambda-runtime-invoked-function-arn", invocation.function_arn);
+        if let Some(trace_id) = invocation.trace_id {
+            response = response.header("lambda-runtime-trace-id", trace_id);
+        }
+        response
+            .body(Full::new(invocation.payload))
+            .unwrap_or_else(|_| status_response(StatusCode::INTERNAL_SERVER_ERROR))
+    }
+}
+
+fn status_response(status: StatusCode) -> hyper::Response<Full<Bytes>> {
+    let mut response = hyper::Response::new(Full::new(Bytes::new()));
+    *response.status_mut() = status;
+    response
+}
+
+/// The message of an error the runtime posted, `{"errorType":..,"errorMessage":..}`.
+fn error_message(body: &[u8]) -> String {
+    let error: Value = serde_json::from_slice(body).unwrap_or_default();
+    let message = error
+        .get("errorMessage")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .unwrap_or_else(|| String::from_utf8_lossy(body).to_string());
+    match error.get("errorType").and_then(Value::as_str) {
+        Some(error_type) => format!("{error_type}: {message}"),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_message() {
+        assert_eq!(
+            "ValueError: bad input",
+            error_message(br#"{"errorType":"ValueError","errorMessage":"bad input"}"#)
+        );
+        assert_eq!("bad input", error_message(br#"{"errorMessage":"bad input"}"#));
+        assert_eq!("crashed", error_message(b"crashed"));
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::header::{CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, LOCATION};
+use http::{HeaderMap, HeaderValue, Method, StatusCode};
+use lambda_http::{Error, Response};
+
+use crate::aws::AwsClient;
+use crate::{full_body, ResponseBody};
+
+/// Buffered responses can carry at most 6MB, and binary bodies grow by a
+/// third when they are base64 encoded.
+pub(crate) const DEFAULT_OFFLOAD_THRESHOLD: u64 = 4 * 1024 * 1024;
+
+/// The request header that refers to a request body uploaded to S3.
+pub(crate) const UPLOAD_KEY: &str = "x-lwa-upload-key";
+
+/// Headers of the app response that are stored with the S3 object, so they
+/// are sent again when the object is downloaded.
+const STORED_HEADERS: [http::header::HeaderName; 4] =
+    [CONTENT_TYPE, CONTENT_DISPOSITION, CONTENT_ENCODING, CACHE_CONTROL];
+
+/// Uploads responses that are too large for a buffered Lambda response to S3,
+/// and answers with a `303 See Other` redirect to a presigned URL, or with a
+/// `307 Temporary Redirect` for `GET` and `HEAD` requests when configured.
+pub(crate) struct S3Offload {
+    aws: AwsClient,
+    bucket: String,
+    prefix: String,
+    threshold: u64,
+    url_ttl: Duration,
+    redirect: StatusCode,
+}
+
+impl S3Offload {
+    pub(crate) fn new(bucket: &str, prefix: &str, threshold: u64, url_ttl: Duration) -> Result<Self, Error> {
+        Ok(S3Offload {
+            aws: AwsClient::new()?,
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+            threshold,
+            url_ttl,
+            redirect: StatusCode::SEE_OTHER,
+        })
+    }
+
+    /// Redirect downloads with `307 Temporary Redirect` instead of `303 See Other`.
+    pub(crate) fn with_temporary_redirect(mut self) -> Self {
+        self.redirect = StatusCode::TEMPORARY_REDIRECT;
+        self
+    }
+
+    pub(crate) fn threshold(&self) -> u64 {
+        self.threshold
+    }
+
+    /// Upload a response body and build the redirect to download it. Only
+    /// `GET` and `HEAD` keep their method through a `307`, the presigned URL
+    /// is for downloads, so other requests always get a `303`.
+    pub(crate) async fn offload(
+        &self,
+        method: &Method,
+        key: &str,
+        headers: &HeaderMap,
+        body: Bytes,
+    ) -> Result<Response<ResponseBody>, Error> {
+        let url = object_url(&self.aws, &self.bucket, &format!("{}{}", self.prefix, key));
+        let mut request = http::Request::builder().method(Method::PUT).uri(&url);
+        for name in STORED_HEADERS {
+            if let Some(value) = headers.get(&name) {
+                request = request.header(name, value);
+            }
+        }
+        let size = body.len();
+        self.aws.send("s3", request.body(body)?).await?;
+
+        let location = self.aws.presign("s3", Method::GET, &url, self.url_ttl)?;
+        tracing::info!(size, url = %url, "offloaded large response to S3");
+
+        let status = match *method {
+            Method::GET | Method::HEAD => self.redirect,
+            _ => StatusCode::SEE_OTHER,
+        };
+        Ok(Response::builder()
+            .status(status)
+            .header(LOCATION, HeaderValue::from_str(&location)?)
+            .header(CACHE_CONTROL, "no-store")
+            .body(full_body(Bytes::new()))?)
+    }
+}
+
+pub(crate) fn object_url(aws: &AwsClient, bucket: &str, key: &str) -> String {
+    match AwsClient::endpoint_override("s3") {
+        Some(endpoint) => format!("{}/{}/{}", endpoint, bucket, key),
+        None => format!("https://{}.s3.{}.amazonaws.com/{}", bucket, aws.region(), key),
+    }
+}
+
+/// Gives requests too large for Lambda a way around the payload limit.
+///
+/// A request with a body above the threshold is answered with `413` and a
+/// presigned URL to upload the body to S3. The client uploads the body, then
+/// sends the request again without a body and with the `x-lwa-upload-key`
+/// header. The adapter downloads the object and forwards it to the app as
+/// the request body.
+pub(crate) struct S3Uploads {
+    aws: AwsClient,
+    bucket: String,
+    prefix: String,
+    threshold: u64,
+    url_ttl: Duration,
+}
+
+impl S3Uploads {
+    pub(crate) fn new(bucket: &str, prefix: &str, threshold: u64, url_ttl: Duration) -> Result<Self, Error> {
+        Ok(S3Uploads {
+            aws: AwsClient::new()?,
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+            threshold,
+            url_ttl,
+        })
+    }
+
+    /// Whether a request body is over the threshold, by its `content-length`
+    /// header or by its actual size.
+    pub(crate) fn exceeds(&self, headers: &HeaderMap, body_len: usize) -> bool {
+        let content_length = headers
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or_default();
+        content_length.max(body_len as u64) > self.threshold
+    }
+
+    /// Reject a request with `413` and the URL to upload its body to.
+    pub(crate) fn reject(&self, request_id: &str) -> Result<Response<ResponseBody>, Error> {
+        let key = format!("{}{}", self.prefix, request_id);
+        let url = object_url(&self.aws, &self.bucket, &key);
+        let upload_url = self.aws.presign("s3", Method::PUT, &url, self.url_ttl)?;
+        let body = serde_json::json!({
+            "upload_url": upload_url,
+            "upload_key": key,
+            "upload_header": UPLOAD_KEY,
+            "expires_in": self.url_ttl.as_secs(),
+        });
+
+        Ok(Response::builder()
+            .status(StatusCode::PAYLOAD_TOO_LARGE)
+            .header(CONTENT_TYPE, "application/json")
+            .body(full_body(serde_json::to_vec(&body)?))?)
+    }
+
+    /// Whether an upload key from a client refers to an object this adapter handed out.
+    pub(crate) fn is_valid_key(&self, key: &str) -> bool {
+        key.strip_prefix(&self.prefix)
+            .is_some_and(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+    }
+
+    /// Download an uploaded request body.
+    pub(crate) async fn fetch(&self, key: &str) -> Result<Bytes, Error> {
+        let url = object_url(&self.aws, &self.bucket, key);
+        let request = http::Request::builder()
+            .method(Method::GET)
+            .uri(url)
+            .body(Bytes::new())?;
+        Ok(self.aws.send("s3", request).await?.into_body())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_s3_uploads() {
+        std::env::set_var("AWS_REGION", "us-east-1");
+        std::env::set_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE");
+        std::env::set_var("AWS_SECRET_ACCESS_KEY", "secret");
+        let uploads = S3Uploads::new("bucket", "uploads/", 10, Duration::from_secs(60)).unwrap();
+
+        let mut headers = HeaderMap::new();
+        assert!(!uploads.exceeds(&headers, 10));
+        assert!(uploads.exceeds(&headers, 11));
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("1024"));
+        assert!(uploads.exceeds(&headers, 0));
+
+        assert!(uploads.is_valid_key("uploads/8476a536-e9f4-11e8-9739-2dfe598c3fcd"));
+        assert!(!uploads.is_valid_key("uploads/"));
+        assert!(!uploads.is_valid_key("uploads/../secrets"));
+        assert!(!uploads.is_valid_key("other/8476a536"));
+    }
+}
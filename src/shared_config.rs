@@ -0,0 +1,119 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Adapter configuration shared by a fleet of functions, from an S3 object.
+//!
+//! The object is a configuration document, fetched once at init and added
+//! under the environment and the configuration file, so both override the
+//! shared configuration. It is only read into the options of the adapter,
+//! and never changes the environment. The last object fetched is kept in
+//! `/tmp`, and used when S3 cannot be reached.
+
+use std::env;
+use std::path::Path;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::Method;
+use lambda_http::Error;
+
+use crate::aws::AwsClient;
+use crate::capture::sanitize_key;
+use crate::config::{self, Settings, CONFIG_FILE};
+use crate::s3::object_url;
+
+pub(crate) const CONFIG_S3_URI: &str = "AWS_LWA_CONFIG_S3_URI";
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Fetch the shared configuration at `s3://bucket/key` and add it to the
+/// settings.
+pub(crate) async fn load(settings: &mut Settings, uri: &str) -> Result<(), Error> {
+    let (bucket, key) = parse_uri(uri)?;
+    let aws = AwsClient::new()?;
+    let url = object_url(&aws, bucket, key);
+    let cache = env::temp_dir().join(format!("aws-lwa-config-{}", sanitize_key(uri)));
+    let document = fetch(&aws, &url, &cache).await?;
+
+    let (total, overridden) = settings.add(&config::parse(key, &document)?, &[CONFIG_S3_URI, CONFIG_FILE])?;
+    tracing::info!(uri, options = total, overridden, "loaded shared configuration");
+    Ok(())
+}
+
+fn parse_uri(uri: &str) -> Result<(&str, &str), Error> {
+    uri.strip_prefix("s3://")
+        .and_then(|location| location.split_once('/'))
+        .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+        .ok_or_else(|| Error::from(format!("invalid S3 URI {uri}, expected s3://bucket/key")))
+}
+
+/// The object, kept at `cache`, or the cached copy when it cannot be fetched.
+async fn fetch(aws: &AwsClient, url: &str, cache: &Path) -> Result<String, Error> {
+    let request = http::Request::builder()
+        .method(Method::GET)
+        .uri(url)
+        .body(Bytes::new())?;
+    let fetched = match tokio::time::timeout(FETCH_TIMEOUT, aws.send("s3", request)).await {
+        Ok(Ok(response)) => Ok(response.into_body()),
+        Ok(Err(err)) => Err(err),
+        Err(_) => Err(Error::from("timed out fetching the shared configuration")),
+    };
+
+    match fetched {
+        Ok(body) => {
+            let document = String::from_utf8(body.to_vec())?;
+            if let Err(err) = std::fs::write(cache, &document) {
+                tracing::debug!(error = %err, "failed to cache the shared configuration");
+            }
+            Ok(document)
+        }
+        Err(err) => {
+            let document = std::fs::read_to_string(cache).map_err(|_| err)?;
+            tracing::warn!(cache = %cache.display(), "failed to fetch the shared configuration, using the cached copy");
+            Ok(document)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httpmock::{Method::GET, MockServer};
+
+    #[test]
+    fn test_shared_config_uri() {
+        assert_eq!(
+            ("fleet-config", "lwa/prod.yaml"),
+            parse_uri("s3://fleet-config/lwa/prod.yaml").unwrap()
+        );
+        assert!(parse_uri("s3://fleet-config").is_err());
+        assert!(parse_uri("s3:///lwa.yaml").is_err());
+        assert!(parse_uri("https://fleet-config/lwa.yaml").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shared_config_fetch_falls_back_to_cache() {
+        env::set_var("AWS_REGION", "us-east-1");
+        env::set_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE");
+        env::set_var("AWS_SECRET_ACCESS_KEY", "secret");
+        let server = MockServer::start();
+        let mut object = server.mock(|when, then| {
+            when.method(GET).path("/fleet-config/lwa.yaml");
+            then.status(200).body("port: 8000\n");
+        });
+        let aws = AwsClient::new().unwrap();
+        let url = server.url("/fleet-config/lwa.yaml");
+        let cache = env::temp_dir().join(format!("aws-lwa-config-test-{}", std::process::id()));
+
+        assert_eq!("port: 8000\n", fetch(&aws, &url, &cache).await.unwrap());
+        object.delete();
+        server.mock(|when, then| {
+            when.method(GET).path("/fleet-config/lwa.yaml");
+            then.status(503);
+        });
+        assert_eq!("port: 8000\n", fetch(&aws, &url, &cache).await.unwrap());
+
+        std::fs::remove_file(&cache).unwrap();
+        assert!(fetch(&aws, &url, &cache).await.is_err());
+    }
+}
@@ -0,0 +1,249 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A limit on the requests in flight to the app, shedding low-priority routes
+//! first.
+//!
+//! Up to the limit, requests of any priority go through. At the limit,
+//! low-priority requests, such as scrapers or metrics, are answered with a
+//! `429 Too Many Requests` right away, while the others queue for the next
+//! free slot, high-priority requests first. A slot is held until the body of
+//! the response has been sent.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use lambda_http::Error;
+use tokio::sync::oneshot;
+
+use crate::glob_match;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Priority {
+    /// Shed at the limit.
+    Low,
+    /// Queue at the limit.
+    #[default]
+    Normal,
+    /// Queue at the limit, ahead of normal requests.
+    High,
+}
+
+impl TryFrom<&str> for Priority {
+    type Error = Error;
+
+    fn try_from(priority: &str) -> Result<Self, Error> {
+        match priority.trim().to_lowercase().as_str() {
+            "low" => Ok(Priority::Low),
+            "normal" => Ok(Priority::Normal),
+            "high" => Ok(Priority::High),
+            other => Err(Error::from(format!("invalid priority {other:?}"))),
+        }
+    }
+}
+
+/// The priorities of paths. Paths without a priority are normal.
+#[derive(Debug, Default)]
+pub(crate) struct RoutePriorities {
+    routes: Vec<(String, Priority)>,
+}
+
+impl RoutePriorities {
+    /// Parse a JSON object of path patterns to priorities, such as
+    /// `{"/metrics":"low","/bots/*":"low","/checkout/*":"high"}`.
+    pub(crate) fn new(routes: &str) -> Result<Self, Error> {
+        let routes: serde_json::Map<String, serde_json::Value> = serde_json::from_str(routes)?;
+        let mut routes: Vec<(String, Priority)> = routes
+            .into_iter()
+            .map(|(pattern, priority)| match priority.as_str() {
+                Some(priority) => Ok((pattern, Priority::try_from(priority)?)),
+                None => Err(Error::from(format!("invalid priority for {pattern}: {priority}"))),
+            })
+            .collect::<Result<_, Error>>()?;
+        // the longest pattern is the most specific one
+        routes.sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.len()));
+        Ok(RoutePriorities { routes })
+    }
+
+    /// The priority of a path, by the longest pattern that matches it.
+    pub(crate) fn priority(&self, path: &str) -> Priority {
+        self.routes
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, path))
+            .map(|(_, priority)| *priority)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    in_flight: usize,
+    high: VecDeque<oneshot::Sender<Slot>>,
+    normal: VecDeque<oneshot::Sender<Slot>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct LoadShedder {
+    limit: usize,
+    priorities: RoutePriorities,
+    state: Arc<Mutex<State>>,
+}
+
+impl LoadShedder {
+    pub(crate) fn new(limit: usize, priorities: RoutePriorities) -> Self {
+        LoadShedder {
+            limit,
+            priorities,
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    /// Take a slot for a request to `path`, waiting for one at the limit, or
+    /// `None` when the request is shed.
+    pub(crate) async fn admit(&self, path: &str) -> Option<Slot> {
+        let priority = self.priorities.priority(path);
+        let waiting = {
+            let mut state = self.state.lock().unwrap();
+            if state.in_flight < self.limit {
+                state.in_flight += 1;
+                return Some(Slot {
+                    state: Some(self.state.clone()),
+                });
+            }
+            let (sender, receiver) = oneshot::channel();
+            match priority {
+                Priority::Low => {
+                    tracing::debug!(path, limit = self.limit, "shedding a low-priority request");
+                    return None;
+                }
+                Priority::Normal => state.normal.push_back(sender),
+                Priority::High => state.high.push_back(sender),
+            }
+            receiver
+        };
+        tracing::debug!(
+            path,
+            limit = self.limit,
+            "too many requests in flight, queueing the request"
+        );
+        Some(waiting.await.expect("a waiting request is handed a slot or dropped"))
+    }
+}
+
+/// A request in flight. Dropping it hands the slot to the next waiting
+/// request, or frees it.
+#[derive(Debug)]
+pub(crate) struct Slot {
+    state: Option<Arc<Mutex<State>>>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let Some(shared) = self.state.take() else {
+            return;
+        };
+        let mut state = shared.lock().unwrap();
+        loop {
+            let Some(waiting) = state.high.pop_front().or_else(|| state.normal.pop_front()) else {
+                state.in_flight -= 1;
+                return;
+            };
+            let slot = Slot {
+                state: Some(shared.clone()),
+            };
+            // a request that stopped waiting cannot take the slot, try the next one
+            match waiting.send(slot) {
+                Ok(()) => return,
+                Err(mut slot) => slot.state = None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    fn shedder(limit: usize) -> Arc<LoadShedder> {
+        let priorities = RoutePriorities::new(r#"{"/metrics":"low","/bots/*":"low","/checkout/*":"high"}"#).unwrap();
+        Arc::new(LoadShedder::new(limit, priorities))
+    }
+
+    #[test]
+    fn test_route_priorities() {
+        let priorities = RoutePriorities::new(r#"{"/api/*":"low","/api/orders/*":"high"}"#).unwrap();
+        assert_eq!(Priority::High, priorities.priority("/api/orders/1"));
+        assert_eq!(Priority::Low, priorities.priority("/api/bots"));
+        assert_eq!(Priority::Normal, priorities.priority("/"));
+
+        assert!(RoutePriorities::new(r#"{"/metrics":"lowest"}"#).is_err());
+        assert!(RoutePriorities::new(r#"{"/metrics":0}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_load_shedder_sheds_low_priority_at_the_limit() {
+        let shedder = shedder(1);
+        let first = shedder.admit("/metrics").await;
+        assert!(first.is_some());
+        assert!(shedder.admit("/bots/crawler").await.is_none());
+
+        // normal requests wait for the slot instead
+        let waiting = tokio::spawn({
+            let shedder = shedder.clone();
+            async move { shedder.admit("/home").await.is_some() }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!waiting.is_finished());
+        drop(first);
+        assert!(timeout(Duration::from_secs(1), waiting).await.unwrap().unwrap());
+
+        // the slot was freed again
+        assert!(shedder.admit("/metrics").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_load_shedder_serves_high_priority_first() {
+        let shedder = shedder(1);
+        let first = shedder.admit("/home").await.unwrap();
+
+        let normal = tokio::spawn({
+            let shedder = shedder.clone();
+            async move { shedder.admit("/home").await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let high = tokio::spawn({
+            let shedder = shedder.clone();
+            async move { shedder.admit("/checkout/cart").await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        drop(first);
+        let high = timeout(Duration::from_secs(1), high).await.unwrap().unwrap();
+        assert!(high.is_some());
+        assert!(!normal.is_finished());
+
+        drop(high);
+        assert!(timeout(Duration::from_secs(1), normal)
+            .await
+            .unwrap()
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_load_shedder_skips_requests_that_stopped_waiting() {
+        let shedder = shedder(1);
+        let first = shedder.admit("/home").await.unwrap();
+        assert!(timeout(Duration::from_millis(10), shedder.admit("/home"))
+            .await
+            .is_err());
+
+        drop(first);
+        assert!(timeout(Duration::from_millis(10), shedder.admit("/home"))
+            .await
+            .unwrap()
+            .is_some());
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Graceful shutdown of the app when Lambda ends the execution environment.
+//!
+//! Unless the adapter started the app itself, it finds the app's process by
+//! the socket it listens on, through `/proc`. At the `SHUTDOWN` event it can
+//! send that process a signal the app treats as a graceful stop, and hold
+//! the shutdown phase until the process exits, so the app can flush its
+//! work before Lambda ends the execution environment.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How often the adapter checks whether the app has exited.
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// `st` of listening TCP sockets in `/proc/net/tcp`.
+const TCP_LISTEN: &str = "0A";
+
+/// `__SO_ACCEPTCON`, the flag of listening sockets in `/proc/net/unix`.
+const UNIX_ACCEPTCON: u32 = 1 << 16;
+
+/// The number of a signal name, such as `SIGTERM` or `TERM`.
+pub(crate) fn parse_signal(name: &str) -> Option<i32> {
+    let name = name.trim().to_uppercase();
+    let signal = match name.strip_prefix("SIG").unwrap_or(&name) {
+        "TERM" => libc::SIGTERM,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "HUP" => libc::SIGHUP,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "WINCH" => libc::SIGWINCH,
+        _ => return None,
+    };
+    Some(signal)
+}
+
+/// The process listening on the app's Unix socket, or on its TCP port.
+pub(crate) fn app_pid(port: u16, unix_socket: Option<&Path>) -> Option<i32> {
+    let inodes = match unix_socket {
+        Some(path) => unix_listener_inodes(&fs::read_to_string("/proc/net/unix").ok()?, path),
+        None => ["/proc/net/tcp", "/proc/net/tcp6"]
+            .iter()
+            .filter_map(|table| fs::read_to_string(table).ok())
+            .flat_map(|table| tcp_listener_inodes(&table, port))
+            .collect(),
+    };
+    if inodes.is_empty() {
+        return None;
+    }
+    let sockets: Vec<String> = inodes.iter().map(|inode| format!("socket:[{inode}]")).collect();
+    let own_pid = std::process::id() as i32;
+    fs::read_dir("/proc")
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<i32>().ok())
+        .filter(|pid| *pid != own_pid)
+        .find(|pid| {
+            fs::read_dir(format!("/proc/{pid}/fd")).is_ok_and(|fds| {
+                fds.filter_map(|fd| fs::read_link(fd.ok()?.path()).ok())
+                    .any(|link| link.to_str().is_some_and(|link| sockets.iter().any(|s| s == link)))
+            })
+        })
+}
+
+/// The inodes of the TCP sockets listening on `port` in a `/proc/net/tcp` table.
+fn tcp_listener_inodes(table: &str, port: u16) -> Vec<u64> {
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let local_port = u16::from_str_radix(fields.get(1)?.rsplit_once(':')?.1, 16).ok()?;
+            if *fields.get(3)? != TCP_LISTEN || local_port != port {
+                return None;
+            }
+            fields.get(9)?.parse().ok()
+        })
+        .collect()
+}
+
+/// The inodes of the Unix sockets listening on `path` in `/proc/net/unix`.
+fn unix_listener_inodes(table: &str, path: &Path) -> Vec<u64> {
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let flags = u32::from_str_radix(fields.get(3)?, 16).ok()?;
+            if flags & UNIX_ACCEPTCON == 0 || Path::new(fields.get(7)?) != path {
+                return None;
+            }
+            fields.get(6)?.parse().ok()
+        })
+        .collect()
+}
+
+pub(crate) fn send_signal(pid: i32, signal: i32) -> io::Result<()> {
+    // SAFETY: kill has no memory safety requirements
+    match unsafe { libc::kill(pid, signal) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Wait up to `drain` for the process to exit. Returns whether it did.
+pub(crate) async fn wait_for_exit(pid: i32, drain: Duration) -> bool {
+    let deadline = Instant::now() + drain;
+    while is_running(pid) {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(EXIT_POLL_INTERVAL).await;
+    }
+    true
+}
+
+/// Whether the process is still running. A zombie has exited, but stays in
+/// `/proc` until its parent reaps it.
+fn is_running(pid: i32) -> bool {
+    match fs::read_to_string(format!("/proc/{pid}/stat")) {
+        Ok(stat) => stat
+            .rsplit_once(')')
+            .and_then(|(_, rest)| rest.split_whitespace().next())
+            .is_some_and(|state| state != "Z" && state != "X"),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(Some(libc::SIGTERM), parse_signal("SIGTERM"));
+        assert_eq!(Some(libc::SIGQUIT), parse_signal("quit"));
+        assert_eq!(Some(libc::SIGUSR1), parse_signal("SigUsr1"));
+        assert_eq!(None, parse_signal("SIGKILL"));
+        assert_eq!(None, parse_signal("15"));
+    }
+
+    #[test]
+    fn test_listener_inodes() {
+        let tcp = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n   \
+                   0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 4242 1 0 100 0 0 10 0\n   \
+                   1: 0100007F:1F90 0100007F:D2F0 01 00000000:00000000 00:00000000 00000000  1000        0 4343 1 0 20 4 30 10 -1\n   \
+                   2: 0100007F:0BB8 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 4444 1 0 100 0 0 10 0";
+        assert_eq!(vec![4242], tcp_listener_inodes(tcp, 8080));
+        assert_eq!(Vec::<u64>::new(), tcp_listener_inodes(tcp, 9090));
+
+        let unix = "Num       RefCount Protocol Flags    Type St Inode Path\n\
+                    0000000000000000: 00000002 00000000 00010000 0001 01 5151 /tmp/app.sock\n\
+                    0000000000000000: 00000003 00000000 00000000 0001 03 5252 /tmp/app.sock\n\
+                    0000000000000000: 00000002 00000000 00010000 0001 01 5353 /tmp/other.sock";
+        assert_eq!(vec![5151], unix_listener_inodes(unix, Path::new("/tmp/app.sock")));
+    }
+
+    #[tokio::test]
+    async fn test_app_pid_and_exit() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // the adapter's own sockets are not the app's
+        assert_eq!(None, app_pid(port, None));
+
+        assert!(is_running(std::process::id() as i32));
+        assert!(wait_for_exit(i32::MAX, Duration::from_millis(10)).await);
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The content type of responses the app sent without one, from their body.
+//!
+//! Without a `Content-Type`, a buffered response is passed to Lambda as UTF-8
+//! text, so binary bodies such as images are mangled instead of being
+//! base64 encoded. Common binary formats are told by their magic bytes, and
+//! other bodies by whether they are valid text.
+
+use http::header::{CONTENT_ENCODING, CONTENT_TYPE};
+use http::{HeaderMap, HeaderValue};
+use http_body_util::BodyExt;
+use lambda_http::{Error, Response};
+
+use crate::{full_body, ResponseBody};
+
+/// Binary formats by the bytes they start with.
+const MAGIC_BYTES: [(&[u8], &str); 14] = [
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"wOFF", "font/woff"),
+    (b"wOF2", "font/woff2"),
+    (b"\x00\x00\x01\x00", "image/x-icon"),
+    (b"OggS", "audio/ogg"),
+    (b"ID3", "audio/mpeg"),
+    (b"\x1a\x45\xdf\xa3", "video/webm"),
+    (b"\x00asm", "application/wasm"),
+];
+
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// Whether the content type of a response should be sniffed: it has none,
+/// and its body is not encoded.
+pub(crate) fn is_missing(headers: &HeaderMap) -> bool {
+    !headers.contains_key(CONTENT_TYPE) && !headers.contains_key(CONTENT_ENCODING)
+}
+
+/// Set the content type of a response from its body. Empty bodies are left
+/// without one.
+pub(crate) async fn apply(response: Response<ResponseBody>) -> Result<Response<ResponseBody>, Error> {
+    let (mut parts, body) = response.into_parts();
+    let bytes = body.collect().await?.to_bytes();
+    if let Some(content_type) = content_type(&bytes) {
+        tracing::debug!(content_type, "the app sent no content type, sniffed one");
+        parts
+            .headers
+            .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    }
+    Ok(Response::from_parts(parts, full_body(bytes)))
+}
+
+/// The content type of a body: a binary format by its magic bytes, text with
+/// its charset, or `application/octet-stream`.
+fn content_type(body: &[u8]) -> Option<&'static str> {
+    if body.is_empty() {
+        return None;
+    }
+    if let Some((_, content_type)) = MAGIC_BYTES.iter().find(|(magic, _)| body.starts_with(magic)) {
+        return Some(content_type);
+    }
+    if body.len() >= 12 && &body[4..8] == b"ftyp" {
+        return Some(match &body[8..12] {
+            b"avif" => "image/avif",
+            b"heic" => "image/heic",
+            _ => "video/mp4",
+        });
+    }
+    if body.len() >= 12 && body.starts_with(b"RIFF") && &body[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    if body.starts_with(b"\xfe\xff") {
+        return Some("text/plain; charset=utf-16be");
+    }
+    if body.starts_with(b"\xff\xfe") {
+        return Some("text/plain; charset=utf-16le");
+    }
+
+    let Ok(text) = std::str::from_utf8(body.strip_prefix(UTF8_BOM).unwrap_or(body)) else {
+        return Some("application/octet-stream");
+    };
+    // control characters other than whitespace only show up in binary data
+    if text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c'))
+    {
+        return Some("application/octet-stream");
+    }
+    let text = text.trim_start();
+    let start: String = text.chars().take(14).collect::<String>().to_ascii_lowercase();
+    Some(if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        "text/html; charset=utf-8"
+    } else if start.starts_with("<?xml") {
+        "application/xml; charset=utf-8"
+    } else if start.starts_with("<svg") {
+        "image/svg+xml"
+    } else if (start.starts_with('{') || start.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(text).is_ok()
+    {
+        "application/json"
+    } else {
+        "text/plain; charset=utf-8"
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff_binary_formats() {
+        assert_eq!(Some("image/png"), content_type(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"));
+        assert_eq!(Some("image/jpeg"), content_type(b"\xff\xd8\xff\xe0\0\x10JFIF"));
+        assert_eq!(Some("image/webp"), content_type(b"RIFF\x24\0\0\0WEBPVP8 "));
+        assert_eq!(Some("video/mp4"), content_type(b"\0\0\0\x18ftypmp42"));
+        assert_eq!(Some("image/avif"), content_type(b"\0\0\0\x1cftypavif"));
+        assert_eq!(Some("application/pdf"), content_type(b"%PDF-1.7\n"));
+        assert_eq!(Some("application/octet-stream"), content_type(b"\x00\x01\x02\x03"));
+        assert_eq!(Some("application/octet-stream"), content_type(&[0xc3, 0x28]));
+    }
+
+    #[test]
+    fn test_sniff_text() {
+        assert_eq!(None, content_type(b""));
+        assert_eq!(Some("application/json"), content_type(br#" {"hello": "world"}"#));
+        assert_eq!(Some("text/plain; charset=utf-8"), content_type(b"{not json"));
+        assert_eq!(
+            Some("text/html; charset=utf-8"),
+            content_type(b"\xef\xbb\xbf<!DOCTYPE html><html></html>")
+        );
+        assert_eq!(
+            Some("application/xml; charset=utf-8"),
+            content_type(b"<?xml version=\"1.0\"?><a/>")
+        );
+        assert_eq!(
+            Some("image/svg+xml"),
+            content_type(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>")
+        );
+        assert_eq!(Some("text/plain; charset=utf-8"), content_type("héllo\r\n".as_bytes()));
+        assert_eq!(Some("text/plain; charset=utf-16le"), content_type(b"\xff\xfeh\0i\0"));
+    }
+
+    #[test]
+    fn test_sniff_is_missing() {
+        let mut headers = HeaderMap::new();
+        assert!(is_missing(&headers));
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        assert!(!is_missing(&headers));
+        headers.remove(CONTENT_ENCODING);
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+        assert!(!is_missing(&headers));
+    }
+}
@@ -0,0 +1,114 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Dispatch of SOAP calls by their action, for legacy SOAP backends that
+//! serve each operation on its own path.
+//!
+//! SOAP 1.1 clients name the action in a quoted `SOAPAction` header, SOAP 1.2
+//! clients in the `action` parameter of the `application/soap+xml` content
+//! type. Both are only read from XML requests.
+
+use http::header::CONTENT_TYPE;
+use http::HeaderMap;
+use lambda_http::Error;
+
+use crate::glob_match;
+
+#[derive(Debug)]
+pub(crate) struct SoapRoutes {
+    routes: Vec<(String, String)>,
+}
+
+impl SoapRoutes {
+    /// Parse a JSON object of action patterns to paths, such as
+    /// `{"urn:orders#GetOrder":"/orders/read","urn:orders#*":"/orders/write"}`.
+    pub(crate) fn new(routes: &str) -> Result<Self, Error> {
+        let routes: serde_json::Map<String, serde_json::Value> = serde_json::from_str(routes)?;
+        let mut routes: Vec<(String, String)> = routes
+            .into_iter()
+            .map(|(action, path)| match path.as_str() {
+                Some(path) if path.starts_with('/') => Ok((action, path.to_string())),
+                _ => Err(Error::from(format!("invalid path for SOAP action {action}: {path}"))),
+            })
+            .collect::<Result<_, Error>>()?;
+        // the longest pattern is the most specific one
+        routes.sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.len()));
+        Ok(SoapRoutes { routes })
+    }
+
+    /// The path for a request, by the longest pattern that matches its action.
+    pub(crate) fn route(&self, headers: &HeaderMap) -> Option<&str> {
+        let action = action(headers)?;
+        self.routes
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, &action))
+            .map(|(_, path)| path.as_str())
+    }
+}
+
+/// The SOAP action of an XML request, without its quotes.
+pub(crate) fn action(headers: &HeaderMap) -> Option<String> {
+    let content_type = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    let mut params = content_type.split(';');
+    let media_type = params.next().unwrap_or_default().trim().to_lowercase();
+    let action = match media_type.as_str() {
+        "text/xml" => headers.get("soapaction")?.to_str().ok()?.to_string(),
+        "application/soap+xml" => params
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("action"))
+            .map(|(_, value)| value.trim().to_string())?,
+        _ => return None,
+    };
+    let action = action.trim().trim_matches('"');
+    (!action.is_empty()).then(|| action.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn headers(content_type: &'static str, soap_action: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        if let Some(soap_action) = soap_action {
+            headers.insert("soapaction", HeaderValue::from_static(soap_action));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_soap_action() {
+        assert_eq!(
+            Some("urn:orders#GetOrder".to_string()),
+            action(&headers("text/xml; charset=utf-8", Some("\"urn:orders#GetOrder\"")))
+        );
+        assert_eq!(
+            Some("urn:orders#GetOrder".to_string()),
+            action(&headers(
+                "application/soap+xml; charset=utf-8; action=\"urn:orders#GetOrder\"",
+                None
+            ))
+        );
+        // an empty action means the intent is in the envelope
+        assert_eq!(None, action(&headers("text/xml", Some("\"\""))));
+        assert_eq!(None, action(&headers("application/json", Some("urn:orders#GetOrder"))));
+    }
+
+    #[test]
+    fn test_soap_routes() {
+        let routes =
+            SoapRoutes::new(r#"{"urn:orders#GetOrder":"/orders/read","urn:orders#*":"/orders/write"}"#).unwrap();
+        assert_eq!(
+            Some("/orders/read"),
+            routes.route(&headers("text/xml", Some("\"urn:orders#GetOrder\"")))
+        );
+        assert_eq!(
+            Some("/orders/write"),
+            routes.route(&headers("text/xml", Some("urn:orders#CancelOrder")))
+        );
+        assert_eq!(None, routes.route(&headers("text/xml", Some("urn:billing#Pay"))));
+
+        assert!(SoapRoutes::new(r#"{"urn:orders#GetOrder":"orders"}"#).is_err());
+    }
+}
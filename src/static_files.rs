@@ -0,0 +1,357 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use bytes::Bytes;
+use http::header::{
+    ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH, VARY,
+};
+use http::{HeaderMap, HeaderValue, Method, StatusCode};
+use lambda_http::{Error, Response};
+use percent_encoding::percent_decode_str;
+
+use crate::{full_body, ResponseBody};
+
+/// Precompressed variants of a file, in order of preference, as the content
+/// coding and the extension of the file next to the original.
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+/// Serves files from a directory without calling the web application.
+/// Requests for files that don't exist fall through to the app.
+///
+/// When a file has a precompressed `.br` or `.gz` variant next to it, that
+/// variant is served to clients that accept its encoding.
+#[derive(Clone, Debug)]
+pub(crate) struct StaticFiles {
+    dir: PathBuf,
+    prefix: String,
+    cache_control: HeaderValue,
+}
+
+impl StaticFiles {
+    pub(crate) fn new(dir: impl Into<PathBuf>, prefix: &str, cache_control: &str) -> Result<Self, Error> {
+        Ok(StaticFiles {
+            dir: dir.into(),
+            prefix: prefix.trim_end_matches('/').to_string(),
+            cache_control: HeaderValue::from_str(cache_control)?,
+        })
+    }
+
+    /// The file for a request path, if the path is under the prefix and
+    /// stays inside the directory.
+    fn file_path(&self, path: &str) -> Option<PathBuf> {
+        let relative = path.strip_prefix(&self.prefix)?;
+        if !relative.is_empty() && !relative.starts_with('/') {
+            return None;
+        }
+        let relative = percent_decode_str(relative).decode_utf8().ok()?;
+
+        let mut file = self.dir.clone();
+        for component in Path::new(relative.trim_start_matches('/')).components() {
+            match component {
+                Component::Normal(segment) => file.push(segment),
+                _ => return None,
+            }
+        }
+        if relative.is_empty() || relative.ends_with('/') {
+            file.push("index.html");
+        }
+        Some(file)
+    }
+
+    /// Serve a `GET` or `HEAD` request from the directory. Returns `None`
+    /// when there is no matching file.
+    pub(crate) async fn serve(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+    ) -> Result<Option<Response<ResponseBody>>, Error> {
+        if method != Method::GET && method != Method::HEAD {
+            return Ok(None);
+        }
+        let Some(file) = self.file_path(path) else {
+            return Ok(None);
+        };
+        let metadata = match tokio::fs::metadata(&file).await {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => return Ok(None),
+        };
+
+        let mut variants = Vec::new();
+        for (encoding, extension) in PRECOMPRESSED {
+            let mut variant = file.clone().into_os_string();
+            variant.push(".");
+            variant.push(extension);
+            let variant = PathBuf::from(variant);
+            if let Ok(metadata) = tokio::fs::metadata(&variant).await {
+                if metadata.is_file() {
+                    variants.push((encoding, variant, metadata));
+                }
+            }
+        }
+        let accepted = accepted_encodings(headers);
+        let variant = preferred_encoding(&accepted, variants.iter().map(|(encoding, _, _)| *encoding))
+            .and_then(|encoding| variants.iter().find(|(variant, _, _)| *variant == encoding));
+        let (encoding, served, metadata) = match variant {
+            Some((encoding, served, metadata)) => (Some(*encoding), served, metadata.clone()),
+            None => (None, &file, metadata),
+        };
+
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| modified.as_secs())
+            .unwrap_or_default();
+        let etag = HeaderValue::from_str(&format!("W/\"{:x}-{:x}\"", metadata.len(), modified))?;
+
+        let mut builder = Response::builder()
+            .header(ETAG, etag.clone())
+            .header(CACHE_CONTROL, self.cache_control.clone());
+        // caches must keep each encoding of a file apart
+        if !variants.is_empty() {
+            builder = builder.header(VARY, "accept-encoding");
+        }
+
+        let not_modified = headers
+            .get(IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*"));
+        if not_modified {
+            return Ok(Some(
+                builder.status(StatusCode::NOT_MODIFIED).body(full_body(Bytes::new()))?,
+            ));
+        }
+
+        let body = if method == Method::HEAD {
+            Bytes::new()
+        } else {
+            Bytes::from(tokio::fs::read(served).await?)
+        };
+        tracing::debug!(file = %served.display(), "serving static file");
+
+        if let Some(encoding) = encoding {
+            builder = builder.header(CONTENT_ENCODING, encoding);
+        }
+        Ok(Some(
+            builder
+                .header(CONTENT_TYPE, content_type(&file))
+                .header(CONTENT_LENGTH, metadata.len())
+                .body(full_body(body))?,
+        ))
+    }
+}
+
+/// The content codings in an `Accept-Encoding` header, with their weights.
+fn accepted_encodings(headers: &HeaderMap) -> Vec<(String, f32)> {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|coding| {
+            let mut params = coding.split(';');
+            let name = params.next()?.trim().to_lowercase();
+            if name.is_empty() {
+                return None;
+            }
+            let weight = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|weight| weight.trim().parse().ok())
+                .unwrap_or(1.0);
+            Some((name, weight))
+        })
+        .collect()
+}
+
+/// The available encoding with the highest weight, preferring the earlier
+/// ones on a tie. Codings the client did not name are weighed by `*`.
+fn preferred_encoding<'a>(accepted: &[(String, f32)], available: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let weight = |encoding: &str| {
+        accepted
+            .iter()
+            .find(|(name, _)| name == encoding)
+            .or_else(|| accepted.iter().find(|(name, _)| name == "*"))
+            .map(|(_, weight)| *weight)
+            .unwrap_or(0.0)
+    };
+    available
+        .map(|encoding| (encoding, weight(encoding)))
+        .filter(|(_, weight)| *weight > 0.0)
+        .fold(None, |best: Option<(&str, f32)>, (encoding, weight)| match best {
+            Some((_, best_weight)) if best_weight >= weight => best,
+            _ => Some((encoding, weight)),
+        })
+        .map(|(encoding, _)| encoding)
+}
+
+fn content_type(file: &Path) -> &'static str {
+    let extension = file
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "webmanifest" => "application/manifest+json",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    fn static_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lwa-static-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(dir.join("assets")).unwrap();
+        std::fs::write(dir.join("index.html"), "<html></html>").unwrap();
+        std::fs::write(dir.join("assets/app.js"), "console.log(1)").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_file_path() {
+        let files = StaticFiles::new("/var/task/public", "/static/", "no-cache").unwrap();
+        assert_eq!(
+            Some(PathBuf::from("/var/task/public/assets/app.js")),
+            files.file_path("/static/assets/app.js")
+        );
+        assert_eq!(
+            Some(PathBuf::from("/var/task/public/index.html")),
+            files.file_path("/static")
+        );
+        assert_eq!(
+            Some(PathBuf::from("/var/task/public/my file.txt")),
+            files.file_path("/static/my%20file.txt")
+        );
+        assert_eq!(None, files.file_path("/staticfile.js"));
+        assert_eq!(None, files.file_path("/api/users"));
+        assert_eq!(None, files.file_path("/static/../etc/passwd"));
+        assert_eq!(None, files.file_path("/static/%2e%2e/etc/passwd"));
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let dir = static_dir("serve");
+        let files = StaticFiles::new(&dir, "/", "public, max-age=60").unwrap();
+
+        let response = files
+            .serve(&Method::GET, "/assets/app.js", &HeaderMap::new())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(200, response.status());
+        assert_eq!("text/javascript; charset=utf-8", response.headers()[CONTENT_TYPE]);
+        assert_eq!("public, max-age=60", response.headers()[CACHE_CONTROL]);
+        let etag = response.headers()[ETAG].clone();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!("console.log(1)", body);
+
+        // a matching etag is answered with 304
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, etag);
+        let response = files
+            .serve(&Method::GET, "/assets/app.js", &headers)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(304, response.status());
+
+        // directories serve their index.html
+        let response = files
+            .serve(&Method::GET, "/", &HeaderMap::new())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!("text/html; charset=utf-8", response.headers()[CONTENT_TYPE]);
+
+        // missing files and other methods go to the app
+        assert!(files
+            .serve(&Method::GET, "/api/users", &HeaderMap::new())
+            .await
+            .unwrap()
+            .is_none());
+        assert!(files
+            .serve(&Method::POST, "/assets/app.js", &HeaderMap::new())
+            .await
+            .unwrap()
+            .is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_preferred_encoding() {
+        let preferred = |accept_encoding: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(accept_encoding));
+            preferred_encoding(&accepted_encodings(&headers), ["br", "gzip"].into_iter())
+        };
+        assert_eq!(Some("br"), preferred("gzip, deflate, br"));
+        assert_eq!(Some("gzip"), preferred("gzip;q=1.0, br;q=0.5"));
+        assert_eq!(Some("gzip"), preferred("gzip, br;q=0"));
+        assert_eq!(Some("br"), preferred("*"));
+        assert_eq!(Some("gzip"), preferred("br;q=0, *;q=0.1"));
+        assert_eq!(None, preferred("identity"));
+        assert_eq!(None, preferred_encoding(&[], ["br", "gzip"].into_iter()));
+    }
+
+    #[tokio::test]
+    async fn test_serve_precompressed() {
+        let dir = static_dir("precompressed");
+        std::fs::write(dir.join("assets/app.js.gz"), "gzipped").unwrap();
+        let files = StaticFiles::new(&dir, "/", "public, max-age=60").unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, br"));
+        let response = files
+            .serve(&Method::GET, "/assets/app.js", &headers)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!("gzip", response.headers()[CONTENT_ENCODING]);
+        assert_eq!("accept-encoding", response.headers()[VARY]);
+        assert_eq!("text/javascript; charset=utf-8", response.headers()[CONTENT_TYPE]);
+        assert_eq!("7", response.headers()[CONTENT_LENGTH]);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!("gzipped", body);
+
+        // clients without gzip get the original, which also varies
+        let response = files
+            .serve(&Method::GET, "/assets/app.js", &HeaderMap::new())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!("accept-encoding", response.headers()[VARY]);
+
+        // files without variants do not
+        let response = files.serve(&Method::GET, "/", &headers).await.unwrap().unwrap();
+        assert!(!response.headers().contains_key(VARY));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Headers added to every response, such as CORS and security headers.
+//!
+//! The headers are validated once when the adapter starts, so adding them to
+//! a response only clones the shared values. Headers the app sets itself are
+//! kept as they are.
+
+use http::header::Entry;
+use http::{HeaderMap, HeaderName, HeaderValue};
+use lambda_http::Error;
+use serde_json::Value;
+
+#[derive(Debug)]
+pub(crate) struct StaticHeaders {
+    headers: Vec<(HeaderName, Vec<HeaderValue>)>,
+}
+
+impl StaticHeaders {
+    /// Load the headers from an inline JSON object, or from a JSON or YAML file.
+    pub(crate) fn load(setting: &str) -> Result<Self, Error> {
+        let document = match setting.trim_start().starts_with('{') {
+            true => serde_json::from_str(setting)?,
+            false => {
+                let text = std::fs::read_to_string(setting)?;
+                match serde_json::from_str(&text) {
+                    Ok(document) => document,
+                    Err(_) => serde_yaml::from_str(&text)?,
+                }
+            }
+        };
+        Self::new(&document)
+    }
+
+    /// Parse an object of header names and values, or lists of values, such as
+    /// `{"x-frame-options":"DENY","vary":["Origin","Accept-Encoding"]}`.
+    pub(crate) fn new(document: &Value) -> Result<Self, Error> {
+        let fields = document
+            .as_object()
+            .ok_or_else(|| Error::from("static headers must be an object of header names and values"))?;
+        let mut headers = Vec::with_capacity(fields.len());
+        for (name, values) in fields {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| Error::from(format!("invalid header name {name:?}")))?;
+            let values = match values {
+                Value::Array(values) => values.iter().collect(),
+                value => vec![value],
+            };
+            let values = values
+                .into_iter()
+                .map(|value| {
+                    let text = match value {
+                        Value::String(text) => text.clone(),
+                        Value::Number(_) | Value::Bool(_) => value.to_string(),
+                        _ => return Err(Error::from(format!("invalid value of header {name}"))),
+                    };
+                    HeaderValue::from_str(&text).map_err(|_| Error::from(format!("invalid value of header {name}")))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            headers.push((name, values));
+        }
+        Ok(StaticHeaders { headers })
+    }
+
+    /// Add the headers the response does not have yet.
+    pub(crate) fn apply(&self, headers: &mut HeaderMap) {
+        for (name, values) in &self.headers {
+            if let Entry::Vacant(entry) = headers.entry(name) {
+                let mut values = values.iter().cloned();
+                if let Some(first) = values.next() {
+                    let mut entry = entry.insert_entry(first);
+                    values.for_each(|value| entry.append(value));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_static_headers_parse() {
+        let headers = StaticHeaders::new(&json!({
+            "X-Frame-Options": "DENY",
+            "vary": ["Origin", "Accept-Encoding"],
+            "access-control-max-age": 600,
+        }))
+        .unwrap();
+        assert_eq!(3, headers.headers.len());
+
+        assert!(StaticHeaders::new(&json!(["x-frame-options"])).is_err());
+        assert!(StaticHeaders::new(&json!({"bad name": "x"})).is_err());
+        assert!(StaticHeaders::new(&json!({"x-bad": "line\nbreak"})).is_err());
+        assert!(StaticHeaders::new(&json!({"x-bad": {"nested": true}})).is_err());
+        assert!(StaticHeaders::load(r#"{"x-frame-options":"DENY"}"#).is_ok());
+    }
+
+    #[test]
+    fn test_static_headers_keep_app_headers() {
+        let static_headers = StaticHeaders::new(&json!({
+            "x-frame-options": "DENY",
+            "vary": ["Origin", "Accept-Encoding"],
+            "cache-control": "no-store",
+        }))
+        .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("cache-control", HeaderValue::from_static("max-age=60"));
+        static_headers.apply(&mut headers);
+
+        assert_eq!("DENY", headers["x-frame-options"]);
+        assert_eq!(
+            vec!["Origin", "Accept-Encoding"],
+            headers.get_all("vary").iter().collect::<Vec<_>>()
+        );
+        assert_eq!("max-age=60", headers["cache-control"]);
+    }
+}
@@ -0,0 +1,501 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use http::header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE, TRAILER};
+use http_body::{Body, Frame, SizeHint};
+use http_body_util::BodyExt;
+use lambda_http::{Error, Response};
+use tokio::time::{Instant, Sleep};
+
+use crate::{full_body, ResponseBody};
+
+/// Lambda's soft limit for the size of a streamed response.
+pub(crate) const STREAM_SOFT_LIMIT: u64 = 20 * 1024 * 1024;
+/// Streamed responses are sent without bandwidth limits up to this size.
+pub(crate) const UNTHROTTLED_BYTES: u64 = 6 * 1024 * 1024;
+/// The bandwidth cap, in bytes per second, applied after the first 6MB.
+pub(crate) const THROTTLED_BYTES_PER_SEC: u64 = 2 * 1024 * 1024;
+
+const SOFT_LIMIT_WARNING: u64 = STREAM_SOFT_LIMIT / 10 * 8;
+
+/// Counts the bytes of a streamed response and warns when it approaches
+/// Lambda's response streaming limits. When `pacing` is set, chunks after
+/// the first 6MB are delayed to stay within the bandwidth cap.
+pub(crate) struct MeteredBody {
+    inner: ResponseBody,
+    pacing: bool,
+    bytes: u64,
+    started: Instant,
+    throttled_since: Option<Instant>,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl MeteredBody {
+    pub(crate) fn new(inner: ResponseBody, pacing: bool) -> Self {
+        MeteredBody {
+            inner,
+            pacing,
+            bytes: 0,
+            started: Instant::now(),
+            throttled_since: None,
+            delay: None,
+        }
+    }
+
+    fn record(&mut self, len: u64) {
+        let before = self.bytes;
+        self.bytes += len;
+
+        if before < UNTHROTTLED_BYTES && self.bytes >= UNTHROTTLED_BYTES {
+            self.throttled_since = Some(Instant::now());
+            tracing::warn!(
+                bytes_streamed = self.bytes,
+                "streamed response passed 6MB, Lambda limits the remaining bandwidth to 2MB/s"
+            );
+        }
+        if before < SOFT_LIMIT_WARNING && self.bytes >= SOFT_LIMIT_WARNING {
+            tracing::warn!(
+                bytes_streamed = self.bytes,
+                "streamed response is approaching the 20MB response streaming soft limit"
+            );
+        }
+        if before <= STREAM_SOFT_LIMIT && self.bytes > STREAM_SOFT_LIMIT {
+            tracing::warn!(
+                bytes_streamed = self.bytes,
+                "streamed response exceeded the 20MB response streaming soft limit"
+            );
+        }
+    }
+
+    /// How long to wait before the next chunk to stay within the bandwidth cap.
+    fn pacing_delay(&self) -> Option<Duration> {
+        let throttled_since = self.throttled_since?;
+        let throttled_bytes = self.bytes.saturating_sub(UNTHROTTLED_BYTES);
+        let allowed = Duration::from_secs_f64(throttled_bytes as f64 / THROTTLED_BYTES_PER_SEC as f64);
+        allowed
+            .checked_sub(throttled_since.elapsed())
+            .filter(|delay| !delay.is_zero())
+    }
+}
+
+impl Body for MeteredBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        let this = &mut *self;
+        if let Some(delay) = this.delay.as_mut() {
+            ready!(delay.as_mut().poll(cx));
+            this.delay = None;
+        }
+
+        let frame = ready!(Pin::new(&mut this.inner).poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    this.record(data.len() as u64);
+                    if this.pacing {
+                        this.delay = this.pacing_delay().map(|delay| Box::pin(tokio::time::sleep(delay)));
+                    }
+                }
+            }
+            None => {
+                tracing::debug!(
+                    bytes_streamed = this.bytes,
+                    duration_ms = this.started.elapsed().as_millis() as u64,
+                    "finished streaming response"
+                );
+            }
+            Some(Err(_)) => {}
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Wait up to `wait` for the body of a streamed response before its prelude
+/// is committed. A body that ends in time is sent in full, with the fields of
+/// its trailers, such as a `Set-Cookie` decided after the first write, moved
+/// into the headers. Otherwise the bytes read so far start the stream.
+pub(crate) async fn finalize_prelude(
+    response: Response<ResponseBody>,
+    wait: Duration,
+) -> Result<Response<ResponseBody>, Error> {
+    let (mut parts, mut body) = response.into_parts();
+    let deadline = Instant::now() + wait;
+    let mut head = BytesMut::new();
+    loop {
+        let Ok(frame) = tokio::time::timeout_at(deadline, body.frame()).await else {
+            let body = Prelude {
+                head: Some(head.freeze()).filter(|head| !head.is_empty()),
+                inner: body,
+            };
+            return Ok(Response::from_parts(parts, body.boxed_unsync()));
+        };
+        match frame.transpose()? {
+            Some(frame) => match frame.into_data() {
+                Ok(data) => head.extend_from_slice(&data),
+                Err(frame) => {
+                    if let Ok(trailers) = frame.into_trailers() {
+                        parts.headers.remove(TRAILER);
+                        for (name, value) in trailers.iter() {
+                            parts.headers.append(name, value.clone());
+                        }
+                    }
+                }
+            },
+            None => {
+                parts.headers.insert(CONTENT_LENGTH, head.len().into());
+                return Ok(Response::from_parts(parts, full_body(head.freeze())));
+            }
+        }
+    }
+}
+
+/// A streamed body with the bytes read while waiting for the prelude in front.
+struct Prelude {
+    head: Option<Bytes>,
+    inner: ResponseBody,
+}
+
+impl Body for Prelude {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        if let Some(head) = self.head.take() {
+            return Poll::Ready(Some(Ok(Frame::data(head))));
+        }
+        Pin::new(&mut self.inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.head.is_none() && self.inner.is_end_stream()
+    }
+}
+
+/// Whether a content type is newline-delimited JSON.
+pub(crate) fn is_ndjson(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    [
+        "application/x-ndjson",
+        "application/ndjson",
+        "application/jsonl",
+        "application/x-jsonlines",
+        "application/jsonlines",
+    ]
+    .iter()
+    .any(|ndjson| media_type.eq_ignore_ascii_case(ndjson))
+}
+
+/// Sends a streamed newline-delimited JSON response one complete line or
+/// more at a time, so clients never receive half a record. With `sse`, each
+/// line becomes a `data:` event of a server-sent event stream.
+pub(crate) struct NdjsonBody {
+    inner: ResponseBody,
+    sse: bool,
+    buffer: BytesMut,
+    pending: Option<Frame<Bytes>>,
+    done: bool,
+}
+
+impl NdjsonBody {
+    pub(crate) fn new(inner: ResponseBody, sse: bool) -> Self {
+        NdjsonBody {
+            inner,
+            sse,
+            buffer: BytesMut::new(),
+            pending: None,
+            done: false,
+        }
+    }
+
+    /// The buffered lines up to `end`, converted to events with `sse`.
+    fn take_lines(&mut self, end: usize) -> Bytes {
+        let lines = self.buffer.split_to(end).freeze();
+        if !self.sse {
+            return lines;
+        }
+        let mut events = BytesMut::with_capacity(lines.len() + 16);
+        for line in lines.split(|byte| *byte == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if !line.is_empty() {
+                events.extend_from_slice(b"data: ");
+                events.extend_from_slice(line);
+                events.extend_from_slice(b"\n\n");
+            }
+        }
+        events.freeze()
+    }
+}
+
+impl Body for NdjsonBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        let this = &mut *self;
+        loop {
+            if let Some(frame) = this.pending.take() {
+                return Poll::Ready(Some(Ok(frame)));
+            }
+            if this.done {
+                return Poll::Ready(None);
+            }
+            let end = match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => {
+                        this.buffer.extend_from_slice(&data);
+                        this.buffer.iter().rposition(|byte| *byte == b'\n').map(|last| last + 1)
+                    }
+                    // send what is left of the last line before the trailers
+                    Err(trailers) => {
+                        this.pending = Some(trailers);
+                        Some(this.buffer.len())
+                    }
+                },
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => {
+                    this.done = true;
+                    Some(this.buffer.len())
+                }
+            };
+            if let Some(end) = end.filter(|end| *end > 0) {
+                let lines = this.take_lines(end);
+                if !lines.is_empty() {
+                    return Poll::Ready(Some(Ok(Frame::data(lines))));
+                }
+            }
+        }
+    }
+}
+
+/// Whether a response is a server-sent event stream.
+pub(crate) fn is_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"text/event-stream"))
+}
+
+/// Sends a streamed server-sent event response up to the end of its last
+/// complete event, so events written together are not held back and a half
+/// written event waits for the rest. Events end with a blank line, after
+/// lines ending in `\n`, `\r\n` or `\r`.
+pub(crate) struct SseBody {
+    inner: ResponseBody,
+    buffer: BytesMut,
+    /// Where the line not yet scanned for its end starts in the buffer.
+    line_start: usize,
+    pending: Option<Frame<Bytes>>,
+    done: bool,
+}
+
+impl SseBody {
+    pub(crate) fn new(inner: ResponseBody) -> Self {
+        SseBody {
+            inner,
+            buffer: BytesMut::new(),
+            line_start: 0,
+            pending: None,
+            done: false,
+        }
+    }
+
+    /// The end of the last complete event in the buffer, or 0.
+    fn events_end(&mut self) -> usize {
+        let mut end = 0;
+        let mut line_start = self.line_start;
+        while let Some(offset) = self.buffer[line_start..]
+            .iter()
+            .position(|byte| *byte == b'\n' || *byte == b'\r')
+        {
+            let line_end = line_start + offset;
+            let len = match (self.buffer[line_end], self.buffer.get(line_end + 1)) {
+                (b'\r', Some(b'\n')) => 2,
+                // the rest of a `\r\n` may still come
+                (b'\r', None) => break,
+                _ => 1,
+            };
+            if line_end == line_start {
+                end = line_end + len;
+            }
+            line_start = line_end + len;
+        }
+        self.line_start = line_start - end;
+        end
+    }
+}
+
+impl Body for SseBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        let this = &mut *self;
+        loop {
+            if let Some(frame) = this.pending.take() {
+                return Poll::Ready(Some(Ok(frame)));
+            }
+            if this.done {
+                return Poll::Ready(None);
+            }
+            let end = match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => {
+                        this.buffer.extend_from_slice(&data);
+                        this.events_end()
+                    }
+                    // send what is left of the last event before the trailers
+                    Err(trailers) => {
+                        this.pending = Some(trailers);
+                        this.buffer.len()
+                    }
+                },
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => {
+                    this.done = true;
+                    this.buffer.len()
+                }
+            };
+            if end > 0 {
+                return Poll::Ready(Some(Ok(Frame::data(this.buffer.split_to(end).freeze()))));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunked_body, frames_body};
+    use futures_util::{stream, StreamExt};
+    use http_body_util::{Full, StreamBody};
+
+    #[tokio::test]
+    async fn test_metered_body_counts_bytes() {
+        let inner = Full::new(Bytes::from("Hello World"))
+            .map_err(Error::from)
+            .boxed_unsync();
+        let mut body = MeteredBody::new(inner, false);
+
+        let data = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        assert_eq!("Hello World", data);
+        assert_eq!(11, body.bytes);
+        assert!(body.throttled_since.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pacing_delay_after_unthrottled_bytes() {
+        let mut body = MeteredBody::new(Full::new(Bytes::new()).map_err(Error::from).boxed_unsync(), true);
+        body.record(UNTHROTTLED_BYTES);
+        assert!(body.pacing_delay().is_none());
+
+        // one more second worth of throttled bandwidth
+        body.record(THROTTLED_BYTES_PER_SEC);
+        let delay = body.pacing_delay().unwrap();
+        assert!(delay <= Duration::from_secs(1) && delay > Duration::from_millis(900));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(body.pacing_delay().is_none());
+    }
+
+    async fn frames(mut body: impl Body<Data = Bytes, Error = Error> + Unpin) -> Vec<String> {
+        let mut frames = Vec::new();
+        while let Some(frame) = body.frame().await {
+            let data = frame.unwrap().into_data().unwrap();
+            frames.push(String::from_utf8(data.to_vec()).unwrap());
+        }
+        frames
+    }
+
+    #[test]
+    fn test_is_ndjson() {
+        assert!(is_ndjson("application/x-ndjson"));
+        assert!(is_ndjson("application/jsonl; charset=utf-8"));
+        assert!(!is_ndjson("application/json"));
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_body_flushes_complete_lines() {
+        let body = NdjsonBody::new(chunked_body(&["{\"a\":1}\n{\"b\"", ":2}\n", "{\"c\":3}"]), false);
+        assert_eq!(vec!["{\"a\":1}\n", "{\"b\":2}\n", "{\"c\":3}"], frames(body).await);
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_body_to_sse() {
+        let body = NdjsonBody::new(chunked_body(&["{\"a\":1}\r\n\n{\"b\"", ":2}\n"]), true);
+        assert_eq!(vec!["data: {\"a\":1}\n\n", "data: {\"b\":2}\n\n"], frames(body).await);
+    }
+
+    #[tokio::test]
+    async fn test_finalize_prelude_moves_trailers_into_headers() {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("set-cookie", "session=abc".parse().unwrap());
+        let body = frames_body(vec![Frame::data(Bytes::from("Hello")), Frame::trailers(trailers)]);
+        let response = Response::builder().header(TRAILER, "set-cookie").body(body).unwrap();
+
+        let response = finalize_prelude(response, Duration::from_millis(50)).await.unwrap();
+        assert_eq!("session=abc", response.headers()["set-cookie"]);
+        assert_eq!("5", response.headers()[CONTENT_LENGTH]);
+        assert!(!response.headers().contains_key(TRAILER));
+        assert_eq!("Hello", response.into_body().collect().await.unwrap().to_bytes());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_finalize_prelude_streams_slow_bodies() {
+        // the last frame comes a second after the first one
+        let frames = stream::iter([Ok(Frame::data(Bytes::from("Hello ")))]).chain(stream::once(async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok::<_, Error>(Frame::data(Bytes::from("World")))
+        }));
+        let body = StreamBody::new(frames).boxed_unsync();
+
+        let response = finalize_prelude(Response::new(body), Duration::from_millis(50))
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key(CONTENT_LENGTH));
+        assert_eq!("Hello World", response.into_body().collect().await.unwrap().to_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_sse_body_flushes_complete_events() {
+        let body = SseBody::new(chunked_body(&[
+            "data: a\n\ndata: b\n",
+            "\nevent: c\r",
+            "\ndata: c\r\n\r",
+            "\n: ping\r\r",
+            "data: d",
+        ]));
+        assert_eq!(
+            vec![
+                "data: a\n\n",
+                "data: b\n\n",
+                "event: c\r\ndata: c\r\n\r\n",
+                ": ping\r\r",
+                "data: d"
+            ],
+            frames(body).await
+        );
+    }
+
+    #[test]
+    fn test_is_event_stream() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_event_stream(&headers));
+        headers.insert(CONTENT_TYPE, "text/event-stream; charset=utf-8".parse().unwrap());
+        assert!(is_event_stream(&headers));
+    }
+}
@@ -0,0 +1,519 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Runs the web application as a child process of the adapter.
+//!
+//! Container images whose entrypoint is the adapter can name the app's
+//! command in `AWS_LWA_APP_CMD` instead of starting it from a wrapper
+//! script. The adapter forwards the app's output line by line, so it does
+//! not interleave with the adapter's own logs, and keeps the last lines for
+//! failure bundles. It restarts the app when it exits, and treats the app as
+//! unready while it is not running. Restarts back off, so an app that
+//! crashes at start does not spin. At shutdown the adapter signals the app
+//! and waits for it to exit, so no process is left behind unreaped.
+//!
+//! Variables of the app can be templates with placeholders, such as
+//! `https://{domainName}/{stage}`. Placeholders of the function, such as
+//! `{functionName}`, are filled from its environment. Placeholders of the
+//! request context are filled from the first request, so an app that needs
+//! them starts with the first invoke instead of at init.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Write};
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use lambda_http::Error;
+use serde_json::{Map, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::shutdown;
+
+/// The delay before the first restart, doubled after each quick crash.
+const MIN_RESTART_DELAY: Duration = Duration::from_millis(100);
+
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(10);
+
+/// An app that ran this long before it exited restarts without delay.
+const STABLE_RUN: Duration = Duration::from_secs(60);
+
+/// Lines of the app's output kept for failure bundles.
+const LOG_TAIL_LINES: usize = 50;
+
+/// Longer lines are cut, so a tail stays small.
+const MAX_LOG_LINE: usize = 1024;
+
+/// Placeholders of the function, and the variables they are read from.
+const FUNCTION_FIELDS: [(&str, &str); 4] = [
+    ("functionName", "AWS_LAMBDA_FUNCTION_NAME"),
+    ("functionVersion", "AWS_LAMBDA_FUNCTION_VERSION"),
+    ("memorySize", "AWS_LAMBDA_FUNCTION_MEMORY_SIZE"),
+    ("region", "AWS_REGION"),
+];
+
+/// Placeholders of the stable request context of the first request.
+const REQUEST_FIELDS: [&str; 5] = ["accountId", "apiId", "domainName", "source", "stage"];
+
+pub(crate) struct Supervisor {
+    program: String,
+    args: Vec<String>,
+    /// Variables of the app, as templates.
+    env: BTreeMap<String, String>,
+    /// The values of the placeholders of the function.
+    function: BTreeMap<&'static str, String>,
+    /// The stable request context of the first request, once the app has
+    /// been started by it.
+    request_context: OnceLock<Map<String, Value>>,
+    started: AtomicBool,
+    /// The last lines of the app's output.
+    log_tail: Arc<Mutex<VecDeque<String>>>,
+    /// The process id of the running app, or 0.
+    pid: AtomicI32,
+    /// The shutdown signal and drain timeout, once the app is being stopped.
+    stop: watch::Sender<Option<(i32, Duration)>>,
+    task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Supervisor {
+    pub(crate) fn new(command: &str) -> Result<Self, Error> {
+        let mut words = split_command(command)?.into_iter();
+        let program = words.next().ok_or_else(|| Error::from("empty command"))?;
+        Ok(Supervisor {
+            program,
+            args: words.collect(),
+            env: BTreeMap::new(),
+            function: BTreeMap::new(),
+            request_context: OnceLock::new(),
+            started: AtomicBool::new(false),
+            log_tail: Arc::default(),
+            pid: AtomicI32::new(0),
+            stop: watch::Sender::new(None),
+            task: Mutex::new(None),
+        })
+    }
+
+    /// Pass the app the variables of a JSON object of names to templates.
+    /// Placeholders of the function are filled from the variables `function`
+    /// looks up, such as those Lambda sets in the environment.
+    pub(crate) fn set_env(&mut self, templates: &str, function: impl Fn(&str) -> Option<String>) -> Result<(), Error> {
+        let env: BTreeMap<String, String> = serde_json::from_str(templates)?;
+        for template in env.values() {
+            if let Some(field) = placeholders(template).find(|field| !is_field(field)) {
+                return Err(Error::from(format!("unknown placeholder {{{field}}}")));
+            }
+        }
+        self.env = env;
+        self.function = FUNCTION_FIELDS
+            .iter()
+            .map(|(field, variable)| (*field, function(variable).unwrap_or_default()))
+            .collect();
+        Ok(())
+    }
+
+    /// Whether the variables of the app take values from a request context,
+    /// so the app has to be started with [`Supervisor::start_with_context`].
+    pub(crate) fn needs_request(&self) -> bool {
+        self.env
+            .values()
+            .any(|template| placeholders(template).any(|field| REQUEST_FIELDS.contains(&field)))
+    }
+
+    /// Start the app with the stable request context of the first request,
+    /// unless it has been started already.
+    pub(crate) fn start_with_context(
+        self: &Arc<Self>,
+        context: impl FnOnce() -> Option<Value>,
+        ready: Arc<AtomicBool>,
+    ) {
+        if self.started.load(Ordering::Acquire) {
+            return;
+        }
+        let context = match context() {
+            Some(Value::Object(context)) => context,
+            _ => Map::new(),
+        };
+        let _ = self.request_context.set(context);
+        self.start(ready);
+    }
+
+    /// Start the app, and restart it whenever it exits until it is stopped.
+    /// `ready` is cleared when the app exits, so requests wait for the
+    /// restarted app to pass the readiness check.
+    pub(crate) fn start(self: &Arc<Self>, ready: Arc<AtomicBool>) {
+        if self.started.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let supervisor = self.clone();
+        let task = tokio::spawn(async move {
+            let mut delay = MIN_RESTART_DELAY;
+            while !supervisor.is_stopping() {
+                let started = Instant::now();
+                let status = supervisor.run().await;
+                match status {
+                    Ok(status) if supervisor.is_stopping() => {
+                        tracing::info!(program = %supervisor.program, %status, "app stopped");
+                        return;
+                    }
+                    Err(err) if supervisor.is_stopping() => {
+                        tracing::warn!(program = %supervisor.program, "failed to stop the app: {}", err);
+                        return;
+                    }
+                    Ok(status) => tracing::warn!(program = %supervisor.program, %status, "app exited"),
+                    Err(err) => tracing::error!(program = %supervisor.program, "failed to start the app: {}", err),
+                }
+                ready.store(false, Ordering::Release);
+                if started.elapsed() >= STABLE_RUN {
+                    delay = MIN_RESTART_DELAY;
+                }
+                tracing::info!("restarting the app in {:?}", delay);
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = supervisor.stopping() => return,
+                }
+                delay = (delay * 2).min(MAX_RESTART_DELAY);
+            }
+        });
+        *self.task.lock().unwrap() = Some(task);
+    }
+
+    /// Run the app until it exits, or until it is stopped.
+    async fn run(&self) -> io::Result<ExitStatus> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .envs(self.env())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        self.pid.store(child.id().unwrap_or_default() as i32, Ordering::Release);
+        tracing::info!(program = %self.program, pid = child.id(), "started the app");
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(forward(stdout, std::io::stdout, self.log_tail.clone()));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(forward(stderr, std::io::stderr, self.log_tail.clone()));
+        }
+        let status = tokio::select! {
+            status = child.wait() => status,
+            (signal, drain) = self.stopping() => terminate(&mut child, signal, drain).await,
+        };
+        self.pid.store(0, Ordering::Release);
+        status
+    }
+
+    /// The variables of the app, with their placeholders filled.
+    fn env(&self) -> Vec<(String, String)> {
+        self.env
+            .iter()
+            .map(|(name, template)| (name.clone(), render(template, |field| self.field(field))))
+            .collect()
+    }
+
+    fn field(&self, name: &str) -> String {
+        if let Some(value) = self.function.get(name) {
+            return value.clone();
+        }
+        self.request_context
+            .get()
+            .and_then(|context| context.get(name))
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// The process id of the app while it runs.
+    pub(crate) fn pid(&self) -> Option<i32> {
+        Some(self.pid.load(Ordering::Acquire)).filter(|pid| *pid > 0)
+    }
+
+    pub(crate) fn is_running(&self) -> bool {
+        self.pid().is_some()
+    }
+
+    /// The last lines the app wrote to its standard output and error, oldest
+    /// first.
+    pub(crate) fn log_tail(&self) -> Vec<String> {
+        self.log_tail.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn is_stopping(&self) -> bool {
+        self.stop.borrow().is_some()
+    }
+
+    /// Wait until the app is being stopped, for the signal and drain timeout.
+    async fn stopping(&self) -> (i32, Duration) {
+        let mut stop = self.stop.subscribe();
+        let request = stop.wait_for(Option::is_some).await.map(|request| *request);
+        match request {
+            Ok(Some(request)) => request,
+            _ => std::future::pending().await,
+        }
+    }
+
+    /// Stop restarting the app, send it `signal` and wait up to `drain` for
+    /// it to exit.
+    pub(crate) async fn stop(&self, signal: i32, drain: Duration) {
+        self.stop.send_replace(Some((signal, drain)));
+        let task = self.task.lock().unwrap().take();
+        if let Some(task) = task {
+            let _ = task.await;
+        }
+    }
+}
+
+fn is_field(name: &str) -> bool {
+    FUNCTION_FIELDS.iter().any(|(field, _)| *field == name) || REQUEST_FIELDS.contains(&name)
+}
+
+/// The names of the `{name}` placeholders of a template. Other braces are
+/// kept as they are.
+fn placeholders(template: &str) -> impl Iterator<Item = &str> {
+    template.split('{').skip(1).filter_map(|part| {
+        let (name, _) = part.split_once('}')?;
+        (!name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric())).then_some(name)
+    })
+}
+
+/// Fill the placeholders of a template with the values of `field`.
+fn render(template: &str, field: impl Fn(&str) -> String) -> String {
+    let mut parts = template.split('{');
+    let mut rendered = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        match part.split_once('}') {
+            Some((name, rest)) if is_field(name) => {
+                rendered.push_str(&field(name));
+                rendered.push_str(rest);
+            }
+            _ => {
+                rendered.push('{');
+                rendered.push_str(part);
+            }
+        }
+    }
+    rendered
+}
+
+/// Send the app the shutdown signal and wait for it to exit, and kill it
+/// when it is still running after `drain`.
+async fn terminate(child: &mut Child, signal: i32, drain: Duration) -> io::Result<ExitStatus> {
+    if let Some(pid) = child.id() {
+        shutdown::send_signal(pid as i32, signal)?;
+    }
+    match tokio::time::timeout(drain, child.wait()).await {
+        Ok(status) => status,
+        Err(_) => {
+            tracing::warn!(
+                pid = child.id(),
+                "app did not exit within {:?} of the shutdown signal, killing it",
+                drain
+            );
+            child.kill().await?;
+            child.wait().await
+        }
+    }
+}
+
+/// Copy the output of the app a line at a time, and keep the last lines.
+async fn forward<R, W>(output: R, sink: fn() -> W, tail: Arc<Mutex<VecDeque<String>>>)
+where
+    R: AsyncRead + Unpin,
+    W: Write,
+{
+    let mut output = BufReader::new(output);
+    let mut line = Vec::new();
+    while output.read_until(b'\n', &mut line).await.is_ok_and(|read| read > 0) {
+        if !line.ends_with(b"\n") {
+            line.push(b'\n');
+        }
+        let _ = sink().write_all(&line);
+        let kept = &line[..(line.len() - 1).min(MAX_LOG_LINE)];
+        let mut tail = tail.lock().unwrap();
+        if tail.len() == LOG_TAIL_LINES {
+            tail.pop_front();
+        }
+        tail.push_back(String::from_utf8_lossy(kept).trim_end_matches('\r').to_string());
+        drop(tail);
+        line.clear();
+    }
+}
+
+/// Split a command line into words, as a shell would for plain words,
+/// quoted strings and backslash escapes. No variables are expanded.
+fn split_command(command: &str) -> Result<Vec<String>, Error> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(Error::from("unterminated single quote")),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(Error::from("unterminated double quote")),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(Error::from("unterminated double quote")),
+                    }
+                }
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    word.get_or_insert_with(String::new).push(c);
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(
+            vec!["node", "index.js", "--port", "8080"],
+            split_command("  node index.js  --port 8080 ").unwrap()
+        );
+        assert_eq!(
+            vec!["python", "-c", "print('hi there')", "a b", "", "c\"d"],
+            split_command(r#"python -c "print('hi there')" a\ b '' 'c"d'"#).unwrap()
+        );
+        assert!(split_command("echo 'oops").is_err());
+        assert!(Supervisor::new("   ").is_err());
+    }
+
+    #[test]
+    fn test_supervisor_env_templates() {
+        let function = |variable: &str| (variable == "AWS_LAMBDA_FUNCTION_NAME").then(|| "orders".to_string());
+        let mut supervisor = Supervisor::new("node index.js").unwrap();
+        supervisor
+            .set_env(
+                r#"{"BASE_URL":"https://{domainName}/{stage}","SERVICE":"{functionName}","JSON":"{\"a\":1}"}"#,
+                function,
+            )
+            .unwrap();
+        assert!(supervisor.needs_request());
+        let _ = supervisor.request_context.set(Map::from_iter([
+            ("domainName".to_string(), Value::from("api.example.com")),
+            ("stage".to_string(), Value::from("prod")),
+        ]));
+        assert_eq!(
+            vec![
+                ("BASE_URL".to_string(), "https://api.example.com/prod".to_string()),
+                ("JSON".to_string(), r#"{"a":1}"#.to_string()),
+                ("SERVICE".to_string(), "orders".to_string()),
+            ],
+            supervisor.env()
+        );
+
+        let mut supervisor = Supervisor::new("node index.js").unwrap();
+        supervisor
+            .set_env(r#"{"SERVICE":"{functionName}-{region}"}"#, function)
+            .unwrap();
+        assert!(!supervisor.needs_request());
+        assert!(supervisor
+            .set_env(r#"{"BASE_URL":"https://{host}"}"#, function)
+            .is_err());
+        assert!(!supervisor.needs_request());
+    }
+
+    #[tokio::test]
+    async fn test_supervisor_restarts_app() {
+        let supervisor = Arc::new(Supervisor::new("sleep 0.05").unwrap());
+        let ready = Arc::new(AtomicBool::new(true));
+        supervisor.start(ready.clone());
+
+        let mut pids = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pids.len() < 2 && Instant::now() < deadline {
+            if let Some(pid) = supervisor.pid().filter(|pid| !pids.contains(pid)) {
+                pids.push(pid);
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(2, pids.len());
+        assert!(!ready.load(Ordering::Acquire));
+        supervisor.stop(libc::SIGTERM, Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn test_supervisor_keeps_log_tail() {
+        let supervisor = Arc::new(Supervisor::new("sh -c \"seq 1 60; echo oops >&2; exec sleep 30\"").unwrap());
+        supervisor.start(Arc::new(AtomicBool::new(false)));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while supervisor.log_tail().len() < LOG_TAIL_LINES && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let tail = supervisor.log_tail();
+        assert_eq!(LOG_TAIL_LINES, tail.len());
+        assert!(tail.contains(&"oops".to_string()));
+        assert!(tail.contains(&"60".to_string()));
+        assert!(!tail.contains(&"1".to_string()));
+        supervisor.stop(libc::SIGTERM, Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn test_supervisor_stops_app() {
+        let supervisor = Arc::new(Supervisor::new("sleep 30").unwrap());
+        supervisor.start(Arc::new(AtomicBool::new(false)));
+        while !supervisor.is_running() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let pid = supervisor.pid().unwrap();
+
+        let started = Instant::now();
+        supervisor.stop(libc::SIGTERM, Duration::from_secs(5)).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!supervisor.is_running());
+        // the app has been waited for, so no zombie is left behind
+        assert!(!std::path::Path::new(&format!("/proc/{pid}")).exists());
+    }
+
+    #[tokio::test]
+    async fn test_supervisor_kills_app_after_drain() {
+        let supervisor = Arc::new(Supervisor::new("sh -c \"trap '' TERM; exec sleep 30\"").unwrap());
+        supervisor.start(Arc::new(AtomicBool::new(false)));
+        while !supervisor.is_running() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        // give the shell time to ignore SIGTERM before it is sent
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let pid = supervisor.pid().unwrap();
+
+        let started = Instant::now();
+        supervisor.stop(libc::SIGTERM, Duration::from_millis(200)).await;
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!std::path::Path::new(&format!("/proc/{pid}")).exists());
+    }
+}
@@ -0,0 +1,339 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A subscriber of the Lambda Telemetry API, for the platform events of the
+//! execution environment.
+//!
+//! The extension subscribes once it has registered, and Lambda then posts
+//! batches of platform events, such as the init report and the report of each
+//! invoke, to a local listener. Each invoke report is logged together with
+//! what the adapter saw of the same request, the latency and the status of
+//! the app, so the time spent outside the app shows up next to the billed
+//! duration. The events can also be forwarded to a path of the app.
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::{HeaderValue, Method, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use lambda_http::{Body, Error};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use url::Url;
+
+/// The schema of the events the subscription asks for.
+const SCHEMA_VERSION: &str = "2022-12-13";
+
+/// Requests the adapter remembers until their report arrives.
+const MAX_RECORDS: usize = 1000;
+
+/// How long forwarding a batch to the app may take, since Lambda waits for it.
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What the adapter saw of a request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Record {
+    upstream: Duration,
+    status: Option<StatusCode>,
+}
+
+#[derive(Debug, Default)]
+struct Records {
+    by_request: HashMap<String, Record>,
+    order: VecDeque<String>,
+}
+
+pub(crate) struct Telemetry {
+    port: u16,
+    forward: Option<Url>,
+    client: Client<HttpConnector, Body>,
+    records: Mutex<Records>,
+}
+
+impl Telemetry {
+    /// Listen for events on `port`, and forward them to the app at `forward`.
+    pub(crate) fn new(port: u16, forward: Option<Url>) -> Self {
+        Telemetry {
+            port,
+            forward,
+            client: Client::builder(TokioExecutor::new()).build(HttpConnector::new()),
+            records: Mutex::new(Records::default()),
+        }
+    }
+
+    /// Remember a call to the app, with the status of its response or `None`
+    /// when the app did not respond, until the report of the invoke arrives.
+    pub(crate) fn record(&self, request_id: &str, upstream: Duration, status: Option<StatusCode>) {
+        let mut records = self.records.lock().unwrap();
+        if records.order.len() >= MAX_RECORDS {
+            if let Some(oldest) = records.order.pop_front() {
+                records.by_request.remove(&oldest);
+            }
+        }
+        records.order.push_back(request_id.to_string());
+        records
+            .by_request
+            .insert(request_id.to_string(), Record { upstream, status });
+    }
+
+    /// Start the listener and subscribe it to the platform events, as the
+    /// extension with `extension_id`.
+    pub(crate) async fn subscribe(
+        self: &Arc<Self>,
+        client: &Client<HttpConnector, Body>,
+        runtime_api: &str,
+        extension_id: &HeaderValue,
+    ) -> Result<(), Error> {
+        let listener = std::net::TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], self.port)))?;
+        listener.set_nonblocking(true)?;
+        tokio::spawn(self.clone().accept(TcpListener::from_std(listener)?));
+
+        let subscription = json!({
+            "schemaVersion": SCHEMA_VERSION,
+            "destination": { "protocol": "HTTP", "URI": format!("http://sandbox.localdomain:{}", self.port) },
+            "types": ["platform"],
+            "buffering": { "maxItems": 1000, "maxBytes": 262144, "timeoutMs": 100 },
+        });
+        let request = hyper::Request::builder()
+            .method(Method::PUT)
+            .uri(format!("http://{runtime_api}/2022-07-01/telemetry"))
+            .header("Lambda-Extension-Identifier", extension_id)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(subscription.to_string()))?;
+        let response = client.request(request).await?;
+        let status = response.status();
+        let body = response.into_body().collect().await?.to_bytes();
+        if !status.is_success() {
+            return Err(Error::from(format!(
+                "telemetry subscription failed with {status}: {}",
+                String::from_utf8_lossy(&body)
+            )));
+        }
+        tracing::info!(port = self.port, "subscribed to the Lambda Telemetry API");
+        Ok(())
+    }
+
+    /// Log the reports of a batch of events, and add what the adapter saw of
+    /// each invoke to its report.
+    fn process(&self, mut events: Vec<Value>) -> Vec<Value> {
+        for event in events.iter_mut() {
+            let record = &event["record"];
+            let metrics = &record["metrics"];
+            match event["type"].as_str() {
+                Some("platform.initReport") => tracing::info!(
+                    initialization_type = record["initializationType"].as_str(),
+                    phase = record["phase"].as_str(),
+                    status = record["status"].as_str(),
+                    init_duration_ms = metrics["durationMs"].as_f64(),
+                    "lambda init report"
+                ),
+                Some("platform.report") => {
+                    let request_id = record["requestId"].as_str().unwrap_or_default().to_string();
+                    let adapter = {
+                        let mut records = self.records.lock().unwrap();
+                        let adapter = records.by_request.remove(&request_id);
+                        if adapter.is_some() {
+                            records.order.retain(|id| *id != request_id);
+                        }
+                        adapter
+                    };
+                    let upstream_ms = adapter.map(|adapter| adapter.upstream.as_secs_f64() * 1000.0);
+                    let app_status = adapter.and_then(|adapter| adapter.status).map(|status| status.as_u16());
+                    tracing::info!(
+                        request_id,
+                        status = record["status"].as_str(),
+                        duration_ms = metrics["durationMs"].as_f64(),
+                        billed_duration_ms = metrics["billedDurationMs"].as_u64(),
+                        max_memory_used_mb = metrics["maxMemoryUsedMB"].as_u64(),
+                        memory_size_mb = metrics["memorySizeMB"].as_u64(),
+                        init_duration_ms = metrics["initDurationMs"].as_f64(),
+                        upstream_ms,
+                        app_status,
+                        "lambda invoke report"
+                    );
+                    if adapter.is_some() {
+                        event["adapter"] = json!({ "upstreamMs": upstream_ms, "appStatus": app_status });
+                    }
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+
+    /// Send a batch of events to the app as a JSON array.
+    async fn forward(&self, url: &Url, events: &[Value]) {
+        let send = async {
+            let request = hyper::Request::builder()
+                .method(Method::POST)
+                .uri(url.as_str())
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_string(events)?))?;
+            let response = self.client.request(request).await?;
+            let status = response.status();
+            response.into_body().collect().await?;
+            Ok::<_, Error>(status)
+        };
+        match timeout(FORWARD_TIMEOUT, send).await {
+            Ok(Ok(status)) => tracing::debug!(%status, "forwarded telemetry events to the app"),
+            Ok(Err(err)) => tracing::warn!("failed to forward telemetry events to the app: {}", err),
+            Err(_) => tracing::warn!("timed out forwarding telemetry events to the app"),
+        }
+    }
+
+    async fn accept(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    tracing::warn!(error = %err, "failed to accept telemetry connection");
+                    continue;
+                }
+            };
+            let telemetry = self.clone();
+            tokio::spawn(async move {
+                let service = service_fn(|request| telemetry.clone().receive(request));
+                if let Err(err) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    tracing::debug!(error = %err, "telemetry connection closed");
+                }
+            });
+        }
+    }
+
+    async fn receive(
+        self: Arc<Self>,
+        request: hyper::Request<Incoming>,
+    ) -> Result<hyper::Response<Full<Bytes>>, Infallible> {
+        let mut response = hyper::Response::new(Full::new(Bytes::new()));
+        let events = match request.into_body().collect().await {
+            Ok(body) => serde_json::from_slice::<Vec<Value>>(&body.to_bytes()),
+            Err(err) => {
+                tracing::warn!(error = %err, "failed to read telemetry events");
+                *response.status_mut() = StatusCode::BAD_REQUEST;
+                return Ok(response);
+            }
+        };
+        match events {
+            Ok(events) => {
+                let events = self.process(events);
+                if let Some(url) = self.forward.as_ref() {
+                    self.forward(url, &events).await;
+                }
+            }
+            Err(err) => {
+                tracing::warn!(error = %err, "invalid telemetry events");
+                *response.status_mut() = StatusCode::BAD_REQUEST;
+            }
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httpmock::{Method::POST, Method::PUT, MockServer};
+
+    fn report(request_id: &str) -> Value {
+        json!({
+            "time": "2024-05-01T12:34:56.789Z",
+            "type": "platform.report",
+            "record": {
+                "requestId": request_id,
+                "status": "success",
+                "metrics": { "durationMs": 120.5, "billedDurationMs": 121, "memorySizeMB": 512, "maxMemoryUsedMB": 90 },
+            },
+        })
+    }
+
+    #[test]
+    fn test_telemetry_correlates_reports() {
+        let telemetry = Telemetry::new(0, None);
+        telemetry.record("req-1", Duration::from_millis(100), Some(StatusCode::CREATED));
+        telemetry.record("req-2", Duration::from_millis(5), None);
+
+        let init = json!({ "type": "platform.initReport", "record": { "metrics": { "durationMs": 250.0 } } });
+        let events = telemetry.process(vec![init.clone(), report("req-1"), report("req-3")]);
+        assert_eq!(init, events[0]);
+        assert_eq!(json!({ "upstreamMs": 100.0, "appStatus": 201 }), events[1]["adapter"]);
+        assert_eq!(Value::Null, events[2]["adapter"]);
+
+        // a report is only correlated once
+        assert_eq!(Value::Null, telemetry.process(vec![report("req-1")])[0]["adapter"]);
+        let events = telemetry.process(vec![report("req-2")]);
+        assert_eq!(json!({ "upstreamMs": 5.0, "appStatus": null }), events[0]["adapter"]);
+        assert!(telemetry.records.lock().unwrap().order.is_empty());
+    }
+
+    #[test]
+    fn test_telemetry_records_are_bounded() {
+        let telemetry = Telemetry::new(0, None);
+        for request in 0..=MAX_RECORDS {
+            telemetry.record(&format!("req-{request}"), Duration::ZERO, None);
+        }
+        let records = telemetry.records.lock().unwrap();
+        assert_eq!(MAX_RECORDS, records.by_request.len());
+        assert!(!records.by_request.contains_key("req-0"));
+    }
+
+    #[tokio::test]
+    async fn test_telemetry_subscribes_and_forwards() {
+        let runtime_api = MockServer::start();
+        let subscribe = runtime_api.mock(|when, then| {
+            when.method(PUT)
+                .path("/2022-07-01/telemetry")
+                .header("Lambda-Extension-Identifier", "ext-1")
+                .json_body_partial(r#"{"types": ["platform"], "schemaVersion": "2022-12-13"}"#);
+            then.status(200).body("OK");
+        });
+        let app = MockServer::start();
+        let forward = app.mock(|when, then| {
+            when.method(POST)
+                .path("/_lwa/telemetry")
+                .body_contains(r#""adapter":{"appStatus":200,"upstreamMs":10.0}"#);
+            then.status(204);
+        });
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let telemetry = Arc::new(Telemetry::new(
+            port,
+            Some(Url::parse(&app.url("/_lwa/telemetry")).unwrap()),
+        ));
+        let client = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+        telemetry
+            .subscribe(
+                &client,
+                &runtime_api.address().to_string(),
+                &HeaderValue::from_static("ext-1"),
+            )
+            .await
+            .unwrap();
+        subscribe.assert();
+
+        telemetry.record("req-1", Duration::from_millis(10), Some(StatusCode::OK));
+        let request = hyper::Request::post(format!("http://127.0.0.1:{port}/"))
+            .body(Body::from(json!([report("req-1")]).to_string()))
+            .unwrap();
+        let response = client.request(request).await.unwrap();
+        assert_eq!(200, response.status());
+        forward.assert();
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Per-request timings of the adapter's own stages, to tell its overhead
+//! apart from the time the app takes.
+//!
+//! The stages of a request are marked as it passes them, in a task-local
+//! timer, so the connector can add the time it spends connecting to the app
+//! without a handle on the request. Outside a timed request the marks do
+//! nothing.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+tokio::task_local! {
+    static TIMER: RefCell<StageTimer>;
+}
+
+/// The points a request passes on its way through the adapter.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Stage {
+    /// The event has been checked and taken apart into the request.
+    Decoded,
+    /// The request to the app has been built.
+    Built,
+    /// The app's response headers have arrived.
+    Responded,
+}
+
+#[derive(Debug)]
+struct StageTimer {
+    started: Instant,
+    decoded: Option<Instant>,
+    built: Option<Instant>,
+    responded: Option<Instant>,
+    connect: Duration,
+}
+
+/// How long a request spent in each stage.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct StageTimings {
+    pub(crate) decode: Duration,
+    pub(crate) headers: Duration,
+    pub(crate) connect: Duration,
+    pub(crate) upstream: Duration,
+    pub(crate) encode: Duration,
+}
+
+impl StageTimer {
+    fn new() -> Self {
+        StageTimer {
+            started: Instant::now(),
+            decoded: None,
+            built: None,
+            responded: None,
+            connect: Duration::ZERO,
+        }
+    }
+
+    /// Split the time up to `finished` by the stages that were reached. A
+    /// request answered by the adapter itself skips the later stages, and
+    /// the time after the last mark counts as encoding the response.
+    fn timings(&self, finished: Instant) -> StageTimings {
+        let decoded = self.decoded.unwrap_or(finished);
+        let built = self.built.unwrap_or(decoded).max(decoded);
+        let responded = self.responded.unwrap_or(built).max(built);
+        let sent = responded.duration_since(built);
+        StageTimings {
+            decode: decoded.duration_since(self.started),
+            headers: built.duration_since(decoded),
+            connect: self.connect.min(sent),
+            upstream: sent.saturating_sub(self.connect),
+            encode: finished.duration_since(responded),
+        }
+    }
+}
+
+/// Time the stages of the request `future` handles.
+pub(crate) async fn timed<F: Future>(future: F) -> (F::Output, StageTimings) {
+    TIMER
+        .scope(RefCell::new(StageTimer::new()), async {
+            let output = future.await;
+            let timings = TIMER.with(|timer| timer.borrow().timings(Instant::now()));
+            (output, timings)
+        })
+        .await
+}
+
+/// Mark that the current request reached `stage`.
+pub(crate) fn mark(stage: Stage) {
+    let now = Instant::now();
+    let _ = TIMER.try_with(|timer| {
+        let mut timer = timer.borrow_mut();
+        match stage {
+            Stage::Decoded => timer.decoded = Some(now),
+            Stage::Built => timer.built = Some(now),
+            Stage::Responded => timer.responded = Some(now),
+        }
+    });
+}
+
+/// Add the time spent opening a connection to the app for the current request.
+pub(crate) fn connected(elapsed: Duration) {
+    let _ = TIMER.try_with(|timer| timer.borrow_mut().connect += elapsed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stage_timings() {
+        let started = Instant::now();
+        let at = |ms| started + Duration::from_millis(ms);
+        let timer = StageTimer {
+            started,
+            decoded: Some(at(1)),
+            built: Some(at(3)),
+            responded: Some(at(20)),
+            connect: Duration::from_millis(5),
+        };
+        assert_eq!(
+            StageTimings {
+                decode: Duration::from_millis(1),
+                headers: Duration::from_millis(2),
+                connect: Duration::from_millis(5),
+                upstream: Duration::from_millis(12),
+                encode: Duration::from_millis(4),
+            },
+            timer.timings(at(24))
+        );
+
+        // a response of the adapter itself is all decoding and encoding
+        let timer = StageTimer {
+            started,
+            decoded: None,
+            built: None,
+            responded: None,
+            connect: Duration::ZERO,
+        };
+        assert_eq!(
+            StageTimings {
+                decode: Duration::from_millis(2),
+                ..Default::default()
+            },
+            timer.timings(at(2))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_marks_outside_timed_requests() {
+        mark(Stage::Decoded);
+        connected(Duration::from_millis(1));
+
+        let ((), timings) = timed(async {
+            mark(Stage::Decoded);
+            mark(Stage::Built);
+            connected(Duration::from_millis(1));
+            std::thread::sleep(Duration::from_millis(5));
+            mark(Stage::Responded);
+        })
+        .await;
+        assert_eq!(Duration::from_millis(1), timings.connect);
+        assert!(timings.upstream >= Duration::from_millis(4));
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! TLS to apps that only listen on HTTPS, such as ASP.NET Core apps with
+//! their development certificate.
+
+use std::sync::Arc;
+
+use lambda_http::Error;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+
+/// The TLS settings for connections to the app. The system roots are
+/// trusted, and so are the certificates of `ca_file`, a PEM bundle.
+pub(crate) fn client_config(ca_file: Option<&str>, skip_verify: bool) -> ClientConfig {
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .expect("the default crypto provider supports the default protocol versions");
+    if skip_verify {
+        return builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+            .with_no_client_auth();
+    }
+
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(rustls_native_certs::load_native_certs().unwrap_or_default());
+    if let Some(ca_file) = ca_file {
+        match ca_certs(ca_file) {
+            Ok(certs) => {
+                let (added, _) = roots.add_parsable_certificates(certs);
+                tracing::debug!(ca_file, added, "trusting the app's CA certificates");
+            }
+            Err(err) => tracing::warn!("ignoring invalid AWS_LWA_APP_TLS_CA_FILE: {}", err),
+        }
+    }
+    builder.with_root_certificates(roots).with_no_client_auth()
+}
+
+/// The TLS settings for apps on plain HTTP, which never get used. Loading the
+/// system roots is left out, since it slows down the cold start.
+pub(crate) fn unused_client_config() -> ClientConfig {
+    ClientConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+        .with_safe_default_protocol_versions()
+        .expect("the default crypto provider supports the default protocol versions")
+        .with_root_certificates(RootCertStore::empty())
+        .with_no_client_auth()
+}
+
+fn ca_certs(ca_file: &str) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let pem = std::fs::read(ca_file).map_err(|err| format!("{ca_file}: {err}"))?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice()).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(Error::from(format!("{ca_file}: no certificates found")));
+    }
+    Ok(certs)
+}
+
+/// Accepts any certificate of the app, for self-signed development
+/// certificates. Signatures are still checked, so the handshake stays sound.
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ca_certs() {
+        let ca_file = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/integ_tests/fixtures/ca.pem");
+        assert_eq!(1, ca_certs(ca_file).unwrap().len());
+
+        let key_file = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/integ_tests/fixtures/localhost-key.pem"
+        );
+        assert!(ca_certs(key_file).is_err());
+        assert!(ca_certs("/nonexistent/ca.pem").is_err());
+    }
+}
@@ -0,0 +1,472 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use http::header::{CONTENT_ENCODING, CONTENT_TYPE};
+use http::HeaderMap;
+use lambda_http::Error;
+use serde_json::{Map, Value};
+
+use crate::glob_match;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Which body a rule applies to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Direction {
+    Request,
+    Response,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum KeyCase {
+    Snake,
+    Camel,
+}
+
+#[derive(Debug, PartialEq)]
+enum Transform {
+    /// Replace the values of these fields, at any depth.
+    Redact(Vec<String>),
+    /// Convert the keys of every object.
+    Case(KeyCase),
+    /// Replace the body by one of its fields, or the value at a JSON pointer.
+    Unwrap(String),
+    /// Delete the values at these JSON paths.
+    Remove(Vec<JsonPath>),
+    /// Replace the values at these JSON paths.
+    Mask(Vec<JsonPath>),
+}
+
+/// One step of a JSON path such as `$.users[*].ssn`.
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Field(String),
+    Index(usize),
+    /// Every field of an object or item of an array.
+    Any,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct JsonPath(Vec<Segment>);
+
+impl JsonPath {
+    /// Parse a path of `.field`, `['field']`, `[index]` and `*` steps from the root `$`.
+    fn parse(path: &str) -> Result<Self, Error> {
+        let invalid = || Error::from(format!("invalid JSON path {path}"));
+        let mut rest = path.trim().strip_prefix('$').ok_or_else(invalid)?;
+        let mut segments = Vec::new();
+        while !rest.is_empty() {
+            let (segment, next) = if let Some(field) = rest.strip_prefix('.') {
+                let end = field.find(['.', '[']).unwrap_or(field.len());
+                let segment = match &field[..end] {
+                    "" => return Err(invalid()),
+                    "*" => Segment::Any,
+                    name => Segment::Field(name.to_string()),
+                };
+                (segment, &field[end..])
+            } else if let Some(index) = rest.strip_prefix('[') {
+                let (index, next) = index.split_once(']').ok_or_else(invalid)?;
+                let segment = match index.trim() {
+                    "*" => Segment::Any,
+                    quoted if quoted.len() >= 2 && (quoted.starts_with('\'') || quoted.starts_with('"')) => {
+                        Segment::Field(quoted[1..quoted.len() - 1].to_string())
+                    }
+                    index => Segment::Index(index.parse().map_err(|_| invalid())?),
+                };
+                (segment, next)
+            } else {
+                return Err(invalid());
+            };
+            segments.push(segment);
+            rest = next;
+        }
+        match segments.is_empty() {
+            true => Err(invalid()),
+            false => Ok(JsonPath(segments)),
+        }
+    }
+
+    /// Remove, or mask when `mask` is set, every value the path leads to.
+    fn edit(&self, document: &mut Value, mask: bool) {
+        edit(document, &self.0, mask);
+    }
+}
+
+fn edit(value: &mut Value, segments: &[Segment], mask: bool) {
+    let Some((segment, rest)) = segments.split_first() else {
+        return;
+    };
+    if rest.is_empty() {
+        match (value, segment) {
+            (Value::Object(object), Segment::Field(name)) if mask => {
+                if let Some(value) = object.get_mut(name) {
+                    *value = Value::from(REDACTED);
+                }
+            }
+            (Value::Object(object), Segment::Field(name)) => {
+                object.remove(name);
+            }
+            (Value::Object(object), Segment::Any) if mask => {
+                object.values_mut().for_each(|value| *value = Value::from(REDACTED));
+            }
+            (Value::Object(object), Segment::Any) => object.clear(),
+            (Value::Array(items), Segment::Index(index)) if mask => {
+                if let Some(item) = items.get_mut(*index) {
+                    *item = Value::from(REDACTED);
+                }
+            }
+            (Value::Array(items), Segment::Index(index)) if *index < items.len() => {
+                items.remove(*index);
+            }
+            (Value::Array(items), Segment::Any) if mask => {
+                items.iter_mut().for_each(|item| *item = Value::from(REDACTED));
+            }
+            (Value::Array(items), Segment::Any) => items.clear(),
+            _ => {}
+        }
+        return;
+    }
+    match (value, segment) {
+        (Value::Object(object), Segment::Field(name)) => {
+            if let Some(value) = object.get_mut(name) {
+                edit(value, rest, mask);
+            }
+        }
+        (Value::Object(object), Segment::Any) => object.values_mut().for_each(|value| edit(value, rest, mask)),
+        (Value::Array(items), Segment::Index(index)) => {
+            if let Some(item) = items.get_mut(*index) {
+                edit(item, rest, mask);
+            }
+        }
+        (Value::Array(items), Segment::Any) => items.iter_mut().for_each(|item| edit(item, rest, mask)),
+        _ => {}
+    }
+}
+
+#[derive(Debug)]
+struct Rule {
+    path: String,
+    content_type: String,
+    request: bool,
+    response: bool,
+    transforms: Vec<Transform>,
+}
+
+/// Chains of transformations of JSON request and response bodies, chosen by
+/// path and content type.
+#[derive(Debug)]
+pub(crate) struct BodyTransforms {
+    rules: Vec<Rule>,
+}
+
+impl BodyTransforms {
+    /// Load the rules from inline JSON, or from a JSON or YAML file.
+    pub(crate) fn load(setting: &str) -> Result<Self, Error> {
+        let document = match setting.trim_start().starts_with('[') {
+            true => serde_json::from_str(setting)?,
+            false => {
+                let text = std::fs::read_to_string(setting)?;
+                match serde_json::from_str(&text) {
+                    Ok(document) => document,
+                    Err(_) => serde_yaml::from_str(&text)?,
+                }
+            }
+        };
+        Self::new(&document)
+    }
+
+    /// Parse a list of rules such as
+    /// `[{"path":"/api/*","apply_to":"both","transforms":[{"redact":["password"]},{"case":"camel"}]}]`.
+    pub(crate) fn new(document: &Value) -> Result<Self, Error> {
+        let rules = document
+            .as_array()
+            .ok_or_else(|| Error::from("body transforms must be a list of rules"))?;
+        let rules = rules.iter().map(Rule::new).collect::<Result<_, Error>>()?;
+        Ok(BodyTransforms { rules })
+    }
+
+    /// Whether a body with these headers sent to or from `path` is transformed.
+    pub(crate) fn matches(&self, direction: Direction, path: &str, headers: &HeaderMap) -> bool {
+        self.rules.iter().any(|rule| rule.matches(direction, path, headers))
+    }
+
+    /// Transform a body. Returns `None` when no rule applies or the body is not JSON.
+    pub(crate) fn apply(&self, direction: Direction, path: &str, headers: &HeaderMap, body: &[u8]) -> Option<Vec<u8>> {
+        let mut transforms = self
+            .rules
+            .iter()
+            .filter(|rule| rule.matches(direction, path, headers))
+            .flat_map(|rule| &rule.transforms)
+            .peekable();
+        transforms.peek()?;
+        let mut document: Value = serde_json::from_slice(body).ok()?;
+        for transform in transforms {
+            document = transform.apply(document);
+        }
+        serde_json::to_vec(&document).ok()
+    }
+}
+
+impl Rule {
+    fn new(rule: &Value) -> Result<Self, Error> {
+        let text = |key: &str, default: &str| rule.get(key).and_then(Value::as_str).unwrap_or(default).to_string();
+        let (request, response) = match text("apply_to", "response").as_str() {
+            "request" => (true, false),
+            "response" => (false, true),
+            "both" => (true, true),
+            other => {
+                return Err(Error::from(format!(
+                    "invalid apply_to {other}, use request, response or both"
+                )))
+            }
+        };
+        let transforms = rule
+            .get("transforms")
+            .and_then(Value::as_array)
+            .ok_or_else(|| Error::from("body transform rule needs a list of transforms"))?
+            .iter()
+            .map(Transform::new)
+            .collect::<Result<_, Error>>()?;
+        Ok(Rule {
+            path: text("path", "*"),
+            content_type: text("content_type", "application/json").to_lowercase(),
+            request,
+            response,
+            transforms,
+        })
+    }
+
+    fn matches(&self, direction: Direction, path: &str, headers: &HeaderMap) -> bool {
+        let applies = match direction {
+            Direction::Request => self.request,
+            Direction::Response => self.response,
+        };
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_lowercase();
+        applies
+            && !headers.contains_key(CONTENT_ENCODING)
+            && content_type.starts_with(&self.content_type)
+            && glob_match(&self.path, path)
+    }
+}
+
+impl Transform {
+    fn new(transform: &Value) -> Result<Self, Error> {
+        let Some((name, argument)) = transform.as_object().and_then(|transform| transform.iter().next()) else {
+            return Err(Error::from(
+                "a body transform must be an object such as {\"case\":\"camel\"}",
+            ));
+        };
+        match (name.as_str(), argument) {
+            ("redact", Value::Array(fields)) => Ok(Transform::Redact(
+                fields.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+            )),
+            ("case", Value::String(case)) if case == "snake" => Ok(Transform::Case(KeyCase::Snake)),
+            ("case", Value::String(case)) if case == "camel" => Ok(Transform::Case(KeyCase::Camel)),
+            ("unwrap", Value::String(field)) => Ok(Transform::Unwrap(field.clone())),
+            ("remove", Value::Array(paths)) => Ok(Transform::Remove(json_paths(paths)?)),
+            ("mask", Value::Array(paths)) => Ok(Transform::Mask(json_paths(paths)?)),
+            _ => Err(Error::from(format!("invalid body transform {transform}"))),
+        }
+    }
+
+    fn apply(&self, document: Value) -> Value {
+        match self {
+            Transform::Redact(fields) => redact(document, fields),
+            Transform::Case(case) => convert_keys(document, *case),
+            Transform::Unwrap(field) => {
+                let inner = match field.starts_with('/') {
+                    true => document.pointer(field),
+                    false => document.get(field),
+                };
+                inner.cloned().unwrap_or(document)
+            }
+            Transform::Remove(paths) | Transform::Mask(paths) => {
+                let mask = matches!(self, Transform::Mask(_));
+                let mut document = document;
+                paths.iter().for_each(|path| path.edit(&mut document, mask));
+                document
+            }
+        }
+    }
+}
+
+fn json_paths(paths: &[Value]) -> Result<Vec<JsonPath>, Error> {
+    paths
+        .iter()
+        .map(|path| JsonPath::parse(path.as_str().unwrap_or_default()))
+        .collect()
+}
+
+fn redact(document: Value, fields: &[String]) -> Value {
+    match document {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| match fields.contains(&key) {
+                    true => (key, Value::from(REDACTED)),
+                    false => (key, redact(value, fields)),
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(|item| redact(item, fields)).collect()),
+        value => value,
+    }
+}
+
+fn convert_keys(document: Value, case: KeyCase) -> Value {
+    match document {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| (convert_key(&key, case), convert_keys(value, case)))
+                .collect::<Map<_, _>>(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(|item| convert_keys(item, case)).collect()),
+        value => value,
+    }
+}
+
+fn convert_key(key: &str, case: KeyCase) -> String {
+    let mut converted = String::with_capacity(key.len() + 4);
+    match case {
+        KeyCase::Snake => {
+            for (i, c) in key.chars().enumerate() {
+                if c.is_uppercase() {
+                    if i > 0 {
+                        converted.push('_');
+                    }
+                    converted.extend(c.to_lowercase());
+                } else {
+                    converted.push(c);
+                }
+            }
+        }
+        KeyCase::Camel => {
+            let mut upper = false;
+            for c in key.chars() {
+                match c {
+                    '_' if !converted.is_empty() => upper = true,
+                    c if upper => {
+                        converted.extend(c.to_uppercase());
+                        upper = false;
+                    }
+                    c => converted.push(c),
+                }
+            }
+        }
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+    use serde_json::json;
+
+    fn json_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/json; charset=utf-8"),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_convert_key() {
+        assert_eq!("user_id", convert_key("userId", KeyCase::Snake));
+        assert_eq!("created_at_utc", convert_key("createdAtUtc", KeyCase::Snake));
+        assert_eq!("userId", convert_key("user_id", KeyCase::Camel));
+        assert_eq!("_private", convert_key("_private", KeyCase::Camel));
+        assert_eq!("id", convert_key("id", KeyCase::Camel));
+    }
+
+    #[test]
+    fn test_apply() {
+        let transforms = BodyTransforms::load(
+            r#"[
+                {"path":"/users/*","apply_to":"both","transforms":[{"redact":["password"]},{"case":"camel"}]},
+                {"path":"/wrapped","transforms":[{"unwrap":"data"}]}
+            ]"#,
+        )
+        .unwrap();
+        let headers = json_headers();
+
+        let body = br#"{"user_name":"ana","password":"secret","roles":[{"role_name":"admin"}]}"#;
+        let transformed = transforms
+            .apply(Direction::Request, "/users/1", &headers, body)
+            .unwrap();
+        assert_eq!(
+            json!({"userName":"ana","password":"[REDACTED]","roles":[{"roleName":"admin"}]}),
+            serde_json::from_slice::<Value>(&transformed).unwrap()
+        );
+
+        // unwrap only applies to responses
+        let body = br#"{"data":{"id":1}}"#;
+        assert_eq!(None, transforms.apply(Direction::Request, "/wrapped", &headers, body));
+        let transformed = transforms
+            .apply(Direction::Response, "/wrapped", &headers, body)
+            .unwrap();
+        assert_eq!(json!({"id":1}), serde_json::from_slice::<Value>(&transformed).unwrap());
+
+        // other paths, content types and bodies that are not JSON are left alone
+        assert!(!transforms.matches(Direction::Response, "/orders", &headers));
+        let mut text = HeaderMap::new();
+        text.insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+        assert!(!transforms.matches(Direction::Response, "/users/1", &text));
+        assert_eq!(
+            None,
+            transforms.apply(Direction::Response, "/users/1", &headers, b"not json")
+        );
+    }
+
+    #[test]
+    fn test_json_path() {
+        assert_eq!(
+            JsonPath(vec![
+                Segment::Field("users".to_string()),
+                Segment::Any,
+                Segment::Field("ssn".to_string())
+            ]),
+            JsonPath::parse("$.users[*].ssn").unwrap()
+        );
+        assert_eq!(
+            JsonPath(vec![Segment::Field("first name".to_string()), Segment::Index(0)]),
+            JsonPath::parse("$['first name'][0]").unwrap()
+        );
+        assert!(JsonPath::parse("users.ssn").is_err());
+        assert!(JsonPath::parse("$").is_err());
+        assert!(JsonPath::parse("$..ssn").is_err());
+        assert!(JsonPath::parse("$.users[x]").is_err());
+    }
+
+    #[test]
+    fn test_remove_and_mask() {
+        let transforms = BodyTransforms::load(
+            r#"[{"path":"/users/*","transforms":[{"remove":["$.user.ssn","$.cards[*].cvv"]},{"mask":["$.cards[*].number","$.user.missing"]}]}]"#,
+        )
+        .unwrap();
+        let body = br#"{"user":{"name":"ana","ssn":"123-45-6789"},"cards":[{"number":"4111","cvv":"123"}]}"#;
+        let transformed = transforms
+            .apply(Direction::Response, "/users/1", &json_headers(), body)
+            .unwrap();
+        assert_eq!(
+            json!({"user":{"name":"ana"},"cards":[{"number":"[REDACTED]"}]}),
+            serde_json::from_slice::<Value>(&transformed).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!(BodyTransforms::load(r#"[{"transforms":[{"case":"kebab"}]}]"#).is_err());
+        assert!(BodyTransforms::load(r#"[{"apply_to":"sometimes","transforms":[]}]"#).is_err());
+        assert!(BodyTransforms::load(r#"[{"path":"/users/*"}]"#).is_err());
+        assert!(BodyTransforms::load(r#"{"path":"/users/*"}"#).is_err());
+        assert!(BodyTransforms::load(r#"[{"transforms":[{"remove":["user.ssn"]}]}]"#).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Connections to the web application over TCP or a Unix domain socket.
+//!
+//! Apps that bind to a Unix socket skip the TCP stack, and need no free
+//! port. The socket only stands in for the app's own host and port, and for
+//! its readiness check, so traffic splits, host routes and sidecars on other
+//! ports still connect over TCP.
+
+use std::error::Error as StdError;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use http::Uri;
+use hyper::rt::{Read, ReadBufCursor, Write};
+use hyper_util::client::legacy::connect::{Connected, Connection, HttpConnector};
+use hyper_util::rt::TokioIo;
+use tokio::net::{TcpStream, UnixStream};
+use tower::Service;
+use url::Url;
+
+use crate::timing;
+
+/// Connects to the app socket for requests to the app, and over TCP otherwise.
+#[derive(Clone)]
+pub struct UpstreamConnector {
+    http: HttpConnector,
+    unix_socket: Option<Arc<UnixSocket>>,
+}
+
+struct UnixSocket {
+    path: PathBuf,
+    /// The hosts and ports of the URLs that stand for the socket.
+    authorities: Vec<(String, u16)>,
+}
+
+impl UpstreamConnector {
+    /// Connect over TCP only.
+    pub(crate) fn tcp(http: HttpConnector) -> Self {
+        UpstreamConnector {
+            http,
+            unix_socket: None,
+        }
+    }
+
+    /// Connect to the socket at `path` for requests to any of `urls`.
+    pub(crate) fn unix(http: HttpConnector, path: &str, urls: &[&Url]) -> Self {
+        let authorities = urls
+            .iter()
+            .filter_map(|url| Some((url.host_str()?.to_string(), url.port_or_known_default()?)))
+            .collect();
+        UpstreamConnector {
+            http,
+            unix_socket: Some(Arc::new(UnixSocket {
+                path: PathBuf::from(path),
+                authorities,
+            })),
+        }
+    }
+}
+
+impl UnixSocket {
+    fn serves(&self, uri: &Uri) -> bool {
+        let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+            Some("https") => 443,
+            _ => 80,
+        });
+        uri.host()
+            .is_some_and(|host| self.authorities.iter().any(|(h, p)| h == host && *p == port))
+    }
+}
+
+impl Service<Uri> for UpstreamConnector {
+    type Response = UpstreamStream;
+    type Error = Box<dyn StdError + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        match self.unix_socket.clone().filter(|socket| socket.serves(&uri)) {
+            Some(socket) => Box::pin(async move {
+                let started = Instant::now();
+                let stream = UnixStream::connect(&socket.path)
+                    .await
+                    .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", socket.path.display(), err)))?;
+                timing::connected(started.elapsed());
+                Ok(UpstreamStream::Unix(TokioIo::new(stream)))
+            }),
+            None => {
+                let started = Instant::now();
+                let connecting = self.http.call(uri);
+                Box::pin(async move {
+                    let stream = connecting.await?;
+                    timing::connected(started.elapsed());
+                    Ok(UpstreamStream::Tcp(stream))
+                })
+            }
+        }
+    }
+}
+
+/// A connection to the app over TCP or a Unix socket.
+pub enum UpstreamStream {
+    Tcp(TokioIo<TcpStream>),
+    Unix(TokioIo<UnixStream>),
+}
+
+impl Connection for UpstreamStream {
+    fn connected(&self) -> Connected {
+        match self {
+            UpstreamStream::Tcp(stream) => stream.connected(),
+            UpstreamStream::Unix(_) => Connected::new(),
+        }
+    }
+}
+
+impl Read for UpstreamStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: ReadBufCursor<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            UpstreamStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl Write for UpstreamStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            UpstreamStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            UpstreamStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            UpstreamStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            UpstreamStream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            UpstreamStream::Tcp(stream) => stream.is_write_vectored(),
+            UpstreamStream::Unix(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            UpstreamStream::Tcp(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            UpstreamStream::Unix(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unix_socket_serves_app_urls() {
+        let app: Url = "http://127.0.0.1:8080/".parse().unwrap();
+        let readiness: Url = "http://127.0.0.1:8081/health".parse().unwrap();
+        let connector = UpstreamConnector::unix(HttpConnector::new(), "/tmp/app.sock", &[&app, &readiness]);
+        let socket = connector.unix_socket.unwrap();
+        assert!(socket.serves(&"http://127.0.0.1:8080/hello".parse().unwrap()));
+        assert!(socket.serves(&"http://127.0.0.1:8081/health".parse().unwrap()));
+        assert!(!socket.serves(&"http://127.0.0.1:9090/hello".parse().unwrap()));
+        assert!(!socket.serves(&"http://localhost:8080/hello".parse().unwrap()));
+    }
+}
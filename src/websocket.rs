@@ -0,0 +1,565 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Bridges API Gateway WebSocket APIs to the app.
+//!
+//! API Gateway keeps the WebSocket connection to the client and invokes the
+//! function with an event for each connection, message and disconnection.
+//! The adapter passes these to the app in one of two ways:
+//!
+//! - over HTTP, as a `POST` request for each event, to a path ending with
+//!   the route, such as `/websocket/connect`, with the connection id, route
+//!   key and event type in `x-websocket-*` headers. The body of the app's
+//!   response to a message is sent back to the client.
+//! - over a WebSocket connection to the app, opened at `$connect`, that the
+//!   messages are written to. Messages the app writes are sent to the client.
+//!
+//! Either way, messages go back to the client through the `@connections`
+//! API of the WebSocket API, so no route responses need to be configured.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue, Method, StatusCode};
+use http_body_util::BodyExt;
+use lambda_http::request::RequestContext;
+use lambda_http::{Body, Error, Request, RequestExt, Response};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use url::Url;
+
+use crate::aws::AwsClient;
+use crate::{full_body, ResponseBody, WebSocketMode};
+
+/// The largest message API Gateway accepts, which also bounds messages read
+/// from the app.
+const MAX_MESSAGE: usize = 128 * 1024;
+
+/// The longest response to the WebSocket handshake read from the app.
+const MAX_HANDSHAKE: usize = 16 * 1024;
+
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.');
+
+/// Headers of the client's handshake that only apply to its connection with
+/// API Gateway.
+const HANDSHAKE_HEADERS: [&str; 7] = [
+    "host",
+    "upgrade",
+    "connection",
+    "content-length",
+    "sec-websocket-key",
+    "sec-websocket-version",
+    "sec-websocket-extensions",
+];
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum EventType {
+    Connect,
+    Disconnect,
+    Message,
+}
+
+impl EventType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventType::Connect => "CONNECT",
+            EventType::Disconnect => "DISCONNECT",
+            EventType::Message => "MESSAGE",
+        }
+    }
+}
+
+/// An event of an API Gateway WebSocket API.
+#[derive(Debug)]
+pub(crate) struct WebSocketEvent {
+    connection_id: String,
+    route_key: String,
+    event_type: EventType,
+    api_id: Option<String>,
+    stage: Option<String>,
+}
+
+impl WebSocketEvent {
+    /// The WebSocket event of a request, or `None` for other requests.
+    pub(crate) fn of(context: &RequestContext) -> Option<Self> {
+        let RequestContext::WebSocket(context) = context else {
+            return None;
+        };
+        let event_type = match context.event_type.as_deref() {
+            Some("CONNECT") => EventType::Connect,
+            Some("DISCONNECT") => EventType::Disconnect,
+            _ => EventType::Message,
+        };
+        Some(WebSocketEvent {
+            connection_id: context.connection_id.clone()?,
+            route_key: context.route_key.clone().unwrap_or_else(|| "$default".to_string()),
+            event_type,
+            api_id: context.apiid.clone(),
+            stage: context.stage.clone(),
+        })
+    }
+
+    /// The route key as a path segment, such as `connect` for `$connect`.
+    fn route(&self) -> String {
+        utf8_percent_encode(self.route_key.trim_start_matches('$'), PATH_SEGMENT).to_string()
+    }
+}
+
+/// A message to write to the app's WebSocket connection.
+#[derive(Debug)]
+enum Message {
+    Text(Bytes),
+    Binary(Bytes),
+    Pong(Bytes),
+}
+
+pub(crate) struct WebSocketBridge {
+    mode: WebSocketMode,
+    path: String,
+    /// The app's address, for WebSocket connections.
+    host: String,
+    port: u16,
+    /// The open WebSocket connections to the app, by connection id.
+    connections: Mutex<HashMap<String, mpsc::UnboundedSender<Message>>>,
+    aws: OnceLock<Option<AwsClient>>,
+}
+
+impl WebSocketBridge {
+    pub(crate) fn new(mode: WebSocketMode, path: &str, app: &Url) -> Self {
+        WebSocketBridge {
+            mode,
+            path: format!("/{}", path.trim_matches('/')),
+            host: app.host_str().unwrap_or("127.0.0.1").to_string(),
+            port: app.port_or_known_default().unwrap_or(8080),
+            connections: Mutex::new(HashMap::new()),
+            aws: OnceLock::new(),
+        }
+    }
+
+    pub(crate) fn mode(&self) -> WebSocketMode {
+        self.mode
+    }
+
+    /// The HTTP request for an event. Connection events keep the headers and
+    /// query string of the client's handshake, for authorization.
+    pub(crate) fn http_request(&self, event: Request, websocket: &WebSocketEvent) -> Request {
+        let path = format!("{}/{}", self.path.trim_end_matches('/'), websocket.route());
+        let mut event = event.with_raw_http_path(path);
+        *event.method_mut() = Method::POST;
+        let headers = event.headers_mut();
+        for name in HANDSHAKE_HEADERS.into_iter().filter(|name| *name != "host") {
+            headers.remove(name);
+        }
+        if let Ok(connection_id) = HeaderValue::from_str(&websocket.connection_id) {
+            headers.insert("x-websocket-connection-id", connection_id);
+        }
+        if let Ok(route_key) = HeaderValue::from_str(&websocket.route_key) {
+            headers.insert("x-websocket-route-key", route_key);
+        }
+        headers.insert(
+            "x-websocket-event-type",
+            HeaderValue::from_static(websocket.event_type.as_str()),
+        );
+        event
+    }
+
+    /// Send the app's response to a message back to the client. Responses to
+    /// connection events are passed on, so an error status rejects the
+    /// connection.
+    pub(crate) async fn reply(
+        &self,
+        websocket: &WebSocketEvent,
+        response: Response<ResponseBody>,
+    ) -> Result<Response<ResponseBody>, Error> {
+        if websocket.event_type != EventType::Message || !response.status().is_success() {
+            return Ok(response);
+        }
+        let body = response.into_body().collect().await?.to_bytes();
+        if !body.is_empty() {
+            self.post(websocket, body).await?;
+        }
+        empty_response(StatusCode::OK)
+    }
+
+    /// Pass an event on to the app's WebSocket connection.
+    pub(crate) async fn relay(
+        self: &Arc<Self>,
+        websocket: &WebSocketEvent,
+        event: Request,
+    ) -> Result<Response<ResponseBody>, Error> {
+        match websocket.event_type {
+            EventType::Connect => match self.connect(websocket, event.headers()).await? {
+                StatusCode::SWITCHING_PROTOCOLS => empty_response(StatusCode::OK),
+                status => empty_response(status),
+            },
+            EventType::Message => {
+                let message = match event.into_body() {
+                    Body::Empty => return empty_response(StatusCode::OK),
+                    Body::Text(text) => Message::Text(Bytes::from(text)),
+                    Body::Binary(binary) => Message::Binary(Bytes::from(binary)),
+                };
+                let sender = self.connections.lock().unwrap().get(&websocket.connection_id).cloned();
+                let sender = match sender {
+                    Some(sender) => sender,
+                    // the connection was opened in another execution environment
+                    None => match self.connect(websocket, &HeaderMap::new()).await? {
+                        StatusCode::SWITCHING_PROTOCOLS => self
+                            .connections
+                            .lock()
+                            .unwrap()
+                            .get(&websocket.connection_id)
+                            .cloned()
+                            .ok_or_else(|| Error::from("the app closed the WebSocket connection"))?,
+                        status => return Err(Error::from(format!("the app refused the WebSocket with {status}"))),
+                    },
+                };
+                sender
+                    .send(message)
+                    .map_err(|_| Error::from("the app closed the WebSocket connection"))?;
+                empty_response(StatusCode::OK)
+            }
+            EventType::Disconnect => {
+                // dropping the sender closes the connection to the app
+                self.connections.lock().unwrap().remove(&websocket.connection_id);
+                empty_response(StatusCode::OK)
+            }
+        }
+    }
+
+    /// Open a WebSocket connection to the app for a client connection, with
+    /// the headers of the client's handshake. Returns `101 Switching Protocols`
+    /// when the app accepted the connection, and the app's status otherwise.
+    async fn connect(self: &Arc<Self>, websocket: &WebSocketEvent, headers: &HeaderMap) -> Result<StatusCode, Error> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        let key = BASE64.encode(fastrand::u128(..).to_be_bytes());
+        let mut handshake = format!(
+            "GET {} HTTP/1.1\r\nhost: {}:{}\r\nupgrade: websocket\r\nconnection: Upgrade\r\n\
+             sec-websocket-key: {}\r\nsec-websocket-version: 13\r\nx-websocket-connection-id: {}\r\n",
+            self.path, self.host, self.port, key, websocket.connection_id
+        );
+        for (name, value) in headers {
+            if let (false, Ok(value)) = (HANDSHAKE_HEADERS.contains(&name.as_str()), value.to_str()) {
+                handshake.push_str(&format!("{name}: {value}\r\n"));
+            }
+        }
+        handshake.push_str("\r\n");
+        stream.write_all(handshake.as_bytes()).await?;
+
+        let (status, rest) = read_handshake(&mut stream).await?;
+        if status != StatusCode::SWITCHING_PROTOCOLS {
+            tracing::debug!(connection_id = %websocket.connection_id, %status, "the app refused the WebSocket");
+            return Ok(status);
+        }
+
+        let (reader, writer) = stream.into_split();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let pong = sender.downgrade();
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(websocket.connection_id.clone(), sender);
+        tokio::spawn(write_messages(writer, receiver));
+
+        let bridge = self.clone();
+        let websocket = WebSocketEvent {
+            connection_id: websocket.connection_id.clone(),
+            route_key: websocket.route_key.clone(),
+            event_type: EventType::Message,
+            api_id: websocket.api_id.clone(),
+            stage: websocket.stage.clone(),
+        };
+        tokio::spawn(async move {
+            let mut messages = MessageReader::new(io::Cursor::new(rest).chain(reader));
+            loop {
+                match messages.next().await {
+                    Ok((OPCODE_PING, payload)) => {
+                        if let Some(pong) = pong.upgrade() {
+                            let _ = pong.send(Message::Pong(payload));
+                        }
+                    }
+                    Ok((OPCODE_TEXT | OPCODE_BINARY, payload)) => {
+                        if let Err(err) = bridge.post(&websocket, payload).await {
+                            tracing::warn!(connection_id = %websocket.connection_id, "failed to send a message to the client: {}", err);
+                        }
+                    }
+                    Ok((OPCODE_PONG, _)) => {}
+                    Ok(_) => break,
+                    Err(err) => {
+                        tracing::debug!(connection_id = %websocket.connection_id, "WebSocket connection to the app ended: {}", err);
+                        break;
+                    }
+                }
+            }
+            bridge.connections.lock().unwrap().remove(&websocket.connection_id);
+        });
+        Ok(status)
+    }
+
+    /// Send a message to the client through the `@connections` API.
+    async fn post(&self, websocket: &WebSocketEvent, message: Bytes) -> Result<(), Error> {
+        let aws = self
+            .aws
+            .get_or_init(|| match AwsClient::new() {
+                Ok(aws) => Some(aws),
+                Err(err) => {
+                    tracing::warn!("cannot send WebSocket messages to clients: {}", err);
+                    None
+                }
+            })
+            .as_ref()
+            .ok_or_else(|| Error::from("no AWS client to send WebSocket messages"))?;
+        let stage = websocket.stage.as_deref().unwrap_or_default();
+        let endpoint = match AwsClient::endpoint_override("apigatewaymanagementapi") {
+            Some(endpoint) => format!("{endpoint}/{stage}"),
+            None => {
+                let api_id = websocket
+                    .api_id
+                    .as_deref()
+                    .ok_or_else(|| Error::from("the event has no API id"))?;
+                format!("https://{api_id}.execute-api.{}.amazonaws.com/{stage}", aws.region())
+            }
+        };
+        let request = http::Request::builder()
+            .method(Method::POST)
+            .uri(format!(
+                "{endpoint}/@connections/{}",
+                utf8_percent_encode(&websocket.connection_id, PATH_SEGMENT)
+            ))
+            .body(message)?;
+        aws.send("execute-api", request).await?;
+        Ok(())
+    }
+}
+
+fn empty_response(status: StatusCode) -> Result<Response<ResponseBody>, Error> {
+    Ok(Response::builder().status(status).body(full_body(Bytes::new()))?)
+}
+
+/// Read the app's response to the WebSocket handshake. Returns its status and
+/// the bytes read past its headers.
+async fn read_handshake<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(StatusCode, Vec<u8>), Error> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 1024];
+    let end = loop {
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        if buffer.len() > MAX_HANDSHAKE {
+            return Err(Error::from("the WebSocket handshake response is too long"));
+        }
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(Error::from(
+                "the app closed the connection during the WebSocket handshake",
+            ));
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+    let status = std::str::from_utf8(&buffer[..end])
+        .ok()
+        .and_then(|head| head.split_whitespace().nth(1))
+        .and_then(|status| status.parse::<StatusCode>().ok())
+        .ok_or_else(|| Error::from("invalid WebSocket handshake response"))?;
+    Ok((status, buffer.split_off(end)))
+}
+
+/// Reads the messages of a WebSocket connection, joining fragmented ones.
+struct MessageReader<R> {
+    reader: R,
+    /// The opcode and data of a fragmented message so far.
+    partial: Option<(u8, Vec<u8>)>,
+}
+
+impl<R: AsyncRead + Unpin> MessageReader<R> {
+    fn new(reader: R) -> Self {
+        MessageReader { reader, partial: None }
+    }
+
+    /// Read a whole message, or a control frame, which may come between the
+    /// fragments of a message.
+    async fn next(&mut self) -> io::Result<(u8, Bytes)> {
+        loop {
+            let (fin, opcode, payload) = read_frame(&mut self.reader).await?;
+            match (opcode, &mut self.partial) {
+                (OPCODE_CONTINUATION, Some((_, data))) => data.extend_from_slice(&payload),
+                (OPCODE_CONTINUATION, None) => return Err(invalid("continuation without a message")),
+                (OPCODE_TEXT | OPCODE_BINARY, None) => self.partial = Some((opcode, payload)),
+                (OPCODE_TEXT | OPCODE_BINARY, Some(_)) => return Err(invalid("message inside a fragmented message")),
+                (opcode, _) => return Ok((opcode, Bytes::from(payload))),
+            }
+            if self.partial.as_ref().is_some_and(|(_, data)| data.len() > MAX_MESSAGE) {
+                return Err(invalid("message too large"));
+            }
+            if fin {
+                let (opcode, data) = self.partial.take().unwrap_or_default();
+                return Ok((opcode, Bytes::from(data)));
+            }
+        }
+    }
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<(bool, u8, Vec<u8>)> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head).await?;
+    let fin = head[0] & 0x80 != 0;
+    let opcode = head[0] & 0x0f;
+    let len = match head[1] & 0x7f {
+        126 => reader.read_u16().await? as u64,
+        127 => reader.read_u64().await?,
+        len => len as u64,
+    };
+    if len > MAX_MESSAGE as u64 {
+        return Err(invalid("message too large"));
+    }
+    let mask = match head[1] & 0x80 != 0 {
+        true => Some(reader.read_u32().await?.to_be_bytes()),
+        false => None,
+    };
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).await?;
+    if let Some(mask) = mask {
+        payload
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, byte)| *byte ^= mask[i % 4]);
+    }
+    Ok((fin, opcode, payload))
+}
+
+/// A masked frame, as clients send them.
+fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len if len < 126 => frame.push(0x80 | len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    let mask = fastrand::u32(..).to_be_bytes();
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+    frame
+}
+
+/// Write messages to the app until the client disconnects, then close.
+async fn write_messages<W: AsyncWrite + Unpin>(mut writer: W, mut messages: mpsc::UnboundedReceiver<Message>) {
+    while let Some(message) = messages.recv().await {
+        let frame = match message {
+            Message::Text(text) => encode_frame(OPCODE_TEXT, &text),
+            Message::Binary(binary) => encode_frame(OPCODE_BINARY, &binary),
+            Message::Pong(payload) => encode_frame(OPCODE_PONG, &payload),
+        };
+        if writer.write_all(&frame).await.is_err() {
+            return;
+        }
+    }
+    // 1000, normal closure
+    let _ = writer
+        .write_all(&encode_frame(OPCODE_CLOSE, &1000u16.to_be_bytes()))
+        .await;
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lambda_http::aws_lambda_events::apigw::ApiGatewayWebsocketProxyRequestContext;
+
+    fn context(event_type: &str, route_key: &str) -> RequestContext {
+        RequestContext::WebSocket(ApiGatewayWebsocketProxyRequestContext {
+            connection_id: Some("L0SM9cOFvHcCIhw=".to_string()),
+            route_key: Some(route_key.to_string()),
+            event_type: Some(event_type.to_string()),
+            apiid: Some("abc123".to_string()),
+            stage: Some("prod".to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_websocket_event() {
+        let connect = WebSocketEvent::of(&context("CONNECT", "$connect")).unwrap();
+        assert_eq!(EventType::Connect, connect.event_type);
+        assert_eq!("connect", connect.route());
+
+        let message = WebSocketEvent::of(&context("MESSAGE", "sendmessage")).unwrap();
+        assert_eq!(EventType::Message, message.event_type);
+        assert_eq!("sendmessage", message.route());
+
+        assert!(WebSocketEvent::of(&RequestContext::PassThrough).is_none());
+    }
+
+    #[test]
+    fn test_http_request() {
+        let app = Url::parse("http://127.0.0.1:8080").unwrap();
+        let bridge = WebSocketBridge::new(WebSocketMode::Http, "/websocket/", &app);
+        let websocket = WebSocketEvent::of(&context("CONNECT", "$connect")).unwrap();
+        let event = http::Request::builder()
+            .uri("/?token=secret")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .header("authorization", "Bearer token")
+            .body(Body::Empty)
+            .unwrap();
+
+        let request = bridge.http_request(event, &websocket);
+        assert_eq!(Method::POST, request.method());
+        assert_eq!("/websocket/connect", request.raw_http_path());
+        assert_eq!(Some("token=secret"), request.uri().query());
+        assert_eq!("Bearer token", request.headers()["authorization"]);
+        assert_eq!("L0SM9cOFvHcCIhw=", request.headers()["x-websocket-connection-id"]);
+        assert_eq!("$connect", request.headers()["x-websocket-route-key"]);
+        assert_eq!("CONNECT", request.headers()["x-websocket-event-type"]);
+        assert!(!request.headers().contains_key("sec-websocket-key"));
+    }
+
+    #[tokio::test]
+    async fn test_frames() {
+        let mut frames = encode_frame(OPCODE_TEXT, b"hello");
+        frames.extend(encode_frame(OPCODE_BINARY, &vec![7u8; 70_000]));
+        // a fragmented message with a ping in between
+        frames.extend([0x01, 0x03, b'a', b'b', b'c']);
+        frames.extend([0x89, 0x00]);
+        frames.extend([0x80, 0x02, b'd', b'e']);
+
+        let mut messages = MessageReader::new(frames.as_slice());
+        assert_eq!((OPCODE_TEXT, Bytes::from("hello")), messages.next().await.unwrap());
+        let (opcode, binary) = messages.next().await.unwrap();
+        assert_eq!((OPCODE_BINARY, 70_000), (opcode, binary.len()));
+        assert_eq!((OPCODE_PING, Bytes::new()), messages.next().await.unwrap());
+        assert_eq!((OPCODE_TEXT, Bytes::from("abcde")), messages.next().await.unwrap());
+        assert!(messages.next().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_handshake() {
+        let mut reader: &[u8] = b"HTTP/1.1 101 Switching Protocols\r\nupgrade: websocket\r\n\r\n\x81\x00";
+        let (status, rest) = read_handshake(&mut reader).await.unwrap();
+        assert_eq!(StatusCode::SWITCHING_PROTOCOLS, status);
+        assert_eq!(vec![0x81, 0x00], rest);
+
+        let mut reader: &[u8] = b"HTTP/1.1 403 Forbidden\r\ncontent-length: 0\r\n\r\n";
+        assert_eq!(StatusCode::FORBIDDEN, read_handshake(&mut reader).await.unwrap().0);
+    }
+}
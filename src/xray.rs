@@ -0,0 +1,197 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! X-Ray subsegments for the calls to the app.
+//!
+//! Lambda traces each invoke in a segment of the function. A subsegment of
+//! it for the call to the app, sent to the X-Ray daemon of the execution
+//! environment, shows the time the app took apart from the time of the whole
+//! invoke. The app gets the subsegment as the parent in its
+//! `X-Amzn-Trace-Id`, so subsegments the app sends itself nest below it.
+
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use http::header::{HeaderName, HeaderValue};
+use http::{HeaderMap, Method, StatusCode};
+use lambda_http::Error;
+use serde_json::json;
+use url::Url;
+
+const TRACE_ID: HeaderName = HeaderName::from_static("x-amzn-trace-id");
+
+/// The header each document sent to the daemon starts with.
+const DAEMON_HEADER: &str = "{\"format\": \"json\", \"version\": 1}\n";
+
+#[derive(Debug)]
+pub(crate) struct XRay {
+    socket: UdpSocket,
+    daemon: SocketAddr,
+}
+
+impl XRay {
+    /// Send to the daemon at `address`, as set by Lambda in
+    /// `AWS_XRAY_DAEMON_ADDRESS`: `host:port`, or `tcp:host:port udp:host:port`.
+    pub(crate) fn new(address: &str) -> Result<Self, Error> {
+        let udp = address
+            .split_whitespace()
+            .find_map(|address| address.strip_prefix("udp:"))
+            .unwrap_or(address.trim());
+        let daemon = udp
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::from(format!("no address for the X-Ray daemon {udp}")))?;
+        let socket = UdpSocket::bind(if daemon.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+        socket.set_nonblocking(true)?;
+        Ok(XRay { socket, daemon })
+    }
+
+    /// Start a subsegment of the function segment in the Lambda trace header
+    /// and make it the parent in the trace header of the request to the app.
+    /// `None` when the invoke is not traced or not sampled.
+    pub(crate) fn start(&self, headers: &mut HeaderMap, trace_header: Option<&str>) -> Option<Subsegment> {
+        let (trace_id, parent_id) = parse_trace_header(trace_header?)?;
+        let id = format!("{:016x}", fastrand::u64(1..));
+        let propagated = format!("Root={trace_id};Parent={id};Sampled=1");
+        if let Ok(propagated) = HeaderValue::from_str(&propagated) {
+            headers.insert(TRACE_ID, propagated);
+        }
+        Some(Subsegment {
+            id,
+            trace_id,
+            parent_id,
+            start: unix_seconds(),
+        })
+    }
+
+    /// End a subsegment with the status of the response, or `None` when the
+    /// app did not respond, and send it to the daemon.
+    pub(crate) fn send(&self, subsegment: Subsegment, method: &Method, url: &Url, status: Option<StatusCode>) {
+        let document = subsegment.document(method, url, status, unix_seconds());
+        let datagram = format!("{DAEMON_HEADER}{document}");
+        if let Err(err) = self.socket.send_to(datagram.as_bytes(), self.daemon) {
+            tracing::warn!("failed to send the X-Ray subsegment: {}", err);
+        }
+    }
+}
+
+/// The call to the app in a trace.
+#[derive(Debug)]
+pub(crate) struct Subsegment {
+    id: String,
+    trace_id: String,
+    parent_id: String,
+    start: f64,
+}
+
+impl Subsegment {
+    fn document(&self, method: &Method, url: &Url, status: Option<StatusCode>, end: f64) -> serde_json::Value {
+        let name = match url.port_or_known_default() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let mut http = json!({ "request": { "method": method.as_str(), "url": url.as_str() } });
+        if let Some(status) = status {
+            http["response"] = json!({ "status": status.as_u16() });
+        }
+        json!({
+            "type": "subsegment",
+            "name": name,
+            "id": self.id,
+            "trace_id": self.trace_id,
+            "parent_id": self.parent_id,
+            "start_time": self.start,
+            "end_time": end,
+            "namespace": "remote",
+            "http": http,
+            "error": status.is_some_and(|status| status.is_client_error()),
+            "throttle": status == Some(StatusCode::TOO_MANY_REQUESTS),
+            "fault": status.is_none_or(|status| status.is_server_error()),
+        })
+    }
+}
+
+/// The root and parent of a sampled `Root=...;Parent=...;Sampled=1` header.
+fn parse_trace_header(header: &str) -> Option<(String, String)> {
+    let (mut root, mut parent, mut sampled) = (None, None, false);
+    for field in header.split(';') {
+        match field.trim().split_once('=') {
+            Some(("Root", value)) => root = Some(value.to_string()),
+            Some(("Parent", value)) => parent = Some(value.to_string()),
+            Some(("Sampled", value)) => sampled = value == "1",
+            _ => {}
+        }
+    }
+    if !sampled {
+        return None;
+    }
+    Some((root?, parent?))
+}
+
+fn unix_seconds() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const TRACE_HEADER: &str = "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1";
+
+    #[test]
+    fn test_parse_trace_header() {
+        assert_eq!(
+            Some((
+                "1-5759e988-bd862e3fe1be46a994272793".to_string(),
+                "53995c3f42cd8ad8".to_string()
+            )),
+            parse_trace_header(TRACE_HEADER)
+        );
+        assert_eq!(
+            None,
+            parse_trace_header("Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=0")
+        );
+        assert_eq!(
+            None,
+            parse_trace_header("Root=1-5759e988-bd862e3fe1be46a994272793;Sampled=1")
+        );
+    }
+
+    #[test]
+    fn test_xray_sends_subsegment() {
+        let daemon = UdpSocket::bind("127.0.0.1:0").unwrap();
+        daemon.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let address = format!("tcp:127.0.0.1:1 udp:{}", daemon.local_addr().unwrap());
+        let xray = XRay::new(&address).unwrap();
+
+        let mut headers = HeaderMap::new();
+        assert!(xray.start(&mut headers, None).is_none());
+        let subsegment = xray.start(&mut headers, Some(TRACE_HEADER)).unwrap();
+        let propagated = format!(
+            "Root=1-5759e988-bd862e3fe1be46a994272793;Parent={};Sampled=1",
+            subsegment.id
+        );
+        assert_eq!(propagated, headers[TRACE_ID]);
+
+        let url = Url::parse("http://127.0.0.1:8080/hello").unwrap();
+        xray.send(subsegment, &Method::GET, &url, Some(StatusCode::SERVICE_UNAVAILABLE));
+
+        let mut datagram = [0; 4096];
+        let len = daemon.recv(&mut datagram).unwrap();
+        let datagram = std::str::from_utf8(&datagram[..len]).unwrap();
+        let document = datagram.strip_prefix(DAEMON_HEADER).unwrap();
+        let document: serde_json::Value = serde_json::from_str(document).unwrap();
+        assert_eq!("subsegment", document["type"]);
+        assert_eq!("127.0.0.1:8080", document["name"]);
+        assert_eq!("53995c3f42cd8ad8", document["parent_id"]);
+        assert_eq!("1-5759e988-bd862e3fe1be46a994272793", document["trace_id"]);
+        assert_eq!(503, document["http"]["response"]["status"]);
+        assert_eq!(true, document["fault"]);
+        assert_eq!(false, document["error"]);
+        assert!(document["end_time"].as_f64() >= document["start_time"].as_f64());
+    }
+}
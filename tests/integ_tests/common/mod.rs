@@ -7,18 +7,13 @@ use lambda_http::aws_lambda_events::alb::{AlbTargetGroupRequest, AlbTargetGroupR
 use lambda_http::aws_lambda_events::query_map::QueryMap;
 use lambda_http::request::LambdaRequest;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub enum LambdaEventType {
+    #[default]
     ALB,
     // TODO: Add other event types
 }
 
-impl Default for LambdaEventType {
-    fn default() -> Self {
-        LambdaEventType::ALB
-    }
-}
-
 /// Makes it easy to construct events for testing purposes.
 ///
 /// Example:
@@ -78,6 +73,11 @@ impl LambdaEventBuilder {
         self
     }
 
+    pub fn with_body(mut self, body: &str) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
     pub fn with_event_type(mut self, event_type: LambdaEventType) -> Self {
         self.event_type = event_type;
         self
@@ -1,11 +1,15 @@
 pub mod common;
 
+use std::convert::Infallible;
 use std::env;
 use std::io;
 use std::io::prelude::*;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 
 use crate::common::LambdaEventBuilder;
+use bytes::Bytes;
 use http::HeaderMap;
 use http::Request;
 use http::{Method, Response};
@@ -13,38 +17,62 @@ use httpmock::{
     Method::{DELETE, GET, POST, PUT},
     MockServer,
 };
-use hyper::body::Incoming;
+use hyper::service::service_fn;
+use hyper_util::rt::{TokioExecutor, TokioIo};
 use lambda_http::Body;
 use lambda_http::Context;
-use lambda_web_adapter::{Adapter, AdapterOptions, LambdaInvokeMode, Protocol};
+use lambda_web_adapter::{
+    full_body, Adapter, AdapterOptions, AppProtocol, ChaosOptions, ErrorStatusRule, HeaderCase, LambdaInvokeMode,
+    LwaPlugin, NdjsonStreaming, Protocol, RequestCompression, ResponseBody, StripTransferEncoding, WebSocketMode,
+};
 use tower::{Service, ServiceBuilder};
 
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use http_body_util::BodyExt;
+use http_body::Body as _;
+use http_body_util::{BodyExt, Full};
 use lambda_http::lambda_runtime::Config;
 use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
 use tower_http::compression::{CompressionBody, CompressionLayer};
 
+/// The options tests set variables of the whole process, so they take turns,
+/// and remove the variables again before other tests read their options.
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+fn options_from_env(vars: &[(&str, &str)]) -> AdapterOptions {
+    let _lock = ENV_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    for (name, value) in vars {
+        env::set_var(name, value);
+    }
+    let options = AdapterOptions::default();
+    for (name, _) in vars {
+        env::remove_var(name);
+    }
+    options
+}
+
 #[test]
 fn test_adapter_options_from_env() {
-    env::set_var("PORT", "3000");
-    env::set_var("HOST", "localhost");
-    env::set_var("READINESS_CHECK_PORT", "8000");
-    env::set_var("READINESS_CHECK_PROTOCOL", "TCP");
-    env::set_var("READINESS_CHECK_PATH", "/healthcheck");
-    env::set_var("REMOVE_BASE_PATH", "/prod");
-    env::set_var("ASYNC_INIT", "true");
-    env::set_var("AWS_LWA_ENABLE_COMPRESSION", "true");
-    env::set_var("AWS_LWA_ENABLE_TLS", "true");
-    env::set_var("AWS_LWA_TLS_SERVER_NAME", "api.example.com");
     env::remove_var("AWS_LWA_TLS_CERT_FILE");
-    env::set_var("AWS_LWA_INVOKE_MODE", "buffered");
-    env::set_var("AWS_LWA_AUTHORIZATION_SOURCE", "auth-token");
 
     // Initialize adapter with env options
-    let options = AdapterOptions::default();
+    let options = options_from_env(&[
+        ("PORT", "3000"),
+        ("HOST", "localhost"),
+        ("READINESS_CHECK_PORT", "8000"),
+        ("READINESS_CHECK_PROTOCOL", "TCP"),
+        ("READINESS_CHECK_PATH", "/healthcheck"),
+        ("REMOVE_BASE_PATH", "/prod"),
+        ("ASYNC_INIT", "true"),
+        ("AWS_LWA_ENABLE_COMPRESSION", "true"),
+        ("AWS_LWA_ENABLE_TLS", "true"),
+        ("AWS_LWA_TLS_SERVER_NAME", "api.example.com"),
+        ("AWS_LWA_INVOKE_MODE", "buffered"),
+        ("AWS_LWA_AUTHORIZATION_SOURCE", "auth-token"),
+    ]);
     Adapter::new(&options);
 
     assert_eq!("3000", options.port);
@@ -61,20 +89,20 @@ fn test_adapter_options_from_env() {
 
 #[test]
 fn test_adapter_options_from_namespaced_env() {
-    env::set_var("AWS_LWA_PORT", "3000");
-    env::set_var("AWS_LWA_HOST", "localhost");
-    env::set_var("AWS_LWA_READINESS_CHECK_MIN_UNHEALTHY_STATUS", "400");
-    env::set_var("AWS_LWA_READINESS_CHECK_PORT", "8000");
-    env::set_var("AWS_LWA_READINESS_CHECK_PROTOCOL", "TCP");
-    env::set_var("AWS_LWA_READINESS_CHECK_PATH", "/healthcheck");
-    env::set_var("AWS_LWA_REMOVE_BASE_PATH", "/prod");
-    env::set_var("AWS_LWA_ASYNC_INIT", "true");
-    env::set_var("AWS_LWA_ENABLE_COMPRESSION", "true");
-    env::set_var("AWS_LWA_INVOKE_MODE", "response_stream");
-    env::set_var("AWS_LWA_AUTHORIZATION_SOURCE", "auth-token");
-
     // Initialize adapter with env options
-    let options = AdapterOptions::default();
+    let options = options_from_env(&[
+        ("AWS_LWA_PORT", "3000"),
+        ("AWS_LWA_HOST", "localhost"),
+        ("AWS_LWA_READINESS_CHECK_MIN_UNHEALTHY_STATUS", "400"),
+        ("AWS_LWA_READINESS_CHECK_PORT", "8000"),
+        ("AWS_LWA_READINESS_CHECK_PROTOCOL", "TCP"),
+        ("AWS_LWA_READINESS_CHECK_PATH", "/healthcheck"),
+        ("AWS_LWA_REMOVE_BASE_PATH", "/prod"),
+        ("AWS_LWA_ASYNC_INIT", "true"),
+        ("AWS_LWA_ENABLE_COMPRESSION", "true"),
+        ("AWS_LWA_INVOKE_MODE", "response_stream"),
+        ("AWS_LWA_AUTHORIZATION_SOURCE", "auth-token"),
+    ]);
     Adapter::new(&options);
 
     assert_eq!("3000", options.port);
@@ -92,12 +120,8 @@ fn test_adapter_options_from_namespaced_env() {
 
 #[test]
 fn test_readiness_check_port_fallback_to_lwa_port() {
-    env::remove_var("AWS_LWA_READINESS_CHECK_PORT");
-    env::remove_var("READINESS_CHECK_PORT");
-    env::set_var("AWS_LWA_PORT", "3000");
-
     // Initialize adapter with env options
-    let options = AdapterOptions::default();
+    let options = options_from_env(&[("AWS_LWA_PORT", "3000")]);
     Adapter::new(&options);
 
     assert_eq!("3000", options.readiness_check_port);
@@ -575,7 +599,7 @@ async fn test_http_content_encoding_suffix() {
         when.method(GET).path("/json");
         then.status(200)
             .header("content-type", "application/graphql-response+json; charset=utf-8")
-            .body(json_data.to_owned());
+            .body(&json_data);
     });
 
     // Initialize adapter and do readiness check
@@ -643,6 +667,47 @@ async fn test_http_error_status_codes() {
     error_endpoint.assert();
 }
 
+#[tokio::test]
+async fn test_http_error_status_rules() {
+    // Start app server
+    let app_server = MockServer::start();
+    let payments = app_server.mock(|when, then| {
+        when.method(GET).path("/payments/123");
+        then.status(503).body("Service Unavailable");
+    });
+    let orders = app_server.mock(|when, then| {
+        when.method(GET).path("/orders/123");
+        then.status(503).body("Service Unavailable");
+    });
+
+    // Initialize adapter with error status codes scoped to payments
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        error_status_rules: vec![ErrorStatusRule {
+            method: None,
+            path: "/payments/*".to_string(),
+            codes: (500..=599).collect(),
+        }],
+        ..Default::default()
+    });
+
+    // A 503 from payments fails the invocation
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/payments/123").build());
+    add_lambda_context_to_request(&mut request);
+    assert!(adapter.call(request).await.is_err());
+    payments.assert();
+
+    // A 503 from elsewhere is passed through
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/orders/123").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(503, response.status());
+    orders.assert();
+}
+
 #[tokio::test]
 async fn test_http_authorization_source() {
     // Start app server
@@ -750,21 +815,3888 @@ async fn test_http_context_multi_headers() {
     assert_eq!("OK", body_to_string(response).await);
 }
 
-async fn body_to_string(res: Response<Incoming>) -> String {
-    let body_bytes = res.collect().await.unwrap().to_bytes();
-    String::from_utf8_lossy(&body_bytes).to_string()
+#[tokio::test]
+async fn test_http2_single_connection() {
+    // Start an HTTP/2 app server that counts accepted connections
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let accepted = connections.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            accepted.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(async move {
+                let service =
+                    service_fn(|_req| async { Ok::<_, Infallible>(Response::new(Full::new(Bytes::from("OK")))) });
+                let _ = hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    // Initialize adapter with a single shared HTTP/2 connection
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: "127.0.0.1".to_string(),
+        port: port.to_string(),
+        readiness_check_port: port.to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        async_init: false,
+        http_http2_only: true,
+        http_http2_single_connection: true,
+        ..Default::default()
+    });
+
+    // Call the adapter service several times
+    for _ in 0..3 {
+        let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+        add_lambda_context_to_request(&mut request);
+
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!(200, response.status());
+        assert_eq!("OK", body_to_string(response).await);
+    }
+
+    // All requests were multiplexed over one connection
+    assert_eq!(1, connections.load(Ordering::SeqCst));
 }
 
-async fn compressed_body_to_string(res: Response<CompressionBody<Incoming>>) -> String {
-    let body_bytes = res.collect().await.unwrap().to_bytes();
-    decode_reader(&body_bytes).unwrap()
+#[tokio::test]
+async fn test_http_hedged_request() {
+    // Start an app server where the first request stalls
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let received = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let received = received.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |_req| {
+                    let attempt = received.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        if attempt == 0 {
+                            tokio::time::sleep(Duration::from_secs(5)).await;
+                        }
+                        Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(format!("attempt {attempt}")))))
+                    }
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    // Initialize adapter with hedging enabled
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: "127.0.0.1".to_string(),
+        port: port.to_string(),
+        readiness_check_port: port.to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        async_init: false,
+        hedge_after: Some(Duration::from_millis(50)),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+
+    let started = Instant::now();
+    let response = adapter.call(request).await.expect("Request failed");
+
+    // The hedged attempt answered without waiting for the stalled one
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(200, response.status());
+    assert_eq!("attempt 1", body_to_string(response).await);
+    assert_eq!(2, requests.load(Ordering::SeqCst));
 }
 
-fn decode_reader(bytes: &[u8]) -> io::Result<String> {
-    let mut gz = GzDecoder::new(bytes);
-    let mut s = String::new();
-    gz.read_to_string(&mut s)?;
-    Ok(s)
+#[tokio::test]
+async fn test_http_static_headers() {
+    // Start app server that sets one of the static headers itself
+    let app_server = MockServer::start();
+    let hello = app_server.mock(|when, then| {
+        when.method(GET).path("/hello");
+        then.status(200)
+            .header("cache-control", "max-age=60")
+            .body("Hello World");
+    });
+
+    // Initialize adapter with static headers from a YAML file
+    let file = env::temp_dir().join(format!("static-headers-{}.yaml", app_server.port()));
+    std::fs::write(
+        &file,
+        "x-frame-options: DENY\ncache-control: no-store\nvary:\n  - Origin\n  - Accept-Encoding\n",
+    )
+    .unwrap();
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        static_headers: Some(file.to_string_lossy().to_string()),
+        ..Default::default()
+    });
+    std::fs::remove_file(&file).unwrap();
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+
+    let response = adapter.call(request).await.expect("Request failed");
+
+    // The static headers are added, and the app's own header is kept
+    hello.assert();
+    assert_eq!(200, response.status());
+    assert_eq!("DENY", response.headers()["x-frame-options"]);
+    assert_eq!("max-age=60", response.headers()["cache-control"]);
+    assert_eq!(
+        vec!["Origin", "Accept-Encoding"],
+        response.headers().get_all("vary").iter().collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn test_http_request_timeout() {
+    // Start an app server that hangs
+    let app_server = MockServer::start();
+    let slow = app_server.mock(|when, then| {
+        when.method(GET).path("/slow");
+        then.status(200).delay(Duration::from_secs(5)).body("too late");
+    });
+
+    // Initialize adapter with a request timeout and a timeout response
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        request_timeout: Some("100".to_string()),
+        request_timeout_response: Some(r#"{"message":"try again"}"#.to_string()),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/slow").build());
+    add_lambda_context_to_request(&mut request);
+
+    let started = Instant::now();
+    let response = adapter.call(request).await.expect("Request failed");
+
+    // The adapter answers with a 504 instead of waiting for the app
+    assert!(started.elapsed() < Duration::from_secs(5));
+    slow.assert();
+    assert_eq!(504, response.status());
+    assert_eq!("application/json", response.headers()["content-type"]);
+    assert_eq!(r#"{"message":"try again"}"#, body_to_string(response).await);
+}
+
+#[tokio::test]
+async fn test_http_hedged_request_respects_retry_budget() {
+    // Start an app server where the first request is slow
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let received = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let received = received.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |_req| {
+                    let attempt = received.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        if attempt == 0 {
+                            tokio::time::sleep(Duration::from_millis(200)).await;
+                        }
+                        Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(format!("attempt {attempt}")))))
+                    }
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    // Initialize adapter with hedging enabled but no retries left in the budget
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: "127.0.0.1".to_string(),
+        port: port.to_string(),
+        readiness_check_port: port.to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        async_init: false,
+        hedge_after: Some(Duration::from_millis(50)),
+        retry_budget: Some(0),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+
+    let response = adapter.call(request).await.expect("Request failed");
+
+    // No hedged attempt was sent
+    assert_eq!(200, response.status());
+    assert_eq!("attempt 0", body_to_string(response).await);
+    assert_eq!(1, requests.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_http_upstream_retries() {
+    // Start an app server that drops the connection of the first request
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let received = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let received = received.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req: Request<hyper::body::Incoming>| {
+                    let attempt = match req.uri().path() {
+                        "/hello" => received.fetch_add(1, Ordering::SeqCst),
+                        _ => 1,
+                    };
+                    async move {
+                        if attempt == 0 {
+                            return Err("worker recycled");
+                        }
+                        Ok(Response::new(Full::new(Bytes::from(format!("attempt {attempt}")))))
+                    }
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    // Initialize adapter with upstream retries
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: "127.0.0.1".to_string(),
+        port: port.to_string(),
+        readiness_check_port: port.to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        async_init: false,
+        upstream_retries: 2,
+        upstream_retry_backoff: Duration::from_millis(10),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+
+    let response = adapter.call(request).await.expect("Request failed");
+
+    // The GET request was sent again after the app dropped the connection
+    assert_eq!(200, response.status());
+    assert_eq!("attempt 1", body_to_string(response).await);
+    assert_eq!(2, requests.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_http_serialize_requests() {
+    // Start an app server that records how many requests it handles at once
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let most = Arc::new(AtomicUsize::new(0));
+    let (counter, recorded) = (in_flight.clone(), most.clone());
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (counter, recorded) = (counter.clone(), recorded.clone());
+            tokio::spawn(async move {
+                let service = service_fn(move |_req| {
+                    let (counter, recorded) = (counter.clone(), recorded.clone());
+                    async move {
+                        recorded.fetch_max(counter.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        counter.fetch_sub(1, Ordering::SeqCst);
+                        Ok::<_, Infallible>(Response::new(Full::new(Bytes::from("done"))))
+                    }
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    // Initialize adapter with serialized requests
+    let adapter = Adapter::new(&AdapterOptions {
+        host: "127.0.0.1".to_string(),
+        port: port.to_string(),
+        readiness_check_port: port.to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        async_init: false,
+        serialize_requests: true,
+        ..Default::default()
+    });
+
+    let calls = (0..3).map(|_| {
+        let mut adapter = adapter.clone();
+        let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+        add_lambda_context_to_request(&mut request);
+        tokio::spawn(async move {
+            let response = adapter.call(request).await.expect("Request failed");
+            body_to_string(response).await
+        })
+    });
+    for call in calls.collect::<Vec<_>>() {
+        assert_eq!("done", call.await.unwrap());
+    }
+
+    // The app never handled two requests at once
+    assert_eq!(1, most.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn test_http_max_in_flight_sheds_low_priority_routes() {
+    // Start app server with a slow route
+    let app_server = MockServer::start();
+    let slow = app_server.mock(|when, then| {
+        when.method(GET).path("/slow");
+        then.status(200).delay(Duration::from_millis(200)).body("slow");
+    });
+    let hello = app_server.mock(|when, then| {
+        when.method(GET).path("/hello");
+        then.status(200).body("hello");
+    });
+    let metrics = app_server.mock(|when, then| {
+        when.method(GET).path("/metrics");
+        then.status(200).body("metrics");
+    });
+
+    // Initialize adapter with room for one request in flight
+    let adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        async_init: false,
+        invoke_mode: LambdaInvokeMode::Buffered,
+        max_in_flight: Some(1),
+        route_priorities: Some(r#"{"/metrics":"low"}"#.to_string()),
+        ..Default::default()
+    });
+    let call = |path: &str| {
+        let mut adapter = adapter.clone();
+        let mut request = Request::from(LambdaEventBuilder::new().with_path(path).build());
+        add_lambda_context_to_request(&mut request);
+        tokio::spawn(async move {
+            let response = adapter.call(request).await.expect("Request failed");
+            let retry_after = response.headers().get("retry-after").cloned();
+            (response.status(), retry_after, body_to_string(response).await)
+        })
+    };
+
+    let in_flight = call("/slow");
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // The low-priority route is shed while the limit is reached
+    let (status, retry_after, _) = call("/metrics").await.unwrap();
+    assert_eq!(429, status);
+    assert_eq!(Some("1"), retry_after.as_ref().map(|value| value.to_str().unwrap()));
+    metrics.assert_hits(0);
+
+    // Other routes wait for the request in flight
+    let (status, _, body) = call("/hello").await.unwrap();
+    assert_eq!(200, status);
+    assert_eq!("hello", body);
+    assert_eq!("slow", in_flight.await.unwrap().2);
+    slow.assert();
+    hello.assert();
+
+    // Below the limit, low-priority routes go through
+    let (status, _, body) = call("/metrics").await.unwrap();
+    assert_eq!(200, status);
+    assert_eq!("metrics", body);
+}
+
+#[tokio::test]
+async fn test_http_expect_continue_for_large_uploads() {
+    // Start app server
+    let app_server = MockServer::start();
+    let upload = app_server.mock(|when, then| {
+        when.method(POST)
+            .path("/upload")
+            .header("expect", "100-continue")
+            .body("a large upload");
+        then.status(201).body("stored");
+    });
+    let small = app_server.mock(|when, then| {
+        when.method(POST).path("/small").body("tiny");
+        then.status(201).body("stored");
+    });
+
+    // Initialize adapter with the handshake for bodies of 10 bytes or more
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        async_init: false,
+        invoke_mode: LambdaInvokeMode::Buffered,
+        expect_continue_bytes: Some(10),
+        ..Default::default()
+    });
+
+    for (path, body) in [("/upload", "a large upload"), ("/small", "tiny")] {
+        let mut request = Request::from(
+            LambdaEventBuilder::new()
+                .with_method(Method::POST)
+                .with_path(path)
+                .with_body(body)
+                .build(),
+        );
+        add_lambda_context_to_request(&mut request);
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!(201, response.status());
+        assert_eq!("stored", body_to_string(response).await);
+    }
+    upload.assert();
+    small.assert();
+}
+
+#[tokio::test]
+async fn test_http_sniff_content_type() {
+    // Start app server that sends no content types
+    let app_server = MockServer::start();
+    let image = app_server.mock(|when, then| {
+        when.method(GET).path("/logo");
+        then.status(200).body(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR");
+    });
+    let typed = app_server.mock(|when, then| {
+        when.method(GET).path("/typed");
+        then.status(200).header("content-type", "text/csv").body("a,b");
+    });
+
+    // Initialize adapter with content type sniffing
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        async_init: false,
+        invoke_mode: LambdaInvokeMode::Buffered,
+        sniff_content_type: true,
+        ..Default::default()
+    });
+
+    for (path, content_type) in [("/logo", "image/png"), ("/typed", "text/csv")] {
+        let mut request = Request::from(LambdaEventBuilder::new().with_path(path).build());
+        add_lambda_context_to_request(&mut request);
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!(200, response.status());
+        assert_eq!(content_type, response.headers()["content-type"]);
+    }
+    image.assert();
+    typed.assert();
+}
+
+#[tokio::test]
+async fn test_websocket_events_over_http() {
+    // Start the @connections API of the WebSocket API
+    let connections = MockServer::start();
+    let reply = connections.mock(|when, then| {
+        when.method(POST)
+            .path("/prod/@connections/conn-http")
+            .body("echo: hello");
+        then.status(200);
+    });
+    env::set_var(
+        "AWS_ENDPOINT_URL_APIGATEWAYMANAGEMENTAPI",
+        format!("http://127.0.0.1:{}", connections.port()),
+    );
+    env::set_var("AWS_REGION", "us-east-1");
+    env::set_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE");
+    env::set_var("AWS_SECRET_ACCESS_KEY", "secret");
+
+    // Start app server with a route for each WebSocket route
+    let app_server = MockServer::start();
+    let connect = app_server.mock(|when, then| {
+        when.method(POST)
+            .path("/websocket/connect")
+            .header("authorization", "Bearer token")
+            .header("x-websocket-connection-id", "conn-http")
+            .header("x-websocket-event-type", "CONNECT");
+        then.status(200);
+    });
+    let message = app_server.mock(|when, then| {
+        when.method(POST)
+            .path("/websocket/default")
+            .header("x-websocket-route-key", "$default")
+            .body("hello");
+        then.status(200).body("echo: hello");
+    });
+    let disconnect = app_server.mock(|when, then| {
+        when.method(POST).path("/websocket/disconnect");
+        then.status(200);
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        websocket_mode: WebSocketMode::Http,
+        websocket_path: "/websocket".to_string(),
+        ..Default::default()
+    });
+
+    let response = adapter
+        .call(websocket_event("CONNECT", "$connect", "conn-http", None))
+        .await
+        .expect("Request failed");
+    assert_eq!(200, response.status());
+    connect.assert();
+
+    // The app's response to a message is sent back to the client
+    let response = adapter
+        .call(websocket_event("MESSAGE", "$default", "conn-http", Some("hello")))
+        .await
+        .expect("Request failed");
+    assert_eq!(200, response.status());
+    message.assert();
+    reply.assert();
+
+    let response = adapter
+        .call(websocket_event("DISCONNECT", "$disconnect", "conn-http", None))
+        .await
+        .expect("Request failed");
+    assert_eq!(200, response.status());
+    disconnect.assert();
+}
+
+#[tokio::test]
+async fn test_websocket_events_over_websocket() {
+    // Start the @connections API of the WebSocket API
+    let connections = MockServer::start();
+    let reply = connections.mock(|when, then| {
+        when.method(POST).path("/prod/@connections/conn-ws").body("echo: hello");
+        then.status(200);
+    });
+    env::set_var(
+        "AWS_ENDPOINT_URL_APIGATEWAYMANAGEMENTAPI",
+        format!("http://127.0.0.1:{}", connections.port()),
+    );
+    env::set_var("AWS_REGION", "us-east-1");
+    env::set_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE");
+    env::set_var("AWS_SECRET_ACCESS_KEY", "secret");
+
+    // Start an app server that answers each WebSocket message with an echo
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (handshake_tx, mut handshake_rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(stream.read_u8().await.unwrap());
+        }
+        handshake_tx.send(String::from_utf8(head).unwrap()).unwrap();
+        stream
+            .write_all(b"HTTP/1.1 101 Switching Protocols\r\nupgrade: websocket\r\nconnection: Upgrade\r\n\r\n")
+            .await
+            .unwrap();
+        loop {
+            // client frames are masked
+            let opcode = stream.read_u8().await.unwrap() & 0x0f;
+            let len = (stream.read_u8().await.unwrap() & 0x7f) as usize;
+            let mut mask = [0u8; 4];
+            stream.read_exact(&mut mask).await.unwrap();
+            let mut payload = vec![0u8; len];
+            stream.read_exact(&mut payload).await.unwrap();
+            payload
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i, byte)| *byte ^= mask[i % 4]);
+            if opcode == 0x8 {
+                handshake_tx.send("closed".to_string()).unwrap();
+                return;
+            }
+            let echo = format!("echo: {}", String::from_utf8(payload).unwrap());
+            let mut frame = vec![0x81, echo.len() as u8];
+            frame.extend_from_slice(echo.as_bytes());
+            stream.write_all(&frame).await.unwrap();
+        }
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: "127.0.0.1".to_string(),
+        port: port.to_string(),
+        readiness_check_port: port.to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        async_init: false,
+        websocket_mode: WebSocketMode::WebSocket,
+        websocket_path: "/ws".to_string(),
+        ..Default::default()
+    });
+
+    // The connection opens a WebSocket to the app with the client's headers
+    let response = adapter
+        .call(websocket_event("CONNECT", "$connect", "conn-ws", None))
+        .await
+        .expect("Request failed");
+    assert_eq!(200, response.status());
+    let handshake = handshake_rx.recv().await.unwrap().to_lowercase();
+    assert!(handshake.starts_with("get /ws http/1.1\r\n"));
+    assert!(handshake.contains("authorization: bearer token\r\n"));
+    assert!(handshake.contains("x-websocket-connection-id: conn-ws\r\n"));
+
+    // Messages from the app are sent to the client
+    let response = adapter
+        .call(websocket_event("MESSAGE", "$default", "conn-ws", Some("hello")))
+        .await
+        .expect("Request failed");
+    assert_eq!(200, response.status());
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while reply.hits() == 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    reply.assert();
+
+    // Disconnecting closes the WebSocket to the app
+    let response = adapter
+        .call(websocket_event("DISCONNECT", "$disconnect", "conn-ws", None))
+        .await
+        .expect("Request failed");
+    assert_eq!(200, response.status());
+    assert_eq!("closed", handshake_rx.recv().await.unwrap());
+}
+
+#[tokio::test]
+async fn test_http_fast_fail_while_app_is_unhealthy() {
+    // Reserve a port without an app listening on it
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+
+    // Initialize adapter with fast-fail enabled
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: "127.0.0.1".to_string(),
+        port: port.to_string(),
+        readiness_check_port: port.to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        async_init: false,
+        fast_fail_retry_after: Some(Duration::from_secs(1)),
+        ..Default::default()
+    });
+
+    // The first request reaches for the app and fails
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+    assert!(adapter.call(request).await.is_err());
+
+    // Following requests are answered right away
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(503, response.status());
+    assert_eq!("1", response.headers().get("retry-after").unwrap());
+
+    // Start the app, requests are forwarded again after the recheck interval
+    let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let service = service_fn(|_req| async {
+                    Ok::<_, Infallible>(Response::new(Full::new(Bytes::from("Hello World"))))
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(200, response.status());
+    assert_eq!("Hello World", body_to_string(response).await);
+}
+
+#[tokio::test]
+async fn test_http_health_scoring_opens_circuit_on_errors() {
+    // Start an app server that fails every request
+    let app_server = MockServer::start();
+    let failing = app_server.mock(|when, then| {
+        when.path("/hello");
+        then.status(500);
+    });
+
+    // Initialize adapter with fast-fail and health scoring enabled
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        fast_fail_retry_after: Some(Duration::from_secs(2)),
+        health_scoring: true,
+        ..Default::default()
+    });
+    let hello = || {
+        let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+        add_lambda_context_to_request(&mut request);
+        request
+    };
+
+    // 5xx responses lower the score until the circuit opens
+    for _ in 0..5 {
+        let response = adapter.call(hello()).await.expect("Request failed");
+        assert_eq!(500, response.status());
+    }
+    let response = adapter.call(hello()).await.expect("Request failed");
+    assert_eq!(503, response.status());
+    assert_eq!("2", response.headers().get("retry-after").unwrap());
+    failing.assert_hits(5);
+}
+
+#[tokio::test]
+async fn test_http_problem_details_for_adapter_errors() {
+    // Reserve a port without an app listening on it
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+
+    // Initialize adapter with problem details enabled
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: "127.0.0.1".to_string(),
+        port: port.to_string(),
+        readiness_check_port: port.to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        async_init: false,
+        problem_details: true,
+        problem_details_template: Some(r#"{"instance":"{request_id}"}"#.to_string()),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+
+    // The unreachable app is reported as a machine-readable 502
+    assert_eq!(502, response.status());
+    assert_eq!(
+        "application/problem+json",
+        response.headers().get("content-type").unwrap()
+    );
+    let problem: serde_json::Value = serde_json::from_str(&body_to_string(response).await).unwrap();
+    assert_eq!("Bad Gateway", problem["title"]);
+    assert_eq!(502, problem["status"]);
+    assert_eq!("my_id", problem["instance"]);
+}
+
+#[tokio::test]
+async fn test_http_failure_capture() {
+    // Reserve a port without an app listening on it
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+    let capture_dir = std::env::temp_dir().join(format!("lwa-integ-failures-{}", std::process::id()));
+
+    // Initialize adapter capturing failure bundles
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: "127.0.0.1".to_string(),
+        port: port.to_string(),
+        readiness_check_port: port.to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        async_init: false,
+        problem_details: true,
+        failure_capture: Some(capture_dir.to_str().unwrap().to_string()),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_path("/hello")
+            .with_header("authorization", "Bearer secret")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(502, response.status());
+
+    // The bundle has the error and the request, without credentials
+    let bundles: Vec<_> = std::fs::read_dir(&capture_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(1, bundles.len());
+    let bundle: serde_json::Value = serde_json::from_slice(&std::fs::read(&bundles[0]).unwrap()).unwrap();
+    assert_eq!("my_id", bundle["request_id"]);
+    assert_eq!(502, bundle["status"]);
+    assert!(bundle["error"].as_str().unwrap().contains("error"));
+    assert_eq!("/hello", bundle["request"]["path"]);
+    assert_eq!("[REDACTED]", bundle["request"]["headers"]["authorization"]);
+
+    std::fs::remove_dir_all(&capture_dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_http_replay_capture() {
+    // Start app server
+    let app_server = MockServer::start();
+    let orders = app_server.mock(|when, then| {
+        when.method(POST).path("/orders");
+        then.status(201)
+            .header("content-type", "application/json")
+            .header("set-cookie", "session=secret")
+            .body(r#"{"id":1}"#);
+    });
+    let capture_dir = std::env::temp_dir().join(format!("lwa-integ-replay-{}", std::process::id()));
+
+    // Initialize adapter capturing every request
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        invoke_mode: LambdaInvokeMode::Buffered,
+        replay_capture: Some(capture_dir.to_str().unwrap().to_string()),
+        replay_sample_percent: 100.0,
+        ..Default::default()
+    });
+
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_method(Method::POST)
+            .with_path("/orders")
+            .with_query("page", "2")
+            .with_query("api_token", "secret")
+            .with_header("authorization", "Bearer secret")
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"item":"book"}"#)
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(201, response.status());
+    assert_eq!(r#"{"id":1}"#, body_to_string(response).await);
+    orders.assert();
+
+    // The entry has both halves of the exchange, without credentials
+    let entries: Vec<_> = std::fs::read_dir(&capture_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(1, entries.len());
+    let har: serde_json::Value = serde_json::from_slice(&std::fs::read(&entries[0]).unwrap()).unwrap();
+    let entry = &har["log"]["entries"][0];
+    assert_eq!("1.2", har["log"]["version"]);
+    assert_eq!("my_id", entry["_requestId"]);
+    assert_eq!("POST", entry["request"]["method"]);
+    assert!(entry["request"]["url"].as_str().unwrap().contains("/orders?"));
+    assert!(entry["request"]["url"].as_str().unwrap().contains("page=2"));
+    assert!(!entry["request"]["url"].as_str().unwrap().contains("secret"));
+    assert_eq!(r#"{"item":"book"}"#, entry["request"]["postData"]["text"]);
+    assert_eq!(201, entry["response"]["status"]);
+    assert_eq!(r#"{"id":1}"#, entry["response"]["content"]["text"]);
+    let har = har.to_string();
+    assert!(!har.contains("Bearer secret"));
+    assert!(!har.contains("session=secret"));
+
+    std::fs::remove_dir_all(&capture_dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_http_idempotency_key_replays_response() {
+    // Start app server
+    let app_server = MockServer::start();
+    let orders = app_server.mock(|when, then| {
+        when.method(POST).path("/orders");
+        then.status(201).body("order created");
+    });
+
+    // Initialize adapter with idempotency keys enabled
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        idempotency_ttl: Some(Duration::from_secs(60)),
+        ..Default::default()
+    });
+
+    // The second delivery of the same request is replayed
+    for replayed in [false, true] {
+        let mut request = Request::from(
+            LambdaEventBuilder::new()
+                .with_method(Method::POST)
+                .with_path("/orders")
+                .with_header("idempotency-key", "order-1")
+                .build(),
+        );
+        add_lambda_context_to_request(&mut request);
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!(201, response.status());
+        assert_eq!(replayed, response.headers().contains_key("idempotent-replayed"));
+        assert_eq!("order created", body_to_string(response).await);
+    }
+    orders.assert_hits(1);
+
+    // A different key reaches the app
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_method(Method::POST)
+            .with_path("/orders")
+            .with_header("idempotency-key", "order-2")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    adapter.call(request).await.expect("Request failed");
+    orders.assert_hits(2);
+}
+
+#[tokio::test]
+async fn test_http_mirror_requests() {
+    // Start the app server and a mirror server
+    let app_server = MockServer::start();
+    let hello = app_server.mock(|when, then| {
+        when.method(GET).path("/hello").query_param("name", "world");
+        then.status(200).body("Hello World");
+    });
+    let mirror_server = MockServer::start();
+    let mirrored = mirror_server.mock(|when, then| {
+        when.method(GET).path("/hello").query_param("name", "world");
+        then.status(500).body("mirror responses are discarded");
+    });
+
+    // Initialize adapter mirroring every request to the second port
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        mirror_target: Some(mirror_server.port().to_string()),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_path("/hello")
+            .with_query("name", "world")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+
+    // The client gets the app response
+    assert_eq!(200, response.status());
+    assert_eq!("Hello World", body_to_string(response).await);
+    hello.assert();
+
+    // The mirrored copy arrives in the background
+    for _ in 0..50 {
+        if mirrored.hits() == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    mirrored.assert();
+}
+
+#[tokio::test]
+async fn test_http_traffic_split() {
+    // Start blue and green app servers
+    let blue_server = MockServer::start();
+    let blue = blue_server.mock(|when, then| {
+        when.method(GET).path("/hello");
+        then.status(200).body("blue");
+    });
+    let green_server = MockServer::start();
+    let green = green_server.mock(|when, then| {
+        when.method(GET).path("/hello");
+        then.status(200).body("green");
+    });
+
+    // Initialize adapter sending all traffic to green
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: blue_server.host(),
+        port: blue_server.port().to_string(),
+        readiness_check_port: blue_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        traffic_split: Some(format!(
+            r#"{{"{}":0,"{}":100}}"#,
+            blue_server.port(),
+            green_server.port()
+        )),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+
+    assert_eq!("green", body_to_string(response).await);
+    green.assert();
+    blue.assert_hits(0);
+}
+
+#[tokio::test]
+async fn test_http_traffic_split_affinity_cookie() {
+    // Start blue and green app servers
+    let blue_server = MockServer::start();
+    let blue = blue_server.mock(|when, then| {
+        when.method(GET).path("/hello");
+        then.status(200).body("blue");
+    });
+    let green_server = MockServer::start();
+    let green = green_server.mock(|when, then| {
+        when.method(GET).path("/hello");
+        then.status(200).body("green");
+    });
+
+    // Initialize adapter splitting traffic evenly
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: blue_server.host(),
+        port: blue_server.port().to_string(),
+        readiness_check_port: blue_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        traffic_split: Some(format!(
+            r#"{{"{}":50,"{}":50}}"#,
+            blue_server.port(),
+            green_server.port()
+        )),
+        traffic_split_affinity_cookie: Some("lwa-worker".to_string()),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    let cookie = response.headers()["set-cookie"].to_str().unwrap().to_string();
+    let first = body_to_string(response).await;
+
+    // Requests with the cookie stay on the same app server
+    let cookie = cookie.split(';').next().unwrap();
+    for _ in 0..20 {
+        let mut request = Request::from(
+            LambdaEventBuilder::new()
+                .with_path("/hello")
+                .with_header("cookie", cookie)
+                .build(),
+        );
+        add_lambda_context_to_request(&mut request);
+        let response = adapter.call(request).await.expect("Request failed");
+        assert!(response.headers().get("set-cookie").is_none());
+        assert_eq!(first, body_to_string(response).await);
+    }
+    assert_eq!(21, blue.hits() + green.hits());
+}
+
+#[tokio::test]
+async fn test_http_host_routes() {
+    // Start the default app server and a tenant app server
+    let app_server = MockServer::start();
+    let tenant_a = app_server.mock(|when, then| {
+        when.method(GET).path("/tenant-a/hello");
+        then.status(200).body("tenant a");
+    });
+    let tenant_b_server = MockServer::start();
+    let tenant_b = tenant_b_server.mock(|when, then| {
+        when.method(GET).path("/hello");
+        then.status(200).body("tenant b");
+    });
+
+    // Initialize adapter routing custom domains
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        host_routes: Some(format!(
+            r#"{{"a.example.com":{{"base_path":"/tenant-a"}},"b.example.com":{{"port":{}}}}}"#,
+            tenant_b_server.port()
+        )),
+        ..Default::default()
+    });
+
+    for (host, body) in [("a.example.com", "tenant a"), ("b.example.com", "tenant b")] {
+        let mut request = Request::from(
+            LambdaEventBuilder::new()
+                .with_path("/hello")
+                .with_header("host", host)
+                .build(),
+        );
+        add_lambda_context_to_request(&mut request);
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!(body, body_to_string(response).await);
+    }
+
+    tenant_a.assert();
+    tenant_b.assert();
+}
+
+#[tokio::test]
+async fn test_http_method_routes() {
+    // Start the writer app server and a reader app server
+    let app_server = MockServer::start();
+    let write = app_server.mock(|when, then| {
+        when.method(POST).path("/items");
+        then.status(201).body("written");
+    });
+    let reader_server = MockServer::start();
+    let read = reader_server.mock(|when, then| {
+        when.method(GET).path("/items");
+        then.status(200).body("read");
+    });
+
+    // Initialize adapter sending reads to the reader
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        method_routes: Some(format!(r#"{{"GET":{}}}"#, reader_server.port())),
+        ..Default::default()
+    });
+
+    for (method, body) in [(Method::GET, "read"), (Method::POST, "written")] {
+        let mut request = Request::from(
+            LambdaEventBuilder::new()
+                .with_method(method)
+                .with_path("/items")
+                .build(),
+        );
+        add_lambda_context_to_request(&mut request);
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!(body, body_to_string(response).await);
+    }
+
+    read.assert();
+    write.assert();
+}
+
+#[tokio::test]
+async fn test_http_soap_routes() {
+    // Start app server with a path per SOAP operation
+    let app_server = MockServer::start();
+    let read = app_server.mock(|when, then| {
+        when.method(POST).path("/orders/read");
+        then.status(200).body("read");
+    });
+    let write = app_server.mock(|when, then| {
+        when.method(POST).path("/orders/write");
+        then.status(200).body("write");
+    });
+
+    // Initialize adapter dispatching SOAP actions
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        soap_routes: Some(r#"{"urn:orders#GetOrder":"/orders/read","urn:orders#*":"/orders/write"}"#.to_string()),
+        ..Default::default()
+    });
+
+    for (content_type, soap_action, body) in [
+        ("text/xml; charset=utf-8", Some("\"urn:orders#GetOrder\""), "read"),
+        ("application/soap+xml; action=\"urn:orders#CancelOrder\"", None, "write"),
+    ] {
+        let mut event = LambdaEventBuilder::new()
+            .with_method(Method::POST)
+            .with_path("/soap")
+            .with_header("content-type", content_type)
+            .with_body("<Envelope/>");
+        if let Some(soap_action) = soap_action {
+            event = event.with_header("soapaction", soap_action);
+        }
+        let mut request = Request::from(event.build());
+        add_lambda_context_to_request(&mut request);
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!(body, body_to_string(response).await);
+    }
+
+    read.assert();
+    write.assert();
+}
+
+#[tokio::test]
+async fn test_http_static_files() {
+    // Create a static asset directory
+    let static_dir = std::env::temp_dir().join(format!("lwa-integ-static-{}", std::process::id()));
+    std::fs::create_dir_all(&static_dir).unwrap();
+    std::fs::write(static_dir.join("logo.svg"), "<svg></svg>").unwrap();
+
+    // Start app server
+    let app_server = MockServer::start();
+    let api = app_server.mock(|when, then| {
+        when.method(GET).path("/static/api.json");
+        then.status(200).body("from the app");
+    });
+
+    // Initialize adapter serving static files under /static
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        static_dir: Some(static_dir.to_string_lossy().to_string()),
+        static_prefix: "/static".to_string(),
+        ..Default::default()
+    });
+
+    // Existing files are served by the adapter
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/static/logo.svg").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(200, response.status());
+    assert_eq!("image/svg+xml", response.headers().get("content-type").unwrap());
+    assert!(response.headers().contains_key("etag"));
+    assert_eq!("<svg></svg>", body_to_string(response).await);
+
+    // Other paths reach the app
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/static/api.json").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!("from the app", body_to_string(response).await);
+    api.assert();
+
+    std::fs::remove_dir_all(static_dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_http_spa_fallback() {
+    // Start app server that only knows the SPA document
+    let app_server = MockServer::start();
+    let index = app_server.mock(|when, then| {
+        when.method(GET).path("/index.html");
+        then.status(200)
+            .header("content-type", "text/html")
+            .body("<div id=\"app\"></div>");
+    });
+
+    // Initialize adapter with the SPA fallback
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        spa_fallback: Some("/index.html".to_string()),
+        ..Default::default()
+    });
+
+    // Browser navigations to client-side routes get the SPA document
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_path("/dashboard/1")
+            .with_header("accept", "text/html,application/xhtml+xml")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(200, response.status());
+    assert_eq!("<div id=\"app\"></div>", body_to_string(response).await);
+    index.assert();
+
+    // API calls keep their 404
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_path("/api/missing")
+            .with_header("accept", "application/json")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(404, response.status());
+    index.assert_hits(1);
+}
+
+#[tokio::test]
+async fn test_http_html_rewrite_prefix() {
+    // Start app server unaware of the stage prefix
+    let app_server = MockServer::start();
+    let page = app_server.mock(|when, then| {
+        when.method(GET).path("/");
+        then.status(200)
+            .header("content-type", "text/html")
+            .body(r#"<base href="/"><script src="/app.js"></script>"#);
+    });
+
+    // Initialize adapter rewriting links for the stage
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        html_rewrite_prefix: Some("/prod".to_string()),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+
+    let expected = r#"<base href="/prod/"><script src="/prod/app.js"></script>"#;
+    assert_eq!(
+        expected.len().to_string(),
+        response.headers().get("content-length").unwrap().to_str().unwrap()
+    );
+    assert_eq!(expected, body_to_string(response).await);
+    page.assert();
+}
+
+#[tokio::test]
+async fn test_http_max_response_bytes() {
+    // Start app server
+    let app_server = MockServer::start();
+    app_server.mock(|when, then| {
+        when.method(GET).path("/report");
+        then.status(200)
+            .header("content-type", "text/csv")
+            .body("a large report");
+    });
+
+    let options = |overflow: &str| AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        invoke_mode: LambdaInvokeMode::Buffered,
+        max_response_bytes: Some(7),
+        max_response_overflow: overflow.to_string(),
+        ..Default::default()
+    };
+    let request = || {
+        let mut request = Request::from(LambdaEventBuilder::new().with_path("/report").build());
+        add_lambda_context_to_request(&mut request);
+        request
+    };
+
+    // rejected with a body saying why
+    let mut adapter = Adapter::new(&options("reject"));
+    let response = adapter.call(request()).await.expect("Request failed");
+    assert_eq!(502, response.status());
+    assert!(body_to_string(response).await.contains("7 byte limit"));
+
+    // or cut at the limit
+    let mut adapter = Adapter::new(&options("truncate"));
+    let response = adapter.call(request()).await.expect("Request failed");
+    assert_eq!(200, response.status());
+    assert_eq!("true", response.headers()["x-lwa-response-truncated"]);
+    assert_eq!("a large", body_to_string(response).await);
+}
+
+#[tokio::test]
+async fn test_http_s3_offload_large_responses() {
+    // Start a fake S3 endpoint that records uploads
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let s3_port = listener.local_addr().unwrap().port();
+    let uploads = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = uploads.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let recorded = recorded.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req: http::Request<hyper::body::Incoming>| {
+                    let recorded = recorded.clone();
+                    async move {
+                        let signed = req.headers().contains_key("authorization");
+                        let content_type = req.headers().get("content-type").cloned();
+                        let path = req.uri().path().to_string();
+                        let body = req.into_body().collect().await.unwrap().to_bytes();
+                        recorded.lock().unwrap().push((path, signed, content_type, body));
+                        Ok::<_, Infallible>(Response::new(Full::new(Bytes::new())))
+                    }
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    env::set_var("AWS_ENDPOINT_URL_S3", format!("http://127.0.0.1:{s3_port}"));
+    env::set_var("AWS_REGION", "us-east-1");
+    env::set_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE");
+    env::set_var("AWS_SECRET_ACCESS_KEY", "secret");
+
+    // Start app server
+    let app_server = MockServer::start();
+    let download = app_server.mock(|when, then| {
+        when.method(GET).path("/download");
+        then.status(200)
+            .header("content-type", "text/csv")
+            .body("a large report");
+    });
+
+    // Initialize adapter offloading responses over 10 bytes
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        invoke_mode: LambdaInvokeMode::Buffered,
+        s3_offload_bucket: Some("reports".to_string()),
+        s3_offload_prefix: "large/".to_string(),
+        s3_offload_threshold: 10,
+        ..Default::default()
+    });
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/download").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    download.assert();
+
+    // The client is redirected to a presigned URL of the uploaded body
+    assert_eq!(303, response.status());
+    let location = response.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.starts_with(&format!("http://127.0.0.1:{s3_port}/reports/large/my_id?")));
+    assert!(location.contains("X-Amz-Signature="));
+
+    {
+        let uploads = uploads.lock().unwrap();
+        assert_eq!(1, uploads.len());
+        let (path, signed, content_type, body) = &uploads[0];
+        assert_eq!("/reports/large/my_id", path);
+        assert!(signed);
+        assert_eq!(Some(http::HeaderValue::from_static("text/csv")), *content_type);
+        assert_eq!("a large report", body);
+    }
+
+    // Downloads can be redirected without changing the method
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        invoke_mode: LambdaInvokeMode::Buffered,
+        s3_offload_bucket: Some("reports".to_string()),
+        s3_offload_threshold: 10,
+        s3_offload_redirect: 307,
+        ..Default::default()
+    });
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/download").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(307, response.status());
+}
+
+#[tokio::test]
+async fn test_http_s3_uploads_for_large_requests() {
+    // Start a fake S3 endpoint that serves an uploaded body
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let s3_port = listener.local_addr().unwrap().port();
+    let downloads = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = downloads.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let recorded = recorded.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req: http::Request<hyper::body::Incoming>| {
+                    let recorded = recorded.clone();
+                    async move {
+                        let signed = req.headers().contains_key("authorization");
+                        recorded.lock().unwrap().push((req.uri().path().to_string(), signed));
+                        Ok::<_, Infallible>(Response::new(Full::new(Bytes::from("a large upload"))))
+                    }
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    env::set_var("AWS_ENDPOINT_URL_S3", format!("http://127.0.0.1:{s3_port}"));
+    env::set_var("AWS_REGION", "us-east-1");
+    env::set_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE");
+    env::set_var("AWS_SECRET_ACCESS_KEY", "secret");
+
+    // Start app server
+    let app_server = MockServer::start();
+    let upload = app_server.mock(|when, then| {
+        when.method(POST).path("/upload").body("a large upload");
+        then.status(200).body("stored");
+    });
+
+    // Initialize adapter rejecting requests over 10 bytes
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        s3_upload_bucket: Some("uploads".to_string()),
+        s3_upload_threshold: 10,
+        ..Default::default()
+    });
+
+    // A large request is answered with a presigned upload URL
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_method(Method::POST)
+            .with_path("/upload")
+            .with_header("content-length", "1048576")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(413, response.status());
+    let body: serde_json::Value = serde_json::from_str(&body_to_string(response).await).unwrap();
+    assert_eq!("uploads/my_id", body["upload_key"]);
+    assert_eq!("x-lwa-upload-key", body["upload_header"]);
+    let upload_url = body["upload_url"].as_str().unwrap();
+    assert!(upload_url.starts_with(&format!("http://127.0.0.1:{s3_port}/uploads/uploads/my_id?")));
+    assert!(upload_url.contains("X-Amz-Signature="));
+
+    // The request is sent again with the key of the uploaded body
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_method(Method::POST)
+            .with_path("/upload")
+            .with_header("x-lwa-upload-key", "uploads/my_id")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    upload.assert();
+    assert_eq!(200, response.status());
+    assert_eq!("stored", body_to_string(response).await);
+    assert_eq!(
+        vec![("/uploads/uploads/my_id".to_string(), true)],
+        *downloads.lock().unwrap()
+    );
+
+    // Keys outside of the upload prefix are refused
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_method(Method::POST)
+            .with_path("/upload")
+            .with_header("x-lwa-upload-key", "private/secrets")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(400, response.status());
+}
+
+#[tokio::test]
+async fn test_http_async_requests_deliver_to_sqs() {
+    // Start a fake AWS endpoint that records Lambda invokes and SQS messages
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let aws_port = listener.local_addr().unwrap().port();
+    let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = calls.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let recorded = recorded.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req: http::Request<hyper::body::Incoming>| {
+                    let recorded = recorded.clone();
+                    async move {
+                        let uri = req.uri().to_string();
+                        let target = req.headers().get("x-amz-target").cloned();
+                        let invocation_type = req.headers().get("x-amz-invocation-type").cloned();
+                        let body = req.into_body().collect().await.unwrap().to_bytes();
+                        recorded.lock().unwrap().push((uri, target, invocation_type, body));
+                        Ok::<_, Infallible>(Response::new(Full::new(Bytes::from("{}"))))
+                    }
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    env::set_var("AWS_ENDPOINT_URL", format!("http://127.0.0.1:{aws_port}"));
+    env::set_var("AWS_REGION", "us-east-1");
+    env::set_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE");
+    env::set_var("AWS_SECRET_ACCESS_KEY", "secret");
+    env::set_var("AWS_LAMBDA_FUNCTION_NAME", "my-function");
+    env::set_var("AWS_LAMBDA_FUNCTION_VERSION", "$LATEST");
+
+    // Start app server
+    let app_server = MockServer::start();
+    let job = app_server.mock(|when, then| {
+        when.method(POST).path("/jobs").query_param("size", "large");
+        then.status(201).body("job done");
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        async_callback: Some("arn:aws:sqs:us-east-1:123456789012:responses".to_string()),
+        ..Default::default()
+    });
+
+    // The request is accepted and handed over to an async invocation
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_method(Method::POST)
+            .with_path("/jobs")
+            .with_query("size", "large")
+            .with_header("prefer", "respond-async")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(202, response.status());
+    assert_eq!(
+        json!({"tracking_id": "my_id"}).to_string(),
+        body_to_string(response).await
+    );
+    job.assert_hits(0);
+
+    let (uri, _, invocation_type, envelope) = calls.lock().unwrap().remove(0);
+    assert_eq!("/2015-03-31/functions/my-function/invocations?Qualifier=%24LATEST", uri);
+    assert_eq!(Some(http::HeaderValue::from_static("Event")), invocation_type);
+
+    // The async invocation calls the app and sends the response to the queue
+    let mut request = Request::from(lambda_http::request::LambdaRequest::PassThrough(
+        String::from_utf8(envelope.to_vec()).unwrap(),
+    ));
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    job.assert();
+    assert_eq!(201, response.status());
+
+    let (uri, target, _, message) = calls.lock().unwrap().remove(0);
+    assert_eq!("/", uri);
+    assert_eq!(Some(http::HeaderValue::from_static("AmazonSQS.SendMessage")), target);
+    let message: serde_json::Value = serde_json::from_slice(&message).unwrap();
+    assert_eq!(
+        format!("http://127.0.0.1:{aws_port}/123456789012/responses"),
+        message["QueueUrl"]
+    );
+    let delivered: serde_json::Value = serde_json::from_str(message["MessageBody"].as_str().unwrap()).unwrap();
+    assert_eq!("my_id", delivered["tracking_id"]);
+    assert_eq!(201, delivered["status"]);
+    assert_eq!("job done", delivered["body"]);
+    assert_eq!(false, delivered["is_base64_encoded"]);
+}
+
+/// Send one request to a local endpoint over a fresh HTTP/1.1 connection.
+async fn send_local(port: u16, request: Request<Full<Bytes>>) -> Response<Bytes> {
+    let stream = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .unwrap();
+    tokio::spawn(connection);
+    let (parts, body) = sender.send_request(request).await.unwrap().into_parts();
+    Response::from_parts(parts, body.collect().await.unwrap().to_bytes())
+}
+
+#[tokio::test]
+async fn test_http_runtime_api_for_non_http_events() {
+    // Reserve a port for the runtime API endpoint
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let runtime_api_port = listener.local_addr().unwrap().port();
+    drop(listener);
+
+    // Initialize adapter exposing the runtime API
+    let app_server = MockServer::start();
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        runtime_api_port: Some(runtime_api_port),
+        ..Default::default()
+    });
+
+    // The app's runtime answers the first event and fails the second one
+    let runtime = tokio::spawn(async move {
+        for outcome in ["response", "error"] {
+            let next = Request::get("/2018-06-01/runtime/invocation/next")
+                .header("host", "localhost")
+                .body(Full::new(Bytes::new()))
+                .unwrap();
+            let event = send_local(runtime_api_port, next).await;
+            assert_eq!(200, event.status());
+            let request_id = event.headers()["lambda-runtime-aws-request-id"]
+                .to_str()
+                .unwrap()
+                .to_string();
+            assert_eq!("my_id", request_id);
+            let payload: serde_json::Value = serde_json::from_slice(event.body()).unwrap();
+
+            let body = match outcome {
+                "response" => json!({"handled": payload["detail-type"]}),
+                _ => json!({"errorType": "ValueError", "errorMessage": "unknown event"}),
+            };
+            let result = Request::post(format!("/2018-06-01/runtime/invocation/{request_id}/{outcome}"))
+                .header("host", "localhost")
+                .body(Full::new(Bytes::from(body.to_string())))
+                .unwrap();
+            assert_eq!(202, send_local(runtime_api_port, result).await.status());
+        }
+    });
+
+    let event = || {
+        let mut request = Request::from(lambda_http::request::LambdaRequest::PassThrough(
+            json!({"detail-type": "OrderPlaced", "detail": {}}).to_string(),
+        ));
+        add_lambda_context_to_request(&mut request);
+        request
+    };
+    let response = adapter.call(event()).await.expect("Request failed");
+    assert_eq!(
+        json!({"handled": "OrderPlaced"}).to_string(),
+        body_to_string(response).await
+    );
+
+    let err = adapter.call(event()).await.unwrap_err();
+    assert_eq!("ValueError: unknown event", err.to_string());
+    runtime.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_http_prometheus_metrics() {
+    // Reserve a port for the metrics endpoint
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let metrics_port = listener.local_addr().unwrap().port();
+    drop(listener);
+
+    // Start app server
+    let app_server = MockServer::start();
+    app_server.mock(|when, then| {
+        when.method(GET).path("/hello");
+        then.status(200).body("Hello World");
+    });
+
+    // Initialize adapter with the metrics endpoint
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        invoke_mode: LambdaInvokeMode::Buffered,
+        metrics_port: Some(metrics_port),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!("Hello World", body_to_string(response).await);
+
+    // Scrape the metrics
+    let scrape = Request::get("/metrics")
+        .header("host", "localhost")
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+    let metrics = send_local(metrics_port, scrape).await;
+    assert_eq!(200, metrics.status());
+    let metrics = String::from_utf8(metrics.body().to_vec()).unwrap();
+    for line in [
+        "lwa_requests_total 1\n",
+        "lwa_upstream_requests_total{status=\"200\"} 1\n",
+        "lwa_upstream_latency_seconds_count 1\n",
+        "lwa_in_flight_requests 0\n",
+        "lwa_response_bytes_total 11\n",
+    ] {
+        assert!(metrics.contains(line), "{line:?} missing from {metrics}");
+    }
+}
+
+#[tokio::test]
+async fn test_http_publish_responses_to_eventbridge() {
+    // Start a fake EventBridge endpoint that records events
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let events_port = listener.local_addr().unwrap().port();
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let recorded = recorded.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req: http::Request<hyper::body::Incoming>| {
+                    let recorded = recorded.clone();
+                    async move {
+                        let body = req.into_body().collect().await.unwrap().to_bytes();
+                        recorded
+                            .lock()
+                            .unwrap()
+                            .push(serde_json::from_slice::<serde_json::Value>(&body).unwrap());
+                        Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(r#"{"FailedEntryCount":0}"#))))
+                    }
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    env::set_var("AWS_ENDPOINT_URL_EVENTS", format!("http://127.0.0.1:{events_port}"));
+    env::set_var("AWS_REGION", "us-east-1");
+    env::set_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE");
+    env::set_var("AWS_SECRET_ACCESS_KEY", "secret");
+
+    // Start app server
+    let app_server = MockServer::start();
+    let create_order = app_server.mock(|when, then| {
+        when.method(POST).path("/orders");
+        then.status(201)
+            .header("x-event-detail", r#"{"order_id":42}"#)
+            .body("created");
+    });
+    let list_orders = app_server.mock(|when, then| {
+        when.method(GET).path("/orders");
+        then.status(200).body("[]");
+    });
+
+    // Only created orders are published
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        eventbridge_bus: Some("orders".to_string()),
+        eventbridge_responses: vec![ErrorStatusRule {
+            method: Some(Method::POST),
+            path: "/orders".to_string(),
+            codes: vec![201],
+        }],
+        eventbridge_detail_header: Some("x-event-detail".to_string()),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_method(Method::POST)
+            .with_path("/orders")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    create_order.assert();
+    assert_eq!(201, response.status());
+    assert!(!response.headers().contains_key("x-event-detail"));
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/orders").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    list_orders.assert();
+    assert_eq!(200, response.status());
+
+    let events = events.lock().unwrap();
+    assert_eq!(1, events.len());
+    let entry = &events[0]["Entries"][0];
+    assert_eq!("orders", entry["EventBusName"]);
+    assert_eq!("lambda-web-adapter", entry["Source"]);
+    assert_eq!("HTTP Response", entry["DetailType"]);
+    let detail: serde_json::Value = serde_json::from_str(entry["Detail"].as_str().unwrap()).unwrap();
+    assert_eq!(
+        json!({
+            "method": "POST",
+            "path": "/orders",
+            "route": null,
+            "status": 201,
+            "request_id": "my_id",
+            "custom": {"order_id": 42},
+        }),
+        detail
+    );
+}
+
+#[tokio::test]
+async fn test_http_audit_log_to_firehose() {
+    // Start a fake Firehose endpoint that records batches
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let firehose_port = listener.local_addr().unwrap().port();
+    let batches = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = batches.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let recorded = recorded.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req: http::Request<hyper::body::Incoming>| {
+                    let recorded = recorded.clone();
+                    async move {
+                        let target = req.headers().get("x-amz-target").cloned();
+                        let body = req.into_body().collect().await.unwrap().to_bytes();
+                        let batch: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                        recorded.lock().unwrap().push((target, batch));
+                        Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(r#"{"FailedPutCount":0}"#))))
+                    }
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    env::set_var("AWS_ENDPOINT_URL_FIREHOSE", format!("http://127.0.0.1:{firehose_port}"));
+    env::set_var("AWS_REGION", "us-east-1");
+    env::set_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE");
+    env::set_var("AWS_SECRET_ACCESS_KEY", "secret");
+
+    // Start app server
+    let app_server = MockServer::start();
+    let hello = app_server.mock(|when, then| {
+        when.method(GET).path("/hello");
+        then.status(200).body("Hello World");
+    });
+
+    // Records are sent in batches of two
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        audit_log_stream: Some("access-logs".to_string()),
+        audit_log_batch_size: 2,
+        audit_log_flush_interval: Duration::from_secs(3600),
+        ..Default::default()
+    });
+
+    for _ in 0..2 {
+        let mut request = Request::from(
+            LambdaEventBuilder::new()
+                .with_path("/hello")
+                .with_header("user-agent", "integ-test")
+                .build(),
+        );
+        add_lambda_context_to_request(&mut request);
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!(200, response.status());
+    }
+    hello.assert_hits(2);
+
+    let started = Instant::now();
+    while batches.lock().unwrap().is_empty() && started.elapsed() < Duration::from_secs(5) {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let batches = batches.lock().unwrap();
+    assert_eq!(1, batches.len());
+    let (target, batch) = &batches[0];
+    assert_eq!(
+        Some(http::HeaderValue::from_static("Firehose_20150804.PutRecordBatch")),
+        *target
+    );
+    assert_eq!("access-logs", batch["DeliveryStreamName"]);
+    let records = batch["Records"].as_array().unwrap();
+    assert_eq!(2, records.len());
+
+    use base64::Engine;
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(records[0]["Data"].as_str().unwrap())
+        .unwrap();
+    assert!(data.ends_with(b"\n"));
+    let record: serde_json::Value = serde_json::from_slice(&data).unwrap();
+    assert_eq!("my_id", record["request_id"]);
+    assert_eq!("GET", record["method"]);
+    assert_eq!("/hello", record["path"]);
+    assert_eq!("integ-test", record["user_agent"]);
+    assert_eq!(200, record["status"]);
+}
+
+#[tokio::test]
+async fn test_http_openapi_validation() {
+    let spec = env::temp_dir().join(format!("lwa-openapi-{}.yaml", std::process::id()));
+    std::fs::write(
+        &spec,
+        "openapi: 3.0.3\npaths:\n  /pets:\n    get:\n      parameters:\n      - name: limit\n        in: query\n        required: true\n",
+    )
+    .unwrap();
+
+    // Start app server
+    let app_server = MockServer::start();
+    let list_pets = app_server.mock(|when, then| {
+        when.method(GET).path("/pets").query_param("limit", "10");
+        then.status(200).body("[]");
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        openapi_spec: Some(spec.to_string_lossy().to_string()),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_path("/pets")
+            .with_query("limit", "10")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    list_pets.assert();
+    assert_eq!(200, response.status());
+
+    // Requests the spec does not declare never reach the app
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/pets").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(400, response.status());
+    assert_eq!("missing required query parameter limit", body_to_string(response).await);
+
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_method(Method::DELETE)
+            .with_path("/pets")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(405, response.status());
+    assert_eq!("GET", response.headers().get("allow").unwrap());
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/owners").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(404, response.status());
+
+    list_pets.assert_hits(1);
+    std::fs::remove_file(spec).unwrap();
+}
+
+#[tokio::test]
+async fn test_http_graphql_persisted_queries() {
+    use sha2::{Digest, Sha256};
+
+    let query = "{ hello }";
+    let hash: String = Sha256::digest(query.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    let extensions = json!({ "persistedQuery": { "version": 1, "sha256Hash": hash } });
+
+    // Start app server
+    let app_server = MockServer::start();
+    let graphql = app_server.mock(|when, then| {
+        when.method(POST)
+            .path("/graphql")
+            .json_body_partial(json!({ "query": query }).to_string());
+        then.status(200).body(r#"{"data":{"hello":"world"}}"#);
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        graphql_apq_path: Some("/graphql".to_string()),
+        ..Default::default()
+    });
+    let graphql_request = |body: serde_json::Value| {
+        let mut request = Request::from(
+            LambdaEventBuilder::new()
+                .with_method(Method::POST)
+                .with_path("/graphql")
+                .with_header("content-type", "application/json")
+                .with_body(&body.to_string())
+                .build(),
+        );
+        add_lambda_context_to_request(&mut request);
+        request
+    };
+
+    // An unknown hash asks the client for the full query
+    let response = adapter
+        .call(graphql_request(json!({ "extensions": extensions })))
+        .await
+        .expect("Request failed");
+    assert_eq!(200, response.status());
+    assert!(body_to_string(response).await.contains("PERSISTED_QUERY_NOT_FOUND"));
+    graphql.assert_hits(0);
+
+    // The full query is forwarded and remembered
+    let response = adapter
+        .call(graphql_request(json!({ "query": query, "extensions": extensions })))
+        .await
+        .expect("Request failed");
+    assert_eq!(200, response.status());
+    graphql.assert_hits(1);
+
+    // Later requests with only the hash are expanded
+    let response = adapter
+        .call(graphql_request(json!({ "extensions": extensions })))
+        .await
+        .expect("Request failed");
+    assert_eq!(r#"{"data":{"hello":"world"}}"#, body_to_string(response).await);
+    graphql.assert_hits(2);
+}
+
+#[tokio::test]
+async fn test_http_normalize_requests() {
+    // Start app server
+    let app_server = MockServer::start();
+    let items = app_server.mock(|when, then| {
+        when.method(GET)
+            .path("/items")
+            .query_param("q", "hello world")
+            .query_param("page", "2")
+            .header("cookie", "theme=dark; session=abc")
+            .header("x-forwarded-proto", "https");
+        then.status(200).body("items");
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        normalize_requests: true,
+        ..Default::default()
+    });
+
+    // an HTTP API request to the named stage "prod"
+    let http_api = json!({
+        "version": "2.0",
+        "rawPath": "/prod/items",
+        "rawQueryString": "q=hello%20world&page=2",
+        "cookies": ["theme=dark", "session=abc"],
+        "headers": { "host": "api.example.com" },
+        "requestContext": {
+            "domainName": "api.example.com",
+            "http": { "method": "GET", "path": "/prod/items", "protocol": "HTTP/1.1", "sourceIp": "203.0.113.7" },
+            "stage": "prod"
+        },
+        "isBase64Encoded": false
+    });
+    // the same request through an Application Load Balancer
+    let alb = json!({
+        "httpMethod": "GET",
+        "path": "/items",
+        "queryStringParameters": { "page": "2", "q": "hello%20world" },
+        "headers": { "host": "api.example.com", "cookie": "theme=dark;session=abc" },
+        "requestContext": { "elb": { "targetGroupArn": "arn:aws:elasticloadbalancing:us-east-1:123456789012:targetgroup/app/1" } },
+        "body": "",
+        "isBase64Encoded": true
+    });
+
+    for event in [http_api, alb] {
+        let mut request = lambda_http::request::from_str(&event.to_string()).expect("invalid event");
+        add_lambda_context_to_request(&mut request);
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!(200, response.status());
+        assert_eq!("items", body_to_string(response).await);
+    }
+    items.assert_hits(2);
+}
+
+#[tokio::test]
+async fn test_http_auto_base_path() {
+    // Start app server unaware of the stage and the custom domain mapping
+    let app_server = MockServer::start();
+    let orders = app_server.mock(|when, then| {
+        when.method(GET).path("/orders/1");
+        then.status(200).body("order");
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        base_path: Some("auto".to_string()),
+        ..Default::default()
+    });
+
+    // an HTTP API request to the named stage "prod"
+    let http_api = json!({
+        "version": "2.0",
+        "routeKey": "ANY /{proxy+}",
+        "rawPath": "/prod/orders/1",
+        "rawQueryString": "",
+        "headers": { "host": "api.example.com" },
+        "pathParameters": { "proxy": "orders/1" },
+        "requestContext": {
+            "domainName": "api.example.com",
+            "http": { "method": "GET", "path": "/prod/orders/1", "protocol": "HTTP/1.1", "sourceIp": "203.0.113.7" },
+            "routeKey": "ANY /{proxy+}",
+            "stage": "prod"
+        },
+        "isBase64Encoded": false
+    });
+    // a REST API request through the custom domain mapping "shop"
+    let rest_api = json!({
+        "resource": "/orders/{id}",
+        "path": "/shop/orders/1",
+        "httpMethod": "GET",
+        "headers": { "Host": "shop.example.com" },
+        "pathParameters": { "id": "1" },
+        "requestContext": {
+            "resourcePath": "/orders/{id}",
+            "httpMethod": "GET",
+            "path": "/shop/orders/1",
+            "stage": "prod"
+        },
+        "isBase64Encoded": false
+    });
+
+    for event in [http_api, rest_api] {
+        let mut request = lambda_http::request::from_str(&event.to_string()).expect("invalid event");
+        add_lambda_context_to_request(&mut request);
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!(200, response.status());
+        assert_eq!("order", body_to_string(response).await);
+    }
+    orders.assert_hits(2);
+}
+
+#[tokio::test]
+async fn test_http_add_base_path() {
+    // Start app server mounted under a context path
+    let app_server = MockServer::start();
+    let orders = app_server.mock(|when, then| {
+        when.method(GET).path("/internal/orders").query_param("page", "2");
+        then.status(200).body("orders");
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        base_path: Some("/prod".to_string()),
+        add_base_path: Some("/internal/".to_string()),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_path("/prod/orders")
+            .with_query("page", "2")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(200, response.status());
+    assert_eq!("orders", body_to_string(response).await);
+    orders.assert();
+}
+
+#[tokio::test]
+async fn test_http_adapter_health_path() {
+    // Start app server
+    let app_server = MockServer::start();
+    let healthz = app_server.mock(|when, then| {
+        when.path("/healthz");
+        then.status(500);
+    });
+
+    let options = |async_init| AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        adapter_health_path: Some("/healthz".to_string()),
+        async_init,
+        ..Default::default()
+    };
+    let health_request = || {
+        let mut request = Request::from(LambdaEventBuilder::new().with_path("/healthz").build());
+        add_lambda_context_to_request(&mut request);
+        request
+    };
+
+    // The app is not asked while it is still initializing in the background
+    let mut adapter = Adapter::new(&options(true));
+    let response = adapter.call(health_request()).await.expect("Request failed");
+    assert_eq!(503, response.status());
+    assert_eq!(r#"{"status":"initializing"}"#, body_to_string(response).await);
+
+    let mut adapter = Adapter::new(&options(false));
+    let response = adapter.call(health_request()).await.expect("Request failed");
+    assert_eq!(200, response.status());
+    assert_eq!(r#"{"status":"ready"}"#, body_to_string(response).await);
+    healthz.assert_hits(0);
+}
+
+#[tokio::test]
+async fn test_http_plugins() {
+    struct Policy;
+
+    impl LwaPlugin for Policy {
+        fn on_request(&self, request: &mut Request<Body>) -> Option<Response<ResponseBody>> {
+            if request.uri().path() == "/admin" {
+                return Response::builder().status(403).body(full_body("forbidden")).ok();
+            }
+            request
+                .headers_mut()
+                .insert("x-policy", http::HeaderValue::from_static("checked"));
+            None
+        }
+
+        fn on_response(&self, response: &mut Response<ResponseBody>) {
+            response
+                .headers_mut()
+                .insert("x-policy-version", http::HeaderValue::from_static("1"));
+        }
+
+        fn on_error(&self, _error: &lambda_web_adapter::Error) -> Option<Response<ResponseBody>> {
+            Response::builder().status(503).body(full_body("try again")).ok()
+        }
+    }
+
+    // Start app server
+    let app_server = MockServer::start();
+    let hello = app_server.mock(|when, then| {
+        when.method(GET).path("/hello").header("x-policy", "checked");
+        then.status(200).body("Hello World");
+    });
+    let admin = app_server.mock(|when, then| {
+        when.path("/admin");
+        then.status(200).body("secrets");
+    });
+
+    let options = |port: String| AdapterOptions {
+        host: app_server.host(),
+        port,
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        ..Default::default()
+    };
+    let request = |path| {
+        let mut request = Request::from(LambdaEventBuilder::new().with_path(path).build());
+        add_lambda_context_to_request(&mut request);
+        request
+    };
+
+    let mut adapter = Adapter::new(&options(app_server.port().to_string()));
+    adapter.register_plugin(Policy);
+
+    let response = adapter.call(request("/hello")).await.expect("Request failed");
+    assert_eq!(200, response.status());
+    assert_eq!("1", response.headers()["x-policy-version"]);
+    assert_eq!("Hello World", body_to_string(response).await);
+    hello.assert_hits(1);
+
+    // Requests answered by a plugin never reach the app
+    let response = adapter.call(request("/admin")).await.expect("Request failed");
+    assert_eq!(403, response.status());
+    assert_eq!("1", response.headers()["x-policy-version"]);
+    admin.assert_hits(0);
+
+    // Errors are turned into responses when a plugin returns one
+    let mut adapter = Adapter::new(&options("1".to_string()));
+    adapter.register_plugin(Policy);
+    let response = adapter.call(request("/hello")).await.expect("Request failed");
+    assert_eq!(503, response.status());
+    assert_eq!("try again", body_to_string(response).await);
+}
+
+#[tokio::test]
+async fn test_http_accept_rules() {
+    // Start app server
+    let app_server = MockServer::start();
+    let orders = app_server.mock(|when, then| {
+        when.method(GET)
+            .path("/api/orders")
+            .header("accept", "application/json");
+        then.status(200).body("[]");
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        accept_rules: Some(r#"{"/api/*":"application/json"}"#.to_string()),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_path("/api/orders")
+            .with_header("accept", "text/html,application/xhtml+xml,*/*;q=0.8")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(200, response.status());
+    orders.assert();
+}
+
+#[tokio::test]
+async fn test_http_request_compression() {
+    // Start app server
+    let app_server = MockServer::start();
+    let upload = app_server.mock(|when, then| {
+        when.method(POST)
+            .path("/upload")
+            .header("content-encoding", "gzip")
+            .matches(|request| {
+                let mut decoded = String::new();
+                GzDecoder::new(request.body.as_deref().unwrap_or_default())
+                    .read_to_string(&mut decoded)
+                    .is_ok_and(|_| decoded.starts_with(r#"{"items":"#))
+            });
+        then.status(201);
+    });
+    let small = app_server.mock(|when, then| {
+        when.method(POST).path("/small").matches(|request| {
+            let headers = request.headers.as_deref().unwrap_or_default();
+            !headers
+                .iter()
+                .any(|(name, _)| name.eq_ignore_ascii_case("content-encoding"))
+        });
+        then.status(201);
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        request_compression: RequestCompression::Gzip,
+        request_compression_threshold: 64,
+        ..Default::default()
+    });
+    let post = |path, body: &str| {
+        let mut request = Request::from(
+            LambdaEventBuilder::new()
+                .with_method(Method::POST)
+                .with_path(path)
+                .with_header("content-type", "application/json")
+                .with_body(body)
+                .build(),
+        );
+        add_lambda_context_to_request(&mut request);
+        request
+    };
+
+    let items = json!({ "items": vec!["item"; 100] }).to_string();
+    let response = adapter.call(post("/upload", &items)).await.expect("Request failed");
+    assert_eq!(201, response.status());
+    upload.assert();
+
+    let response = adapter.call(post("/small", "{}")).await.expect("Request failed");
+    assert_eq!(201, response.status());
+    small.assert();
+}
+
+#[tokio::test]
+async fn test_http_websocket_upgrade() {
+    // Start app server
+    let app_server = MockServer::start();
+    let socket = app_server.mock(|when, then| {
+        when.path("/socket");
+        then.status(200);
+    });
+
+    let options = |upgrade_status| AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        upgrade_status,
+        ..Default::default()
+    };
+    let upgrade_request = || {
+        let mut request = Request::from(
+            LambdaEventBuilder::new()
+                .with_path("/socket")
+                .with_header("connection", "Upgrade")
+                .with_header("upgrade", "websocket")
+                .build(),
+        );
+        add_lambda_context_to_request(&mut request);
+        request
+    };
+
+    let mut adapter = Adapter::new(&options(501));
+    let response = adapter.call(upgrade_request()).await.expect("Request failed");
+    assert_eq!(501, response.status());
+    assert!(body_to_string(response).await.contains("API Gateway WebSocket API"));
+
+    let mut adapter = Adapter::new(&options(426));
+    let response = adapter.call(upgrade_request()).await.expect("Request failed");
+    assert_eq!(426, response.status());
+    assert_eq!("HTTP/1.1", response.headers()["upgrade"]);
+    socket.assert_hits(0);
+}
+
+#[tokio::test]
+async fn test_http_synthesize_methods() {
+    let spec = env::temp_dir().join(format!("lwa-openapi-methods-{}.yaml", std::process::id()));
+    std::fs::write(&spec, "openapi: 3.0.3\npaths:\n  /pets:\n    get: {}\n    post: {}\n").unwrap();
+
+    // Start app server
+    let app_server = MockServer::start();
+    let list_pets = app_server.mock(|when, then| {
+        when.method(GET).path("/pets");
+        then.status(200).header("content-type", "application/json").body("[]");
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        openapi_spec: Some(spec.to_string_lossy().to_string()),
+        synthesize_methods: true,
+        ..Default::default()
+    });
+
+    // HEAD is sent to the app as GET, without the body in the response
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_method(Method::HEAD)
+            .with_path("/pets")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    list_pets.assert();
+    assert_eq!(200, response.status());
+    assert_eq!("application/json", response.headers()["content-type"]);
+    assert_eq!("", body_to_string(response).await);
+
+    // OPTIONS is answered from the spec
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_method(Method::OPTIONS)
+            .with_path("/pets")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(204, response.status());
+    assert_eq!("GET, POST, HEAD, OPTIONS", response.headers()["allow"]);
+
+    list_pets.assert_hits(1);
+    std::fs::remove_file(spec).unwrap();
+}
+
+#[tokio::test]
+async fn test_http_range_requests() {
+    // Start app server
+    let app_server = MockServer::start();
+    let partial = app_server.mock(|when, then| {
+        when.path("/partial").header("range", "bytes=0-3");
+        then.status(206).header("content-range", "bytes 0-3/10").body("0123");
+    });
+    let full = app_server.mock(|when, then| {
+        when.path("/full").header("range", "bytes=4-");
+        then.status(200).header("etag", "\"v1\"").body("0123456789");
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        range_slicing: true,
+        ..Default::default()
+    });
+
+    // partial responses of the app are returned as they are
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_path("/partial")
+            .with_header("range", "bytes=0-3")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    partial.assert();
+    assert_eq!(206, response.status());
+    assert_eq!("bytes 0-3/10", response.headers()["content-range"]);
+    assert_eq!("0123", body_to_string(response).await);
+
+    // full responses are sliced
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_path("/full")
+            .with_header("range", "bytes=4-")
+            .with_header("if-range", "\"v1\"")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    full.assert();
+    assert_eq!(206, response.status());
+    assert_eq!("bytes 4-9/10", response.headers()["content-range"]);
+    assert_eq!("456789", body_to_string(response).await);
+}
+
+#[tokio::test]
+async fn test_http_deadline_header() {
+    // Start app server
+    let app_server = MockServer::start();
+    let with_deadline = app_server.mock(|when, then| {
+        when.path("/deadline").matches(|request| {
+            request.headers.as_ref().unwrap().iter().any(|(key, value)| {
+                key == "x-lwa-deadline-ms" && value.parse::<u64>().is_ok_and(|ms| ms > 0 && ms <= 30_000)
+            })
+        });
+        then.status(200);
+    });
+    let without_deadline = app_server.mock(|when, then| {
+        when.path("/unknown").matches(|request| {
+            !request
+                .headers
+                .as_ref()
+                .unwrap()
+                .iter()
+                .any(|(key, _)| key == "x-lwa-deadline-ms")
+        });
+        then.status(200);
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        deadline_header: Some("x-lwa-deadline-ms".to_string()),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/deadline").build());
+    add_lambda_context_to_request(&mut request);
+    let deadline = std::time::SystemTime::now() + Duration::from_secs(30);
+    request.extensions_mut().get_mut::<Context>().unwrap().deadline =
+        deadline.duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as u64;
+    let response = adapter.call(request).await.expect("Request failed");
+    with_deadline.assert();
+    assert_eq!(200, response.status());
+
+    // the header is dropped when the deadline is unknown, even if the client sent it
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_path("/unknown")
+            .with_header("x-lwa-deadline-ms", "999999")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    without_deadline.assert();
+    assert_eq!(200, response.status());
+}
+
+#[tokio::test]
+async fn test_http_duplicate_invocation_replays_response() {
+    // Start app server
+    let app_server = MockServer::start();
+    let orders = app_server.mock(|when, then| {
+        when.method(POST).path("/orders");
+        then.status(201).body("order created");
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        duplicate_invocation_ttl: Some(Duration::from_secs(60)),
+        ..Default::default()
+    });
+    let order_request = |request_id: &str| {
+        let mut request = Request::from(
+            LambdaEventBuilder::new()
+                .with_method(Method::POST)
+                .with_path("/orders")
+                .build(),
+        );
+        add_lambda_context_to_request(&mut request);
+        request.extensions_mut().get_mut::<Context>().unwrap().request_id = request_id.to_string();
+        request
+    };
+
+    // A retry of the same invocation is replayed
+    for replayed in [false, true] {
+        let response = adapter
+            .call(order_request("invocation-1"))
+            .await
+            .expect("Request failed");
+        assert_eq!(201, response.status());
+        assert_eq!(replayed, response.headers().contains_key("idempotent-replayed"));
+        assert_eq!("order created", body_to_string(response).await);
+    }
+    orders.assert_hits(1);
+
+    // A new invocation reaches the app
+    adapter
+        .call(order_request("invocation-2"))
+        .await
+        .expect("Request failed");
+    orders.assert_hits(2);
+}
+
+#[tokio::test]
+async fn test_http_maintenance_mode() {
+    let flag = env::temp_dir().join(format!("lwa-maintenance-{}", std::process::id()));
+    std::fs::write(&flag, "").unwrap();
+
+    // Start app server
+    let app_server = MockServer::start();
+    let orders = app_server.mock(|when, then| {
+        when.path("/orders");
+        then.status(200).body("orders");
+    });
+    let health = app_server.mock(|when, then| {
+        when.path("/health");
+        then.status(200).body("OK");
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        maintenance_mode: Some(flag.to_string_lossy().to_string()),
+        maintenance_allowlist: Some("/health".to_string()),
+        maintenance_response: Some(r#"{"message":"back soon"}"#.to_string()),
+        ..Default::default()
+    });
+
+    // While the flag file exists, only allowlisted paths reach the app
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/orders").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(503, response.status());
+    assert_eq!("application/json", response.headers()["content-type"]);
+    assert_eq!(r#"{"message":"back soon"}"#, body_to_string(response).await);
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/health").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    health.assert();
+    assert_eq!(200, response.status());
+    orders.assert_hits(0);
+
+    // Removing the flag file ends maintenance
+    std::fs::remove_file(&flag).unwrap();
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/orders").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    orders.assert();
+    assert_eq!(200, response.status());
+}
+
+#[tokio::test]
+async fn test_http_accounting_headers() {
+    // Start app server
+    let app_server = MockServer::start();
+    let echo = app_server.mock(|when, then| {
+        when.method(POST).path("/echo");
+        then.status(200).body("0123456789");
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        accounting_headers: true,
+        ..Default::default()
+    });
+
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_method(Method::POST)
+            .with_path("/echo")
+            .with_body("hello")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    echo.assert();
+    assert_eq!(200, response.status());
+    assert_eq!("5", response.headers()["x-lwa-request-bytes"]);
+    assert_eq!("10", response.headers()["x-lwa-response-bytes"]);
+    let upstream_ms: u64 = response.headers()["x-lwa-upstream-ms"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(upstream_ms < 10_000);
+}
+
+#[tokio::test]
+async fn test_http2_auto_negotiation() {
+    // Start an app server that speaks HTTP/1.1 and h2c, and counts HTTP/2 requests
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let http2_requests = Arc::new(AtomicUsize::new(0));
+    let counted = http2_requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let counted = counted.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req: Request<hyper::body::Incoming>| {
+                    if req.version() == http::Version::HTTP_2 {
+                        counted.fetch_add(1, Ordering::SeqCst);
+                    }
+                    async { Ok::<_, Infallible>(Response::new(Full::new(Bytes::from("OK")))) }
+                });
+                let mut preface = [0; 4];
+                let _ = stream.peek(&mut preface).await;
+                if &preface == b"PRI " {
+                    let _ = hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                } else {
+                    let _ = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                }
+            });
+        }
+    });
+
+    let options = |port: String| AdapterOptions {
+        host: "127.0.0.1".to_string(),
+        port: port.clone(),
+        readiness_check_port: port,
+        readiness_check_path: "/healthcheck".to_string(),
+        async_init: false,
+        http_http2_auto: true,
+        ..Default::default()
+    };
+
+    // The adapter finds out the app speaks h2c at readiness
+    let mut adapter = Adapter::new(&options(port.to_string()));
+    adapter.check_init_health().await;
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(200, response.status());
+    assert_eq!("OK", body_to_string(response).await);
+    // the probe and the request
+    assert_eq!(2, http2_requests.load(Ordering::SeqCst));
+
+    // HTTP/1.1 apps keep getting HTTP/1.1
+    let app_server = MockServer::start();
+    let hello = app_server.mock(|when, then| {
+        when.path("/hello");
+        then.status(200).body("OK");
+    });
+    let mut adapter = Adapter::new(&options(app_server.port().to_string()));
+    adapter.check_init_health().await;
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    hello.assert();
+    assert_eq!(200, response.status());
+}
+
+#[tokio::test]
+async fn test_register_extension_after_ready() {
+    // Start the Lambda runtime API and app servers
+    let runtime_api = MockServer::start();
+    let register = runtime_api.mock(|when, then| {
+        when.method(POST).path("/2020-01-01/extension/register");
+        then.status(200).header("Lambda-Extension-Identifier", "extension-1");
+    });
+    runtime_api.mock(|when, then| {
+        when.method(GET).path("/2020-01-01/extension/event/next");
+        then.status(200).body(r#"{"eventType":"SHUTDOWN"}"#);
+    });
+    env::set_var("AWS_LAMBDA_RUNTIME_API", runtime_api.address().to_string());
+    let app_server = MockServer::start();
+    let healthcheck = app_server.mock(|when, then| {
+        when.method(GET).path("/healthcheck");
+        then.status(200);
+    });
+
+    struct Registration(Arc<std::sync::Mutex<Option<String>>>);
+
+    impl LwaPlugin for Registration {
+        fn on_extension_registered(&self, extension_id: &str) {
+            *self.0.lock().unwrap() = Some(extension_id.to_string());
+        }
+    }
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        async_init: false,
+        register_extension_after_ready: true,
+        ..Default::default()
+    });
+    let registered = Arc::new(std::sync::Mutex::new(None));
+    adapter.register_plugin(Registration(registered.clone()));
+
+    // The extension registers once the app is ready
+    adapter.register_default_extension();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    register.assert_hits(0);
+
+    adapter.check_init_health().await;
+    healthcheck.assert();
+    register.assert();
+    assert_eq!(Some("extension-1".to_string()), *registered.lock().unwrap());
+}
+
+#[tokio::test]
+async fn test_http_body_transforms() {
+    // Start app server
+    let app_server = MockServer::start();
+    let users = app_server.mock(|when, then| {
+        when.method(POST)
+            .path("/users")
+            .json_body(json!({ "user_name": "ana", "password": "[REDACTED]" }));
+        then.status(201)
+            .header("content-type", "application/json")
+            .json_body(json!({ "data": { "user_id": 1, "user_name": "ana" } }));
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        body_transforms: Some(
+            r#"[
+                {"path":"/users","apply_to":"request","transforms":[{"redact":["password"]},{"case":"snake"}]},
+                {"path":"/users","transforms":[{"unwrap":"data"},{"case":"camel"}]}
+            ]"#
+            .to_string(),
+        ),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_method(Method::POST)
+            .with_path("/users")
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"userName":"ana","password":"secret"}"#)
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(201, response.status());
+    users.assert();
+    let body: serde_json::Value = serde_json::from_str(&body_to_string(response).await).unwrap();
+    assert_eq!(json!({ "userId": 1, "userName": "ana" }), body);
+}
+
+#[tokio::test]
+async fn test_http_websocket_sse_fallback() {
+    // Start app server with an SSE endpoint next to the socket path
+    let app_server = MockServer::start();
+    let events = app_server.mock(|when, then| {
+        when.method(GET)
+            .path("/socket/events")
+            .header("accept", "text/event-stream")
+            .matches(|request| {
+                let headers = request.headers.as_deref().unwrap_or_default();
+                !headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("upgrade"))
+            });
+        then.status(200)
+            .header("content-type", "text/event-stream")
+            .body("data: hello\n\n");
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        sse_fallback_path: Some("{path}/events".to_string()),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_path("/socket")
+            .with_header("upgrade", "websocket")
+            .with_header("connection", "Upgrade")
+            .with_header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .with_header("sec-websocket-version", "13")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(200, response.status());
+    assert_eq!("sse", response.headers().get("x-lwa-transport").unwrap());
+    assert_eq!("data: hello\n\n", body_to_string(response).await);
+    events.assert();
+}
+
+#[tokio::test]
+async fn test_http_polling_etags() {
+    // Start an app server that does not implement ETags
+    let app_server = MockServer::start();
+    let status = app_server.mock(|when, then| {
+        when.method(GET).path("/status");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"state":"running"}"#);
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        polling_etag_paths: Some("/status".to_string()),
+        ..Default::default()
+    });
+    let poll = |etag: Option<&str>| {
+        let mut builder = LambdaEventBuilder::new().with_path("/status");
+        if let Some(etag) = etag {
+            builder = builder.with_header("if-none-match", etag);
+        }
+        let mut request = Request::from(builder.build());
+        add_lambda_context_to_request(&mut request);
+        request
+    };
+
+    let response = adapter.call(poll(None)).await.expect("Request failed");
+    assert_eq!(200, response.status());
+    let etag = response.headers().get("etag").unwrap().to_str().unwrap().to_string();
+
+    let response = adapter.call(poll(Some(&etag))).await.expect("Request failed");
+    assert_eq!(304, response.status());
+    assert_eq!("", body_to_string(response).await);
+    status.assert_hits(2);
+}
+
+#[tokio::test]
+async fn test_http_sidecar_routes() {
+    // Start app server and a collector sidecar
+    let app_server = MockServer::start();
+    let hello = app_server.mock(|when, then| {
+        when.method(GET).path("/hello");
+        then.status(200).body("from the app");
+    });
+    let collector = MockServer::start();
+    let traces = collector.mock(|when, then| {
+        when.method(POST).path("/v1/traces");
+        then.status(200).body("from the collector");
+    });
+
+    // Initialize adapter routing /otel/* to the collector
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        sidecar_routes: Some(r#"{"/otel/*":"otel","/logs/*":"logs"}"#.to_string()),
+        sidecar_ports: std::collections::HashMap::from([("OTEL".to_string(), collector.port())]),
+        ..Default::default()
+    });
+
+    for (method, path, status, body) in [
+        (Method::POST, "/otel/v1/traces", 200, "from the collector"),
+        (Method::GET, "/hello", 200, "from the app"),
+        (Method::GET, "/logs/tail", 503, "sidecar logs is not registered"),
+    ] {
+        let mut request = Request::from(LambdaEventBuilder::new().with_method(method).with_path(path).build());
+        add_lambda_context_to_request(&mut request);
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!(status, response.status().as_u16());
+        assert_eq!(body, body_to_string(response).await);
+    }
+
+    traces.assert();
+    hello.assert();
+}
+
+#[tokio::test]
+async fn test_http_title_case_headers() {
+    // Start an app server that records the raw request head
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(async move {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut head = Vec::new();
+        let mut buf = [0; 1024];
+        while !head.ends_with(b"\r\n\r\n") {
+            let read = stream.read(&mut buf).await.unwrap();
+            head.extend_from_slice(&buf[..read]);
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+            .await
+            .unwrap();
+        String::from_utf8(head).unwrap()
+    });
+
+    // Initialize adapter writing Title-Case header names
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: "127.0.0.1".to_string(),
+        port: port.to_string(),
+        readiness_check_port: port.to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        header_case: HeaderCase::Title,
+        ..Default::default()
+    });
+
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_path("/hello")
+            .with_header("x-custom-header", "value")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!("ok", body_to_string(response).await);
+
+    let head = server.await.unwrap();
+    assert!(head.contains("\r\nX-Custom-Header: value\r\n"), "{head}");
+    assert!(head.contains("\r\nX-Amzn-Request-Context: "), "{head}");
+}
+
+#[tokio::test]
+async fn test_http_ipv6_host() {
+    // Start an app server on the IPv6 loopback address
+    let listener = TcpListener::bind("[::1]:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut head = Vec::new();
+                let mut buf = [0; 1024];
+                while !head.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(read) => head.extend_from_slice(&buf[..read]),
+                    }
+                }
+                let _ = stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 4\r\n\r\nipv6")
+                    .await;
+            });
+        }
+    });
+
+    for protocol in [Protocol::Tcp, Protocol::Http] {
+        let mut adapter = Adapter::new(&AdapterOptions {
+            host: "::1".to_string(),
+            port: port.to_string(),
+            readiness_check_port: port.to_string(),
+            readiness_check_path: "/healthcheck".to_string(),
+            readiness_check_protocol: protocol,
+            ..Default::default()
+        });
+        adapter.check_init_health().await;
+
+        let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+        add_lambda_context_to_request(&mut request);
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!(200, response.status());
+        assert_eq!("ipv6", body_to_string(response).await);
+    }
+}
+
+/// Start an app server on HTTPS with the certificate of `localhost` and
+/// `127.0.0.1` in the fixtures, signed by the fixture CA.
+async fn start_tls_app() -> u16 {
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/integ_tests/fixtures");
+    let certs = rustls_pemfile::certs(&mut std::fs::read(format!("{fixtures}/localhost.pem")).unwrap().as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let key = rustls_pemfile::private_key(
+        &mut std::fs::read(format!("{fixtures}/localhost-key.pem"))
+            .unwrap()
+            .as_slice(),
+    )
+    .unwrap()
+    .unwrap();
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .unwrap();
+    let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let Ok(stream) = acceptor.accept(stream).await else {
+                    return;
+                };
+                let service = service_fn(|_request| async {
+                    Ok::<_, Infallible>(hyper::Response::new(Full::new(Bytes::from("over tls"))))
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    port
+}
+
+#[tokio::test]
+async fn test_http_app_protocol_https() {
+    let port = start_tls_app().await;
+    let ca_file = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/integ_tests/fixtures/ca.pem");
+    let options = |ca_file: Option<&str>, skip_verify| AdapterOptions {
+        host: "localhost".to_string(),
+        port: port.to_string(),
+        readiness_check_port: port.to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        app_protocol: AppProtocol::Https,
+        app_tls_ca_file: ca_file.map(str::to_string),
+        app_tls_skip_verify: skip_verify,
+        ..Default::default()
+    };
+
+    // the certificate is trusted with the CA bundle, or without verification
+    for (ca_file, skip_verify) in [(Some(ca_file), false), (None, true)] {
+        let mut adapter = Adapter::new(&options(ca_file, skip_verify));
+        adapter.check_init_health().await;
+
+        let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+        add_lambda_context_to_request(&mut request);
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!(200, response.status());
+        assert_eq!("over tls", body_to_string(response).await);
+    }
+
+    // and rejected otherwise
+    let mut adapter = Adapter::new(&options(None, false));
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await;
+    assert!(response.is_err() || response.unwrap().status() != 200);
+}
+
+#[tokio::test]
+async fn test_http_app_protocol_grpc() {
+    // A gRPC response: one empty message, then the status in the trailers
+    struct GrpcResponse(std::collections::VecDeque<http_body::Frame<Bytes>>);
+
+    impl http_body::Body for GrpcResponse {
+        type Data = Bytes;
+        type Error = Infallible;
+
+        fn poll_frame(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Option<Result<http_body::Frame<Bytes>, Infallible>>> {
+            std::task::Poll::Ready(self.0.pop_front().map(Ok))
+        }
+    }
+
+    // Start an app server that only speaks HTTP/2 with prior knowledge
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let service = service_fn(|req: Request<hyper::body::Incoming>| async move {
+                    let te = req.headers().get("te").cloned();
+                    let mut trailers = HeaderMap::new();
+                    trailers.insert("grpc-status", "0".parse().unwrap());
+                    trailers.insert("grpc-message", "ok".parse().unwrap());
+                    let body = GrpcResponse(
+                        vec![
+                            http_body::Frame::data(Bytes::from_static(&[0, 0, 0, 0, 0])),
+                            http_body::Frame::trailers(trailers),
+                        ]
+                        .into(),
+                    );
+                    let mut response = Response::builder()
+                        .header("content-type", "application/grpc+proto")
+                        .header("trailer", "grpc-status, grpc-message");
+                    if let Some(te) = te {
+                        response = response.header("x-received-te", te);
+                    }
+                    Ok::<_, Infallible>(response.body(body).unwrap())
+                });
+                let _ = hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: "127.0.0.1".to_string(),
+        port: port.to_string(),
+        readiness_check_port: port.to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        app_protocol: AppProtocol::Grpc,
+        invoke_mode: LambdaInvokeMode::Buffered,
+        ..Default::default()
+    });
+
+    let mut request = Request::from(
+        LambdaEventBuilder::new()
+            .with_method(Method::POST)
+            .with_path("/helloworld.Greeter/SayHello")
+            .with_header("content-type", "application/grpc+proto")
+            .build(),
+    );
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+
+    // the app got `te: trailers`, and its trailers came back as headers
+    assert_eq!(200, response.status());
+    assert_eq!("trailers", response.headers()["x-received-te"]);
+    assert_eq!("0", response.headers()["grpc-status"]);
+    assert_eq!("ok", response.headers()["grpc-message"]);
+    assert!(!response.headers().contains_key("trailer"));
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&[0, 0, 0, 0, 0][..], &body[..]);
+}
+
+#[tokio::test]
+async fn test_http_response_header_limit() {
+    // Start app server answering with oversized headers
+    let app_server = MockServer::start();
+    app_server.mock(|when, then| {
+        when.method(GET).path("/hello");
+        then.status(200)
+            .header("x-debug", "d".repeat(20000))
+            .header("x-trace", "t".repeat(20000))
+            .body("Hello World");
+    });
+
+    let options = |trim: &str| AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        problem_details: true,
+        response_header_trim: Some(trim.to_string()),
+        ..Default::default()
+    };
+
+    // dropping a low-priority header is enough
+    let mut adapter = Adapter::new(&options("x-debug"));
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(200, response.status());
+    assert!(!response.headers().contains_key("x-debug"));
+    assert!(response.headers().contains_key("x-trace"));
+
+    // otherwise the adapter says which headers are too large
+    let mut adapter = Adapter::new(&options(""));
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+    let response = adapter.call(request).await.expect("Request failed");
+    assert_eq!(502, response.status());
+    let body = body_to_string(response).await;
+    assert!(body.contains("over the 32768 byte limit of alb"), "{body}");
+    assert!(body.contains("x-debug (20007 bytes)"), "{body}");
+}
+
+#[tokio::test]
+async fn test_http_keep_warm_ping() {
+    // Start app server with a cheap route to touch
+    let app_server = MockServer::start();
+    let touch = app_server.mock(|when, then| {
+        when.method(GET).path("/warm");
+        then.status(204);
+    });
+    let events = app_server.mock(|when, then| {
+        when.method(POST).path("/events");
+        then.status(200).body("handled");
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        keep_warm: true,
+        keep_warm_path: Some("/warm".to_string()),
+        invoke_mode: LambdaInvokeMode::Buffered,
+        ..Default::default()
+    });
+
+    let event = |payload: serde_json::Value| {
+        let mut request = Request::from(lambda_http::request::LambdaRequest::PassThrough(payload.to_string()));
+        add_lambda_context_to_request(&mut request);
+        request
+    };
+
+    // the ping is answered by the adapter, after touching the app
+    let response = adapter
+        .call(event(json!({"keepWarm": true})))
+        .await
+        .expect("Request failed");
+    assert_eq!(200, response.status());
+    assert_eq!(
+        json!({"keepWarm": true, "appStatus": 204}).to_string(),
+        body_to_string(response).await
+    );
+    touch.assert();
+    events.assert_hits(0);
+
+    // other scheduled events still reach the app
+    let response = adapter
+        .call(event(json!({"source": "aws.events", "detail-type": "Scheduled Event"})))
+        .await
+        .expect("Request failed");
+    let result: serde_json::Value = serde_json::from_str(&body_to_string(response).await).unwrap();
+    assert_eq!(json!("handled"), result["body"]);
+    events.assert();
+}
+
+#[tokio::test]
+async fn test_http_pass_through_results() {
+    // Start app server answering events in JSON, text and binary
+    let app_server = MockServer::start();
+    app_server.mock(|when, then| {
+        when.method(POST).path("/events").body_contains("json");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(r#"{"batchItemFailures":[]}"#);
+    });
+    app_server.mock(|when, then| {
+        when.method(POST).path("/events").body_contains("csv");
+        then.status(200)
+            .header("content-type", "text/csv; charset=utf-8")
+            .body("id,name\n1,Ada\n");
+    });
+    app_server.mock(|when, then| {
+        when.method(POST).path("/events").body_contains("image");
+        then.status(200)
+            .header("content-type", "image/png")
+            .body(b"\x89PNG\r\n".as_slice());
+    });
+
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        invoke_mode: LambdaInvokeMode::Buffered,
+        ..Default::default()
+    });
+
+    let mut results = Vec::new();
+    for kind in ["json", "csv", "image"] {
+        let payload = json!({ "kind": kind }).to_string();
+        let mut request = Request::from(lambda_http::request::LambdaRequest::PassThrough(payload));
+        add_lambda_context_to_request(&mut request);
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!("application/json", response.headers()["content-type"]);
+        let result: serde_json::Value = serde_json::from_str(&body_to_string(response).await).unwrap();
+        results.push(result);
+    }
+
+    // JSON bodies are the result, other bodies are wrapped with their content type
+    assert_eq!(json!({"batchItemFailures": []}), results[0]);
+    assert_eq!(
+        json!({
+            "statusCode": 200,
+            "headers": {"content-type": "text/csv; charset=utf-8"},
+            "isBase64Encoded": false,
+            "body": "id,name\n1,Ada\n",
+        }),
+        results[1]
+    );
+    assert_eq!(json!(true), results[2]["isBase64Encoded"]);
+    assert_eq!(json!("iVBORw0K"), results[2]["body"]);
+}
+
+#[tokio::test]
+async fn test_http_upstream_unix_socket() {
+    // Start an app server on a Unix socket
+    let socket = env::temp_dir().join(format!("lwa-test-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&socket);
+    let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let service = service_fn(|request: hyper::Request<hyper::body::Incoming>| async move {
+                    let body = format!("unix {}", request.uri().path());
+                    Ok::<_, Infallible>(hyper::Response::new(Full::new(Bytes::from(body))))
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    for protocol in [Protocol::Tcp, Protocol::Http] {
+        // no app listens on the TCP port
+        let mut adapter = Adapter::new(&AdapterOptions {
+            host: "127.0.0.1".to_string(),
+            port: "1".to_string(),
+            readiness_check_port: "1".to_string(),
+            readiness_check_path: "/healthcheck".to_string(),
+            readiness_check_protocol: protocol,
+            upstream_unix_socket: Some(socket.display().to_string()),
+            ..Default::default()
+        });
+        adapter.check_init_health().await;
+
+        let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+        add_lambda_context_to_request(&mut request);
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!(200, response.status());
+        assert_eq!("unix /hello", body_to_string(response).await);
+    }
+    let _ = std::fs::remove_file(&socket);
+}
+
+#[tokio::test]
+async fn test_http_chaos_mode() {
+    // Start app server
+    let app_server = MockServer::start();
+    let hello = app_server.mock(|when, then| {
+        when.method(GET).path("/hello");
+        then.status(200).body("Hello World");
+    });
+
+    // Every request is delayed
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        chaos: Some(ChaosOptions {
+            latency: Duration::from_millis(100),
+            error_rate: 0.0,
+        }),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+    let started = Instant::now();
+    let response = adapter.call(request).await.expect("Request failed");
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    assert_eq!(200, response.status());
+    hello.assert();
+
+    // Every request fails without reaching the app
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        chaos: Some(ChaosOptions {
+            latency: Duration::ZERO,
+            error_rate: 1.0,
+        }),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+    assert!(adapter.call(request).await.is_err());
+    hello.assert_hits(1);
+}
+
+#[tokio::test]
+async fn test_http_streaming_buffers_small_responses() {
+    // Start app server
+    let app_server = MockServer::start();
+    let hello = app_server.mock(|when, then| {
+        when.method(GET).path("/hello");
+        then.status(200)
+            .header("content-type", "text/plain")
+            .body("Hello World");
+    });
+
+    // Initialize adapter in response streaming mode with buffering of small responses
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        invoke_mode: LambdaInvokeMode::ResponseStream,
+        streaming_buffer_threshold: Some(1024),
+        ..Default::default()
+    });
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+    add_lambda_context_to_request(&mut request);
+
+    let response = adapter.call(request).await.expect("Request failed");
+
+    hello.assert();
+    assert_eq!(200, response.status());
+    assert_eq!(response.headers().get("content-length").unwrap(), "11");
+    assert_eq!(Some(11), response.body().size_hint().exact());
+    assert_eq!("Hello World", body_to_string(response).await);
+}
+
+#[tokio::test]
+async fn test_http_compress_flush_event_stream() {
+    // Start app server
+    let app_server = MockServer::start();
+    let events = app_server.mock(|when, then| {
+        when.method(GET).path("/events");
+        then.status(200)
+            .header("content-type", "text/event-stream")
+            .body("data: one\n\ndata: two\n\n");
+    });
+
+    // Initialize adapter with flush-aware compression in response streaming mode
+    let adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        compression: true,
+        compression_flush: true,
+        invoke_mode: LambdaInvokeMode::ResponseStream,
+        ..Default::default()
+    });
+
+    let mut svc = ServiceBuilder::new().layer(CompressionLayer::new()).service(adapter);
+
+    let req = LambdaEventBuilder::new()
+        .with_path("/events")
+        .with_header("accept-encoding", "gzip")
+        .build();
+    let mut request = Request::from(req);
+    add_lambda_context_to_request(&mut request);
+
+    let response = svc.call(request).await.expect("Request failed");
+
+    events.assert();
+    assert_eq!(200, response.status());
+    assert_eq!(response.headers().get("content-encoding").unwrap(), "gzip");
+    assert!(!response.headers().contains_key("content-length"));
+    assert_eq!("data: one\n\ndata: two\n\n", compressed_body_to_string(response).await);
+}
+
+#[tokio::test]
+async fn test_http_sse_flush() {
+    // Start app server
+    let app_server = MockServer::start();
+    let events = app_server.mock(|when, then| {
+        when.method(GET).path("/events");
+        then.status(200)
+            .header("content-type", "text/event-stream")
+            .body("data: one\n\ndata: two\n\n");
+    });
+
+    // Initialize adapter with flush-aware compression and SSE flushing in response streaming mode
+    let adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        compression: true,
+        compression_flush: true,
+        sse_flush: true,
+        stream_prelude_wait: Some(Duration::from_millis(50)),
+        invoke_mode: LambdaInvokeMode::ResponseStream,
+        ..Default::default()
+    });
+
+    let mut svc = ServiceBuilder::new().layer(CompressionLayer::new()).service(adapter);
+
+    let req = LambdaEventBuilder::new()
+        .with_path("/events")
+        .with_header("accept-encoding", "gzip")
+        .build();
+    let mut request = Request::from(req);
+    add_lambda_context_to_request(&mut request);
+
+    let response = svc.call(request).await.expect("Request failed");
+
+    // events are neither compressed nor held back for the prelude
+    events.assert();
+    assert_eq!(200, response.status());
+    assert!(!response.headers().contains_key("content-encoding"));
+    let mut body = response.into_body();
+    let mut streamed = String::new();
+    while let Some(frame) = body.frame().await {
+        if let Ok(data) = frame.unwrap().into_data() {
+            let data = String::from_utf8(data.to_vec()).unwrap();
+            assert!(data.ends_with("\n\n"));
+            streamed.push_str(&data);
+        }
+    }
+    assert_eq!("data: one\n\ndata: two\n\n", streamed);
+}
+
+#[tokio::test]
+async fn test_http_ndjson_to_sse() {
+    // Start app server
+    let app_server = MockServer::start();
+    let records = app_server.mock(|when, then| {
+        when.method(GET).path("/records");
+        then.status(200)
+            .header("content-type", "application/x-ndjson")
+            .body("{\"id\":1}\n{\"id\":2}\n");
+    });
+
+    // Initialize adapter with NDJSON to SSE conversion in response streaming mode
+    let mut adapter = Adapter::new(&AdapterOptions {
+        host: app_server.host(),
+        port: app_server.port().to_string(),
+        readiness_check_port: app_server.port().to_string(),
+        readiness_check_path: "/healthcheck".to_string(),
+        invoke_mode: LambdaInvokeMode::ResponseStream,
+        ndjson_streaming: NdjsonStreaming::Sse,
+        ..Default::default()
+    });
+
+    let mut request = Request::from(LambdaEventBuilder::new().with_path("/records").build());
+    add_lambda_context_to_request(&mut request);
+
+    let response = adapter.call(request).await.expect("Request failed");
+
+    records.assert();
+    assert_eq!(200, response.status());
+    assert_eq!(response.headers().get("content-type").unwrap(), "text/event-stream");
+    assert!(!response.headers().contains_key("content-length"));
+    assert_eq!(
+        "data: {\"id\":1}\n\ndata: {\"id\":2}\n\n",
+        body_to_string(response).await
+    );
+}
+
+#[tokio::test]
+async fn test_http_strip_transfer_encoding() {
+    // Start an app server that sends chunked responses
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let service = service_fn(|_req| async {
+                    Response::builder()
+                        .header("transfer-encoding", "chunked")
+                        .body(Full::new(Bytes::from("OK")))
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+
+    for (strip, expected) in [
+        (StripTransferEncoding::Always, false),
+        (StripTransferEncoding::Never, true),
+        (StripTransferEncoding::Auto, true),
+    ] {
+        let mut adapter = Adapter::new(&AdapterOptions {
+            host: "127.0.0.1".to_string(),
+            port: port.to_string(),
+            readiness_check_port: port.to_string(),
+            readiness_check_path: "/healthcheck".to_string(),
+            invoke_mode: LambdaInvokeMode::ResponseStream,
+            strip_transfer_encoding: strip,
+            ..Default::default()
+        });
+
+        let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+        add_lambda_context_to_request(&mut request);
+
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!(
+            expected,
+            response.headers().contains_key("transfer-encoding"),
+            "{strip:?}"
+        );
+        assert_eq!("OK", body_to_string(response).await);
+    }
+}
+
+#[tokio::test]
+async fn test_http_interim_responses_are_swallowed() {
+    // Start an app server that sends 103 Early Hints and 100 Continue before the final response
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 4096];
+                while let Ok(n) = stream.read(&mut buf).await {
+                    if n == 0 {
+                        break;
+                    }
+                    stream
+                        .write_all(
+                            b"HTTP/1.1 103 Early Hints\r\nlink: </style.css>; rel=preload\r\n\r\n\
+                              HTTP/1.1 100 Continue\r\n\r\n\
+                              HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nOK",
+                        )
+                        .await
+                        .unwrap();
+                }
+            });
+        }
+    });
+
+    for invoke_mode in [LambdaInvokeMode::Buffered, LambdaInvokeMode::ResponseStream] {
+        let mut adapter = Adapter::new(&AdapterOptions {
+            host: "127.0.0.1".to_string(),
+            port: port.to_string(),
+            readiness_check_port: port.to_string(),
+            readiness_check_path: "/healthcheck".to_string(),
+            invoke_mode,
+            ..Default::default()
+        });
+
+        let mut request = Request::from(LambdaEventBuilder::new().with_path("/hello").build());
+        add_lambda_context_to_request(&mut request);
+
+        // only the final response reaches Lambda
+        let response = adapter.call(request).await.expect("Request failed");
+        assert_eq!(200, response.status(), "{invoke_mode:?}");
+        assert_eq!("OK", body_to_string(response).await);
+    }
+}
+
+async fn body_to_string(res: Response<ResponseBody>) -> String {
+    let body_bytes = res.collect().await.unwrap().to_bytes();
+    String::from_utf8_lossy(&body_bytes).to_string()
+}
+
+async fn compressed_body_to_string(res: Response<CompressionBody<ResponseBody>>) -> String {
+    let body_bytes = res.collect().await.unwrap().to_bytes();
+    decode_reader(&body_bytes).unwrap()
+}
+
+fn decode_reader(bytes: &[u8]) -> io::Result<String> {
+    let mut gz = GzDecoder::new(bytes);
+    let mut s = String::new();
+    gz.read_to_string(&mut s)?;
+    Ok(s)
+}
+
+/// An event of an API Gateway WebSocket API, as Lambda delivers it.
+fn websocket_event(event_type: &str, route_key: &str, connection_id: &str, body: Option<&str>) -> Request<Body> {
+    let event: lambda_http::request::LambdaRequest = serde_json::from_value(json!({
+        "headers": {"Host": "abc123.execute-api.us-east-1.amazonaws.com", "Authorization": "Bearer token"},
+        "multiValueHeaders": {},
+        "requestContext": {
+            "routeKey": route_key,
+            "eventType": event_type,
+            "messageDirection": "IN",
+            "connectionId": connection_id,
+            "apiId": "abc123",
+            "stage": "prod",
+            "domainName": "abc123.execute-api.us-east-1.amazonaws.com",
+            "requestId": "request-1",
+            "connectedAt": 1700000000000u64,
+            "requestTimeEpoch": 1700000000000u64,
+            "identity": {"sourceIp": "192.0.2.1"},
+        },
+        "body": body,
+        "isBase64Encoded": false,
+    }))
+    .unwrap();
+    let mut request = Request::from(event);
+    add_lambda_context_to_request(&mut request);
+    request
 }
 
 fn add_lambda_context_to_request(request: &mut Request<Body>) {